@@ -255,9 +255,23 @@ public partial class LightWalletClient : IDisposable
 	/// A task that never completes successfully when <paramref name="continually"/> is <see langword="true" />,
 	/// or completes with the last <see cref="SyncProgress"/> after the blockchain has been fully downloaded as of when the call was made.
 	/// </returns>
+	public Task<SyncProgress> DownloadTransactionsAsync(
+		IProgress<SyncProgress>? statusUpdates,
+		IProgress<IReadOnlyDictionary<ZcashAccount, IReadOnlyCollection<Transaction>>>? discoveredTransactions,
+		bool continually,
+		CancellationToken cancellationToken)
+		=> this.DownloadTransactionsAsync(statusUpdates, discoveredTransactions, null, continually, cancellationToken);
+
+	/// <inheritdoc cref="DownloadTransactionsAsync(IProgress{SyncProgress}?, IProgress{IReadOnlyDictionary{ZcashAccount, IReadOnlyCollection{Transaction}}}?, bool, CancellationToken)"/>
+	/// <param name="statusUpdates"><inheritdoc cref="DownloadTransactionsAsync(IProgress{SyncProgress}?, IProgress{IReadOnlyDictionary{ZcashAccount, IReadOnlyCollection{Transaction}}}?, bool, CancellationToken)" path="/param[@name='statusUpdates']"/></param>
+	/// <param name="discoveredTransactions"><inheritdoc cref="DownloadTransactionsAsync(IProgress{SyncProgress}?, IProgress{IReadOnlyDictionary{ZcashAccount, IReadOnlyCollection{Transaction}}}?, bool, CancellationToken)" path="/param[@name='discoveredTransactions']"/></param>
+	/// <param name="notifications">Optional receivers of other events that occur during the scan.</param>
+	/// <param name="continually"><inheritdoc cref="DownloadTransactionsAsync(IProgress{SyncProgress}?, IProgress{IReadOnlyDictionary{ZcashAccount, IReadOnlyCollection{Transaction}}}?, bool, CancellationToken)" path="/param[@name='continually']"/></param>
+	/// <param name="cancellationToken"><inheritdoc cref="DownloadTransactionsAsync(IProgress{SyncProgress}?, IProgress{IReadOnlyDictionary{ZcashAccount, IReadOnlyCollection{Transaction}}}?, bool, CancellationToken)" path="/param[@name='cancellationToken']"/></param>
 	public async Task<SyncProgress> DownloadTransactionsAsync(
 		IProgress<SyncProgress>? statusUpdates,
 		IProgress<IReadOnlyDictionary<ZcashAccount, IReadOnlyCollection<Transaction>>>? discoveredTransactions,
+		SyncNotifications? notifications,
 		bool continually,
 		CancellationToken cancellationToken)
 	{
@@ -266,8 +280,9 @@ public partial class LightWalletClient : IDisposable
 			cancellation => LightWalletMethods.Sync(
 				this.dbinit,
 				this.serverUrl.AbsoluteUri,
-				new SyncUpdateSink(this, statusUpdates, discoveredTransactions),
+				new SyncUpdateSink(this, statusUpdates, discoveredTransactions, notifications),
 				continually,
+				new SyncOptions(),
 				cancellation),
 			cancellationToken);
 
@@ -288,7 +303,7 @@ public partial class LightWalletClient : IDisposable
 			throw new InvalidOperationException(Strings.UnrecognizedAccount);
 		}
 
-		return LightWalletMethods.GetTransactions(this.dbinit, accountId, startingBlock)
+		return LightWalletMethods.GetTransactions(this.dbinit, accountId, startingBlock, null, ChangeNotes.Include)
 			.Select(CreateTransaction)
 			.OrderBy(t => t.When)
 			.ToList();
@@ -325,7 +340,8 @@ public partial class LightWalletClient : IDisposable
 				this.dbinit,
 				this.serverUrl.AbsoluteUri,
 				this.GetUnifiedSpendingKeyBytes(account),
-				details);
+				details,
+				new SendOptions());
 			return result.Select(r => new TxId(r.txid)).ToArray();
 		}
 		catch (uniffi.LightWallet.LightWalletException ex)
@@ -351,7 +367,8 @@ public partial class LightWalletClient : IDisposable
 			SendDetails sendDetails = LightWalletMethods.SimulateSend(
 				this.dbinit,
 				account.FullViewing.UnifiedKey.TextEncoding,
-				LineItemsToSendDetails(payments));
+				LineItemsToSendDetails(payments),
+				new SendOptions());
 
 			return new SpendDetails(sendDetails);
 		}
@@ -432,7 +449,8 @@ public partial class LightWalletClient : IDisposable
 		return Task.Run(
 			delegate
 			{
-				return new TxId(LightWalletMethods.Shield(this.dbinit, this.serverUrl.AbsoluteUri, this.GetUnifiedSpendingKeyBytes(account), address).Single().txid);
+				// Shield UTXOs as soon as they appear in the mempool.
+				return new TxId(LightWalletMethods.Shield(this.dbinit, this.serverUrl.AbsoluteUri, this.GetUnifiedSpendingKeyBytes(account), address, 0, null).Single().txid);
 			},
 			cancellationToken);
 	}
@@ -637,6 +655,27 @@ public partial class LightWalletClient : IDisposable
 		public decimal Fee { get; private set; }
 	}
 
+	/// <summary>
+	/// The phases that a range of blocks goes through during a sync.
+	/// </summary>
+	public enum ScanPhase
+	{
+		/// <summary>
+		/// The blocks are being downloaded.
+		/// </summary>
+		Downloading,
+
+		/// <summary>
+		/// The downloaded blocks are being scanned for the wallet's transactions.
+		/// </summary>
+		Scanning,
+
+		/// <summary>
+		/// The blocks have been scanned.
+		/// </summary>
+		Completed,
+	}
+
 	/// <summary>
 	/// Carries details of a progress update on a send operation.
 	/// </summary>
@@ -686,10 +725,56 @@ public partial class LightWalletClient : IDisposable
 		public double PercentComplete => this.TotalSteps == 0 ? 0 : (double)this.CurrentStep * 100 / this.TotalSteps;
 	}
 
+	/// <summary>
+	/// Optional receivers of the events that occur during a <see cref="DownloadTransactionsAsync(IProgress{SyncProgress}?, IProgress{IReadOnlyDictionary{ZcashAccount, IReadOnlyCollection{Transaction}}}?, SyncNotifications?, bool, CancellationToken)"/> operation,
+	/// beyond its progress and the transactions it discovers.
+	/// </summary>
+	public class SyncNotifications
+	{
+		/// <summary>
+		/// Gets a receiver of the block ranges as they are downloaded, scanned, and completed.
+		/// </summary>
+		/// <remarks>
+		/// The range includes <c>StartHeight</c> and excludes <c>EndHeight</c>.
+		/// </remarks>
+		public IProgress<(uint StartHeight, uint EndHeight, ScanPhase Phase)>? ScanRanges { get; init; }
+
+		/// <summary>
+		/// Gets a receiver of an account's balances whenever the scan changes them.
+		/// </summary>
+		public IProgress<(ZcashAccount Account, AccountBalances Balances)>? Balances { get; init; }
+
+		/// <summary>
+		/// Gets a receiver of the payments to the wallet that the scan discovered or saw mined.
+		/// </summary>
+		/// <remarks>
+		/// <c>Confirmations</c> is 0 for a payment that has not been mined.
+		/// </remarks>
+		public IProgress<IReadOnlyList<(ZcashAccount Account, TxId TransactionId, ZcashAddress Address, Pool Pool, decimal Amount, Memo Memo, uint? MinedHeight, uint Confirmations)>>? IncomingPayments { get; init; }
+
+		/// <summary>
+		/// Gets a receiver of the payments that recurring payments added to the send queue because they came due.
+		/// </summary>
+		/// <remarks>
+		/// <c>Id</c> identifies the payment in the send queue.
+		/// </remarks>
+		public IProgress<IReadOnlyList<(uint Id, ZcashAccount Account, ZcashAddress Recipient, decimal Amount, Memo Memo, DateTime Enqueued)>>? ScheduledPayments { get; init; }
+
+		/// <summary>
+		/// Gets a receiver of the chain reorgs that caused the wallet to discard the blocks above <c>RewindHeight</c>.
+		/// </summary>
+		/// <remarks>
+		/// <c>AffectedTransactions</c> had been mined in the discarded blocks and may have been orphaned.
+		/// They are reported again as discovered transactions once the blocks that replaced them are scanned.
+		/// </remarks>
+		public IProgress<(uint RewindHeight, IReadOnlyList<TxId> AffectedTransactions)>? Reorgs { get; init; }
+	}
+
 	private class SyncUpdateSink(
 		LightWalletClient client,
 		IProgress<SyncProgress>? statusUpdates,
-		IProgress<IReadOnlyDictionary<ZcashAccount, IReadOnlyCollection<Transaction>>>? discoveredTransactions)
+		IProgress<IReadOnlyDictionary<ZcashAccount, IReadOnlyCollection<Transaction>>>? discoveredTransactions,
+		SyncNotifications? notifications)
 		: SyncUpdate
 	{
 		public void UpdateStatus(SyncUpdateData data) => statusUpdates?.Report(new(data));
@@ -710,6 +795,71 @@ public partial class LightWalletClient : IDisposable
 					g.Select(CreateTransaction).ToArray()));
 			discoveredTransactions.Report(dictionary);
 		}
+
+		public void ScanRangeEvent(ScanRange range, ScanRangePhase phase)
+		{
+			notifications?.ScanRanges?.Report((range.start, range.end, phase switch
+			{
+				ScanRangePhase.Downloading => ScanPhase.Downloading,
+				ScanRangePhase.Scanning => ScanPhase.Scanning,
+				ScanRangePhase.Completed => ScanPhase.Completed,
+				_ => throw new NotSupportedException(),
+			}));
+		}
+
+		public void ReportBalances(uint accountId, UserBalances balances)
+		{
+			notifications?.Balances?.Report((client.accountsById[accountId], new AccountBalances(client.Network.AsSecurity(), balances)));
+		}
+
+		public void ReportIncomingPayments(List<IncomingPayment> payments)
+		{
+			if (notifications?.IncomingPayments is not { } receiver)
+			{
+				return;
+			}
+
+			receiver.Report((
+				from p in payments
+				select (
+					client.accountsById[p.accountId],
+					new TxId(p.txid),
+					ZcashAddress.Decode(p.address),
+					p.pool switch
+					{
+						uniffi.LightWallet.Pool.Transparent => Pool.Transparent,
+						uniffi.LightWallet.Pool.Sapling => Pool.Sapling,
+						uniffi.LightWallet.Pool.Orchard => Pool.Orchard,
+						_ => throw new NotSupportedException(),
+					},
+					ZatsToZEC(p.value),
+					p.memo is null ? Memo.NoMemo : new Memo(p.memo),
+					p.minedHeight,
+					p.confirmations)).ToArray());
+		}
+
+		public void ReportScheduledPayments(List<QueuedPayment> payments)
+		{
+			if (notifications?.ScheduledPayments is not { } receiver)
+			{
+				return;
+			}
+
+			receiver.Report((
+				from p in payments
+				select (
+					p.id,
+					client.accountsById[p.accountId],
+					ZcashAddress.Decode(p.recipient),
+					ZatsToZEC(p.value),
+					p.memo is null ? Memo.NoMemo : new Memo(p.memo),
+					p.enqueued)).ToArray());
+		}
+
+		public void ReportReorg(uint rewindHeight, List<byte[]> affectedTxids)
+		{
+			notifications?.Reorgs?.Report((rewindHeight, affectedTxids.Select(txid => new TxId(txid)).ToArray()));
+		}
 	}
 
 	private class Cancellation : CancellationSource, IDisposable
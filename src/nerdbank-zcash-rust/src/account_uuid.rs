@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use blake2b_simd::Params;
+use zcash_client_backend::data_api::WalletRead;
+use zcash_client_sqlite::AccountId;
+use zcash_keys::keys::UnifiedFullViewingKey;
+use zcash_primitives::consensus::Network;
+
+use crate::{backing_store::Db, error::Error};
+
+/// Derives a UUID that identifies an account by its viewing key.
+///
+/// Unlike the wallet's own account IDs, which are assigned in the order accounts are added,
+/// this is the same in every wallet the account is added to, including one recreated from the same seed or UFVK.
+/// It is a version 8 (custom) UUID whose bits come from a hash of the encoded UFVK.
+pub(crate) fn account_uuid(ufvk: &UnifiedFullViewingKey, network: &Network) -> Vec<u8> {
+    let hash = Params::new()
+        .hash_length(16)
+        .personal(b"NerdbankAcctUUID")
+        .hash(ufvk.encode(network).as_bytes());
+    let mut uuid = hash.as_bytes().to_vec();
+    uuid[6] = (uuid[6] & 0x0f) | 0x80;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    uuid
+}
+
+/// Gets the UUID of every account in the wallet.
+pub(crate) fn account_uuids(db: &Db, network: &Network) -> Result<HashMap<u32, Vec<u8>>, Error> {
+    Ok(db
+        .data
+        .get_unified_full_viewing_keys()?
+        .iter()
+        .map(|(id, ufvk)| (u32::from(*id), account_uuid(ufvk, network)))
+        .collect())
+}
+
+/// Finds the wallet's ID for the account with the given UUID.
+pub(crate) fn get_account_id(db: &Db, network: &Network, uuid: &[u8]) -> Result<AccountId, Error> {
+    account_uuids(db, network)?
+        .into_iter()
+        .find(|(_, u)| u == uuid)
+        .map(|(id, _)| AccountId::from(id))
+        .ok_or_else(|| Error::InvalidArgument("No account has that UUID.".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::Account;
+
+    use crate::test_constants::setup_test;
+
+    use super::*;
+
+    #[tokio_shared_rt::test]
+    async fn test_account_uuid_is_stable() {
+        let mut setup = setup_test().await;
+        let (_, birthday, account_id, usk) = setup.create_account().await.unwrap();
+        let uuid =
+            account_uuids(&setup.db, &setup.network).unwrap()[&u32::from(account_id)].clone();
+        assert_eq!(16, uuid.len());
+        assert_eq!(0x80, uuid[6] & 0xf0);
+        assert_eq!(
+            account_id,
+            get_account_id(&setup.db, &setup.network, &uuid).unwrap()
+        );
+        assert!(get_account_id(&setup.db, &setup.network, &[0; 16]).is_err());
+
+        // The same account gets the same UUID in another wallet.
+        let other_file = setup.data_file.with_file_name("other.sqlite");
+        let mut other = Db::init(&other_file, setup.network).unwrap();
+        let other_account = other
+            .import_account_ufvk(
+                &usk.to_unified_full_viewing_key(),
+                false,
+                birthday,
+                &mut setup.client,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            uuid,
+            account_uuids(&other, &setup.network).unwrap()[&u32::from(other_account.id())]
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::time::SystemTime;
+
+use rusqlite::{named_params, Connection};
+use time::OffsetDateTime;
+use zcash_client_sqlite::AccountId;
+use zcash_primitives::{consensus::Network, zip32::DiversifierIndex};
+
+use crate::{
+    backing_store::Db,
+    error::Error,
+    sql_statements::{
+        GET_MAX_ALLOCATED_DIVERSIFIER_INDEX, RESERVE_DIVERSIFIER_INDEX, SET_ALLOCATED_ADDRESS,
+    },
+};
+
+/// The most diversifier indexes to reserve when looking for one that produces a valid address.
+/// About half of all indexes produce a valid Sapling receiver, so this is very unlikely to be exhausted.
+const MAX_DIVERSIFIER_ATTEMPTS: u32 = 64;
+
+/// A diversified address that was handed out for one purpose and will never be handed out again.
+#[derive(Debug, Clone)]
+pub struct AddressAllocation {
+    pub account_id: u32,
+    pub address: String,
+    /// The little-endian diversifier index of `address`.
+    pub diversifier_index: Vec<u8>,
+    /// What the address was allocated for, as given by the caller.
+    pub purpose: String,
+    pub allocated: SystemTime,
+}
+
+/// Allocates a diversified address of the account that has never been handed out before.
+///
+/// The diversifier index is reserved in the database before the address is derived from it,
+/// and a unique constraint on the reservation means two callers (even in different processes)
+/// can never be given the same index.
+pub fn allocate_payment_address(
+    db: &mut Db,
+    conn: &Connection,
+    network: &Network,
+    account_id: AccountId,
+    purpose: &str,
+) -> Result<AddressAllocation, Error> {
+    let allocated = SystemTime::now();
+    for _ in 0..MAX_DIVERSIFIER_ATTEMPTS {
+        let index = reserve_next_index(conn, account_id, purpose, allocated)?;
+
+        // Indexes that don't produce a valid address stay reserved (without an address) so they aren't tried again.
+        if let Ok(ua) = db.add_diversifier(account_id, index) {
+            let address = ua.encode(network);
+            conn.execute(
+                SET_ALLOCATED_ADDRESS,
+                named_params! {
+                    ":account_id": u32::from(account_id),
+                    ":diversifier_index_be": be_bytes(&index),
+                    ":address": address,
+                },
+            )?;
+
+            return Ok(AddressAllocation {
+                account_id: account_id.into(),
+                address,
+                diversifier_index: index.as_bytes().to_vec(),
+                purpose: purpose.to_string(),
+                allocated,
+            });
+        }
+    }
+
+    Err(Error::Internal(
+        "No valid diversifier index could be found.".to_string(),
+    ))
+}
+
+/// Reserves the diversifier index after the highest one the account has used or reserved.
+fn reserve_next_index(
+    conn: &Connection,
+    account_id: AccountId,
+    purpose: &str,
+    allocated: SystemTime,
+) -> Result<DiversifierIndex, Error> {
+    loop {
+        let max_index: Option<Vec<u8>> = conn.query_row(
+            GET_MAX_ALLOCATED_DIVERSIFIER_INDEX,
+            named_params! { ":account_id": u32::from(account_id) },
+            |row| row.get(0),
+        )?;
+        let mut index = match max_index {
+            Some(mut be) => {
+                be.reverse();
+                let le: [u8; 11] = be
+                    .try_into()
+                    .map_err(|_| Error::Internal("Invalid diversifier index.".to_string()))?;
+                DiversifierIndex::from(le)
+            }
+            None => DiversifierIndex::new(),
+        };
+        index
+            .increment()
+            .map_err(|_| Error::Internal("The account has run out of addresses.".to_string()))?;
+
+        let reserved = conn.execute(
+            RESERVE_DIVERSIFIER_INDEX,
+            named_params! {
+                ":account_id": u32::from(account_id),
+                ":diversifier_index_be": be_bytes(&index),
+                ":purpose": purpose,
+                ":allocated": OffsetDateTime::from(allocated).unix_timestamp(),
+            },
+        )?;
+
+        // Another caller reserved this index first, so look again.
+        if reserved > 0 {
+            return Ok(index);
+        }
+    }
+}
+
+fn be_bytes(index: &DiversifierIndex) -> Vec<u8> {
+    let mut bytes = index.as_bytes().to_vec();
+    bytes.reverse();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{backing_store::open_connection, test_constants::setup_local_test};
+
+    use super::*;
+
+    #[test]
+    fn test_allocate_payment_address() {
+        let mut setup = setup_local_test();
+        let (_, _, account_id, _) = setup.create_account().unwrap();
+        let conn = open_connection(&setup.data_file).unwrap();
+
+        let first =
+            allocate_payment_address(&mut setup.db, &conn, &setup.network, account_id, "order 1")
+                .unwrap();
+        let second =
+            allocate_payment_address(&mut setup.db, &conn, &setup.network, account_id, "order 2")
+                .unwrap();
+        assert_ne!(first.address, second.address);
+        assert_eq!("order 1", first.purpose);
+        assert!(index_value(&first.diversifier_index) < index_value(&second.diversifier_index));
+
+        // Addresses added by index, without an allocation, must not be allocated later.
+        let mut added = DiversifierIndex::from(index_value(&second.diversifier_index) as u32 + 1);
+        while setup.db.add_diversifier(account_id, added).is_err() {
+            added.increment().unwrap();
+        }
+        let third =
+            allocate_payment_address(&mut setup.db, &conn, &setup.network, account_id, "order 3")
+                .unwrap();
+        assert!(index_value(&third.diversifier_index) > index_value(added.as_bytes()));
+    }
+
+    fn index_value(index: &[u8]) -> u128 {
+        let mut bytes = [0; 16];
+        bytes[..11].copy_from_slice(index);
+        u128::from_le_bytes(bytes)
+    }
+}
@@ -0,0 +1,109 @@
+use crate::error::Error;
+
+/// The number of zatoshis in one ZEC.
+pub(crate) const COIN: u64 = 1_0000_0000;
+
+/// The number of decimal places in a ZEC amount.
+const DECIMALS: usize = 8;
+
+/// The largest amount that can ever exist, in zatoshis.
+const MAX_MONEY: u64 = 21_000_000 * COIN;
+
+/// Formats an amount of zatoshis as ZEC, e.g. `-1.5`.
+///
+/// The decimal separator is always `.`, there are no digit group separators,
+/// and trailing zeros after the decimal point are omitted.
+pub fn format_zec(zatoshis: i64) -> String {
+    let abs_value = zatoshis.unsigned_abs();
+    let sign = if zatoshis.is_negative() { "-" } else { "" };
+    let whole = abs_value / COIN;
+    let frac = abs_value % COIN;
+    if frac == 0 {
+        format!("{sign}{whole}")
+    } else {
+        let frac = format!("{:0width$}", frac, width = DECIMALS);
+        format!("{sign}{whole}.{}", frac.trim_end_matches('0'))
+    }
+}
+
+/// Parses a ZEC amount, e.g. `1.5` or `.001`, into zatoshis.
+///
+/// Only the ASCII digits and a `.` decimal separator are accepted,
+/// with no more than 8 digits after the separator.
+/// Leading and trailing whitespace is ignored.
+pub fn parse_zec(value: &str) -> Result<u64, Error> {
+    let invalid = || Error::InvalidArgument(format!("Invalid ZEC amount: \"{value}\""));
+
+    let trimmed = value.trim();
+    let (whole, frac) = trimmed.split_once('.').unwrap_or((trimmed, ""));
+    if (whole.is_empty() && frac.is_empty())
+        || frac.len() > DECIMALS
+        || !whole
+            .bytes()
+            .chain(frac.bytes())
+            .all(|b| b.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+
+    let whole: u64 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().map_err(|_| invalid())?
+    };
+    let frac: u64 = if frac.is_empty() {
+        0
+    } else {
+        format!("{:0<width$}", frac, width = DECIMALS)
+            .parse()
+            .map_err(|_| invalid())?
+    };
+
+    let zatoshis = whole
+        .checked_mul(COIN)
+        .and_then(|v| v.checked_add(frac))
+        .filter(|v| *v <= MAX_MONEY)
+        .ok_or_else(|| Error::InvalidArgument(format!("ZEC amount out of range: \"{value}\"")))?;
+
+    Ok(zatoshis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_zec() {
+        assert_eq!("0", format_zec(0));
+        assert_eq!("1", format_zec(1_0000_0000));
+        assert_eq!("1.5", format_zec(1_5000_0000));
+        assert_eq!("0.00000001", format_zec(1));
+        assert_eq!("-0.5", format_zec(-5000_0000));
+        assert_eq!("-92233720368.54775808", format_zec(i64::MIN));
+    }
+
+    #[test]
+    fn test_parse_zec() {
+        assert_eq!(0, parse_zec("0").unwrap());
+        assert_eq!(1_5000_0000, parse_zec("1.5").unwrap());
+        assert_eq!(1_0000_0000, parse_zec("1.").unwrap());
+        assert_eq!(10_0000, parse_zec(".001").unwrap());
+        assert_eq!(1, parse_zec(" 0.00000001 ").unwrap());
+        assert_eq!(MAX_MONEY, parse_zec("21000000").unwrap());
+
+        for invalid in [
+            "",
+            ".",
+            "-1",
+            "+1",
+            "1,5",
+            "1.5.0",
+            "1e3",
+            "0.000000001",
+            "21000000.00000001",
+            "99999999999999999999",
+        ] {
+            assert!(parse_zec(invalid).is_err(), "{invalid}");
+        }
+    }
+}
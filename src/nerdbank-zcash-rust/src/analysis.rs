@@ -1,15 +1,21 @@
-use std::num::NonZeroU32;
+use std::{
+    collections::{BTreeMap, HashMap},
+    num::NonZeroU32,
+};
 
-use rusqlite::{named_params, Connection};
+use rusqlite::{named_params, Connection, Row};
 use zcash_client_sqlite::AccountId;
-use zcash_primitives::transaction::fees::zip317::{FeeRule, MINIMUM_FEE};
+use zcash_primitives::{
+    consensus::BlockHeight,
+    transaction::fees::zip317::{FeeRule, MINIMUM_FEE},
+};
 
-use zcash_client_backend::data_api::WalletRead;
+use zcash_client_backend::data_api::{self, WalletRead};
 
 use crate::{
-    backing_store::Db,
+    backing_store::{open_connection, Db},
     error::Error,
-    interop::DbInit,
+    interop::{DbInit, Pool, ScanRange},
     sql_statements::{GET_BIRTHDAY_HEIGHTS, GET_UNSPENT_NOTES},
 };
 
@@ -22,26 +28,59 @@ pub struct BirthdayHeights {
     pub birthday_height: Option<u32>,
     /// The block number of the oldest unspent note or UTXO, if any.
     pub rebirth_height: Option<u32>,
+    /// The pool of the oldest unspent note or UTXO, which determined the `rebirth_height`.
+    pub rebirth_pool: Option<Pool>,
 }
 
 pub fn get_birthday_heights(
     config: DbInit,
     account_id: AccountId,
 ) -> Result<BirthdayHeights, Error> {
-    let conn = Connection::open(config.data_file)?;
-    let heights = conn.query_row(
+    let conn = open_connection(config.data_file)?;
+    let (_, heights) = conn.query_row(
         GET_BIRTHDAY_HEIGHTS,
         named_params! {
             ":account_id": u32::from(account_id),
         },
-        |row| Ok((row.get(0)?, row.get::<_, Option<u32>>(1)?, row.get(2)?)),
+        read_birthday_heights,
     )?;
 
-    Ok(BirthdayHeights {
-        original_birthday_height: heights.0,
-        birthday_height: heights.1,
-        rebirth_height: heights.2,
-    })
+    Ok(heights)
+}
+
+/// Gets the birthday heights of every account in the wallet, keyed by account ID, with a single query.
+pub(crate) fn get_all_birthday_heights(
+    conn: &Connection,
+) -> Result<HashMap<u32, BirthdayHeights>, Error> {
+    let mut stmt = conn.prepare(GET_BIRTHDAY_HEIGHTS)?;
+    let heights = stmt
+        .query_map(
+            named_params! { ":account_id": None::<u32> },
+            read_birthday_heights,
+        )?
+        .collect::<Result<_, _>>()?;
+    Ok(heights)
+}
+
+fn read_birthday_heights(row: &Row) -> rusqlite::Result<(u32, BirthdayHeights)> {
+    let rebirth = [
+        (Pool::Transparent, row.get::<_, Option<u32>>(5)?),
+        (Pool::Sapling, row.get(3)?),
+        (Pool::Orchard, row.get(4)?),
+    ]
+    .into_iter()
+    .filter_map(|(pool, height)| height.map(|h| (pool, h)))
+    .min_by_key(|(_, height)| *height);
+
+    Ok((
+        row.get(0)?,
+        BirthdayHeights {
+            original_birthday_height: row.get(1)?,
+            birthday_height: row.get(2)?,
+            rebirth_height: rebirth.map(|(_, height)| height),
+            rebirth_pool: rebirth.map(|(pool, _)| pool),
+        },
+    ))
 }
 
 /// Balances that may be presented to a user in a wallet app.
@@ -68,8 +107,12 @@ pub fn get_birthday_heights(
 /// The more likely scenario is that the sender is trying to send a small amount of value as a new user and doesn't realize
 /// the value is too small to be useful.
 /// A good Zcash wallet should prevent sending dust in the first place.
-#[derive(Default)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct UserBalances {
+    /// Whether the wallet has synced far enough to compute balances.
+    /// When `false`, every balance is zero because nothing is known yet, rather than because the account is empty.
+    pub synced: bool,
+
     /// Available for immediate spending.
     /// Expected fees are *not* deducted from this value, but the app may do so by subtracting `minimum_fees`.
     /// `dust` is excluded from this value.
@@ -100,12 +143,28 @@ pub struct UserBalances {
     /// making them essentially inaccessible.
     pub dust: u64,
 
-    /// The sum of all *unconfirmed* UTXOs and notes that are not change.
+    /// The sum of all *unconfirmed* UTXOs and notes that are not change,
+    /// including transparent payments seen in the mempool.
     /// This value includes any applicable `incoming_dust`.
     pub incoming: u64,
 
     /// The sum of all *unconfirmed* UTXOs and notes that are not change and are each counted as dust.
     pub incoming_dust: u64,
+
+    /// When the shielded notes in `immature_change` and `immature_income` become spendable, soonest first.
+    /// UTXOs are not included, since they must be shielded rather than merely confirmed.
+    pub maturity_schedule: Vec<MaturityEvent>,
+}
+
+/// Funds that become spendable at a particular block height.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MaturityEvent {
+    /// The chain tip height at which the funds become spendable.
+    /// Blocks are mined about every 75 seconds, which can be used to estimate when this will be.
+    pub height: u32,
+
+    /// The sum of the notes that become spendable at this height.
+    pub value: u64,
 }
 
 pub fn get_user_balances(
@@ -113,18 +172,30 @@ pub fn get_user_balances(
     account_id: AccountId,
     min_confirmations: NonZeroU32,
 ) -> Result<UserBalances, Error> {
-    let marginal_fee: u64 = FeeRule::standard().marginal_fee().into();
     let db = Db::load(&config.data_file, config.network.into())?;
+    let conn = open_connection(config.data_file.clone())?;
+    compute_user_balances(&db, &conn, account_id, min_confirmations)
+}
+
+/// Computes an account's balances using a database handle and connection that the caller already has open.
+pub(crate) fn compute_user_balances(
+    db: &Db,
+    conn: &Connection,
+    account_id: AccountId,
+    min_confirmations: NonZeroU32,
+) -> Result<UserBalances, Error> {
+    let marginal_fee: u64 = FeeRule::standard().marginal_fee().into();
     if let Some((_, anchor)) = db.data.get_target_and_anchor_heights(min_confirmations)? {
-        let conn = Connection::open(config.data_file.clone())?;
         let mut balances_query = conn.prepare(GET_UNSPENT_NOTES)?;
         let mut rows = balances_query.query(named_params! {
             ":account_id": u32::from(account_id),
         })?;
 
         let mut balances = UserBalances {
+            synced: true,
             ..Default::default()
         };
+        let mut maturity_schedule = BTreeMap::new();
 
         while let Some(row) = rows.next()? {
             let block_height: Option<u32> = row.get("block")?;
@@ -159,7 +230,13 @@ pub fn get_user_balances(
 
                 if is_spendable {
                     balances.spendable += value;
-                } else if block_height.is_some() {
+                } else if let Some(height) = block_height {
+                    if is_shielded {
+                        // The note becomes spendable once it is at or below the anchor height.
+                        let mature_height = height + u32::from(min_confirmations) - 1;
+                        *maturity_schedule.entry(mature_height).or_insert(0) += value;
+                    }
+
                     if is_change {
                         balances.immature_change += value;
                     } else {
@@ -171,6 +248,11 @@ pub fn get_user_balances(
             }
         }
 
+        balances.maturity_schedule = maturity_schedule
+            .into_iter()
+            .map(|(height, value)| MaturityEvent { height, value })
+            .collect();
+
         // Add the minimum fee for the receiving note,
         // but only if there exists notes to spend in the buckets that are covered by the minimum_fee.
         if balances.minimum_fees > 0 {
@@ -183,15 +265,165 @@ pub fn get_user_balances(
 
         Ok(balances)
     } else {
-        Ok(Default::default())
+        // A new wallet has no anchor until its first sync reaches the chain tip.
+        Ok(UserBalances {
+            synced: false,
+            ..Default::default()
+        })
     }
 }
 
+/// The balances of a single shielded pool within an account.
+pub struct PoolBalance {
+    /// The value of notes that may be spent right now.
+    pub spendable_value: u64,
+    /// The value of change notes that do not yet have enough confirmations to be spent.
+    pub change_pending_confirmation: u64,
+    /// The value of non-change notes that do not yet have enough confirmations to be spent,
+    /// or whose witnesses are not yet available because the wallet has not finished scanning.
+    pub value_pending_spendability: u64,
+}
+
+impl From<&data_api::Balance> for PoolBalance {
+    fn from(balance: &data_api::Balance) -> Self {
+        PoolBalance {
+            spendable_value: balance.spendable_value().into(),
+            change_pending_confirmation: balance.change_pending_confirmation().into(),
+            value_pending_spendability: balance.value_pending_spendability().into(),
+        }
+    }
+}
+
+/// The balances of an account, broken down by pool.
+pub struct AccountBalance {
+    pub account_id: u32,
+    pub sapling: PoolBalance,
+    pub orchard: PoolBalance,
+    /// The value of all unspent transparent outputs received by the account.
+    pub unshielded: u64,
+}
+
+/// A ratio describing how much of some work has been completed.
+pub struct ProgressRatio {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+/// A snapshot of the wallet's balances and scan state.
+pub struct WalletSummary {
+    /// The balances of each account in the wallet, sorted by account ID.
+    pub account_balances: Vec<AccountBalance>,
+    /// The height of the chain tip as last reported to the wallet.
+    pub chain_tip_height: u32,
+    /// The height below which all blocks have been scanned.
+    pub fully_scanned_height: u32,
+    /// The progress of scanning the blocks required to compute the balances, if it can be determined.
+    pub scan_progress: Option<ProgressRatio>,
+}
+
+/// Gets a summary of the wallet's balances and scan state,
+/// or `None` if the wallet has not been synced far enough to produce one.
+pub fn get_wallet_summary(config: &DbInit) -> Result<Option<WalletSummary>, Error> {
+    let db = Db::load(&config.data_file, config.network.into())?;
+    let summary = match db.data.get_wallet_summary(config.min_confirmations)? {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+
+    let mut account_balances = summary
+        .account_balances()
+        .iter()
+        .map(|(id, b)| AccountBalance {
+            account_id: (*id).into(),
+            sapling: b.sapling_balance().into(),
+            orchard: b.orchard_balance().into(),
+            unshielded: b.unshielded().into(),
+        })
+        .collect::<Vec<_>>();
+    account_balances.sort_by_key(|b| b.account_id);
+
+    Ok(Some(WalletSummary {
+        account_balances,
+        chain_tip_height: summary.chain_tip_height().into(),
+        fully_scanned_height: summary.fully_scanned_height().into(),
+        scan_progress: summary.scan_progress().map(|r| ProgressRatio {
+            numerator: *r.numerator(),
+            denominator: *r.denominator(),
+        }),
+    }))
+}
+
+/// Describes how much of the blockchain has been scanned on behalf of a particular account.
+pub struct AccountSyncState {
+    /// The birthday height of the account. Blocks before this height are never scanned for it.
+    pub birthday_height: u32,
+    /// The height through which every block from `birthday_height` has been scanned,
+    /// or `None` if not even the birthday block has been scanned yet.
+    pub fully_scanned_height: Option<u32>,
+    /// The ranges at or above `birthday_height` that remain to be scanned, in the order the wallet suggests scanning them.
+    pub remaining_ranges: Vec<ScanRange>,
+}
+
+pub fn get_account_sync_state(
+    config: &DbInit,
+    account_id: AccountId,
+) -> Result<AccountSyncState, Error> {
+    let db = Db::load(&config.data_file, config.network.into())?;
+    let birthday = db.data.get_account_birthday(account_id)?;
+
+    // Ranges that end at or below the birthday are irrelevant to this account,
+    // and those that straddle it only matter from the birthday onward.
+    let remaining_ranges = db
+        .data
+        .suggest_scan_ranges()?
+        .iter()
+        .filter(|r| r.block_range().end > birthday)
+        .map(|r| {
+            let mut range = ScanRange::from(r);
+            range.start = range.start.max(birthday.into());
+            range
+        })
+        .collect::<Vec<_>>();
+
+    let fully_scanned_height = match remaining_ranges.iter().map(|r| r.start).min() {
+        Some(lowest_unscanned) if lowest_unscanned > u32::from(birthday) => {
+            Some(lowest_unscanned - 1)
+        }
+        Some(_) => None,
+        None => db
+            .data
+            .block_max_scanned()?
+            .map(|m| m.block_height())
+            .filter(|h| *h >= birthday)
+            .map(BlockHeight::into),
+    };
+
+    Ok(AccountSyncState {
+        birthday_height: birthday.into(),
+        fully_scanned_height,
+        remaining_ranges,
+    })
+}
+
+/// Gets the ranges of blocks the wallet still wants scanned, in the order they would be scanned.
+pub fn get_scan_ranges(config: &DbInit) -> Result<Vec<ScanRange>, Error> {
+    let db = Db::load(&config.data_file, config.network.into())?;
+    Ok(db
+        .data
+        .suggest_scan_ranges()?
+        .iter()
+        .map(ScanRange::from)
+        .collect())
+}
+
 #[cfg(test)]
 mod tests {
     use matches::assert_matches;
 
-    use crate::test_constants::{setup_test, MIN_CONFIRMATIONS};
+    use crate::{
+        sql_statements::RECORD_MEMPOOL_RECEIVE,
+        test_constants::{setup_test, MIN_CONFIRMATIONS},
+    };
 
     use super::*;
 
@@ -203,6 +435,14 @@ mod tests {
         assert_matches!(heights.birthday_height, None);
         assert_ne!(heights.original_birthday_height, 0);
         assert_matches!(heights.rebirth_height, None);
+        assert_matches!(heights.rebirth_pool, None);
+
+        let all = get_all_birthday_heights(&open_connection(&setup.data_file).unwrap()).unwrap();
+        assert_eq!(1, all.len());
+        assert_eq!(
+            heights.original_birthday_height,
+            all[&u32::from(account_id)].original_birthday_height
+        );
     }
 
     #[tokio_shared_rt::test(flavor = "multi_thread")]
@@ -217,6 +457,89 @@ mod tests {
             MIN_CONFIRMATIONS.try_into().unwrap(),
         )
         .unwrap();
+        assert!(balances.synced);
+        assert_eq!(balances.spendable, 0);
+        assert!(balances.maturity_schedule.is_empty());
+
+        // A transparent payment seen in the mempool counts as incoming until it is mined.
+        open_connection(&setup.data_file)
+            .unwrap()
+            .execute(
+                RECORD_MEMPOOL_RECEIVE,
+                named_params! {
+                    ":txid": [1u8; 32],
+                    ":output_index": 0,
+                    ":account_id": u32::from(account_id),
+                    ":value": 50_000,
+                    ":expiry_height": 0,
+                },
+            )
+            .unwrap();
+        let balances = get_user_balances(
+            &setup.db_init,
+            account_id,
+            MIN_CONFIRMATIONS.try_into().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(balances.incoming, 50_000);
         assert_eq!(balances.spendable, 0);
     }
+
+    #[tokio_shared_rt::test(flavor = "multi_thread")]
+    async fn test_get_user_balances_before_sync() {
+        let mut setup = setup_test().await;
+        let (_, _, account_id, _) = setup.create_account().await.unwrap();
+
+        let balances = get_user_balances(
+            &setup.db_init,
+            account_id,
+            MIN_CONFIRMATIONS.try_into().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            UserBalances {
+                synced: false,
+                ..Default::default()
+            },
+            balances
+        );
+    }
+
+    #[tokio_shared_rt::test(flavor = "multi_thread")]
+    async fn test_get_account_sync_state() {
+        let mut setup = setup_test().await;
+        let (_, birthday, account_id, _) = setup.create_account().await.unwrap();
+        setup.sync().await;
+
+        let state = get_account_sync_state(&setup.db_init, account_id).unwrap();
+        assert_eq!(state.birthday_height as u64, birthday);
+        assert!(state.fully_scanned_height.unwrap() >= state.birthday_height);
+        assert!(state.remaining_ranges.is_empty());
+    }
+
+    #[tokio_shared_rt::test(flavor = "multi_thread")]
+    async fn test_get_scan_ranges() {
+        let mut setup = setup_test().await;
+        setup.create_account().await.unwrap();
+        assert!(!get_scan_ranges(&setup.db_init).unwrap().is_empty());
+
+        setup.sync().await;
+        assert!(get_scan_ranges(&setup.db_init).unwrap().is_empty());
+    }
+
+    #[tokio_shared_rt::test(flavor = "multi_thread")]
+    async fn test_get_wallet_summary() {
+        let mut setup = setup_test().await;
+        let (_, _, account_id, _) = setup.create_account().await.unwrap();
+        setup.sync().await;
+
+        let summary = get_wallet_summary(&setup.db_init).unwrap().unwrap();
+        assert_eq!(summary.account_balances.len(), 1);
+        assert_eq!(
+            summary.account_balances[0].account_id,
+            u32::from(account_id)
+        );
+        assert_eq!(summary.account_balances[0].orchard.spendable_value, 0);
+        assert!(summary.fully_scanned_height <= summary.chain_tip_height);
+    }
 }
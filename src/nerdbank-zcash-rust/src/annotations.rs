@@ -0,0 +1,247 @@
+use std::collections::HashSet;
+
+use rusqlite::{named_params, Connection};
+use zcash_primitives::consensus::Network;
+
+use crate::{
+    backing_store::Db,
+    error::Error,
+    interop::Transaction,
+    sql_statements::{
+        ADD_TRANSACTION_TAG, CREATE_TAG, DELETE_TAG, DELETE_TAG_ASSIGNMENTS,
+        DELETE_TRANSACTION_LABEL, GET_TAGGED_TXIDS, GET_TAGS, GET_TAG_BY_NAME,
+        REMOVE_TRANSACTION_TAG, RENAME_TAG, SET_TRANSACTION_LABEL,
+    },
+};
+
+/// A tag that can be applied to transactions to categorize them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub id: u32,
+    pub name: String,
+}
+
+/// Sets the user's label for a transaction, or removes it when `label` is `None` or empty.
+///
+/// Labels are keyed by txid rather than by the wallet's own transaction records,
+/// so they survive a rescan that drops and rediscovers the transaction.
+pub fn set_transaction_label(
+    conn: &Connection,
+    txid: &[u8],
+    label: Option<&str>,
+) -> Result<(), Error> {
+    validate_txid(txid)?;
+
+    match label.filter(|l| !l.is_empty()) {
+        Some(label) => conn.execute(
+            SET_TRANSACTION_LABEL,
+            named_params! { ":txid": txid, ":label": label },
+        )?,
+        None => conn.execute(DELETE_TRANSACTION_LABEL, named_params! { ":txid": txid })?,
+    };
+
+    Ok(())
+}
+
+/// Creates a tag with the given name, or gets the existing one if there already is a tag by that name.
+pub fn create_tag(conn: &Connection, name: &str) -> Result<Tag, Error> {
+    let name = validate_tag_name(name)?;
+    conn.execute(CREATE_TAG, named_params! { ":name": name })?;
+    Ok(
+        conn.query_row(GET_TAG_BY_NAME, named_params! { ":name": name }, |row| {
+            Ok(Tag {
+                id: row.get("id")?,
+                name: row.get("name")?,
+            })
+        })?,
+    )
+}
+
+/// Gets all the tags, ordered by name.
+pub fn get_tags(conn: &Connection) -> Result<Vec<Tag>, Error> {
+    let mut stmt = conn.prepare(GET_TAGS)?;
+    let tags = stmt
+        .query_map([], |row| {
+            Ok(Tag {
+                id: row.get("id")?,
+                name: row.get("name")?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(tags)
+}
+
+pub fn rename_tag(conn: &Connection, tag_id: u32, name: &str) -> Result<(), Error> {
+    let name = validate_tag_name(name)?;
+    let updated = conn
+        .execute(
+            RENAME_TAG,
+            named_params! { ":tag_id": tag_id, ":name": name },
+        )
+        .map_err(|e| match e.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::ConstraintViolation) => {
+                Error::InvalidArgument(format!("A tag named \"{name}\" already exists."))
+            }
+            _ => e.into(),
+        })?;
+    if updated == 0 {
+        return Err(unknown_tag(tag_id));
+    }
+
+    Ok(())
+}
+
+/// Deletes a tag, removing it from every transaction it was applied to.
+pub fn delete_tag(conn: &mut Connection, tag_id: u32) -> Result<(), Error> {
+    let tx = conn.transaction()?;
+    tx.execute(DELETE_TAG_ASSIGNMENTS, named_params! { ":tag_id": tag_id })?;
+    tx.execute(DELETE_TAG, named_params! { ":tag_id": tag_id })?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Applies a tag to a transaction. Applying a tag that the transaction already has does nothing.
+pub fn add_transaction_tag(conn: &Connection, txid: &[u8], tag_id: u32) -> Result<(), Error> {
+    validate_txid(txid)?;
+    let exists = get_tags(conn)?.iter().any(|t| t.id == tag_id);
+    if !exists {
+        return Err(unknown_tag(tag_id));
+    }
+
+    conn.execute(
+        ADD_TRANSACTION_TAG,
+        named_params! { ":txid": txid, ":tag_id": tag_id },
+    )?;
+    Ok(())
+}
+
+pub fn remove_transaction_tag(conn: &Connection, txid: &[u8], tag_id: u32) -> Result<(), Error> {
+    conn.execute(
+        REMOVE_TRANSACTION_TAG,
+        named_params! { ":txid": txid, ":tag_id": tag_id },
+    )?;
+    Ok(())
+}
+
+/// Gets the account's transactions that have the given tag.
+pub fn get_transactions_by_tag(
+    db: &mut Db,
+    conn: &mut Connection,
+    network: &Network,
+    account_id: u32,
+    tag_id: u32,
+) -> Result<Vec<Transaction>, Error> {
+    let txids = conn
+        .prepare(GET_TAGGED_TXIDS)?
+        .query_map(named_params! { ":tag_id": tag_id }, |row| {
+            row.get::<_, Vec<u8>>(0)
+        })?
+        .collect::<Result<HashSet<_>, _>>()?;
+    if txids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    Ok(
+        crate::sync::get_transactions(db, conn, network, Some(account_id), None, None)?
+            .into_iter()
+            .filter(|t| txids.contains(&t.txid))
+            .collect(),
+    )
+}
+
+fn validate_tag_name(name: &str) -> Result<&str, Error> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error::InvalidArgument(
+            "A tag name cannot be empty.".to_string(),
+        ));
+    }
+
+    Ok(name)
+}
+
+fn validate_txid(txid: &[u8]) -> Result<(), Error> {
+    if txid.len() != 32 {
+        return Err(Error::InvalidArgument("Bad txid".to_string()));
+    }
+
+    Ok(())
+}
+
+fn unknown_tag(tag_id: u32) -> Error {
+    Error::InvalidArgument(format!("No tag has ID {tag_id}."))
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::OptionalExtension;
+
+    use crate::{backing_store::open_connection, test_constants::setup_local_test};
+
+    use super::*;
+
+    fn get_label(conn: &Connection, txid: &[u8]) -> Option<String> {
+        conn.query_row(
+            "SELECT label FROM nerdbank_transaction_labels WHERE txid = :txid",
+            named_params! { ":txid": txid },
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_set_transaction_label() {
+        let setup = setup_local_test();
+        let conn = open_connection(&setup.data_file).unwrap();
+        let txid = [7u8; 32];
+
+        set_transaction_label(&conn, &txid, Some("rent March")).unwrap();
+        assert_eq!(Some("rent March".to_string()), get_label(&conn, &txid));
+
+        set_transaction_label(&conn, &txid, Some("rent April")).unwrap();
+        assert_eq!(Some("rent April".to_string()), get_label(&conn, &txid));
+
+        set_transaction_label(&conn, &txid, Some("")).unwrap();
+        assert_eq!(None, get_label(&conn, &txid));
+
+        assert!(set_transaction_label(&conn, &[1, 2, 3], Some("x")).is_err());
+    }
+
+    #[test]
+    fn test_tags() {
+        let setup = setup_local_test();
+        let mut conn = open_connection(&setup.data_file).unwrap();
+        let txid = [7u8; 32];
+
+        let rent = create_tag(&conn, "Rent").unwrap();
+        let food = create_tag(&conn, " Food ").unwrap();
+        assert_eq!("Food", food.name);
+        assert_eq!(rent, create_tag(&conn, "Rent").unwrap());
+        assert!(create_tag(&conn, " ").is_err());
+        assert_eq!(vec![food.clone(), rent.clone()], get_tags(&conn).unwrap());
+
+        assert!(rename_tag(&conn, food.id, "Rent").is_err());
+        rename_tag(&conn, food.id, "Groceries").unwrap();
+        assert!(rename_tag(&conn, 1000, "Other").is_err());
+
+        add_transaction_tag(&conn, &txid, rent.id).unwrap();
+        add_transaction_tag(&conn, &txid, rent.id).unwrap();
+        add_transaction_tag(&conn, &txid, food.id).unwrap();
+        assert!(add_transaction_tag(&conn, &txid, 1000).is_err());
+        let count = |conn: &Connection| -> u32 {
+            conn.query_row("SELECT COUNT(*) FROM nerdbank_transaction_tags", [], |r| {
+                r.get(0)
+            })
+            .unwrap()
+        };
+        assert_eq!(2, count(&conn));
+
+        remove_transaction_tag(&conn, &txid, food.id).unwrap();
+        assert_eq!(1, count(&conn));
+
+        delete_tag(&mut conn, rent.id).unwrap();
+        assert_eq!(0, count(&conn));
+        assert_eq!(1, get_tags(&conn).unwrap().len());
+    }
+}
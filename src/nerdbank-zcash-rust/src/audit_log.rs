@@ -0,0 +1,141 @@
+use std::time::SystemTime;
+
+use rusqlite::{named_params, Connection, Row};
+use time::OffsetDateTime;
+
+use crate::{
+    error::Error,
+    sql_statements::{APPEND_AUDIT_LOG, GET_AUDIT_LOG},
+};
+
+/// A significant operation on the wallet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AuditOperation {
+    /// An account was derived from a seed and added to the wallet.
+    AccountAdded,
+    /// An account was imported from a viewing key.
+    KeyImported,
+    /// A transaction was broadcast to the network.
+    SendBroadcast,
+    /// The wallet discarded blocks after a chain reorg.
+    Rewind,
+    /// A copy of the wallet database was written out.
+    BackupExported,
+}
+
+impl AuditOperation {
+    fn code(self) -> u32 {
+        match self {
+            AuditOperation::AccountAdded => 1,
+            AuditOperation::KeyImported => 2,
+            AuditOperation::SendBroadcast => 3,
+            AuditOperation::Rewind => 4,
+            AuditOperation::BackupExported => 5,
+        }
+    }
+
+    fn from_code(code: u32) -> Option<AuditOperation> {
+        Some(match code {
+            1 => AuditOperation::AccountAdded,
+            2 => AuditOperation::KeyImported,
+            3 => AuditOperation::SendBroadcast,
+            4 => AuditOperation::Rewind,
+            5 => AuditOperation::BackupExported,
+            _ => return None,
+        })
+    }
+}
+
+/// An entry in the wallet's audit log.
+#[derive(Debug, Clone)]
+pub struct AuditLogEntry {
+    pub id: u64,
+    pub time: SystemTime,
+    pub operation: AuditOperation,
+    /// The account the operation applied to, if it applied to one.
+    pub account_id: Option<u32>,
+    /// A description of what was done, e.g. the ID of the transaction that was broadcast.
+    pub details: String,
+}
+
+/// Appends an entry to the audit log.
+///
+/// The log is kept in the wallet database, where triggers reject any change to an entry once it is written.
+pub(crate) fn record(
+    conn: &Connection,
+    operation: AuditOperation,
+    account_id: Option<u32>,
+    details: &str,
+) -> Result<(), Error> {
+    conn.execute(
+        APPEND_AUDIT_LOG,
+        named_params! {
+            ":time": OffsetDateTime::now_utc().unix_timestamp(),
+            ":operation": operation.code(),
+            ":account_id": account_id,
+            ":details": details,
+        },
+    )?;
+    Ok(())
+}
+
+/// Gets the whole audit log, oldest first.
+pub fn get_audit_log(conn: &Connection) -> Result<Vec<AuditLogEntry>, Error> {
+    conn.prepare(GET_AUDIT_LOG)?
+        .query_map([], read_audit_log_entry)?
+        .map(|row| row?)
+        .collect()
+}
+
+fn read_audit_log_entry(row: &Row) -> rusqlite::Result<Result<AuditLogEntry, Error>> {
+    let time: i64 = row.get("time")?;
+    let code: u32 = row.get("operation")?;
+    let id = row.get("id")?;
+    let account_id = row.get("account_id")?;
+    let details = row.get("details")?;
+
+    Ok((|| {
+        Ok(AuditLogEntry {
+            id,
+            time: OffsetDateTime::from_unix_timestamp(time)
+                .map_err(|e| Error::Internal(format!("Invalid audit log time: {}", e)))?
+                .into(),
+            operation: AuditOperation::from_code(code).ok_or_else(|| {
+                Error::Internal(format!("Unrecognized audit log operation: {}", code))
+            })?,
+            account_id,
+            details,
+        })
+    })())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{backing_store::open_connection, test_constants::setup_local_test};
+
+    use super::*;
+
+    #[test]
+    fn test_audit_log() {
+        let mut setup = setup_local_test();
+        let (_, _, account_id, _) = setup.create_account().unwrap();
+        let conn = open_connection(&setup.data_file).unwrap();
+
+        let log = get_audit_log(&conn).unwrap();
+        assert_eq!(1, log.len());
+        assert_eq!(AuditOperation::AccountAdded, log[0].operation);
+        assert_eq!(Some(u32::from(account_id)), log[0].account_id);
+
+        record(&conn, AuditOperation::BackupExported, None, "backup.sqlite").unwrap();
+        let log = get_audit_log(&conn).unwrap();
+        assert_eq!(2, log.len());
+        assert_eq!("backup.sqlite", log[1].details);
+
+        // Entries can't be changed or removed once written.
+        assert!(conn
+            .execute("UPDATE nerdbank_audit_log SET details = ''", [])
+            .is_err());
+        assert!(conn.execute("DELETE FROM nerdbank_audit_log", []).is_err());
+        assert_eq!(2, get_audit_log(&conn).unwrap().len());
+    }
+}
@@ -1,12 +1,20 @@
-use std::{fs, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{c_char, c_int, c_void},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Once, OnceLock},
+    time::Duration,
+};
 
-use rusqlite::Connection;
+use rusqlite::{ffi, Connection};
 use secrecy::SecretVec;
 use tonic::transport::Channel;
+use tracing::warn;
 use zcash_client_backend::{
-    data_api::{AccountBirthday, WalletWrite},
+    data_api::{Account as _, AccountBirthday, WalletRead, WalletWrite},
     keys::UnifiedSpendingKey,
-    proto::service::{self, compact_tx_streamer_client::CompactTxStreamerClient},
+    proto::service::compact_tx_streamer_client::CompactTxStreamerClient,
 };
 use zcash_client_sqlite::{
     wallet::{init::init_wallet_db, Account},
@@ -15,11 +23,132 @@ use zcash_client_sqlite::{
 use zcash_keys::{address::UnifiedAddress, keys::UnifiedFullViewingKey};
 use zcash_primitives::{consensus::Network, zip32::DiversifierIndex};
 
-use crate::{block_source::BlockCache, error::Error};
+use crate::{
+    audit_log::{self, AuditOperation},
+    block_source::BlockCache,
+    error::Error,
+    sql_statements::{
+        CLEAR_SCHEDULED_PAYMENTS, COPY_AUDIT_LOG, CREATE_ADDRESS_ALLOCATIONS, CREATE_AUDIT_LOG,
+        CREATE_INVOICES, CREATE_MEMO_INDEX, CREATE_MEMPOOL_RECEIVES, CREATE_PENDING_REORGS,
+        CREATE_PREVOUT_BACKFILL_FAILURES, CREATE_PREVOUT_VALUES, CREATE_PRICES,
+        CREATE_RECURRING_PAYMENTS, CREATE_RETIRED_ACCOUNTS, CREATE_SEND_QUEUE,
+        CREATE_SEND_QUEUE_TRANSACTIONS, CREATE_SPENDING_POLICIES, CREATE_TAGS,
+        CREATE_TRANSACTION_LABELS, DROP_AUDIT_LOG,
+    },
+    tree_states::fetch_tree_state,
+};
+
+/// How long a connection waits for another connection to release its lock on the database before failing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The `data_file` that selects a wallet that is kept only in memory and never touches the disk.
+/// A name may follow it (e.g. `:memory:preview`) to keep several in-memory wallets apart.
+const IN_MEMORY_PREFIX: &str = ":memory:";
+
+lazy_static! {
+    /// The wallet files that have already been initialized (and thus migrated) by this process.
+    static ref INITIALIZED_WALLETS: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+
+    static ref WALLET_LOCKS: Mutex<HashMap<PathBuf, Arc<WalletLocks>>> = Mutex::new(HashMap::new());
+
+    /// A connection to each in-memory wallet, which keeps it alive between the connections that use it.
+    static ref IN_MEMORY_WALLETS: Mutex<HashMap<PathBuf, Connection>> = Mutex::new(HashMap::new());
+}
+
+/// Checks whether the given `data_file` refers to a wallet that is kept only in memory.
+pub(crate) fn is_in_memory<P: AsRef<Path>>(data_file: P) -> bool {
+    data_file
+        .as_ref()
+        .to_str()
+        .is_some_and(|f| f.starts_with(IN_MEMORY_PREFIX))
+}
+
+/// Discards an in-memory wallet. This has no effect on wallets stored in files.
+pub(crate) fn release_in_memory_wallet<P: AsRef<Path>>(data_file: P) {
+    IN_MEMORY_WALLETS.lock().unwrap().remove(data_file.as_ref());
+}
+
+/// Gets the name by which sqlite should open the given wallet.
+///
+/// In-memory wallets use a shared cache so that every connection we open within the process sees the same database.
+fn sqlite_path(data_file: &Path) -> PathBuf {
+    match data_file
+        .to_str()
+        .and_then(|f| f.strip_prefix(IN_MEMORY_PREFIX))
+    {
+        Some(name) => format!("file:nerdbank-zcash-{name}?mode=memory&cache=shared").into(),
+        None => data_file.to_owned(),
+    }
+}
+
+/// Locks that serialize operations on a wallet which would conflict if run concurrently.
+#[derive(Debug, Default)]
+pub(crate) struct WalletLocks {
+    /// Held for the duration of a sync, so that only one sync runs against a wallet at a time.
+    pub(crate) sync: tokio::sync::Mutex<()>,
+
+    /// Held while spending funds or changing accounts,
+    /// so that (for example) two transactions do not select the same notes.
+    pub(crate) write: tokio::sync::Mutex<()>,
+}
+
+/// Gets the locks for the wallet stored in the given file.
+pub(crate) fn wallet_locks<P: AsRef<Path>>(data_file: P) -> Arc<WalletLocks> {
+    // Different paths may refer to the same file.
+    let key = fs::canonicalize(&data_file).unwrap_or_else(|_| data_file.as_ref().to_owned());
+    WALLET_LOCKS.lock().unwrap().entry(key).or_default().clone()
+}
+
+/// Makes every connection that sqlite opens in this process wait for locks held by other connections to be released
+/// instead of immediately failing with a "database is locked" error, which is important since sync holds connections open for a long time.
+///
+/// This is done with an auto extension because [`WalletDb`] opens its own connection,
+/// which we have no other way to configure.
+fn set_busy_timeout_for_all_connections() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        unsafe extern "C" fn set_busy_timeout(
+            db: *mut ffi::sqlite3,
+            _error_message: *mut *mut c_char,
+            _api: *const c_void,
+        ) -> c_int {
+            ffi::sqlite3_busy_timeout(db, BUSY_TIMEOUT.as_millis() as c_int)
+        }
+
+        let entry_point: unsafe extern "C" fn(
+            *mut ffi::sqlite3,
+            *mut *mut c_char,
+            *const c_void,
+        ) -> c_int = set_busy_timeout;
+
+        // SAFETY: sqlite calls auto extensions with the signature of `set_busy_timeout`,
+        // even though the binding declares the entry point as taking no arguments.
+        let result = unsafe {
+            ffi::sqlite3_auto_extension(Some(std::mem::transmute::<
+                unsafe extern "C" fn(*mut ffi::sqlite3, *mut *mut c_char, *const c_void) -> c_int,
+                unsafe extern "C" fn(),
+            >(entry_point)))
+        };
+        if result != ffi::SQLITE_OK {
+            warn!(
+                "Failed to set the busy timeout for sqlite connections: {}",
+                result
+            );
+        }
+    });
+}
+
+/// Opens a connection to the wallet database for queries that go beyond what [`WalletDb`] offers.
+pub(crate) fn open_connection<P: AsRef<Path>>(data_file: P) -> Result<Connection, Error> {
+    set_busy_timeout_for_all_connections();
+    Ok(Connection::open(sqlite_path(data_file.as_ref()))?)
+}
 
 pub(crate) struct Db {
     pub(crate) data: WalletDb<Connection, Network>,
     pub(crate) blocks: BlockCache,
+    pub(crate) data_file: PathBuf,
+    pub(crate) network: Network,
 }
 
 impl Db {
@@ -46,17 +175,29 @@ impl Db {
         let birthday = {
             // Fetch the tree state corresponding to the last block prior to the wallet's
             // birthday height. NOTE: THIS APPROACH LEAKS THE BIRTHDAY TO THE SERVER!
-            let request = service::BlockId {
-                height: birthday - 1,
-                ..Default::default()
-            };
-            let treestate = client.get_tree_state(request).await?.into_inner();
+            // Nothing vouches for the block at that height, so a cached tree state could be for one that was reorged away.
+            let treestate = fetch_tree_state(client, birthday - 1).await?;
             AccountBirthday::from_treestate(treestate, None)?
         };
 
-        Ok(self
-            .data
-            .import_account_hd(seed, account_index, &birthday)?)
+        self.add_account_with_birthday(seed, account_index, &birthday)
+    }
+
+    /// Adds an account whose birthday the caller already has, so no light wallet server is needed.
+    pub(crate) fn add_account_with_birthday(
+        &mut self,
+        seed: &SecretVec<u8>,
+        account_index: zip32::AccountId,
+        birthday: &AccountBirthday,
+    ) -> Result<(Account, UnifiedSpendingKey), Error> {
+        let account = self.data.import_account_hd(seed, account_index, birthday)?;
+        audit_log::record(
+            &open_connection(&self.data_file)?,
+            AuditOperation::AccountAdded,
+            Some(account.0.id().into()),
+            &format!("ZIP-32 account {}", u32::from(account_index)),
+        )?;
+        Ok(account)
     }
 
     pub(crate) async fn import_account_ufvk(
@@ -70,17 +211,25 @@ impl Db {
         let birthday = {
             // Fetch the tree state corresponding to the last block prior to the wallet's
             // birthday height. NOTE: THIS APPROACH LEAKS THE BIRTHDAY TO THE SERVER!
-            let request = service::BlockId {
-                height: birthday - 1,
-                ..Default::default()
-            };
-            let treestate = client.get_tree_state(request).await?.into_inner();
+            // Nothing vouches for the block at that height, so a cached tree state could be for one that was reorged away.
+            let treestate = fetch_tree_state(client, birthday - 1).await?;
             AccountBirthday::from_treestate(treestate, None)?
         };
 
-        Ok(self
+        let account = self
             .data
-            .import_account_ufvk(ufvk, &birthday, spending_key_available)?)
+            .import_account_ufvk(ufvk, &birthday, spending_key_available)?;
+        audit_log::record(
+            &open_connection(&self.data_file)?,
+            AuditOperation::KeyImported,
+            Some(account.id().into()),
+            if spending_key_available {
+                "Spending key available"
+            } else {
+                "View only"
+            },
+        )?;
+        Ok(account)
     }
 
     pub(crate) fn add_diversifier(
@@ -99,27 +248,324 @@ fn get_db_internal<P: AsRef<Path>>(
     network: Network,
     init: bool,
 ) -> Result<Db, Error> {
-    if init {
+    // Schema migrations can only be pending for a wallet that already exists
+    // and that we haven't already initialized in this process.
+    let existed = data_file.as_ref().exists();
+    let needs_backup = init
+        && existed
+        && !INITIALIZED_WALLETS
+            .lock()
+            .unwrap()
+            .contains(data_file.as_ref())
+        && has_pending_migrations(data_file.as_ref(), network)?;
+
+    if init && is_in_memory(&data_file) {
+        // The database is discarded when its last connection closes, so hold one open.
+        let mut wallets = IN_MEMORY_WALLETS.lock().unwrap();
+        if !wallets.contains_key(data_file.as_ref()) {
+            wallets.insert(data_file.as_ref().to_owned(), open_connection(&data_file)?);
+        }
+    } else if init {
         if let Some(data_dir) = data_file.as_ref().to_owned().parent() {
             fs::create_dir_all(data_dir)?;
         }
+
+        // Write-ahead logging allows readers to proceed while sync is writing to the database.
+        // This setting is persisted in the database file, so it only needs to be set once.
+        open_connection(&data_file)?.pragma_update_and_check(
+            None,
+            "journal_mode",
+            "WAL",
+            |_| Ok(()),
+        )?;
     }
 
-    let mut data = WalletDb::for_path(data_file, network)?;
+    let backup_path = if needs_backup {
+        Some(back_up(data_file.as_ref())?)
+    } else {
+        None
+    };
+
+    // Only once any backup has been made can later opens skip it.
+    if init && existed {
+        INITIALIZED_WALLETS
+            .lock()
+            .unwrap()
+            .insert(data_file.as_ref().to_owned());
+    }
+
+    set_busy_timeout_for_all_connections();
+    let mut data = WalletDb::for_path(sqlite_path(data_file.as_ref()), network)?;
+
+    if init {
+        if let Err(error) = init_wallet_db(&mut data, None) {
+            // A failed migration can leave the database unusable, so put back the copy we made before trying.
+            drop(data);
+            if let Some(backup_path) = &backup_path {
+                restore(data_file.as_ref(), backup_path)?;
+            }
+
+            INITIALIZED_WALLETS
+                .lock()
+                .unwrap()
+                .remove(data_file.as_ref());
+            return Err(Error::WalletMigrator { error, backup_path });
+        }
+    }
 
     if init {
-        init_wallet_db(&mut data, None)?;
+        init_side_tables(&open_connection(&data_file)?)?;
+    }
+
+    if let Some(backup_path) = backup_path {
+        fs::remove_file(backup_path)?;
     }
 
     Ok(Db {
         data,
         blocks: BlockCache::new(),
+        data_file: data_file.as_ref().to_owned(),
+        network,
     })
 }
 
+/// Checks whether initializing the given wallet would apply schema migrations to it.
+fn has_pending_migrations(data_file: &Path, network: Network) -> Result<bool, Error> {
+    let applied = applied_migrations(&open_connection(data_file)?)?;
+    Ok(!known_migrations(network)?.is_subset(&applied))
+}
+
+/// Gets the IDs of the schema migrations that have been applied to a database.
+fn applied_migrations(conn: &Connection) -> Result<HashSet<Vec<u8>>, Error> {
+    let has_table: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schemer_migrations')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_table {
+        return Ok(HashSet::new());
+    }
+
+    let mut stmt = conn.prepare("SELECT id FROM schemer_migrations")?;
+    let ids = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<HashSet<Vec<u8>>, _>>()?;
+    Ok(ids)
+}
+
+/// Gets the IDs of every schema migration librustzcash knows of,
+/// which it learns by applying them all to an empty database the first time it is called.
+fn known_migrations(network: Network) -> Result<&'static HashSet<Vec<u8>>, Error> {
+    static KNOWN_MIGRATIONS: OnceLock<HashSet<Vec<u8>>> = OnceLock::new();
+    if let Some(ids) = KNOWN_MIGRATIONS.get() {
+        return Ok(ids);
+    }
+
+    // This connection keeps the in-memory database alive while WalletDb migrates it.
+    let probe = Path::new(":memory:nerdbank-migration-probe");
+    let conn = open_connection(probe)?;
+    let mut data = WalletDb::for_path(sqlite_path(probe), network)?;
+    init_wallet_db(&mut data, None).map_err(|error| Error::WalletMigrator {
+        error,
+        backup_path: None,
+    })?;
+    let ids = applied_migrations(&conn)?;
+    Ok(KNOWN_MIGRATIONS.get_or_init(|| ids))
+}
+
+/// Creates the tables this crate keeps in the wallet database alongside librustzcash's, if they don't exist yet.
+fn init_side_tables(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch(CREATE_MEMO_INDEX)?;
+    conn.execute_batch(CREATE_TRANSACTION_LABELS)?;
+    conn.execute_batch(CREATE_TAGS)?;
+    conn.execute_batch(CREATE_PRICES)?;
+    conn.execute_batch(CREATE_INVOICES)?;
+    conn.execute_batch(CREATE_ADDRESS_ALLOCATIONS)?;
+    conn.execute_batch(CREATE_SEND_QUEUE)?;
+    conn.execute_batch(CREATE_SEND_QUEUE_TRANSACTIONS)?;
+    conn.execute_batch(CREATE_RECURRING_PAYMENTS)?;
+    conn.execute_batch(CREATE_SPENDING_POLICIES)?;
+    conn.execute_batch(CREATE_RETIRED_ACCOUNTS)?;
+    conn.execute_batch(CREATE_AUDIT_LOG)?;
+    conn.execute_batch(CREATE_PREVOUT_VALUES)?;
+    conn.execute_batch(CREATE_PREVOUT_BACKFILL_FAILURES)?;
+    conn.execute_batch(CREATE_MEMPOOL_RECEIVES)?;
+    conn.execute_batch(CREATE_PENDING_REORGS)?;
+    Ok(())
+}
+
+/// Copies the wallet database to a file beside it, and returns the path to the copy.
+fn back_up(data_file: &Path) -> Result<PathBuf, Error> {
+    let mut backup_path = data_file.as_os_str().to_owned();
+    backup_path.push(".backup");
+    let backup_path = PathBuf::from(backup_path);
+    vacuum_into(data_file, &backup_path)?;
+    Ok(backup_path)
+}
+
+/// Writes a consistent copy of the wallet database to the given file, replacing it if it exists.
+///
+/// `VACUUM INTO` produces a copy that includes any changes still in the write-ahead log.
+fn vacuum_into(data_file: &Path, destination: &Path) -> Result<(), Error> {
+    // VACUUM INTO refuses to overwrite an existing file.
+    if destination.exists() {
+        fs::remove_file(destination)?;
+    }
+
+    open_connection(data_file)?.execute(
+        "VACUUM INTO ?",
+        [destination.to_string_lossy().into_owned()],
+    )?;
+
+    Ok(())
+}
+
+/// Replaces the wallet database with a backup made by [`back_up`].
+/// The backup itself is left in place.
+fn restore(data_file: &Path, backup_path: &Path) -> Result<(), Error> {
+    remove_write_ahead_log(data_file)?;
+    fs::copy(backup_path, data_file)?;
+    Ok(())
+}
+
+/// Deletes the write-ahead log of a database that is about to be replaced, since it belongs to the old database.
+fn remove_write_ahead_log(data_file: &Path) -> Result<(), Error> {
+    for suffix in ["-wal", "-shm"] {
+        let mut path = data_file.as_os_str().to_owned();
+        path.push(suffix);
+        match fs::remove_file(PathBuf::from(path)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a snapshot of the wallet, including its note commitment trees and scan progress,
+/// which another device can adopt with [`import_state`] instead of scanning the chain itself.
+///
+/// The audit log belongs to this device, so the snapshot leaves it out.
+pub(crate) fn export_state<P: AsRef<Path>, Q: AsRef<Path>>(
+    data_file: P,
+    destination: Q,
+) -> Result<(), Error> {
+    vacuum_into(data_file.as_ref(), destination.as_ref())?;
+    {
+        // Vacuum again so that the log doesn't linger in the file's free pages.
+        let snapshot_conn = open_connection(destination.as_ref())?;
+        snapshot_conn.execute_batch(DROP_AUDIT_LOG)?;
+        snapshot_conn.execute_batch("VACUUM")?;
+    }
+    audit_log::record(
+        &open_connection(data_file)?,
+        AuditOperation::BackupExported,
+        None,
+        &destination.as_ref().to_string_lossy(),
+    )
+}
+
+/// Replaces a freshly created wallet with a snapshot written by [`export_state`].
+///
+/// The wallet must have the same accounts (by UFVK) as the snapshot, and no transactions yet.
+/// Whether the spending key is available for each account is kept from the wallet rather than the snapshot,
+/// since that depends on the device. So is the audit log.
+/// Account ids are taken from the snapshot.
+pub(crate) fn import_state<P: AsRef<Path>, Q: AsRef<Path>>(
+    data_file: P,
+    network: Network,
+    snapshot: Q,
+) -> Result<(), Error> {
+    let data_file = data_file.as_ref();
+    if is_in_memory(data_file) {
+        return Err(Error::InvalidArgument(
+            "State cannot be imported into an in-memory wallet.".to_string(),
+        ));
+    }
+
+    // Stage the snapshot beside the wallet so that it can be moved into place at once.
+    let mut staged = data_file.as_os_str().to_owned();
+    staged.push(".import");
+    let staged = PathBuf::from(staged);
+    fs::copy(snapshot, &staged)?;
+
+    let result = prepare_import(data_file, network, &staged).and_then(|_| {
+        remove_write_ahead_log(data_file)?;
+        fs::rename(&staged, data_file)?;
+        Ok(())
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&staged);
+    }
+
+    result
+}
+
+/// Verifies that a staged snapshot may replace the wallet, and carries over the wallet's device-specific settings.
+fn prepare_import(data_file: &Path, network: Network, staged: &Path) -> Result<(), Error> {
+    let target = Db::load(data_file, network)?;
+    let target_conn = open_connection(data_file)?;
+    let transaction_count: u32 =
+        target_conn.query_row("SELECT COUNT(*) FROM transactions", [], |row| row.get(0))?;
+    if transaction_count > 0 {
+        return Err(Error::InvalidArgument(
+            "State can only be imported into a wallet that has not been synced.".to_string(),
+        ));
+    }
+
+    let mut spend_key_available = HashMap::new();
+    for (id, ufvk) in target.data.get_unified_full_viewing_keys()? {
+        let available: bool = target_conn.query_row(
+            "SELECT has_spend_key FROM accounts WHERE id = ?",
+            [u32::from(id)],
+            |row| row.get(0),
+        )?;
+        spend_key_available.insert(ufvk.encode(&network), available);
+    }
+
+    // Bring the snapshot's schema up to date, in case it came from an older version,
+    // after dropping any audit log it came with so that it starts again from the wallet's own.
+    open_connection(staged)?.execute_batch(DROP_AUDIT_LOG)?;
+    let snapshot = Db::init(staged, network)?;
+    let snapshot_conn = open_connection(staged)?;
+    let snapshot_keys = snapshot.data.get_unified_full_viewing_keys()?;
+    if snapshot_keys.len() != spend_key_available.len() {
+        return Err(Error::InvalidArgument(
+            "The snapshot is for different accounts than the wallet.".to_string(),
+        ));
+    }
+
+    for (id, ufvk) in snapshot_keys {
+        let available = spend_key_available
+            .get(&ufvk.encode(&network))
+            .ok_or_else(|| {
+                Error::InvalidArgument(
+                    "The snapshot is for different accounts than the wallet.".to_string(),
+                )
+            })?;
+        snapshot_conn.execute(
+            "UPDATE accounts SET has_spend_key = ? WHERE id = ?",
+            (available, u32::from(id)),
+        )?;
+    }
+
+    // The wallet the snapshot came from may still send these itself.
+    snapshot_conn.execute_batch(CLEAR_SCHEDULED_PAYMENTS)?;
+
+    snapshot_conn.execute(
+        "ATTACH DATABASE ? AS source",
+        [sqlite_path(data_file).to_string_lossy().into_owned()],
+    )?;
+    snapshot_conn.execute_batch(COPY_AUDIT_LOG)?;
+    snapshot_conn.execute_batch("DETACH DATABASE source")?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_constants::{local_birthday, setup_local_test, setup_test};
     use testdir::testdir;
 
     #[tokio_shared_rt::test]
@@ -128,4 +574,141 @@ mod tests {
         let data_file = wallet_dir.join("wallet.sqlite");
         Db::init(data_file, Network::TestNetwork).unwrap();
     }
+
+    #[tokio_shared_rt::test]
+    async fn test_in_memory() {
+        let data_file = ":memory:test_in_memory";
+        Db::init(data_file, Network::TestNetwork).unwrap();
+        Db::load(data_file, Network::TestNetwork).unwrap();
+        open_connection(data_file)
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM accounts", [], |row| {
+                row.get::<_, u32>(0)
+            })
+            .unwrap();
+        assert!(!Path::new(data_file).exists());
+        release_in_memory_wallet(data_file);
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_has_pending_migrations() {
+        let wallet_dir = testdir!();
+        let data_file = wallet_dir.join("wallet.sqlite");
+        Db::init(&data_file, Network::TestNetwork).unwrap();
+        assert!(!has_pending_migrations(&data_file, Network::TestNetwork).unwrap());
+
+        open_connection(&data_file)
+            .unwrap()
+            .execute(
+                "DELETE FROM schemer_migrations WHERE rowid = (SELECT MAX(rowid) FROM schemer_migrations)",
+                [],
+            )
+            .unwrap();
+        assert!(has_pending_migrations(&data_file, Network::TestNetwork).unwrap());
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_busy_timeout() {
+        let wallet_dir = testdir!();
+        let data_file = wallet_dir.join("wallet.sqlite");
+        Db::init(&data_file, Network::TestNetwork).unwrap();
+
+        // Connections we don't open ourselves (like the one WalletDb opens) get the timeout too.
+        let timeout: u64 = Connection::open(&data_file)
+            .unwrap()
+            .query_row("PRAGMA busy_timeout", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(BUSY_TIMEOUT.as_millis() as u64, timeout);
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_back_up_and_restore() {
+        let wallet_dir = testdir!();
+        let data_file = wallet_dir.join("wallet.sqlite");
+        Db::init(&data_file, Network::TestNetwork).unwrap();
+
+        let backup_path = back_up(&data_file).unwrap();
+        assert!(backup_path.exists());
+
+        // Damage the wallet, then restore it.
+        fs::write(&data_file, b"garbage").unwrap();
+        restore(&data_file, &backup_path).unwrap();
+        Db::load(&data_file, Network::TestNetwork).unwrap();
+        open_connection(&data_file)
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM accounts", [], |row| {
+                row.get::<_, u32>(0)
+            })
+            .unwrap();
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_export_and_import_state() {
+        let mut setup = setup_test().await;
+        let (_, birthday, _, usk) = setup.create_account().await.unwrap();
+        let snapshot = setup.data_file.with_extension("snapshot");
+        export_state(&setup.data_file, &snapshot).unwrap();
+
+        // A wallet without the account cannot adopt the snapshot.
+        let other_file = setup.data_file.with_file_name("other.sqlite");
+        Db::init(&other_file, setup.network).unwrap();
+        assert!(import_state(&other_file, setup.network, &snapshot).is_err());
+
+        let mut second = Db::init(&other_file, setup.network).unwrap();
+        second
+            .import_account_ufvk(
+                &usk.to_unified_full_viewing_key(),
+                false,
+                birthday,
+                &mut setup.client,
+            )
+            .await
+            .unwrap();
+        drop(second);
+        import_state(&other_file, setup.network, &snapshot).unwrap();
+
+        let has_spend_key: bool = open_connection(&other_file)
+            .unwrap()
+            .query_row("SELECT has_spend_key FROM accounts", [], |row| row.get(0))
+            .unwrap();
+        assert!(!has_spend_key);
+    }
+
+    #[test]
+    fn test_export_and_import_state_keep_the_audit_log() {
+        let mut setup = setup_local_test();
+        let (seed, _, _, _) = setup.create_account().unwrap();
+        let snapshot = setup.data_file.with_extension("snapshot");
+        export_state(&setup.data_file, &snapshot).unwrap();
+
+        let audit_log_tables: u32 = open_connection(&snapshot)
+            .unwrap()
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = 'nerdbank_audit_log'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(0, audit_log_tables);
+
+        let other_file = setup.data_file.with_file_name("other.sqlite");
+        let mut second = Db::init(&other_file, setup.network).unwrap();
+        second
+            .add_account_with_birthday(&seed, zip32::AccountId::ZERO, &local_birthday())
+            .unwrap();
+        drop(second);
+        let log = audit_log::get_audit_log(&open_connection(&other_file).unwrap()).unwrap();
+
+        import_state(&other_file, setup.network, &snapshot).unwrap();
+        let imported = audit_log::get_audit_log(&open_connection(&other_file).unwrap()).unwrap();
+        assert_eq!(
+            log.iter()
+                .map(|e| (e.id, e.details.clone()))
+                .collect::<Vec<_>>(),
+            imported
+                .iter()
+                .map(|e| (e.id, e.details.clone()))
+                .collect::<Vec<_>>()
+        );
+    }
 }
@@ -1,5 +1,7 @@
-use std::{collections::HashMap, ops::Range};
+use std::{collections::HashMap, ops::Range, sync::Arc};
 
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::debug;
 use zcash_client_backend::{data_api::chain::BlockSource, proto::compact_formats::CompactBlock};
 use zcash_primitives::consensus::BlockHeight;
 
@@ -58,6 +60,51 @@ impl BlockCache {
     }
 }
 
+/// Caps the number of actions in downloaded blocks that are held in memory waiting to be scanned.
+///
+/// The downloader reserves room for each chunk before handing it to the scanner,
+/// and the room is released when the scanner drops the reservation after scanning the chunk.
+/// When the cap is reached, the downloader waits instead of downloading more.
+#[derive(Debug, Clone)]
+pub(crate) struct BlockCacheBudget {
+    semaphore: Arc<Semaphore>,
+    max_actions: u32,
+}
+
+impl BlockCacheBudget {
+    pub fn new(max_actions: u32) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_actions as usize)),
+            max_actions,
+        }
+    }
+
+    /// Waits until there is room for the given blocks, and reserves it.
+    ///
+    /// A chunk that is larger than the whole budget waits for the cache to empty, then takes all of it.
+    pub async fn reserve(&self, blocks: &[CompactBlock]) -> OwnedSemaphorePermit {
+        let actions = count_actions(blocks).min(self.max_actions);
+        if self.semaphore.available_permits() < actions as usize {
+            debug!("Block cache is full. Waiting for scanning to catch up.");
+        }
+
+        self.semaphore
+            .clone()
+            .acquire_many_owned(actions)
+            .await
+            .expect("The semaphore is never closed.")
+    }
+}
+
+/// Counts the sapling spends and outputs and orchard actions in the given blocks.
+pub(crate) fn count_actions(blocks: &[CompactBlock]) -> u32 {
+    blocks
+        .iter()
+        .flat_map(|b| b.vtx.iter())
+        .map(|tx| (tx.actions.len() + tx.outputs.len() + tx.spends.len()) as u32)
+        .sum()
+}
+
 impl BlockSource for BlockCache {
     type Error = BlockCacheError;
 
@@ -92,3 +139,44 @@ impl BlockSource for BlockCache {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use zcash_client_backend::proto::compact_formats::{CompactOrchardAction, CompactTx};
+
+    use super::*;
+
+    fn block(actions: usize) -> CompactBlock {
+        CompactBlock {
+            vtx: vec![CompactTx {
+                actions: vec![CompactOrchardAction::default(); actions],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_block_cache_budget() {
+        let budget = BlockCacheBudget::new(100);
+        let first = budget.reserve(&[block(60)]).await;
+
+        // There isn't room for another 60 actions until the first chunk is scanned.
+        let wait = Duration::from_millis(50);
+        assert!(tokio::time::timeout(wait, budget.reserve(&[block(60)]))
+            .await
+            .is_err());
+        drop(first);
+        let second = tokio::time::timeout(wait, budget.reserve(&[block(60)]))
+            .await
+            .unwrap();
+        drop(second);
+
+        // A chunk larger than the whole budget is let through on its own.
+        tokio::time::timeout(wait, budget.reserve(&[block(500)]))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use prost::{bytes::Buf, Message};
+use tracing::warn;
+use zcash_client_backend::proto::{compact_formats::CompactBlock, service::TreeState};
+use zcash_primitives::consensus::BlockHeight;
+
+use crate::{backing_store::is_in_memory, error::Error};
+
+const CHUNK_EXTENSION: &str = "chunk";
+const TEMP_EXTENSION: &str = "tmp";
+
+/// A chunk of downloaded blocks, along with the tree state as of the block just before the first one.
+pub(crate) struct JournaledChunk {
+    pub(crate) blocks: Vec<CompactBlock>,
+    pub(crate) tree_state: TreeState,
+}
+
+impl JournaledChunk {
+    /// The range of heights covered by the blocks in this chunk.
+    pub(crate) fn block_range(&self) -> Range<BlockHeight> {
+        let start = self.blocks.first().map_or(0, |b| b.height as u32);
+        let end = self.blocks.last().map_or(0, |b| b.height as u32 + 1);
+        BlockHeight::from(start)..BlockHeight::from(end)
+    }
+}
+
+/// Persists downloaded blocks until they have been scanned,
+/// so that a sync interrupted by the process being killed can resume without downloading them again.
+///
+/// Each chunk is stored in its own file, named after the height of its first block,
+/// in a directory next to the wallet database.
+/// Nothing is persisted for in-memory wallets.
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkJournal {
+    dir: Option<PathBuf>,
+}
+
+impl ChunkJournal {
+    pub(crate) fn for_wallet<P: AsRef<Path>>(data_file: P) -> Self {
+        if is_in_memory(&data_file) {
+            return Self { dir: None };
+        }
+
+        let mut dir = data_file.as_ref().as_os_str().to_owned();
+        dir.push(".chunks");
+        Self {
+            dir: Some(dir.into()),
+        }
+    }
+
+    /// Persists a chunk of blocks.
+    ///
+    /// The chunk is written to a temporary file first and then renamed,
+    /// so that a chunk is either persisted entirely or not at all.
+    pub(crate) fn write(
+        &self,
+        blocks: &[CompactBlock],
+        tree_state: &TreeState,
+    ) -> Result<(), Error> {
+        let (dir, start) = match (&self.dir, blocks.first()) {
+            (Some(dir), Some(b)) => (dir, b.height),
+            _ => return Ok(()),
+        };
+
+        let mut buf = Vec::new();
+        tree_state
+            .encode_length_delimited(&mut buf)
+            .map_err(|e| Error::Internal(e.to_string()))?;
+        for block in blocks {
+            block
+                .encode_length_delimited(&mut buf)
+                .map_err(|e| Error::Internal(e.to_string()))?;
+        }
+
+        fs::create_dir_all(dir)?;
+        let path = chunk_path(dir, start);
+        let temp_path = path.with_extension(TEMP_EXTENSION);
+        fs::write(&temp_path, buf)?;
+        fs::rename(temp_path, path)?;
+
+        Ok(())
+    }
+
+    /// Removes the chunk that starts at the given height, if there is one.
+    pub(crate) fn remove(&self, start: BlockHeight) -> Result<(), Error> {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+
+        match fs::remove_file(chunk_path(dir, u32::from(start).into())) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads all persisted chunks, ordered by height.
+    ///
+    /// Incomplete or unreadable chunks are deleted and skipped.
+    pub(crate) fn read_all(&self) -> Result<Vec<JournaledChunk>, Error> {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return Ok(Vec::new()),
+        };
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut chunks = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let chunk = if path.extension().is_some_and(|e| e == CHUNK_EXTENSION) {
+                read_chunk(&path)
+            } else {
+                None
+            };
+
+            match chunk {
+                Some(chunk) => chunks.push(chunk),
+                None => {
+                    warn!("Discarding unusable journaled chunk {}", path.display());
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+
+        chunks.sort_by_key(|c| c.block_range().start);
+        Ok(chunks)
+    }
+}
+
+fn chunk_path(dir: &Path, start: u64) -> PathBuf {
+    dir.join(format!("{}.{}", start, CHUNK_EXTENSION))
+}
+
+fn read_chunk(path: &Path) -> Option<JournaledChunk> {
+    let bytes = fs::read(path).ok()?;
+    let mut buf = bytes.as_slice();
+    let tree_state = TreeState::decode_length_delimited(&mut buf).ok()?;
+    let mut blocks = Vec::new();
+    while buf.has_remaining() {
+        blocks.push(CompactBlock::decode_length_delimited(&mut buf).ok()?);
+    }
+
+    if blocks.is_empty() {
+        return None;
+    }
+
+    Some(JournaledChunk { blocks, tree_state })
+}
+
+#[cfg(test)]
+mod tests {
+    use testdir::testdir;
+
+    use super::*;
+
+    fn block(height: u64) -> CompactBlock {
+        CompactBlock {
+            height,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_write_read_remove() {
+        let journal = ChunkJournal::for_wallet(testdir!().join("wallet.sqlite"));
+        assert!(journal.read_all().unwrap().is_empty());
+
+        let tree_state = TreeState {
+            height: 99,
+            ..Default::default()
+        };
+        journal
+            .write(&[block(200), block(201)], &tree_state)
+            .unwrap();
+        journal
+            .write(&[block(100), block(101), block(102)], &tree_state)
+            .unwrap();
+
+        let chunks = journal.read_all().unwrap();
+        assert_eq!(2, chunks.len());
+        assert_eq!(
+            BlockHeight::from(100)..BlockHeight::from(103),
+            chunks[0].block_range()
+        );
+        assert_eq!(99, chunks[0].tree_state.height);
+        assert_eq!(
+            BlockHeight::from(200)..BlockHeight::from(202),
+            chunks[1].block_range()
+        );
+
+        journal.remove(BlockHeight::from(100)).unwrap();
+        journal.remove(BlockHeight::from(100)).unwrap();
+        let chunks = journal.read_all().unwrap();
+        assert_eq!(1, chunks.len());
+    }
+
+    #[test]
+    fn test_corrupt_chunk_discarded() {
+        let journal = ChunkJournal::for_wallet(testdir!().join("wallet.sqlite"));
+        let dir = journal.dir.as_ref().unwrap();
+        fs::create_dir_all(dir).unwrap();
+        fs::write(chunk_path(dir, 5), [0xff, 0xff, 0xff]).unwrap();
+        fs::write(chunk_path(dir, 6).with_extension(TEMP_EXTENSION), [1]).unwrap();
+
+        assert!(journal.read_all().unwrap().is_empty());
+        assert_eq!(0, fs::read_dir(dir).unwrap().count());
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use sysinfo::{System, SystemExt};
+
+/// The fewest actions to put in a chunk.
+/// Fewer than this and trial decryption can't keep all cores busy.
+const MIN_CHUNK_ACTIONS: usize = 10_000;
+
+/// The most actions to put in a chunk, however much memory is available.
+const MAX_CHUNK_ACTIONS: usize = 1_000_000;
+
+/// A generous estimate of the memory a downloaded action takes up, in bytes.
+const BYTES_PER_ACTION: usize = 300;
+
+/// The fraction (as a divisor) of available memory that downloaded blocks may occupy.
+const MEMORY_BUDGET_DIVISOR: usize = 4;
+
+/// How often to check how much memory is available.
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Chooses how many actions to put in each chunk of downloaded blocks.
+///
+/// When scanning can't keep up with downloading, chunks grow, since trial decryption
+/// parallelizes better over larger chunks. When scanning is waiting on downloads, chunks shrink,
+/// so that scanning can start on each one sooner. Either way, chunks are kept small enough
+/// that all the chunks that may be in memory at once fit within a share of the available memory.
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkSizer(Arc<Mutex<ChunkSizerState>>);
+
+#[derive(Debug)]
+struct ChunkSizerState {
+    actions: usize,
+    /// The number of chunks that may be waiting to be scanned.
+    queue_depth: usize,
+    download_wait: Duration,
+    scan_wait: Duration,
+    memory_limit: usize,
+    memory_checked: Option<Instant>,
+}
+
+impl ChunkSizer {
+    pub(crate) fn new(initial_actions: usize, queue_depth: usize) -> Self {
+        Self(Arc::new(Mutex::new(ChunkSizerState {
+            actions: initial_actions.clamp(MIN_CHUNK_ACTIONS, MAX_CHUNK_ACTIONS),
+            queue_depth,
+            download_wait: Duration::ZERO,
+            scan_wait: Duration::ZERO,
+            memory_limit: MAX_CHUNK_ACTIONS,
+            memory_checked: None,
+        })))
+    }
+
+    /// The number of actions to accumulate before submitting a chunk for scanning.
+    pub(crate) fn chunk_actions(&self) -> usize {
+        let state = self.0.lock().unwrap();
+        state.actions.min(state.memory_limit)
+    }
+
+    /// Records how long the downloader waited for the scanner to accept a chunk.
+    pub(crate) fn download_waited(&self, duration: Duration) {
+        self.0.lock().unwrap().download_wait += duration;
+    }
+
+    /// Records how long the scanner waited for the downloader to deliver a chunk.
+    pub(crate) fn scan_waited(&self, duration: Duration) {
+        self.0.lock().unwrap().scan_wait += duration;
+    }
+
+    /// Resizes chunks based on what has been observed since the last adjustment.
+    pub(crate) fn adjust(&self) {
+        let mut state = self.0.lock().unwrap();
+
+        if state
+            .memory_checked
+            .map_or(true, |t| t.elapsed() > MEMORY_CHECK_INTERVAL)
+        {
+            state.memory_limit = memory_limit(state.queue_depth);
+            state.memory_checked = Some(Instant::now());
+        }
+
+        if state.download_wait > state.scan_wait * 2 {
+            state.actions = state.actions / 4 * 5;
+        } else if state.scan_wait > state.download_wait * 2 {
+            state.actions = state.actions / 5 * 4;
+        }
+
+        state.actions = state.actions.clamp(MIN_CHUNK_ACTIONS, MAX_CHUNK_ACTIONS);
+        state.download_wait = Duration::ZERO;
+        state.scan_wait = Duration::ZERO;
+    }
+}
+
+/// Gets the largest chunk (in actions) that fits in our share of the available memory,
+/// given that the queued chunks, the chunk being downloaded, and the chunk being scanned may all be in memory at once.
+fn memory_limit(queue_depth: usize) -> usize {
+    let mut system = System::new();
+    system.refresh_memory();
+    let available = system.available_memory() as usize;
+
+    // Some platforms don't report available memory.
+    if available == 0 {
+        return MAX_CHUNK_ACTIONS;
+    }
+
+    (available / MEMORY_BUDGET_DIVISOR / BYTES_PER_ACTION / (queue_depth + 2))
+        .max(MIN_CHUNK_ACTIONS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actions(sizer: &ChunkSizer) -> usize {
+        sizer.0.lock().unwrap().actions
+    }
+
+    #[test]
+    fn test_adjust() {
+        let sizer = ChunkSizer::new(50_000, 10);
+
+        // Scanning is the bottleneck, so chunks grow.
+        sizer.download_waited(Duration::from_secs(3));
+        sizer.scan_waited(Duration::from_secs(1));
+        sizer.adjust();
+        assert!(actions(&sizer) > 50_000);
+
+        // Downloading is the bottleneck, so chunks shrink.
+        let grown = actions(&sizer);
+        sizer.scan_waited(Duration::from_secs(3));
+        sizer.adjust();
+        assert!(actions(&sizer) < grown);
+
+        // Chunks never shrink below the minimum, whatever the memory limit.
+        for _ in 0..100 {
+            sizer.scan_waited(Duration::from_secs(1));
+            sizer.adjust();
+        }
+        assert_eq!(MIN_CHUNK_ACTIONS, actions(&sizer));
+        assert!(sizer.chunk_actions() >= MIN_CHUNK_ACTIONS);
+    }
+}
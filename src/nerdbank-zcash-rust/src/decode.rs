@@ -0,0 +1,217 @@
+use std::io::Read;
+
+use zcash_client_backend::encoding::AddressCodec;
+use zcash_primitives::{
+    consensus::{BranchId, Network},
+    transaction::{
+        components::{Amount, OutPoint},
+        Transaction, TxVersion,
+    },
+};
+
+use crate::error::Error;
+
+/// A transparent input to a transaction.
+pub struct DecodedTransparentInput {
+    /// The txid of the transaction whose output is being spent.
+    pub prevout_txid: Vec<u8>,
+    /// The index of the output being spent.
+    pub prevout_index: u32,
+}
+
+/// A transparent output of a transaction.
+pub struct DecodedTransparentOutput {
+    pub value: u64,
+    /// The address that receives the output, if its script is a standard one.
+    pub address: Option<String>,
+}
+
+/// A structured view of a raw transaction.
+pub struct DecodedTransaction {
+    pub txid: Vec<u8>,
+    pub version: u32,
+    /// The consensus branch ID the transaction commits to.
+    /// Only transactions with version 5 or later record this.
+    pub consensus_branch_id: Option<u32>,
+    pub lock_time: u32,
+    pub expiry_height: u32,
+    pub transparent_inputs: Vec<DecodedTransparentInput>,
+    pub transparent_outputs: Vec<DecodedTransparentOutput>,
+    pub sapling_spends: u32,
+    pub sapling_outputs: u32,
+    /// The net value leaving the sapling pool, in zatoshis.
+    pub sapling_value_balance: i64,
+    pub orchard_actions: u32,
+    /// The net value leaving the orchard pool, in zatoshis.
+    pub orchard_value_balance: i64,
+    /// The fee paid by the transaction.
+    /// This is only known when the transaction has no transparent inputs,
+    /// since their values are not recorded in the transaction itself.
+    pub fee: Option<u64>,
+}
+
+pub fn decode_transaction(network: &Network, data: &[u8]) -> Result<DecodedTransaction, Error> {
+    let header = read_header(data)?;
+
+    // Version 5 transactions carry their own branch ID. Earlier versions don't, but don't need it to be parsed either.
+    let tx = Transaction::read(data, BranchId::Nu5)?;
+
+    let (transparent_inputs, transparent_outputs) = match tx.transparent_bundle() {
+        Some(b) => (
+            b.vin
+                .iter()
+                .map(|i| DecodedTransparentInput {
+                    prevout_txid: i.prevout.hash().to_vec(),
+                    prevout_index: i.prevout.n(),
+                })
+                .collect(),
+            b.vout
+                .iter()
+                .map(|o| DecodedTransparentOutput {
+                    value: o.value.into(),
+                    address: o.recipient_address().map(|a| a.encode(network)),
+                })
+                .collect(),
+        ),
+        None => (Vec::new(), Vec::new()),
+    };
+
+    Ok(DecodedTransaction {
+        txid: tx.txid().as_ref().to_vec(),
+        version: header.version,
+        consensus_branch_id: header.consensus_branch_id,
+        lock_time: tx.lock_time(),
+        expiry_height: tx.expiry_height().into(),
+        transparent_inputs,
+        transparent_outputs,
+        sapling_spends: tx
+            .sapling_bundle()
+            .map_or(0, |b| b.shielded_spends().len() as u32),
+        sapling_outputs: tx
+            .sapling_bundle()
+            .map_or(0, |b| b.shielded_outputs().len() as u32),
+        sapling_value_balance: tx.sapling_value_balance().into(),
+        orchard_actions: tx.orchard_bundle().map_or(0, |b| b.actions().len() as u32),
+        orchard_value_balance: tx
+            .orchard_bundle()
+            .map_or(0, |b| i64::from(*b.value_balance())),
+        fee: calculate_fee(&tx),
+    })
+}
+
+/// The number of leading bytes of a raw transaction that [`read_header`] needs.
+///
+/// A version 5 header is 20 bytes long, and is followed by the 1 byte counts of transparent inputs and outputs
+/// when there are none of either.
+pub(crate) const HEADER_PREFIX_LENGTH: usize = 22;
+
+/// The fields of a raw transaction that can be read from its first [`HEADER_PREFIX_LENGTH`] bytes.
+pub(crate) struct TransactionHeader {
+    pub version: u32,
+    /// The consensus branch ID the transaction commits to.
+    /// Only transactions with version 5 or later record this.
+    pub consensus_branch_id: Option<u32>,
+    /// Whether the transaction may have transparent inputs or outputs.
+    /// This is only `false` when the transaction certainly has neither.
+    pub may_have_transparent: bool,
+}
+
+/// Reads the header of a raw transaction without parsing the rest of it.
+///
+/// `data` may be just the first [`HEADER_PREFIX_LENGTH`] bytes of the transaction.
+pub(crate) fn read_header(data: &[u8]) -> Result<TransactionHeader, Error> {
+    let mut reader = data;
+    let version = TxVersion::read(&mut reader)?.header() & 0x7FFF_FFFF;
+    let consensus_branch_id = if version >= 5 {
+        let mut branch_id = [0; 4];
+        reader.read_exact(&mut branch_id)?;
+
+        // Skip the lock time and expiry height, which precede the transparent bundle in version 5.
+        let mut lock_time_and_expiry = [0; 8];
+        reader.read_exact(&mut lock_time_and_expiry)?;
+
+        Some(u32::from_le_bytes(branch_id))
+    } else {
+        None
+    };
+
+    // In every version the transparent bundle comes next, starting with the compact size counts of its inputs
+    // and, when there are no inputs, its outputs.
+    let may_have_transparent = !matches!(reader, [0, 0, ..]);
+
+    Ok(TransactionHeader {
+        version,
+        consensus_branch_id,
+        may_have_transparent,
+    })
+}
+
+/// Calculates the fee for a transaction whose inputs are all shielded,
+/// since the values of those inputs are accounted for in the transaction itself.
+fn calculate_fee(tx: &Transaction) -> Option<u64> {
+    let transparent_value_balance = tx.transparent_bundle().map_or(Ok(Amount::zero()), |b| {
+        b.value_balance(|_: &OutPoint| Err(Error::OutPointMissing))
+    });
+    let sprout_value_balance = tx
+        .sprout_bundle()
+        .map_or(Some(Amount::zero()), |b| b.value_balance());
+    let orchard_value_balance = tx
+        .orchard_bundle()
+        .map_or(Amount::zero(), |b| b.value_balance().to_owned());
+
+    let fee = (transparent_value_balance.ok()?
+        + sprout_value_balance?
+        + tx.sapling_value_balance()
+        + orchard_value_balance)?;
+    u64::try_from(i64::from(fee)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_primitives::{
+        consensus::BlockHeight,
+        transaction::{Authorized, TransactionData, TxVersion},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_decode_empty_v5() {
+        let tx = TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            BranchId::Nu5,
+            0,
+            BlockHeight::from(2_000_000),
+            None,
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap();
+        let mut data = Vec::new();
+        tx.write(&mut data).unwrap();
+
+        let decoded = decode_transaction(&Network::MainNetwork, &data).unwrap();
+        assert_eq!(tx.txid().as_ref().to_vec(), decoded.txid);
+        assert_eq!(5, decoded.version);
+        assert_eq!(Some(u32::from(BranchId::Nu5)), decoded.consensus_branch_id);
+        assert_eq!(2_000_000, decoded.expiry_height);
+        assert!(decoded.transparent_inputs.is_empty());
+        assert!(decoded.transparent_outputs.is_empty());
+        assert_eq!(0, decoded.sapling_spends);
+        assert_eq!(0, decoded.orchard_actions);
+        assert_eq!(Some(0), decoded.fee);
+
+        let header = read_header(&data[..HEADER_PREFIX_LENGTH]).unwrap();
+        assert_eq!(5, header.version);
+        assert_eq!(Some(u32::from(BranchId::Nu5)), header.consensus_branch_id);
+        assert!(!header.may_have_transparent);
+    }
+
+    #[test]
+    fn test_decode_garbage() {
+        assert!(decode_transaction(&Network::MainNetwork, &[1, 2, 3]).is_err());
+        assert!(read_header(&[1, 2, 3]).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use hdwallet::{ExtendedPrivKey, KeyIndex};
+use sapling::zip32::ExtendedSpendingKey;
+use zcash_primitives::zip32::ChildIndex;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{error::Error, interop::Pool};
+
+/// The bit that marks a child index as hardened.
+const HARDENED: u32 = 1 << 31;
+
+/// Derives a spending key from a seed along a path of child indices.
+/// Hardened indices have the high bit set, as in BIP-32.
+///
+/// The key material returned depends on the pool:
+/// * Transparent: the 32-byte chain code followed by the 32-byte secp256k1 private key (BIP-32).
+/// * Sapling: the 169-byte encoding of the extended spending key (ZIP-32). Every index must be hardened.
+/// * Orchard: the 32-byte spending key (ZIP-32). Orchard keys can only be derived along `m/32'/coin_type'/account'`.
+///
+/// The byte copies of key material made along the way are zeroized, as are the chain codes of intermediate transparent keys.
+/// The key types themselves don't support zeroization, so their private keys are merely dropped.
+pub fn derive_key_along_path(seed: &[u8], pool: Pool, path: &[u32]) -> Result<Vec<u8>, Error> {
+    match pool {
+        Pool::Transparent => {
+            let mut key = ExtendedPrivKey::with_seed(seed)?;
+            for index in path {
+                let child = key.derive_private_key(KeyIndex::from(*index));
+                key.chain_code.zeroize();
+                key = child?;
+            }
+
+            let mut material = Vec::with_capacity(64);
+            material.extend_from_slice(&key.chain_code);
+            material.extend_from_slice(&*Zeroizing::new(key.private_key.secret_bytes()));
+            key.chain_code.zeroize();
+            Ok(material)
+        }
+        Pool::Sapling => {
+            let mut key = ExtendedSpendingKey::master(seed);
+            for index in path {
+                let index = ChildIndex::from_index(*index).ok_or_else(|| {
+                    Error::InvalidArgument(format!(
+                        "Sapling keys can only be derived along hardened indices, but {index} is not hardened."
+                    ))
+                })?;
+                key = key.derive_child(index);
+            }
+
+            Ok(Zeroizing::new(key.to_bytes()).to_vec())
+        }
+        Pool::Orchard => {
+            let (coin_type, account) = match path {
+                [purpose, coin_type, account]
+                    if *purpose == 32 | HARDENED
+                        && coin_type & HARDENED != 0
+                        && account & HARDENED != 0 =>
+                {
+                    (coin_type & !HARDENED, account & !HARDENED)
+                }
+                _ => {
+                    return Err(Error::InvalidArgument(
+                        "Orchard keys can only be derived along m/32'/coin_type'/account'."
+                            .to_string(),
+                    ))
+                }
+            };
+            let account = zip32::AccountId::try_from(account)
+                .map_err(|_| Error::InvalidArgument("Invalid account index.".to_string()))?;
+            let key = orchard::keys::SpendingKey::from_zip32_seed(seed, coin_type, account)
+                .map_err(|e| Error::InvalidArgument(format!("{e}")))?;
+            Ok(key.to_bytes().to_vec())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: [u8; 32] = [7; 32];
+
+    #[test]
+    fn test_sapling() {
+        let path = [32 | HARDENED, 133 | HARDENED, HARDENED];
+        let expected = ExtendedSpendingKey::from_path(
+            &ExtendedSpendingKey::master(&SEED),
+            &path.map(|i| ChildIndex::from_index(i).unwrap()),
+        );
+        assert_eq!(
+            expected.to_bytes().to_vec(),
+            derive_key_along_path(&SEED, Pool::Sapling, &path).unwrap()
+        );
+        assert_eq!(
+            ExtendedSpendingKey::master(&SEED).to_bytes().to_vec(),
+            derive_key_along_path(&SEED, Pool::Sapling, &[]).unwrap()
+        );
+        assert!(derive_key_along_path(&SEED, Pool::Sapling, &[32]).is_err());
+    }
+
+    #[test]
+    fn test_orchard() {
+        let expected =
+            orchard::keys::SpendingKey::from_zip32_seed(&SEED, 133, zip32::AccountId::ZERO)
+                .unwrap();
+        assert_eq!(
+            expected.to_bytes().to_vec(),
+            derive_key_along_path(
+                &SEED,
+                Pool::Orchard,
+                &[32 | HARDENED, 133 | HARDENED, HARDENED]
+            )
+            .unwrap()
+        );
+        assert!(
+            derive_key_along_path(&SEED, Pool::Orchard, &[32 | HARDENED, 133 | HARDENED]).is_err()
+        );
+        assert!(
+            derive_key_along_path(&SEED, Pool::Orchard, &[32 | HARDENED, 133, HARDENED]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_transparent() {
+        // Non-hardened derivation is allowed for transparent keys.
+        let path = [44 | HARDENED, 133 | HARDENED, HARDENED, 0, 5];
+        let key = derive_key_along_path(&SEED, Pool::Transparent, &path).unwrap();
+        assert_eq!(64, key.len());
+
+        let parent = derive_key_along_path(&SEED, Pool::Transparent, &path[..4]).unwrap();
+        assert_ne!(parent, key);
+
+        // Zeroizing each parent's chain code must not disturb the derivation of its child.
+        let mut expected = ExtendedPrivKey::with_seed(&SEED).unwrap();
+        for index in path {
+            expected = expected.derive_private_key(KeyIndex::from(index)).unwrap();
+        }
+        assert_eq!(&expected.chain_code[..], &key[..32]);
+        assert_eq!(&expected.private_key.secret_bytes()[..], &key[32..]);
+    }
+}
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use schemer::MigratorError;
 use tokio::task::JoinError;
 use uniffi::deps::anyhow;
@@ -12,7 +14,7 @@ use zcash_primitives::{
     transaction::components::amount::{BalanceError, NonNegativeAmount},
 };
 
-use crate::block_source::BlockCacheError;
+use crate::{block_source::BlockCacheError, interop::PrivacyPolicy};
 
 type BackendError<DataSourceError, CommitmentTreeError, SelectionError, FeeError> =
     zcash_client_backend::data_api::error::Error<
@@ -57,7 +59,13 @@ pub enum Error {
 
     SqliteMigrator(MigratorError<rusqlite::Error>),
 
-    WalletMigrator(MigratorError<WalletMigrationError>),
+    /// A wallet schema migration failed.
+    WalletMigrator {
+        error: MigratorError<WalletMigrationError>,
+        /// The path to a copy of the wallet as it was before the migration was attempted,
+        /// which the wallet has been restored from.
+        backup_path: Option<PathBuf>,
+    },
 
     InvalidHeight,
 
@@ -94,6 +102,20 @@ pub enum Error {
 
     ProposalNotSupported,
 
+    /// The transaction would reveal more than the privacy policy it was sent with allows.
+    PrivacyPolicyViolation {
+        /// The least restrictive policy the transaction requires.
+        required: PrivacyPolicy,
+        /// A description of what the transaction would reveal.
+        reason: String,
+    },
+
+    /// The send is not allowed by the account's spending policy.
+    SpendingPolicyViolation(String),
+
+    /// The verification server reported different subtree roots than the server being synced from.
+    SubtreeRootMismatch(String),
+
     NoSpendingKey(String),
 
     KeyNotRecognized,
@@ -117,7 +139,16 @@ impl std::fmt::Display for Error {
             Error::Sqlite(e) => e.fmt(f),
             Error::SqliteClient(e) => e.fmt(f),
             Error::SqliteMigrator(e) => e.fmt(f),
-            Error::WalletMigrator(e) => e.fmt(f),
+            Error::WalletMigrator {
+                error,
+                backup_path: Some(path),
+            } => write!(
+                f,
+                "{} (the wallet was restored from the backup at {})",
+                error,
+                path.display()
+            ),
+            Error::WalletMigrator { error, .. } => error.fmt(f),
             Error::InvalidHeight => f.write_str("Invalid height"),
             Error::Balance(e) => e.fmt(f),
             Error::InvalidAmount => f.write_str("Invalid amount"),
@@ -141,6 +172,15 @@ impl std::fmt::Display for Error {
             Error::Minreq(e) => e.fmt(f),
             Error::OutPointMissing => f.write_str("OutPoint missing"),
             Error::ProposalNotSupported => f.write_str("Proposal not supported"),
+            Error::PrivacyPolicyViolation { required, reason } => write!(
+                f,
+                "The transaction requires the {:?} privacy policy: {}",
+                required, reason
+            ),
+            Error::SpendingPolicyViolation(e) => {
+                write!(f, "The spending policy does not allow this send: {}", e)
+            }
+            Error::SubtreeRootMismatch(e) => write!(f, "Subtree root mismatch: {}", e),
             Error::NoSpendingKey(e) => write!(f, "No spending key: {}", e),
             Error::KeyNotRecognized => f.write_str("No account found with the given key."),
             Error::Join(e) => e.fmt(f),
@@ -218,7 +258,10 @@ impl From<MigratorError<rusqlite::Error>> for Error {
 
 impl From<MigratorError<WalletMigrationError>> for Error {
     fn from(e: MigratorError<WalletMigrationError>) -> Self {
-        Error::WalletMigrator(e)
+        Error::WalletMigrator {
+            error: e,
+            backup_path: None,
+        }
     }
 }
 
@@ -0,0 +1,396 @@
+use std::{
+    collections::HashMap,
+    hint::black_box,
+    ops::Range,
+    path::Path,
+    thread::{self, available_parallelism},
+    time::{Duration, Instant},
+};
+
+use ff::{Field, PrimeField};
+use futures_util::TryStreamExt;
+use group::{Group, GroupEncoding};
+use http::Uri;
+use orchard::note_encryption::{CompactAction, OrchardDomain};
+use pasta_curves::pallas;
+use prost::Message;
+use rand_core::{OsRng, RngCore};
+use sapling::{
+    note_encryption::{CompactOutputDescription, SaplingDomain, Zip212Enforcement},
+    zip32::ExtendedSpendingKey,
+};
+use tonic::transport::Channel;
+use zcash_client_backend::{
+    data_api::{scanning::ScanPriority, WalletRead},
+    proto::service::{self, compact_tx_streamer_client::CompactTxStreamerClient},
+};
+use zcash_note_encryption::{try_compact_note_decryption, EphemeralKeyBytes, COMPACT_NOTE_SIZE};
+use zcash_primitives::consensus::Network;
+
+use crate::{backing_store::Db, error::Error, grpc::get_client};
+
+/// The approximate size of a Sapling output in a compact block, including a share of the spends
+/// (which are about as numerous as outputs).
+const BYTES_PER_SAPLING_OUTPUT: u64 = 148;
+
+/// The approximate size of an Orchard action in a compact block.
+const BYTES_PER_ORCHARD_ACTION: u64 = 148;
+
+/// The approximate size of a compact block's header and transaction framing.
+const BYTES_PER_BLOCK: u64 = 100;
+
+/// The download rate to assume when it has not been measured, in bytes per second.
+const DEFAULT_DOWNLOAD_RATE: u64 = 1_000_000;
+
+/// A conservative estimate of how many outputs one core can trial-decrypt with one viewing key per second.
+const TRIAL_DECRYPTIONS_PER_CORE_SECOND: u64 = 20_000;
+
+/// The number of blocks at the chain tip to download when measuring the download rate.
+const CALIBRATION_BLOCKS: u32 = 100;
+
+/// The number of synthetic Sapling outputs and Orchard actions that [`benchmark_scan`] trial-decrypts, each.
+const BENCHMARK_OUTPUTS: usize = 100;
+
+/// How long [`benchmark_scan`] keeps every core busy with trial decryption.
+const BENCHMARK_DURATION: Duration = Duration::from_secs(1);
+
+/// An estimate of the work a sync has ahead of it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncEstimate {
+    /// The number of blocks to download and scan.
+    pub blocks: u32,
+    pub sapling_outputs: u64,
+    pub orchard_actions: u64,
+    /// The approximate number of bytes of compact blocks to download.
+    pub download_bytes: u64,
+    /// The approximate time the sync will take, in seconds.
+    pub seconds: u64,
+}
+
+/// Estimates how much a sync would download and how long it would take, based on the ranges of blocks
+/// the wallet has yet to scan and the chain tip reported by the server.
+///
+/// When `calibrate` is `true`, the most recent blocks are downloaded to measure the connection's speed.
+/// Otherwise a conservative speed is assumed.
+/// Transparent and full transaction downloads are not included in the estimate.
+pub async fn estimate_sync<P: AsRef<Path>>(
+    data_file: P,
+    server_uri: Uri,
+    network: Network,
+    calibrate: bool,
+) -> Result<SyncEstimate, Error> {
+    let mut client = get_client(server_uri).await?;
+    let tip = client
+        .get_latest_block(service::ChainSpec::default())
+        .await?
+        .into_inner()
+        .height as u32;
+
+    let (ranges, viewing_keys) = {
+        let db = Db::load(data_file, network)?;
+        let birthday: u32 = match db.data.get_wallet_birthday()? {
+            Some(b) => b.into(),
+            None => return Ok(SyncEstimate::default()),
+        };
+
+        let mut ranges: Vec<Range<u32>> = db
+            .data
+            .suggest_scan_ranges()?
+            .iter()
+            .filter(|r| r.priority() > ScanPriority::Scanned)
+            .map(|r| r.block_range().start.into()..r.block_range().end.into())
+            .collect();
+
+        // The wallet doesn't know about blocks beyond the tip it last saw, so they aren't in its scan ranges.
+        let next_unknown = db
+            .data
+            .chain_height()?
+            .map_or(birthday, |h| u32::from(h) + 1)
+            .max(birthday);
+        if next_unknown <= tip {
+            ranges.push(next_unknown..tip + 1);
+        }
+
+        // Each account is scanned with both its external and internal viewing keys.
+        let viewing_keys = db.data.get_unified_full_viewing_keys()?.len() as u64 * 2;
+        (ranges, viewing_keys)
+    };
+
+    let mut estimate = SyncEstimate::default();
+    let mut tree_sizes = HashMap::new();
+    for range in ranges.iter().filter(|r| !r.is_empty()) {
+        let (sapling_before, orchard_before) =
+            get_tree_sizes(&mut client, &mut tree_sizes, range.start - 1).await?;
+        let (sapling_after, orchard_after) =
+            get_tree_sizes(&mut client, &mut tree_sizes, range.end - 1).await?;
+        estimate.blocks += range.len() as u32;
+        estimate.sapling_outputs += sapling_after.saturating_sub(sapling_before);
+        estimate.orchard_actions += orchard_after.saturating_sub(orchard_before);
+    }
+
+    estimate.download_bytes = download_size(
+        estimate.blocks,
+        estimate.sapling_outputs,
+        estimate.orchard_actions,
+    );
+
+    let mut download_rate = DEFAULT_DOWNLOAD_RATE;
+    if calibrate && estimate.blocks > 0 {
+        let (bytes, predicted_bytes, elapsed) = download_sample(&mut client, tip).await?;
+        if elapsed > 0.0 && bytes > 0 {
+            download_rate = ((bytes as f64 / elapsed) as u64).max(1);
+        }
+        if predicted_bytes > 0 {
+            // Correct the size estimate by how far off it was for the sample.
+            estimate.download_bytes =
+                (estimate.download_bytes as u128 * bytes as u128 / predicted_bytes as u128) as u64;
+        }
+    }
+
+    let cores = available_parallelism().map_or(1, |n| n.get()) as u64;
+    let download_seconds = estimate.download_bytes / download_rate;
+    let scan_seconds = (estimate.sapling_outputs + estimate.orchard_actions) * viewing_keys
+        / (TRIAL_DECRYPTIONS_PER_CORE_SECOND * cores);
+
+    // Downloading and scanning happen at the same time, so the slower of the two sets the pace.
+    estimate.seconds = download_seconds.max(scan_seconds);
+
+    Ok(estimate)
+}
+
+/// The measured speed of scanning on this device.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanBenchmark {
+    /// The number of cores that scanning can use.
+    pub cores: u32,
+    /// The number of outputs one core trial-decrypted with one viewing key per second.
+    pub trial_decryptions_per_core_second: u64,
+    /// The number of Sapling outputs and Orchard actions that can be scanned per second
+    /// for all the wallet's accounts, using every core.
+    pub outputs_per_second: u64,
+}
+
+/// Measures how fast this device trial-decrypts outputs, which is most of the work of scanning blocks.
+///
+/// Synthetic Sapling outputs and Orchard actions (addressed to no one, like almost every output a wallet scans)
+/// are trial-decrypted on every core for about a second. This takes no network access.
+pub fn benchmark_scan<P: AsRef<Path>>(
+    data_file: P,
+    network: Network,
+) -> Result<ScanBenchmark, Error> {
+    // Each account is scanned with both its external and internal viewing keys.
+    let viewing_keys = Db::load(data_file, network)?
+        .data
+        .get_unified_full_viewing_keys()?
+        .len()
+        .max(1) as u64
+        * 2;
+
+    let cores = available_parallelism().map_or(1, |n| n.get()) as u64;
+    let rate = measure_trial_decryptions(cores as usize, BENCHMARK_DURATION);
+    Ok(ScanBenchmark {
+        cores: cores as u32,
+        trial_decryptions_per_core_second: rate / cores,
+        outputs_per_second: rate / viewing_keys,
+    })
+}
+
+/// Trial-decrypts synthetic outputs on the given number of threads for the given duration,
+/// and returns the number of trial decryptions per second across all threads.
+fn measure_trial_decryptions(threads: usize, duration: Duration) -> u64 {
+    let sapling_ivk = sapling::keys::PreparedIncomingViewingKey::new(
+        &ExtendedSpendingKey::master(&[0; 32])
+            .to_diversifiable_full_viewing_key()
+            .fvk()
+            .vk
+            .ivk(),
+    );
+    let orchard_ivk = orchard::keys::PreparedIncomingViewingKey::new(
+        &orchard::keys::FullViewingKey::from(
+            &orchard::keys::SpendingKey::from_bytes([0; 32]).unwrap(),
+        )
+        .to_ivk(orchard::keys::Scope::External),
+    );
+
+    let (outputs, actions) = synthetic_outputs(BENCHMARK_OUTPUTS);
+    let started = Instant::now();
+    let decryptions: u64 = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut decryptions = 0;
+                    while started.elapsed() < duration {
+                        for output in &outputs {
+                            black_box(try_compact_note_decryption(
+                                &SaplingDomain::new(Zip212Enforcement::On),
+                                &sapling_ivk,
+                                output,
+                            ));
+                        }
+                        for action in &actions {
+                            black_box(try_compact_note_decryption(
+                                &OrchardDomain::for_compact_action(action),
+                                &orchard_ivk,
+                                action,
+                            ));
+                        }
+                        decryptions += (outputs.len() + actions.len()) as u64;
+                    }
+                    decryptions
+                })
+            })
+            .collect();
+        workers.into_iter().map(|w| w.join().unwrap()).sum()
+    });
+
+    (decryptions as f64 / started.elapsed().as_secs_f64()) as u64
+}
+
+/// Makes Sapling outputs and Orchard actions that are well-formed but addressed to no one,
+/// so that trial decryption does all of its work before failing, as it does for almost every output on the chain.
+fn synthetic_outputs(count: usize) -> (Vec<CompactOutputDescription>, Vec<CompactAction>) {
+    let outputs = (0..count)
+        .map(|_| CompactOutputDescription {
+            ephemeral_key: EphemeralKeyBytes(jubjub::SubgroupPoint::random(OsRng).to_bytes()),
+            cmu: sapling::note::ExtractedNoteCommitment::from_bytes(
+                &jubjub::Base::random(OsRng).to_repr(),
+            )
+            .unwrap(),
+            enc_ciphertext: ciphertext(),
+        })
+        .collect();
+    let actions = (0..count)
+        .map(|_| {
+            CompactAction::from_parts(
+                orchard::note::Nullifier::from_bytes(&pallas::Base::random(OsRng).to_repr())
+                    .unwrap(),
+                orchard::note::ExtractedNoteCommitment::from_bytes(
+                    &pallas::Base::random(OsRng).to_repr(),
+                )
+                .unwrap(),
+                EphemeralKeyBytes(pallas::Point::random(OsRng).to_bytes()),
+                ciphertext(),
+            )
+        })
+        .collect();
+
+    (outputs, actions)
+}
+
+fn ciphertext() -> [u8; COMPACT_NOTE_SIZE] {
+    let mut ciphertext = [0; COMPACT_NOTE_SIZE];
+    OsRng.fill_bytes(&mut ciphertext);
+    ciphertext
+}
+
+/// Gets the sizes of the Sapling and Orchard commitment trees as of the end of the given block.
+async fn get_tree_sizes(
+    client: &mut CompactTxStreamerClient<Channel>,
+    cache: &mut HashMap<u32, (u64, u64)>,
+    height: u32,
+) -> Result<(u64, u64), Error> {
+    if let Some(sizes) = cache.get(&height) {
+        return Ok(*sizes);
+    }
+
+    let chain_state = client
+        .get_tree_state(service::BlockId {
+            height: height.into(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .to_chain_state()?;
+    let sizes = (
+        chain_state.final_sapling_tree().tree_size(),
+        chain_state.final_orchard_tree().tree_size(),
+    );
+    cache.insert(height, sizes);
+    Ok(sizes)
+}
+
+/// Downloads the blocks at the chain tip, and returns the number of bytes downloaded,
+/// the number of bytes [`download_size`] predicted for them, and the seconds it took.
+async fn download_sample(
+    client: &mut CompactTxStreamerClient<Channel>,
+    tip: u32,
+) -> Result<(u64, u64, f64), Error> {
+    let started = Instant::now();
+    let mut stream = client
+        .get_block_range(service::BlockRange {
+            start: Some(service::BlockId {
+                height: tip.saturating_sub(CALIBRATION_BLOCKS - 1).into(),
+                ..Default::default()
+            }),
+            end: Some(service::BlockId {
+                height: tip.into(),
+                ..Default::default()
+            }),
+        })
+        .await?
+        .into_inner();
+
+    let mut bytes = 0;
+    let mut blocks = 0;
+    let mut sapling_outputs = 0;
+    let mut orchard_actions = 0;
+    while let Some(block) = stream.try_next().await? {
+        bytes += block.encoded_len() as u64;
+        blocks += 1;
+        for tx in &block.vtx {
+            sapling_outputs += tx.outputs.len() as u64;
+            orchard_actions += tx.actions.len() as u64;
+        }
+    }
+
+    Ok((
+        bytes,
+        download_size(blocks, sapling_outputs, orchard_actions),
+        started.elapsed().as_secs_f64(),
+    ))
+}
+
+/// Estimates the size of the compact blocks that hold the given outputs.
+fn download_size(blocks: u32, sapling_outputs: u64, orchard_actions: u64) -> u64 {
+    blocks as u64 * BYTES_PER_BLOCK
+        + sapling_outputs * BYTES_PER_SAPLING_OUTPUT
+        + orchard_actions * BYTES_PER_ORCHARD_ACTION
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_constants::setup_test;
+
+    use super::*;
+
+    #[tokio_shared_rt::test]
+    async fn test_estimate_sync() {
+        let mut setup = setup_test().await;
+        let empty = estimate_sync(
+            &setup.data_file,
+            setup.server_uri.clone(),
+            setup.network,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(SyncEstimate::default(), empty);
+
+        setup.create_account().await.unwrap();
+        let estimate = estimate_sync(
+            &setup.data_file,
+            setup.server_uri.clone(),
+            setup.network,
+            true,
+        )
+        .await
+        .unwrap();
+        assert!(estimate.blocks > 0);
+        assert!(estimate.download_bytes > 0);
+    }
+
+    #[test]
+    fn test_measure_trial_decryptions() {
+        assert!(measure_trial_decryptions(2, Duration::from_millis(50)) > 0);
+    }
+}
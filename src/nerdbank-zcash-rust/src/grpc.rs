@@ -1,12 +1,68 @@
-use http::Uri;
-use std::{collections::HashMap, sync::Mutex};
-use tonic::transport::{Channel, ClientTlsConfig};
+use http::{uri::Scheme, Uri};
+use hyper::client::{connect::dns::Name, HttpConnector};
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+    vec,
+};
+use tonic::{
+    codec::CompressionEncoding,
+    transport::{Channel, ClientTlsConfig, Endpoint},
+};
 use zcash_client_backend::proto::service::compact_tx_streamer_client::CompactTxStreamerClient;
 
+use tower_service::Service;
+
+use crate::interop::{ConnectionOptions, IpPreference, NameResolver};
+
+/// The scheme of URIs that name a Unix domain socket, as in `unix://localhost/path/to/socket`.
+pub(crate) const UNIX_SCHEME: &str = "unix";
+
+/// How often to ping the server over an HTTP/2 connection while a request is outstanding,
+/// to check that it's still alive.
+///
+/// Connections over mobile networks can silently die behind a NAT,
+/// which would otherwise leave a long-running stream waiting on an OS timeout.
+/// gRPC servers reject clients that ping more often than every 5 minutes by default
+/// (lightwalletd among them), answering with GOAWAY and closing the connection.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How long to wait for the server to acknowledge a keep-alive ping before considering the connection dead.
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How often the OS should probe an idle TCP connection.
+/// Unlike HTTP/2 pings, these are invisible to the gRPC server, so they can be sent more often.
+const TCP_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(60);
+
 // We'll use a MUTEX to store the shareable gRPC channels, indexed by server URI.
 // gRPC channels are expensive to create, cannot be used concurrently, but cheap to clone for each user.
 lazy_static! {
     static ref CHANNELS: Mutex<HashMap<Uri, Channel>> = Mutex::new(HashMap::new());
+    static ref CONNECTION_OPTIONS: Mutex<ConnectionOptions> =
+        Mutex::new(ConnectionOptions::default());
+    static ref NAME_RESOLVER: Mutex<Option<Arc<dyn NameResolver>>> = Mutex::new(None);
+}
+
+/// Sets the options that apply to clients created after this call.
+pub(crate) fn set_connection_options(options: ConnectionOptions) {
+    *CONNECTION_OPTIONS.lock().unwrap() = options;
+}
+
+/// Sets the resolver to look up host names with for connections made after this call.
+pub(crate) fn set_name_resolver(resolver: Option<Box<dyn NameResolver>>) {
+    *NAME_RESOLVER.lock().unwrap() = resolver.map(Arc::from);
+}
+
+/// Gets whether the host has indicated that data is costly on the current connection.
+pub(crate) fn is_metered() -> bool {
+    CONNECTION_OPTIONS.lock().unwrap().metered
 }
 
 /// Return a gRPC channel for the given URI, creating one if necessary.
@@ -18,17 +74,178 @@ pub(crate) async fn get_grpc_channel(uri: Uri) -> Result<Channel, tonic::transpo
         }
     }
 
-    let tls = ClientTlsConfig::new().domain_name(uri.host().unwrap());
-    let channel = Channel::builder(uri.clone())
-        .tls_config(tls)?
-        .connect()
-        .await?;
+    // Requests over a Unix domain socket still need an HTTP authority.
+    let is_unix_socket = uri.scheme_str() == Some(UNIX_SCHEME);
+    let endpoint_uri = if is_unix_socket {
+        Uri::from_static("http://localhost")
+    } else {
+        uri.clone()
+    };
+    let mut endpoint = Channel::builder(endpoint_uri)
+        .http2_keep_alive_interval(KEEP_ALIVE_INTERVAL)
+        .keep_alive_timeout(KEEP_ALIVE_TIMEOUT)
+        .tcp_keepalive(Some(TCP_KEEP_ALIVE_INTERVAL));
+    if uri.scheme() == Some(&Scheme::HTTPS) {
+        endpoint = endpoint.tls_config(ClientTlsConfig::new().domain_name(uri.host().unwrap()))?;
+    }
+    let resolver = Resolver::new();
+    let channel = if is_unix_socket {
+        connect_unix_socket(endpoint, PathBuf::from(uri.path())).await?
+    } else if resolver.is_system_default() {
+        endpoint.connect().await?
+    } else {
+        let mut connector = HttpConnector::new_with_resolver(resolver);
+        connector.enforce_http(false);
+        connector.set_nodelay(true);
+        connector.set_keepalive(Some(TCP_KEEP_ALIVE_INTERVAL));
+        endpoint.connect_with_connector(connector).await?
+    };
 
     let mut clients = CHANNELS.lock().unwrap();
     clients.insert(uri, channel.clone());
     Ok(channel)
 }
 
+#[cfg(unix)]
+async fn connect_unix_socket(
+    endpoint: Endpoint,
+    path: PathBuf,
+) -> Result<Channel, tonic::transport::Error> {
+    endpoint
+        .connect_with_connector(UnixSocketConnector(Arc::new(path)))
+        .await
+}
+
+/// Unix domain socket URIs are rejected before they get this far on platforms without them.
+#[cfg(not(unix))]
+async fn connect_unix_socket(
+    endpoint: Endpoint,
+    _path: PathBuf,
+) -> Result<Channel, tonic::transport::Error> {
+    endpoint.connect().await
+}
+
+/// Connects to a server listening on a Unix domain socket, whatever URI is requested.
+#[cfg(unix)]
+#[derive(Clone)]
+struct UnixSocketConnector(Arc<PathBuf>);
+
+#[cfg(unix)]
+impl Service<Uri> for UnixSocketConnector {
+    type Response = tokio::net::UnixStream;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: Uri) -> Self::Future {
+        let path = self.0.clone();
+        Box::pin(async move { tokio::net::UnixStream::connect(path.as_path()).await })
+    }
+}
+
+/// Looks up host names according to the [`ConnectionOptions`] and [`NameResolver`] set by the host,
+/// falling back to the system's resolver.
+#[derive(Clone)]
+struct Resolver {
+    host_addresses: Arc<HashMap<String, Vec<String>>>,
+    ip_preference: IpPreference,
+    name_resolver: Option<Arc<dyn NameResolver>>,
+}
+
+impl Resolver {
+    fn new() -> Self {
+        let options = CONNECTION_OPTIONS.lock().unwrap();
+        Resolver {
+            host_addresses: Arc::new(options.host_addresses.clone()),
+            ip_preference: options.ip_preference,
+            name_resolver: NAME_RESOLVER.lock().unwrap().clone(),
+        }
+    }
+
+    /// Gets whether this resolves host names exactly as tonic's own connector would.
+    fn is_system_default(&self) -> bool {
+        self.host_addresses.is_empty()
+            && self.ip_preference == IpPreference::Any
+            && self.name_resolver.is_none()
+    }
+
+    async fn resolve(self, host: String) -> io::Result<Vec<IpAddr>> {
+        let mut addresses = match self.host_addresses.get(&host) {
+            Some(addresses) => parse_ip_addresses(addresses)?,
+            None => Vec::new(),
+        };
+        if addresses.is_empty() {
+            if let Some(name_resolver) = self.name_resolver.clone() {
+                let host = host.clone();
+                let resolved = tokio::task::spawn_blocking(move || name_resolver.resolve(host))
+                    .await
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                addresses = parse_ip_addresses(&resolved)?;
+            }
+        }
+        if addresses.is_empty() {
+            // The port is supplied by the connector.
+            addresses = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .map(|a| a.ip())
+                .collect();
+        }
+
+        addresses.retain(|a| match self.ip_preference {
+            IpPreference::Any => true,
+            IpPreference::Ipv4Only => a.is_ipv4(),
+            IpPreference::Ipv6Only => a.is_ipv6(),
+        });
+        if addresses.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No {:?} addresses found for {host}.", self.ip_preference),
+            ));
+        }
+
+        Ok(addresses)
+    }
+}
+
+impl Service<Name> for Resolver {
+    type Response = vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let addresses = resolver.resolve(name.as_str().to_string()).await?;
+            Ok(addresses
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, 0))
+                .collect::<Vec<_>>()
+                .into_iter())
+        })
+    }
+}
+
+fn parse_ip_addresses(addresses: &[String]) -> io::Result<Vec<IpAddr>> {
+    addresses
+        .iter()
+        .map(|a| {
+            a.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("\"{a}\" is not an IP address."),
+                )
+            })
+        })
+        .collect()
+}
+
 /// This function will release a gRPC channel for the given URI from the shared map, if it exists.
 /// Concurrent users will still be able to use the channel, but it will be destroyed when the last
 /// user disconnects.
@@ -40,16 +257,27 @@ pub(crate) fn destroy_channel(uri: Uri) -> bool {
 }
 
 /// Gets the CompactTxStreamerClient for the given URI for use with communicating with the lightwalletd server.
+///
+/// Zaino serves the same CompactTxStreamer service as lightwalletd, so it can be used as the server as well.
 pub async fn get_client(
     uri: Uri,
 ) -> Result<CompactTxStreamerClient<Channel>, tonic::transport::Error> {
     let channel = get_grpc_channel(uri).await?;
-    Ok(CompactTxStreamerClient::new(channel))
+    let mut client = CompactTxStreamerClient::new(channel);
+
+    // Servers that don't support compression simply ignore the accept-encoding header.
+    if CONNECTION_OPTIONS.lock().unwrap().accept_compressed {
+        client = client
+            .accept_compressed(CompressionEncoding::Zstd)
+            .accept_compressed(CompressionEncoding::Gzip);
+    }
+
+    Ok(client)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::test_constants::LIGHTSERVER_URI;
+    use crate::{interop::NameResolver, test_constants::LIGHTSERVER_URI};
 
     use super::*;
 
@@ -59,4 +287,58 @@ mod tests {
         get_client(LIGHTSERVER_URI.to_owned()).await.unwrap();
         assert!(destroy_channel(LIGHTSERVER_URI.to_owned()));
     }
+
+    #[derive(Debug)]
+    struct FixedResolver(Vec<String>);
+
+    impl NameResolver for FixedResolver {
+        fn resolve(&self, _host: String) -> Vec<String> {
+            self.0.clone()
+        }
+    }
+
+    #[tokio_shared_rt::test]
+    async fn resolve_with_overrides() {
+        let resolver = Resolver {
+            host_addresses: Arc::new(HashMap::from([(
+                "lightwalletd.example".to_string(),
+                vec!["192.0.2.1".to_string(), "2001:db8::1".to_string()],
+            )])),
+            ip_preference: IpPreference::Any,
+            name_resolver: Some(Arc::new(FixedResolver(vec!["192.0.2.2".to_string()]))),
+        };
+        assert_eq!(
+            vec![
+                "192.0.2.1".parse::<IpAddr>().unwrap(),
+                "2001:db8::1".parse().unwrap()
+            ],
+            resolver
+                .clone()
+                .resolve("lightwalletd.example".to_string())
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            vec!["192.0.2.2".parse::<IpAddr>().unwrap()],
+            resolver
+                .clone()
+                .resolve("other.example".to_string())
+                .await
+                .unwrap()
+        );
+
+        let v6_only = Resolver {
+            ip_preference: IpPreference::Ipv6Only,
+            ..resolver
+        };
+        assert_eq!(
+            vec!["2001:db8::1".parse::<IpAddr>().unwrap()],
+            v6_only
+                .clone()
+                .resolve("lightwalletd.example".to_string())
+                .await
+                .unwrap()
+        );
+        assert!(v6_only.resolve("other.example".to_string()).await.is_err());
+    }
 }
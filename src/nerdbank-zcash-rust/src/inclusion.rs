@@ -0,0 +1,259 @@
+use std::num::NonZeroU32;
+
+use http::Uri;
+use orchard::tree::MerkleHashOrchard;
+use rusqlite::{named_params, Connection};
+use zcash_client_backend::data_api::{WalletCommitmentTrees, WalletRead};
+use zcash_client_sqlite::error::SqliteClientError;
+use zcash_primitives::{consensus::BlockHeight, transaction::TxId};
+
+use crate::{
+    backing_store::Db,
+    error::Error,
+    grpc::get_client,
+    interop::Pool,
+    sql_statements::{GET_TRANSACTION_NOTES, TRANSACTION_EXISTS},
+    tree_states::get_tree_state,
+};
+
+/// Whether a note the wallet received is included in the note commitment tree.
+#[derive(Debug, Clone)]
+pub struct NoteInclusion {
+    pub pool: Pool,
+    /// The index of the output (or action) in the transaction that created the note.
+    pub output_index: u32,
+    pub value: u64,
+    /// Whether the wallet has seen a transaction that spends the note.
+    pub spent: bool,
+    /// Whether the note's commitment hashes up to the tree root at the anchor height,
+    /// or `None` if that can't be checked because the note was mined after the anchor height (or not yet at all),
+    /// or it has been spent and the wallet no longer keeps its Merkle path.
+    pub included: Option<bool>,
+}
+
+/// The result of checking that a transaction's notes are in the note commitment tree.
+#[derive(Debug, Clone)]
+pub struct InclusionVerification {
+    /// The height of the tree root the notes were checked against.
+    pub anchor_height: u32,
+    /// Each of the wallet's notes from the transaction.
+    pub notes: Vec<NoteInclusion>,
+}
+
+/// Checks that each of the wallet's notes from a transaction is included in the note commitment tree,
+/// rather than taking the word of the server the wallet synced from for it.
+///
+/// Each note's commitment (which decrypting the note proved matches its contents) is hashed along its Merkle path
+/// up to the root that the wallet computed at the anchor height from the blocks it scanned.
+/// That root is then compared with the tree state that the server at `server_uri` reports for the anchor height,
+/// which fails with [`Error::SubtreeRootMismatch`] if they differ.
+/// Using a different server than the wallet syncs from makes the check independent of that server.
+/// A note that is not included could not be spent, so this is worth doing before relying on a large payment.
+pub async fn verify_inclusion(
+    db: &mut Db,
+    conn: &Connection,
+    server_uri: Uri,
+    txid: TxId,
+    min_confirmations: NonZeroU32,
+) -> Result<InclusionVerification, Error> {
+    let (_, anchor_height) = db
+        .data
+        .get_target_and_anchor_heights(min_confirmations)?
+        .ok_or(Error::SyncFirst)?;
+    let exists: bool = conn.query_row(
+        TRANSACTION_EXISTS,
+        named_params! { ":txid": txid.as_ref() },
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Err(Error::InvalidArgument(format!(
+            "Transaction {} is not in the wallet.",
+            txid
+        )));
+    }
+
+    let mut notes = Vec::new();
+    let mut checkable = Vec::new();
+    let mut stmt = conn.prepare(GET_TRANSACTION_NOTES)?;
+    let mut rows = stmt.query(named_params! { ":txid": txid.as_ref() })?;
+    while let Some(row) = rows.next()? {
+        let pool = match row.get::<_, u32>("output_pool")? {
+            2 => Pool::Sapling,
+            _ => Pool::Orchard,
+        };
+        let mined_height: Option<u32> = row.get("mined_height")?;
+        let position: Option<u64> = row.get("commitment_tree_position")?;
+        if let (Some(height), Some(position)) = (mined_height, position) {
+            if BlockHeight::from_u32(height) <= anchor_height {
+                checkable.push((notes.len(), position));
+            }
+        }
+
+        notes.push(NoteInclusion {
+            pool,
+            output_index: row.get("output_index")?,
+            value: row.get("value")?,
+            spent: row.get("spent")?,
+            included: None,
+        });
+    }
+
+    if checkable.is_empty() {
+        return Ok(InclusionVerification {
+            anchor_height: anchor_height.into(),
+            notes,
+        });
+    }
+
+    let tx = db.data.get_transaction(txid)?.ok_or_else(|| {
+        Error::Internal(format!("Transaction {} has no raw data to check.", txid))
+    })?;
+
+    // Check the wallet's roots at the anchor height against the server's tree state for the same block.
+    let block_hash = db
+        .data
+        .block_metadata(anchor_height)?
+        .map(|b| b.block_hash().0);
+    let chain_state = get_tree_state(
+        &mut get_client(server_uri.clone()).await?,
+        &server_uri,
+        &db.network,
+        u32::from(anchor_height).into(),
+        block_hash.as_ref().map(|h| &h[..]),
+    )
+    .await?
+    .to_chain_state()?;
+    let mismatch = |pool: &str| {
+        Error::SubtreeRootMismatch(format!(
+            "The wallet's {} note commitment tree root at height {} differs from the server's.",
+            pool, anchor_height
+        ))
+    };
+
+    if notes.iter().any(|n| matches!(n.pool, Pool::Sapling)) {
+        let root = db
+            .data
+            .with_sapling_tree_mut::<_, _, SqliteClientError>(|tree| {
+                Ok(tree.root_at_checkpoint_id(&anchor_height)?)
+            })?;
+        if root != chain_state.final_sapling_tree().root() {
+            return Err(mismatch("Sapling"));
+        }
+    }
+    if notes.iter().any(|n| matches!(n.pool, Pool::Orchard)) {
+        let root = db
+            .data
+            .with_orchard_tree_mut::<_, _, SqliteClientError>(|tree| {
+                Ok(tree.root_at_checkpoint_id(&anchor_height)?)
+            })?;
+        if root != chain_state.final_orchard_tree().root() {
+            return Err(mismatch("Orchard"));
+        }
+    }
+
+    for (index, position) in checkable {
+        let note = &mut notes[index];
+        let output_index = note.output_index as usize;
+        let spent = note.spent;
+        note.included = match note.pool {
+            Pool::Sapling => {
+                let cmu = tx
+                    .sapling_bundle()
+                    .and_then(|b| b.shielded_outputs().get(output_index))
+                    .map(|o| *o.cmu())
+                    .ok_or_else(|| Error::Internal("The note's output is missing.".to_string()))?;
+                let leaf = sapling::Node::from_cmu(&cmu);
+                db.data
+                    .with_sapling_tree_mut::<_, _, SqliteClientError>(|tree| {
+                        match tree.witness_at_checkpoint_id(position.into(), &anchor_height) {
+                            Ok(path) => Ok(Some(
+                                path.root(leaf) == tree.root_at_checkpoint_id(&anchor_height)?,
+                            )),
+                            // The wallet stops keeping the Merkle paths of notes once they are spent.
+                            Err(_) if spent => Ok(None),
+                            Err(e) => Err(e.into()),
+                        }
+                    })?
+            }
+            _ => {
+                let cmx = tx
+                    .orchard_bundle()
+                    .and_then(|b| b.actions().get(output_index))
+                    .map(|a| *a.cmx())
+                    .ok_or_else(|| Error::Internal("The note's action is missing.".to_string()))?;
+                let leaf = MerkleHashOrchard::from_cmx(&cmx);
+                db.data
+                    .with_orchard_tree_mut::<_, _, SqliteClientError>(|tree| {
+                        match tree.witness_at_checkpoint_id(position.into(), &anchor_height) {
+                            Ok(path) => Ok(Some(
+                                path.root(leaf) == tree.root_at_checkpoint_id(&anchor_height)?,
+                            )),
+                            Err(_) if spent => Ok(None),
+                            Err(e) => Err(e.into()),
+                        }
+                    })?
+            }
+        };
+    }
+
+    Ok(InclusionVerification {
+        anchor_height: anchor_height.into(),
+        notes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+    use zcash_client_backend::data_api::WalletWrite;
+
+    use crate::{
+        backing_store::open_connection,
+        test_constants::{
+            setup_local_test, LocalTestSetup, LIGHTSERVER_URI, LOCAL_BIRTHDAY_HEIGHT,
+        },
+    };
+
+    use super::*;
+
+    async fn verify(
+        setup: &mut LocalTestSetup,
+        txid: [u8; 32],
+    ) -> Result<InclusionVerification, Error> {
+        let conn = open_connection(&setup.data_file).unwrap();
+        verify_inclusion(
+            &mut setup.db,
+            &conn,
+            LIGHTSERVER_URI.to_owned(),
+            TxId::from_bytes(txid),
+            NonZeroU32::new(1).unwrap(),
+        )
+        .await
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_verify_inclusion() {
+        let mut setup = setup_local_test();
+        setup.create_account().unwrap();
+        assert_matches!(verify(&mut setup, [0; 32]).await, Err(Error::SyncFirst));
+
+        setup
+            .db
+            .data
+            .update_chain_tip(BlockHeight::from_u32(LOCAL_BIRTHDAY_HEIGHT as u32 + 5))
+            .unwrap();
+        assert_matches!(
+            verify(&mut setup, [0; 32]).await,
+            Err(Error::InvalidArgument(_))
+        );
+
+        // A transaction that gave the wallet no notes has nothing to check, so the server isn't consulted.
+        open_connection(&setup.data_file)
+            .unwrap()
+            .execute("INSERT INTO transactions (txid) VALUES (?)", [[1u8; 32]])
+            .unwrap();
+        let verification = verify(&mut setup, [1; 32]).await.unwrap();
+        assert!(verification.notes.is_empty());
+        assert_eq!(LOCAL_BIRTHDAY_HEIGHT as u32 + 5, verification.anchor_height);
+    }
+}
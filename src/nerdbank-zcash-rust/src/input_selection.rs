@@ -0,0 +1,393 @@
+use std::marker::PhantomData;
+
+use zcash_client_backend::{
+    data_api::{
+        wallet::input_selection::{
+            GreedyInputSelector, GreedyInputSelectorError, InputSelector, InputSelectorError,
+        },
+        InputSource, SpendableNotes,
+    },
+    fees::{zip317::SingleOutputChangeStrategy, ChangeStrategy},
+    proposal::Proposal,
+    wallet::{Note, ReceivedNote, WalletTransparentOutput},
+    zip321::TransactionRequest,
+    ShieldedProtocol,
+};
+use zcash_primitives::{
+    consensus::{self, BlockHeight},
+    legacy::TransparentAddress,
+    transaction::{
+        components::{
+            amount::{NonNegativeAmount, MAX_MONEY},
+            OutPoint,
+        },
+        fees::zip317::FeeRule,
+        TxId,
+    },
+};
+
+use crate::{
+    error::Error,
+    interop::{NoteSelectionStrategy, Pool},
+};
+
+/// Selects inputs the way [`GreedyInputSelector`] does, but only from notes in the given shielded pools,
+/// and choosing among them with the given strategy.
+pub(crate) struct WalletInputSelector<DbT> {
+    pools: Vec<ShieldedProtocol>,
+    strategy: NoteSelectionStrategy,
+    fallback_change_pool: ShieldedProtocol,
+    _db: PhantomData<DbT>,
+}
+
+impl<DbT> WalletInputSelector<DbT> {
+    /// Creates an input selector that spends from `source_pool`, or from any shielded pool if it is `None`.
+    ///
+    /// Transparent funds can only be spent by shielding them, so they cannot be the source of a send.
+    pub(crate) fn new(source_pool: Option<Pool>) -> Result<Self, Error> {
+        let pools = match source_pool {
+            None => vec![ShieldedProtocol::Sapling, ShieldedProtocol::Orchard],
+            Some(Pool::Sapling) => vec![ShieldedProtocol::Sapling],
+            Some(Pool::Orchard) => vec![ShieldedProtocol::Orchard],
+            Some(Pool::Transparent) => {
+                return Err(Error::InvalidArgument(
+                    "Transparent funds must be shielded before they can be sent.".to_string(),
+                ))
+            }
+        };
+
+        // When drawing from a single pool, keep the change in that pool too
+        // so the transaction doesn't cross the turnstile unless a recipient requires it.
+        let fallback_change_pool = match pools[..] {
+            [pool] => pool,
+            _ => ShieldedProtocol::Sapling,
+        };
+
+        Ok(Self {
+            pools,
+            strategy: NoteSelectionStrategy::default(),
+            fallback_change_pool,
+            _db: PhantomData,
+        })
+    }
+
+    /// Sets the pool that change goes to when the transaction doesn't otherwise determine it.
+    pub(crate) fn with_fallback_change_pool(mut self, pool: ShieldedProtocol) -> Self {
+        self.fallback_change_pool = pool;
+        self
+    }
+
+    /// Sets the strategy that chooses which notes to spend.
+    pub(crate) fn with_strategy(mut self, strategy: NoteSelectionStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+}
+
+impl<DbT: InputSource> InputSelector for WalletInputSelector<DbT> {
+    type Error = GreedyInputSelectorError<
+        <SingleOutputChangeStrategy as ChangeStrategy>::Error,
+        DbT::NoteRef,
+    >;
+    type InputSource = DbT;
+    type FeeRule = FeeRule;
+
+    fn propose_transaction<ParamsT>(
+        &self,
+        params: &ParamsT,
+        wallet_db: &Self::InputSource,
+        target_height: BlockHeight,
+        anchor_height: BlockHeight,
+        account: DbT::AccountId,
+        transaction_request: TransactionRequest,
+    ) -> Result<Proposal<FeeRule, DbT::NoteRef>, InputSelectorError<DbT::Error, Self::Error>>
+    where
+        ParamsT: consensus::Parameters,
+    {
+        let source = WalletInputSource {
+            inner: wallet_db,
+            pools: &self.pools,
+            strategy: self.strategy,
+        };
+        GreedyInputSelector::new(
+            SingleOutputChangeStrategy::new(FeeRule::standard(), None, self.fallback_change_pool),
+            Default::default(),
+        )
+        .propose_transaction(
+            params,
+            &source,
+            target_height,
+            anchor_height,
+            account,
+            transaction_request,
+        )
+    }
+}
+
+/// An [`InputSource`] that hides the notes in every shielded pool but the given ones,
+/// and offers notes to the input selector in the order the strategy prefers them.
+struct WalletInputSource<'a, DbT> {
+    inner: &'a DbT,
+    pools: &'a [ShieldedProtocol],
+    strategy: NoteSelectionStrategy,
+}
+
+impl<'a, DbT: InputSource> InputSource for WalletInputSource<'a, DbT> {
+    type Error = DbT::Error;
+    type AccountId = DbT::AccountId;
+    type NoteRef = DbT::NoteRef;
+
+    fn get_spendable_note(
+        &self,
+        txid: &TxId,
+        protocol: ShieldedProtocol,
+        index: u32,
+    ) -> Result<Option<ReceivedNote<Self::NoteRef, Note>>, Self::Error> {
+        if !self.pools.contains(&protocol) {
+            return Ok(None);
+        }
+
+        self.inner.get_spendable_note(txid, protocol, index)
+    }
+
+    fn select_spendable_notes(
+        &self,
+        account: Self::AccountId,
+        target_value: NonNegativeAmount,
+        sources: &[ShieldedProtocol],
+        anchor_height: BlockHeight,
+        exclude: &[Self::NoteRef],
+    ) -> Result<SpendableNotes<Self::NoteRef>, Self::Error> {
+        let sources: Vec<ShieldedProtocol> = sources
+            .iter()
+            .copied()
+            .filter(|p| self.pools.contains(p))
+            .collect();
+        if self.strategy == NoteSelectionStrategy::Greedy {
+            return self.inner.select_spendable_notes(
+                account,
+                target_value,
+                &sources,
+                anchor_height,
+                exclude,
+            );
+        }
+
+        // Asking for more than can exist gets every spendable note, for the strategy to choose from.
+        let candidates = self.inner.select_spendable_notes(
+            account,
+            NonNegativeAmount::const_from_u64(MAX_MONEY),
+            &sources,
+            anchor_height,
+            exclude,
+        )?;
+        Ok(select_notes(
+            &candidates,
+            target_value.into(),
+            self.strategy,
+        ))
+    }
+
+    fn get_unspent_transparent_output(
+        &self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<WalletTransparentOutput>, Self::Error> {
+        self.inner.get_unspent_transparent_output(outpoint)
+    }
+
+    fn get_unspent_transparent_outputs(
+        &self,
+        address: &TransparentAddress,
+        max_height: BlockHeight,
+        exclude: &[OutPoint],
+    ) -> Result<Vec<WalletTransparentOutput>, Self::Error> {
+        self.inner
+            .get_unspent_transparent_outputs(address, max_height, exclude)
+    }
+}
+
+/// A note that may be selected, identified by its pool and its index among that pool's candidates.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    pool: ShieldedProtocol,
+    index: usize,
+    value: u64,
+    /// The note's position in its pool's commitment tree, which orders the pool's notes by age.
+    position: u64,
+}
+
+/// Chooses notes from the candidates until they add up to the target value, in the order the strategy prefers.
+/// If the candidates fall short of the target, all of them are selected.
+fn select_notes<NoteRef: Clone>(
+    candidates: &SpendableNotes<NoteRef>,
+    target_value: u64,
+    strategy: NoteSelectionStrategy,
+) -> SpendableNotes<NoteRef> {
+    let sapling = candidates
+        .sapling()
+        .iter()
+        .enumerate()
+        .map(|(i, n)| Candidate {
+            pool: ShieldedProtocol::Sapling,
+            index: i,
+            value: n.note().value().inner(),
+            position: n.note_commitment_tree_position().into(),
+        });
+    let orchard = candidates
+        .orchard()
+        .iter()
+        .enumerate()
+        .map(|(i, n)| Candidate {
+            pool: ShieldedProtocol::Orchard,
+            index: i,
+            value: n.note().value().inner(),
+            position: n.note_commitment_tree_position().into(),
+        });
+    let mut notes: Vec<Candidate> = sapling.chain(orchard).collect();
+    order_candidates(&mut notes, target_value, strategy);
+
+    let mut sapling = Vec::new();
+    let mut orchard = Vec::new();
+    let mut total = 0;
+    for note in notes {
+        if total >= target_value {
+            break;
+        }
+
+        total += note.value;
+        match note.pool {
+            ShieldedProtocol::Sapling => sapling.push(candidates.sapling()[note.index].clone()),
+            ShieldedProtocol::Orchard => orchard.push(candidates.orchard()[note.index].clone()),
+        }
+    }
+
+    SpendableNotes::new(sapling, orchard)
+}
+
+/// Sorts the candidates into the order the strategy prefers to spend them in,
+/// dropping any that the strategy would rather not spend.
+fn order_candidates(
+    notes: &mut Vec<Candidate>,
+    target_value: u64,
+    strategy: NoteSelectionStrategy,
+) {
+    match strategy {
+        NoteSelectionStrategy::Greedy => {}
+        NoteSelectionStrategy::FeeFirst => notes.sort_by(|a, b| b.value.cmp(&a.value)),
+        NoteSelectionStrategy::OldestFirst => {
+            // Positions can only be compared within a pool. Sapling is the older pool.
+            notes.sort_by_key(|n| (n.pool == ShieldedProtocol::Orchard, n.position))
+        }
+        NoteSelectionStrategy::AvoidDust => notes.sort_by_key(|n| n.value),
+        NoteSelectionStrategy::PrivacyFirst => {
+            // Spending from a single pool keeps value from crossing between pools, which reveals the amount.
+            let pool_total = |pool| {
+                notes
+                    .iter()
+                    .filter(|n| n.pool == pool)
+                    .map(|n| n.value)
+                    .sum::<u64>()
+            };
+            let pool = [ShieldedProtocol::Orchard, ShieldedProtocol::Sapling]
+                .into_iter()
+                .find(|p| pool_total(*p) >= target_value);
+            if let Some(pool) = pool {
+                notes.retain(|n| n.pool == pool);
+            }
+            notes.sort_by(|a, b| b.value.cmp(&a.value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_sqlite::WalletDb;
+
+    use super::*;
+
+    type Selector =
+        WalletInputSelector<WalletDb<rusqlite::Connection, zcash_primitives::consensus::Network>>;
+
+    fn candidate(pool: ShieldedProtocol, value: u64, position: u64) -> Candidate {
+        Candidate {
+            pool,
+            index: 0,
+            value,
+            position,
+        }
+    }
+
+    fn values(notes: &[Candidate]) -> Vec<u64> {
+        notes.iter().map(|n| n.value).collect()
+    }
+
+    #[test]
+    fn test_new() {
+        let any = Selector::new(None).unwrap();
+        assert_eq!(2, any.pools.len());
+        assert_eq!(ShieldedProtocol::Sapling, any.fallback_change_pool);
+        assert_eq!(NoteSelectionStrategy::Greedy, any.strategy);
+
+        let orchard = Selector::new(Some(Pool::Orchard)).unwrap();
+        assert_eq!(vec![ShieldedProtocol::Orchard], orchard.pools);
+        assert_eq!(ShieldedProtocol::Orchard, orchard.fallback_change_pool);
+
+        assert!(matches!(
+            Selector::new(Some(Pool::Transparent)),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_with_fallback_change_pool() {
+        let sapling = Selector::new(Some(Pool::Sapling))
+            .unwrap()
+            .with_fallback_change_pool(ShieldedProtocol::Orchard);
+        assert_eq!(vec![ShieldedProtocol::Sapling], sapling.pools);
+        assert_eq!(ShieldedProtocol::Orchard, sapling.fallback_change_pool);
+    }
+
+    #[test]
+    fn test_order_candidates() {
+        let notes = vec![
+            candidate(ShieldedProtocol::Orchard, 300, 1),
+            candidate(ShieldedProtocol::Sapling, 100, 7),
+            candidate(ShieldedProtocol::Sapling, 250, 3),
+        ];
+        let ordered = |strategy, target| {
+            let mut notes = notes.clone();
+            order_candidates(&mut notes, target, strategy);
+            values(&notes)
+        };
+
+        assert_eq!(
+            vec![300, 100, 250],
+            ordered(NoteSelectionStrategy::Greedy, 0)
+        );
+        assert_eq!(
+            vec![300, 250, 100],
+            ordered(NoteSelectionStrategy::FeeFirst, 0)
+        );
+        assert_eq!(
+            vec![250, 100, 300],
+            ordered(NoteSelectionStrategy::OldestFirst, 0)
+        );
+        assert_eq!(
+            vec![100, 250, 300],
+            ordered(NoteSelectionStrategy::AvoidDust, 0)
+        );
+
+        // Orchard alone covers the target.
+        assert_eq!(vec![300], ordered(NoteSelectionStrategy::PrivacyFirst, 250));
+        // Only Sapling covers the target.
+        assert_eq!(
+            vec![250, 100],
+            ordered(NoteSelectionStrategy::PrivacyFirst, 320)
+        );
+        // Neither pool covers the target alone.
+        assert_eq!(
+            vec![300, 250, 100],
+            ordered(NoteSelectionStrategy::PrivacyFirst, 500)
+        );
+    }
+}
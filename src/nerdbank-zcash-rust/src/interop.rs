@@ -8,27 +8,54 @@ use std::{
     time::SystemTime,
 };
 
-use http::{uri::InvalidUri, Uri};
-use rusqlite::Connection;
-use secrecy::SecretVec;
-use tokio::runtime::Runtime;
+use http::{
+    uri::{InvalidUri, Scheme},
+    Uri,
+};
+use rusqlite::named_params;
+use sapling::{keys::ExpandedSpendingKey, PaymentAddress};
+use secrecy::{ExposeSecret, SecretVec};
+use tokio::{runtime::Runtime, select};
 use tokio_util::sync::CancellationToken;
 use zcash_client_backend::{
-    data_api::{Account, WalletRead},
+    data_api::{scanning, Account, WalletRead},
     encoding::AddressCodec,
     keys::{Era, UnifiedSpendingKey},
 };
 use zcash_client_sqlite::error::SqliteClientError;
-use zcash_keys::keys::UnifiedFullViewingKey;
-use zcash_primitives::{consensus::Network, legacy::TransparentAddress, zip32::DiversifierIndex};
+use zcash_keys::{address::Address, keys::UnifiedFullViewingKey};
+use zcash_primitives::{
+    consensus::Network, legacy::TransparentAddress, transaction::TxId, zip32::DiversifierIndex,
+};
+use zeroize::Zeroizing;
 
 use crate::{
-    analysis::{BirthdayHeights, UserBalances},
-    backing_store::Db,
+    account_uuid::account_uuid,
+    allocations::AddressAllocation,
+    analysis::{AccountSyncState, BirthdayHeights, UserBalances, WalletSummary},
+    annotations::Tag,
+    audit_log::AuditLogEntry,
+    backing_store::{open_connection, release_in_memory_wallet, wallet_locks, Db},
+    decode::DecodedTransaction,
     error::Error,
-    grpc::{destroy_channel, get_client},
+    estimate::{ScanBenchmark, SyncEstimate},
+    grpc::{destroy_channel, get_client, UNIX_SCHEME},
+    inclusion::InclusionVerification,
+    invoices::{Invoice, InvoiceStatus},
+    memo_index::MemoMatch,
+    pause::PauseToken,
+    payments::{IncomingPayment, IncomingPaymentsFilter, IncomingPaymentsPage},
+    prices::FiatUserBalances,
+    recurring::RecurringPayment,
+    reporting::{CostBasisMethod, FeeStats, PricePoint, TaxReport, TimeWindow},
+    rotate::SeedRotation,
     send::{create_send_proposal, send_transaction},
+    send_queue::{QueuedPayment, SendQueueResult},
     shield::shield_funds_at_address,
+    spending_policy::SpendingPolicy,
+    sql_statements::GET_ACCOUNT_KEY_INFO,
+    state_diff::WalletStateDiff,
+    viewing_keys::{AddressMismatch, AddressOwnership},
 };
 
 lazy_static! {
@@ -38,6 +65,31 @@ lazy_static! {
 pub trait SyncUpdate: Send + Sync + std::fmt::Debug {
     fn update_status(&self, data: SyncUpdateData);
     fn report_transactions(&self, transactions: Vec<Transaction>);
+    fn scan_range_event(&self, range: ScanRange, phase: ScanRangePhase);
+    fn report_balances(&self, account_id: u32, balances: UserBalances);
+    fn report_incoming_payments(&self, payments: Vec<IncomingPayment>);
+    fn report_scheduled_payments(&self, payments: Vec<QueuedPayment>);
+    fn report_reorg(&self, rewind_height: u32, affected_txids: Vec<Vec<u8>>);
+}
+
+pub trait AddressWatcher: Send + Sync + std::fmt::Debug {
+    fn transaction_seen(&self, address: String, txid: Vec<u8>, mined_height: Option<u32>);
+}
+
+pub trait MigrationProgress: Send + Sync + std::fmt::Debug {
+    fn transaction_sent(&self, txid: Vec<u8>, migrated: u64, remaining: u64);
+}
+
+pub trait NameResolver: Send + Sync + std::fmt::Debug {
+    fn resolve(&self, host: String) -> Vec<String>;
+}
+
+pub trait SpendApprover: Send + Sync + std::fmt::Debug {
+    fn approve(&self, account_id: u32, send_details: Vec<TransactionSendDetail>) -> bool;
+}
+
+pub trait PriceProvider: Send + Sync + std::fmt::Debug {
+    fn get_prices(&self, currency: String, days: Vec<SystemTime>) -> Vec<PricePoint>;
 }
 
 pub trait CancellationSource: Send + Sync + std::fmt::Debug {
@@ -56,9 +108,187 @@ impl From<uniffi::UnexpectedUniFFICallbackError> for LightWalletError {
 pub struct SyncUpdateData {
     pub last_fully_scanned_block: Option<u32>,
     pub tip_height: u32,
+    /// The sum of all unspent transparent funds in the wallet.
+    /// Until the wallet's transparent transactions have been downloaded, this is the balance reported by the server,
+    /// so that the user need not wait for a lengthy download to see it.
+    pub transparent_balance: Option<u64>,
     pub current_step: u64,
     pub total_steps: u64,
+    /// The number of Sapling outputs in the blocks scanned so far.
+    pub sapling_outputs_scanned: u64,
+    /// The number of Orchard actions in the blocks scanned so far.
+    pub orchard_actions_scanned: u64,
+    /// The error that interrupted a continual sync, which is being retried after a delay.
+    /// Cleared once the sync gets through the chain again.
     pub last_error: Option<String>,
+    /// The priority of the range of blocks being scanned, or `None` when no blocks are being scanned.
+    ///
+    /// [`ScanPriority::Verify`] means the wallet is checking its view of the chain, e.g. after a reorg.
+    pub scanning_priority: Option<ScanPriority>,
+    /// The number of blocks left to scan in this pass, by priority, highest priority first.
+    pub remaining_blocks: Vec<RemainingBlocks>,
+}
+
+/// The number of blocks of some priority that are left to scan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemainingBlocks {
+    pub priority: ScanPriority,
+    pub blocks: u64,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Downloads and scans the blockchain for shielded and transparent funds.
+    #[default]
+    Full,
+    /// Only downloads transactions that involve the wallet's transparent addresses.
+    /// Compact blocks are not downloaded or scanned, so shielded funds will not be discovered,
+    /// and transactions will not be assigned their block times until a full sync is performed.
+    TransparentOnly,
+    /// Scans the blockchain for shielded and transparent funds,
+    /// but scans historic blocks from the most recent backward so that spendable funds are discovered sooner
+    /// when restoring a wallet with a long history.
+    ///
+    /// Every block is still downloaded and trial-decrypted.
+    /// Skipping blocks would require asking the server which blocks hold the wallet's nullifiers or note commitments,
+    /// which lightwalletd does not support and which would reveal the wallet's notes to the server.
+    FastRecovery,
+}
+
+/// How [`get_transactions`] presents the change that transactions return to the account.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ChangeNotes {
+    /// Change notes are listed in [`Transaction::change`].
+    #[default]
+    Include,
+    /// Change notes are left out of [`Transaction::change`].
+    /// [`Transaction::account_balance_delta`] still matches the account's balances.
+    Hide,
+    /// Change notes are left out, and [`Transaction::account_balance_delta`] is net of them,
+    /// as it always is.
+    Net,
+}
+
+/// Options that tune how [`sync`] interacts with the lightwalletd server.
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    pub mode: SyncMode,
+
+    /// Skips downloading transactions for the wallet's transparent addresses,
+    /// and skips deriving new transparent addresses to fill the gap limit.
+    ///
+    /// Shielded-only wallets may set this to avoid revealing their transparent addresses to the server.
+    /// Funds sent to transparent addresses will not be discovered while this is set.
+    pub skip_transparent: bool,
+
+    /// The URI of a second lightwalletd server to check the note commitment tree's subtree roots against.
+    ///
+    /// The roots seed the wallet's view of the tree, so a server that lies about them can make the wallet
+    /// believe its notes are spendable when they are not.
+    /// When set, sync fails with [`LightWalletError::SubtreeRootMismatch`] if the servers disagree.
+    pub verification_server: Option<String>,
+
+    /// The most sapling spends and outputs and orchard actions that may be downloaded ahead of scanning.
+    /// Downloading pauses when scanning falls this far behind, which bounds the memory that sync uses.
+    /// When `None`, a default of a few hundred megabytes' worth is used.
+    pub max_cached_actions: Option<u32>,
+
+    /// The block to start looking for transactions involving the wallet's transparent addresses in,
+    /// even for addresses that an earlier sync already searched past it.
+    /// Addresses that have not been searched as far as this block yet resume where they left off.
+    /// When `None`, each address is searched from where the last sync left off, but not before its account's birthday.
+    ///
+    /// Addresses that were imported from another wallet may need an earlier height than the account's birthday.
+    pub transparent_start_height: Option<u32>,
+}
+
+/// Options that control how [`send`] funds a transaction.
+#[derive(Debug, Clone, Default)]
+pub struct SendOptions {
+    /// The pool to draw inputs from, or `None` to draw from any shielded pool.
+    ///
+    /// Transparent funds cannot be sent directly. They must be shielded first.
+    pub source_pool: Option<Pool>,
+
+    /// The most information the transaction may reveal.
+    /// A send that would reveal more fails with [`LightWalletError::PrivacyPolicyViolation`].
+    pub privacy_policy: PrivacyPolicy,
+
+    /// How to choose which notes to spend.
+    pub note_selection: NoteSelectionStrategy,
+}
+
+/// A way to choose which notes fund a send.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum NoteSelectionStrategy {
+    /// Spend notes in the order the wallet received them.
+    #[default]
+    Greedy,
+    /// Spend from a single pool when it can cover the send, so no amount crosses between pools,
+    /// and spend the largest notes first.
+    PrivacyFirst,
+    /// Spend the largest notes first, to keep the number of inputs (and so the fee) low.
+    FeeFirst,
+    /// Spend the oldest notes first.
+    OldestFirst,
+    /// Spend the smallest notes first, consolidating them before they accumulate.
+    AvoidDust,
+}
+
+/// How much a transaction may reveal about its funds and participants, modeled after zcashd's privacy policies.
+/// Each policy allows everything the policies before it allow.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PrivacyPolicy {
+    /// Reveal nothing beyond the fee.
+    FullPrivacy,
+    /// Allow the amounts that move between shielded pools to be revealed.
+    AllowRevealedAmounts,
+    /// Allow transparent recipients, and the amounts they are sent, to be revealed.
+    AllowRevealedRecipients,
+    /// Allow transparent addresses of the same account to be linked by spending from them together.
+    /// This is the least restrictive policy, which every send the wallet can make satisfies.
+    #[default]
+    AllowLinkingAccountAddresses,
+}
+
+/// Options that apply to every connection to a lightwalletd server.
+#[derive(Debug, Clone)]
+pub struct ConnectionOptions {
+    /// Asks the server to compress its responses, which greatly reduces the amount downloaded during sync.
+    pub accept_compressed: bool,
+
+    /// Indicates that data is costly on the current connection.
+    /// Sync then only scans recent blocks (and transparent activity), deferring historic blocks until this is cleared.
+    /// Unlike the other options, this takes effect on syncs that are already running.
+    pub metered: bool,
+
+    /// IP addresses to connect to for a given host name, instead of looking the host name up.
+    pub host_addresses: HashMap<String, Vec<String>>,
+
+    /// Which IP address families to connect over.
+    pub ip_preference: IpPreference,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        Self {
+            accept_compressed: true,
+            metered: false,
+            host_addresses: HashMap::new(),
+            ip_preference: IpPreference::Any,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum IpPreference {
+    /// Connect over whichever addresses the host name resolves to.
+    #[default]
+    Any,
+    /// Only connect over IPv4.
+    Ipv4Only,
+    /// Only connect over IPv6.
+    Ipv6Only,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -74,6 +304,62 @@ pub enum Pool {
     Orchard,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ScanPriority {
+    Ignored,
+    Scanned,
+    Historic,
+    OpenAdjacent,
+    FoundNote,
+    ChainTip,
+    Verify,
+}
+
+impl From<scanning::ScanPriority> for ScanPriority {
+    fn from(priority: scanning::ScanPriority) -> Self {
+        match priority {
+            scanning::ScanPriority::Ignored => ScanPriority::Ignored,
+            scanning::ScanPriority::Scanned => ScanPriority::Scanned,
+            scanning::ScanPriority::Historic => ScanPriority::Historic,
+            scanning::ScanPriority::OpenAdjacent => ScanPriority::OpenAdjacent,
+            scanning::ScanPriority::FoundNote => ScanPriority::FoundNote,
+            scanning::ScanPriority::ChainTip => ScanPriority::ChainTip,
+            scanning::ScanPriority::Verify => ScanPriority::Verify,
+        }
+    }
+}
+
+/// A range of blocks that the wallet wants scanned.
+#[derive(Debug, Clone)]
+pub struct ScanRange {
+    /// The first block in the range.
+    pub start: u32,
+    /// The block *after* the last block in the range.
+    pub end: u32,
+    pub priority: ScanPriority,
+}
+
+impl From<&scanning::ScanRange> for ScanRange {
+    fn from(range: &scanning::ScanRange) -> Self {
+        ScanRange {
+            start: range.block_range().start.into(),
+            end: range.block_range().end.into(),
+            priority: range.priority().into(),
+        }
+    }
+}
+
+/// The stages a scan range goes through during a sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanRangePhase {
+    /// Blocks in the range have begun downloading.
+    Downloading,
+    /// Downloaded blocks in the range have begun scanning.
+    Scanning,
+    /// The range has been fully scanned.
+    Completed,
+}
+
 impl From<ChainType> for Network {
     fn from(chain_type: ChainType) -> Self {
         match chain_type {
@@ -94,25 +380,64 @@ impl From<Network> for ChainType {
 
 pub struct AccountInfo {
     pub id: u32,
+    /// An ID for the account that is derived from its viewing key,
+    /// so it stays the same when the wallet is recreated, unlike `id`.
+    pub uuid: Vec<u8>,
     pub uvk: Option<String>,
     pub birthday_heights: BirthdayHeights,
+    /// Whether the spending key for this account is available to the app,
+    /// as opposed to the account being view-only.
+    pub spending_key_available: bool,
+    /// The ZIP-32 account index, for accounts derived from a seed.
+    pub zip32_account_index: Option<u32>,
+    /// The ZIP-32 fingerprint of the seed the account was derived from, if any.
+    pub seed_fingerprint: Option<Vec<u8>>,
+    /// Whether the account was imported from a UFVK rather than derived from a seed.
+    pub imported: bool,
+    /// The account that this account's funds were moved to when it was retired, if it has been.
+    pub successor_account_id: Option<u32>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Transaction {
     pub account_id: u32,
+    pub account_uuid: Vec<u8>,
     pub txid: Vec<u8>,
     pub block_time: Option<SystemTime>,
     pub mined_height: Option<u32>,
+    /// The height after which the transaction can no longer be mined, or `None` if it never expires.
+    pub expiry_height: Option<u32>,
+    /// For an unmined transaction that expires, the number of blocks that may be mined before it expires.
+    /// This is 0 once the transaction has expired.
+    pub blocks_until_expiry: Option<u32>,
     pub expired_unmined: bool,
     pub account_balance_delta: i64,
     pub fee: Option<u64>,
+    /// The transaction format version (e.g. 4 or 5), if the full transaction has been downloaded.
+    pub version: Option<u32>,
+    /// The size of the transaction in bytes, if the full transaction has been downloaded.
+    pub size: Option<u32>,
+    /// The consensus branch ID the transaction commits to.
+    /// Only transactions with version 5 or later record this.
+    pub consensus_branch_id: Option<u32>,
+    /// Whether the outputs this account sent in this transaction are known.
+    /// For view-only accounts, these are only known when they could be decrypted with the account's outgoing viewing key.
+    /// When this is `false` for a transaction that spends the account's funds, `outgoing` and `change` may be incomplete.
+    pub outgoing_recovered: bool,
+    /// The label the user gave this transaction, if any.
+    pub label: Option<String>,
+    /// The IDs of the tags the user applied to this transaction.
+    pub tags: Vec<u32>,
     /// Notes that are sent by this transaction (and do not appear in `change`).
     pub outgoing: Vec<TransactionNote>,
     /// Notes that are received by this transaction (and do not appear in `change`).
     pub incoming: Vec<TransactionNote>,
     /// Notes that are sent and received by the same account and bear other signs of being implicit change.
     pub change: Vec<TransactionNote>,
+    /// The transparent inputs of the transaction, if the full transaction has been downloaded.
+    pub transparent_inputs: Vec<TransparentInput>,
+    /// The transparent outputs of the transaction, if the full transaction has been downloaded.
+    pub transparent_outputs: Vec<TransparentOutput>,
 }
 
 #[derive(Debug, Clone)]
@@ -121,12 +446,38 @@ pub struct TransparentNote {
     pub recipient: String,
 }
 
+/// A transparent input to a transaction.
+#[derive(Debug, Clone)]
+pub struct TransparentInput {
+    /// The txid of the transaction whose output is being spent.
+    pub prevout_txid: Vec<u8>,
+    /// The index of the output being spent.
+    pub prevout_index: u32,
+    /// The value of the output being spent, if it belonged to the wallet.
+    pub value: Option<u64>,
+}
+
+/// A transparent output of a transaction.
+#[derive(Debug, Clone)]
+pub struct TransparentOutput {
+    /// The index of the output in the transaction.
+    pub index: u32,
+    /// The address that receives the output, if its script is a standard one.
+    pub address: Option<String>,
+    pub value: u64,
+    /// Whether the output was received by one of the wallet's addresses.
+    pub owned: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct TransactionNote {
     pub recipient: String,
     pub pool: Pool,
     pub value: u64,
     pub memo: Option<Vec<u8>>,
+    pub reply_to: Option<String>,
+    pub payment_label: Option<String>,
+    pub payment_message: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -156,6 +507,24 @@ pub enum LightWalletError {
     #[error("Insufficient funds: {required} required but only {available} is available.")]
     InsufficientFunds { required: u64, available: u64 },
 
+    #[error("Wallet migration failed: {message}")]
+    WalletMigration {
+        message: String,
+        backup_path: Option<String>,
+    },
+
+    #[error("The transaction requires the {required:?} privacy policy: {reason}")]
+    PrivacyPolicyViolation {
+        required: PrivacyPolicy,
+        reason: String,
+    },
+
+    #[error("The spending policy does not allow this send: {reason}")]
+    SpendingPolicyViolation { reason: String },
+
+    #[error("The servers disagree on the note commitment tree: {message}")]
+    SubtreeRootMismatch { message: String },
+
     #[error("{message}")]
     Other { message: String },
 }
@@ -166,6 +535,55 @@ impl From<InvalidUri> for LightWalletError {
     }
 }
 
+/// Parses a unified spending key that was passed in from the app.
+///
+/// The bytes are zeroized as soon as they are parsed, whether or not parsing succeeds.
+/// These are uniffi's copy of the key, so the app remains responsible for clearing its own buffer.
+/// The parsed key can't be zeroized, but it is never stored, so it is dropped by the end of the call.
+fn parse_usk(usk: Vec<u8>) -> Result<UnifiedSpendingKey, LightWalletError> {
+    let usk = Zeroizing::new(usk);
+    UnifiedSpendingKey::from_bytes(Era::Orchard, &usk).map_err(|_| {
+        LightWalletError::InvalidArgument {
+            message: "Failure when parsing USK.".to_string(),
+        }
+    })
+}
+
+/// Parses the URI of a lightwalletd server.
+///
+/// Besides `http` and `https` URIs, this accepts `unix:///path/to/socket` for a server listening on a Unix domain socket.
+/// Since [`Uri`] requires an authority, these are represented as `unix://localhost/path/to/socket`.
+pub(crate) fn parse_server_uri(uri: &str) -> Result<Uri, LightWalletError> {
+    if let Some(path) = uri.strip_prefix("unix://") {
+        if cfg!(not(unix)) {
+            return Err(LightWalletError::InvalidArgument {
+                message: "Unix domain sockets are not supported on this platform.".to_string(),
+            });
+        }
+        if !path.starts_with('/') {
+            return Err(LightWalletError::InvalidArgument {
+                message: "The socket path in a unix:// URI must be absolute.".to_string(),
+            });
+        }
+
+        return Ok(Uri::builder()
+            .scheme(UNIX_SCHEME)
+            .authority("localhost")
+            .path_and_query(path)
+            .build()
+            .map_err(|_| LightWalletError::InvalidUri)?);
+    }
+
+    let uri: Uri = uri.parse()?;
+    if uri.host().is_none()
+        || (uri.scheme() != Some(&Scheme::HTTP) && uri.scheme() != Some(&Scheme::HTTPS))
+    {
+        return Err(LightWalletError::InvalidUri);
+    }
+
+    Ok(uri)
+}
+
 impl From<SqliteClientError> for LightWalletError {
     fn from(e: SqliteClientError) -> Self {
         LightWalletError::SqliteClientError {
@@ -206,6 +624,19 @@ impl From<Error> for LightWalletError {
                 required: required.into(),
                 available: available.into(),
             },
+            Error::PrivacyPolicyViolation { required, reason } => {
+                LightWalletError::PrivacyPolicyViolation { required, reason }
+            }
+            Error::SpendingPolicyViolation(reason) => {
+                LightWalletError::SpendingPolicyViolation { reason }
+            }
+            Error::SubtreeRootMismatch(message) => {
+                LightWalletError::SubtreeRootMismatch { message }
+            }
+            Error::WalletMigrator { error, backup_path } => LightWalletError::WalletMigration {
+                message: error.to_string(),
+                backup_path: backup_path.map(|p| p.to_string_lossy().into_owned()),
+            },
             _ => LightWalletError::Other {
                 message: e.to_string(),
             },
@@ -223,16 +654,21 @@ pub struct DbInit {
 lazy_static! {
     static ref CANCELLATION_TOKENS: Mutex<HashMap<u32, CancellationToken>> =
         Mutex::new(HashMap::new());
+    static ref PAUSE_TOKENS: Mutex<HashMap<u32, PauseToken>> = Mutex::new(HashMap::new());
     static ref TOKEN_COUNTER: AtomicU32 = AtomicU32::new(1);
+    static ref WALLETS: Mutex<HashMap<u32, DbInit>> = Mutex::new(HashMap::new());
+    static ref WALLET_COUNTER: AtomicU32 = AtomicU32::new(1);
 }
 
-struct InteropCancellationToken(CancellationToken, Option<u32>);
+struct InteropCancellationToken(CancellationToken, Option<u32>, PauseToken);
 
 impl Drop for InteropCancellationToken {
     fn drop(&mut self) {
         if let Some(id) = self.1 {
             let mut tokens = CANCELLATION_TOKENS.lock().unwrap();
             tokens.remove(&id);
+            let mut pause_tokens = PAUSE_TOKENS.lock().unwrap();
+            pause_tokens.remove(&id);
         }
     }
 }
@@ -242,20 +678,29 @@ fn get_cancellation_token(
 ) -> Result<InteropCancellationToken, LightWalletError> {
     match client {
         Some(source) => {
-            let (handle, token) = {
+            let (handle, token, pause_token) = {
                 let mut tokens = CANCELLATION_TOKENS.lock().unwrap();
                 let handle = TOKEN_COUNTER.fetch_add(1, Ordering::SeqCst);
                 let token = CancellationToken::new();
                 tokens.insert(handle, token.clone());
-                (handle, token)
+                let pause_token = PauseToken::new();
+                PAUSE_TOKENS
+                    .lock()
+                    .unwrap()
+                    .insert(handle, pause_token.clone());
+                (handle, token, pause_token)
             };
 
             // Notify the client of the ID that was assigned so they can call cancel(u32) with it later.
             source.set_cancellation_id(handle);
 
-            Ok(InteropCancellationToken(token, Some(handle)))
+            Ok(InteropCancellationToken(token, Some(handle), pause_token))
         }
-        None => Ok(InteropCancellationToken(CancellationToken::new(), None)),
+        None => Ok(InteropCancellationToken(
+            CancellationToken::new(),
+            None,
+            PauseToken::new(),
+        )),
     }
 }
 
@@ -268,6 +713,25 @@ pub fn cancel(id: u32) -> Result<(), LightWalletError> {
     Ok(())
 }
 
+/// Suspends the operation with the given cancellation ID, without losing the work it has in progress.
+/// Operations that do not support pausing are unaffected.
+pub fn pause(id: u32) -> Result<(), LightWalletError> {
+    if let Some(token) = PAUSE_TOKENS.lock().unwrap().get(&id) {
+        token.pause();
+    }
+
+    Ok(())
+}
+
+/// Resumes an operation previously suspended with [`pause`].
+pub fn resume(id: u32) -> Result<(), LightWalletError> {
+    if let Some(token) = PAUSE_TOKENS.lock().unwrap().get(&id) {
+        token.resume();
+    }
+
+    Ok(())
+}
+
 pub fn init(config: DbInit) -> Result<(), LightWalletError> {
     RT.block_on(async move {
         Db::init(config.data_file, config.network.into())?;
@@ -275,6 +739,81 @@ pub fn init(config: DbInit) -> Result<(), LightWalletError> {
     })
 }
 
+/// Initializes a wallet and registers it, returning a handle that [`sync_wallet`] and [`send_from_wallet`] accept.
+pub fn open_wallet(config: DbInit) -> Result<u32, LightWalletError> {
+    init(config.clone())?;
+    let handle = WALLET_COUNTER.fetch_add(1, Ordering::SeqCst);
+    WALLETS.lock().unwrap().insert(handle, config);
+    Ok(handle)
+}
+
+/// Releases a handle obtained from [`open_wallet`].
+/// An in-memory wallet is discarded when its handle is closed.
+pub fn close_wallet(handle: u32) -> Result<(), LightWalletError> {
+    if let Some(config) = WALLETS.lock().unwrap().remove(&handle) {
+        release_in_memory_wallet(config.data_file);
+    }
+
+    Ok(())
+}
+
+/// Gets the configuration of a wallet opened with [`open_wallet`].
+pub fn get_wallet_config(handle: u32) -> Result<DbInit, LightWalletError> {
+    WALLETS
+        .lock()
+        .unwrap()
+        .get(&handle)
+        .cloned()
+        .ok_or_else(|| LightWalletError::InvalidArgument {
+            message: format!("No open wallet with handle {handle}."),
+        })
+}
+
+/// Writes a snapshot of the wallet that a second device sharing its viewing keys can import
+/// with [`import_wallet_state`] to skip scanning the history this wallet already scanned.
+pub fn export_wallet_state(config: DbInit, destination: String) -> Result<(), LightWalletError> {
+    use crate::backing_store::export_state;
+    Ok(export_state(config.data_file, destination)?)
+}
+
+/// Replaces a freshly created wallet with a snapshot written by [`export_wallet_state`].
+pub fn import_wallet_state(config: DbInit, snapshot: String) -> Result<(), LightWalletError> {
+    use crate::backing_store::import_state;
+    RT.block_on(async move {
+        let locks = wallet_locks(&config.data_file);
+        let _sync_lock = locks.sync.lock().await;
+        let _write_lock = locks.write.lock().await;
+        Ok(import_state(
+            config.data_file,
+            config.network.into(),
+            snapshot,
+        )?)
+    })
+}
+
+/// Gets the changes to the wallet since the given height, to share with another device that has the same viewing keys.
+pub fn export_state_diff(
+    config: DbInit,
+    since_height: u32,
+) -> Result<WalletStateDiff, LightWalletError> {
+    use crate::state_diff::export_diff;
+    Ok(export_diff(
+        &config.data_file,
+        config.network.into(),
+        since_height,
+    )?)
+}
+
+/// Applies changes exported by [`export_state_diff`] from another device.
+pub fn import_state_diff(config: DbInit, diff: WalletStateDiff) -> Result<(), LightWalletError> {
+    use crate::state_diff::import_diff;
+    RT.block_on(async move {
+        let locks = wallet_locks(&config.data_file);
+        let _write_lock = locks.write.lock().await;
+        Ok(import_diff(&config.data_file, config.network.into(), diff)?)
+    })
+}
+
 pub fn add_account(
     config: DbInit,
     uri: String,
@@ -292,11 +831,15 @@ pub fn add_account(
     })?;
 
     RT.block_on(async move {
+        let locks = wallet_locks(&config.data_file);
+        let _write_lock = locks.write.lock().await;
         let mut db = Db::load(config.data_file, config.network.into())?;
-        let mut client = get_client(uri.parse()?).await.map_err(Error::from)?;
+        let mut client = get_client(parse_server_uri(&uri)?)
+            .await
+            .map_err(Error::from)?;
         let birthday_height = match birthday_height {
             Some(v) => v,
-            None => get_block_height(uri.parse()?, cancellation_token.0.clone()).await?,
+            None => get_block_height(parse_server_uri(&uri)?, cancellation_token.0.clone()).await?,
         };
         let secret = SecretVec::new(seed);
         let account = db
@@ -318,11 +861,15 @@ pub fn import_account_ufvk(
     let cancellation_token = get_cancellation_token(cancellation)?;
     let network: Network = config.network.into();
     RT.block_on(async move {
+        let locks = wallet_locks(&config.data_file);
+        let _write_lock = locks.write.lock().await;
         let mut db = Db::load(config.data_file, config.network.into())?;
-        let mut client = get_client(uri.parse()?).await.map_err(Error::from)?;
+        let mut client = get_client(parse_server_uri(&uri)?)
+            .await
+            .map_err(Error::from)?;
         let birthday_height = match birthday_height {
             Some(v) => v,
-            None => get_block_height(uri.parse()?, cancellation_token.0.clone()).await?,
+            None => get_block_height(parse_server_uri(&uri)?, cancellation_token.0.clone()).await?,
         };
         let ufvk = UnifiedFullViewingKey::decode(&network, ufvk.as_str()).map_err(|e| {
             LightWalletError::InvalidArgument {
@@ -341,23 +888,83 @@ pub fn import_account_ufvk(
     })
 }
 
+/// The `account_kind` recorded in the accounts table for accounts imported from a UFVK.
+const IMPORTED_ACCOUNT_KIND: u32 = 1;
+
 pub fn get_accounts(config: DbInit) -> Result<Vec<AccountInfo>, LightWalletError> {
-    use crate::analysis::get_birthday_heights;
+    use crate::analysis::get_all_birthday_heights;
 
     let db = Db::load(config.data_file.clone(), config.network.into())?;
+    let conn = open_connection(&config.data_file)?;
     let network: Network = config.network.into();
+    let mut birthday_heights = get_all_birthday_heights(&conn)?;
+    let successors = crate::rotate::get_account_successors(&conn)?;
     let mut result = Vec::new();
     for account_info in db.data.get_unified_full_viewing_keys()?.iter() {
+        let id: u32 = account_info.0.to_owned().into();
+        let (spending_key_available, account_kind, seed_fingerprint, zip32_account_index) = conn
+            .query_row(
+                GET_ACCOUNT_KEY_INFO,
+                named_params! { ":account_id": id },
+                |row| {
+                    Ok((
+                        row.get("has_spend_key")?,
+                        row.get::<_, u32>("account_kind")?,
+                        row.get("hd_seed_fingerprint")?,
+                        row.get("hd_account_index")?,
+                    ))
+                },
+            )?;
         result.push(AccountInfo {
-            id: account_info.0.to_owned().into(),
+            id,
+            uuid: account_uuid(&account_info.1, &network),
             uvk: Some(account_info.1.encode(&network)),
-            birthday_heights: get_birthday_heights(config.clone(), account_info.0.to_owned())?,
+            birthday_heights: birthday_heights
+                .remove(&id)
+                .ok_or_else(|| Error::Internal(format!("No birthday heights for account {id}.")))?,
+            spending_key_available,
+            zip32_account_index,
+            seed_fingerprint,
+            imported: account_kind == IMPORTED_ACCOUNT_KIND,
+            successor_account_id: successors.get(&id).copied(),
         });
     }
 
     Ok(result)
 }
 
+pub fn get_audit_log(config: DbInit) -> Result<Vec<AuditLogEntry>, LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::audit_log::get_audit_log(&conn)?)
+}
+
+pub fn get_account_id(config: DbInit, uuid: Vec<u8>) -> Result<u32, LightWalletError> {
+    let network: Network = config.network.into();
+    let db = Db::load(config.data_file, network)?;
+    Ok(crate::account_uuid::get_account_id(&db, &network, &uuid)?.into())
+}
+
+pub fn get_account_uivk(config: DbInit, account_id: u32) -> Result<String, LightWalletError> {
+    let network: Network = config.network.into();
+    let db = Db::load(config.data_file, network)?;
+    Ok(crate::viewing_keys::get_uivk(&db, account_id.into())?.encode(&network))
+}
+
+pub fn audit_addresses(
+    config: DbInit,
+    account_id: u32,
+) -> Result<Vec<AddressMismatch>, LightWalletError> {
+    let network: Network = config.network.into();
+    let db = Db::load(&config.data_file, network)?;
+    let conn = open_connection(&config.data_file)?;
+    Ok(crate::viewing_keys::audit_addresses(
+        &db,
+        &conn,
+        &network,
+        account_id.into(),
+    )?)
+}
+
 pub fn add_diversifier(
     config: DbInit,
     account: u32,
@@ -365,6 +972,8 @@ pub fn add_diversifier(
 ) -> Result<String, LightWalletError> {
     RT.block_on(async move {
         let network = config.network.into();
+        let locks = wallet_locks(&config.data_file);
+        let _write_lock = locks.write.lock().await;
         let mut db = Db::load(config.data_file, network)?;
         let diversified_index: [u8; 11] =
             diversifier_index
@@ -379,6 +988,68 @@ pub fn add_diversifier(
     })
 }
 
+pub fn allocate_payment_address(
+    config: DbInit,
+    account_id: u32,
+    purpose: String,
+) -> Result<AddressAllocation, LightWalletError> {
+    RT.block_on(async move {
+        let network = config.network.into();
+        let locks = wallet_locks(&config.data_file);
+        let _write_lock = locks.write.lock().await;
+        let mut db = Db::load(config.data_file.clone(), network)?;
+        let conn = open_connection(config.data_file)?;
+        Ok(crate::allocations::allocate_payment_address(
+            &mut db,
+            &conn,
+            &network,
+            account_id.into(),
+            &purpose,
+        )?)
+    })
+}
+
+pub fn create_invoice(
+    config: DbInit,
+    account_id: u32,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    expiry: Option<SystemTime>,
+) -> Result<Invoice, LightWalletError> {
+    RT.block_on(async move {
+        let network = config.network.into();
+        let locks = wallet_locks(&config.data_file);
+        let _write_lock = locks.write.lock().await;
+        let mut db = Db::load(config.data_file.clone(), network)?;
+        let conn = open_connection(config.data_file)?;
+        Ok(crate::invoices::create_invoice(
+            &mut db,
+            &conn,
+            &network,
+            account_id.into(),
+            amount,
+            memo,
+            expiry,
+        )?)
+    })
+}
+
+pub fn get_invoice_status(
+    config: DbInit,
+    invoice_id: u32,
+) -> Result<InvoiceStatus, LightWalletError> {
+    let network: Network = config.network.into();
+    let db = Db::load(config.data_file.clone(), network)?;
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::invoices::get_invoice_status(
+        &db,
+        &conn,
+        &network,
+        config.min_confirmations,
+        invoice_id,
+    )?)
+}
+
 pub fn get_birthday_height(config: DbInit) -> Result<Option<u32>, LightWalletError> {
     RT.block_on(async move {
         let db = Db::load(config.data_file, config.network.into())?;
@@ -391,11 +1062,30 @@ pub fn get_block_height(
     cancellation: Option<Box<dyn CancellationSource>>,
 ) -> Result<u32, LightWalletError> {
     use crate::lightclient::get_block_height;
-    let uri: Uri = uri.parse()?;
+    let uri: Uri = parse_server_uri(&uri)?;
     let cancellation_token = get_cancellation_token(cancellation)?;
     RT.block_on(async move { Ok(get_block_height(uri, cancellation_token.0.clone()).await?) })
 }
 
+pub fn estimate_sync(
+    config: DbInit,
+    uri: String,
+    calibrate: bool,
+) -> Result<SyncEstimate, LightWalletError> {
+    use crate::estimate::estimate_sync;
+    let uri: Uri = parse_server_uri(&uri)?;
+    RT.block_on(async move {
+        Ok(estimate_sync(config.data_file, uri, config.network.into(), calibrate).await?)
+    })
+}
+
+pub fn benchmark_scan(config: DbInit) -> Result<ScanBenchmark, LightWalletError> {
+    Ok(crate::estimate::benchmark_scan(
+        config.data_file,
+        config.network.into(),
+    )?)
+}
+
 pub fn get_sync_height(config: DbInit) -> Result<Option<u32>, LightWalletError> {
     RT.block_on(async move {
         let db = Db::load(config.data_file, config.network.into())?;
@@ -403,44 +1093,246 @@ pub fn get_sync_height(config: DbInit) -> Result<Option<u32>, LightWalletError>
     })
 }
 
+pub fn get_account_sync_state(
+    config: DbInit,
+    account_id: u32,
+) -> Result<AccountSyncState, LightWalletError> {
+    use crate::analysis::get_account_sync_state;
+    Ok(get_account_sync_state(&config, account_id.into())?)
+}
+
+pub fn get_scan_ranges(config: DbInit) -> Result<Vec<ScanRange>, LightWalletError> {
+    use crate::analysis::get_scan_ranges;
+    Ok(get_scan_ranges(&config)?)
+}
+
+/// Syncs a wallet opened with [`open_wallet`].
+pub fn sync_wallet(
+    handle: u32,
+    uri: String,
+    progress: Option<Box<dyn SyncUpdate>>,
+    continually: bool,
+    options: SyncOptions,
+    cancellation: Option<Box<dyn CancellationSource>>,
+) -> Result<SyncUpdateData, LightWalletError> {
+    sync(
+        get_wallet_config(handle)?,
+        uri,
+        progress,
+        continually,
+        options,
+        cancellation,
+    )
+}
+
 pub fn sync(
     config: DbInit,
     uri: String,
     progress: Option<Box<dyn SyncUpdate>>,
     continually: bool,
+    options: SyncOptions,
     cancellation: Option<Box<dyn CancellationSource>>,
 ) -> Result<SyncUpdateData, LightWalletError> {
     use crate::sync::sync;
-    let uri: Uri = uri.parse()?;
+    let uri: Uri = parse_server_uri(&uri)?;
     let cancellation_token = get_cancellation_token(cancellation)?;
     RT.block_on(async move {
+        // Wait for any other sync of this wallet to finish.
+        let locks = wallet_locks(&config.data_file);
+        let _sync_lock = select! {
+            lock = locks.sync.lock() => lock,
+            _ = cancellation_token.0.cancelled() => return Err(LightWalletError::Canceled),
+        };
         Ok(sync(
             uri,
             config.data_file,
             progress,
             config.min_confirmations,
             continually,
+            options,
+            cancellation_token.2.clone(),
             cancellation_token.0.clone(),
         )
         .await?)
     })
 }
 
+pub fn watch_address(
+    config: DbInit,
+    uri: String,
+    address: String,
+    watcher: Box<dyn AddressWatcher>,
+    cancellation: Option<Box<dyn CancellationSource>>,
+) -> Result<(), LightWalletError> {
+    use crate::watch::watch_address;
+    let uri: Uri = parse_server_uri(&uri)?;
+    let network: Network = config.network.into();
+    let address = match Address::decode(&network, &address) {
+        Some(Address::Transparent(t)) => t,
+        Some(Address::Unified(ua)) => {
+            *ua.transparent().ok_or(LightWalletError::InvalidArgument {
+                message: "Only addresses with a transparent receiver can be watched.".to_string(),
+            })?
+        }
+        Some(_) => {
+            return Err(LightWalletError::InvalidArgument {
+                message: "Only addresses with a transparent receiver can be watched.".to_string(),
+            })
+        }
+        None => return Err(Error::InvalidAddress.into()),
+    };
+    let cancellation_token = get_cancellation_token(cancellation)?;
+    RT.block_on(async move {
+        Ok(watch_address(uri, network, address, watcher, cancellation_token.0.clone()).await?)
+    })
+}
+
+/// Gets the wallet's transactions, optionally limited to one account and to a range of blocks.
+///
+/// `starting_block` and `ending_block` are inclusive. Unmined transactions are always included.
 pub fn get_transactions(
     config: DbInit,
-    account_id: u32,
-    starting_block: u32,
+    account_id: Option<u32>,
+    starting_block: Option<u32>,
+    ending_block: Option<u32>,
+    change_notes: ChangeNotes,
 ) -> Result<Vec<Transaction>, LightWalletError> {
     let network: Network = config.network.into();
     let mut db = Db::load(config.data_file.clone(), network)?;
-    let mut conn = Connection::open(config.data_file)?;
-    Ok(crate::sync::get_transactions(
+    let mut conn = open_connection(config.data_file)?;
+    let mut transactions = crate::sync::get_transactions(
         &mut db,
         &mut conn,
         &network,
-        Some(account_id),
-        Some(starting_block),
-        None,
+        account_id,
+        starting_block,
+        ending_block,
+    )?;
+    crate::sync::present_change_notes(&mut transactions, change_notes);
+    Ok(transactions)
+}
+
+pub fn get_incoming_payments(
+    config: DbInit,
+    filter: IncomingPaymentsFilter,
+) -> Result<IncomingPaymentsPage, LightWalletError> {
+    let network: Network = config.network.into();
+    let db = Db::load(config.data_file.clone(), network)?;
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::payments::get_incoming_payments(
+        &db,
+        &conn,
+        &network,
+        config.min_confirmations,
+        &filter,
+    )?)
+}
+
+pub fn find_transactions_by_memo(
+    config: DbInit,
+    account_id: Option<u32>,
+    memo_data: Vec<u8>,
+    prefix: bool,
+) -> Result<Vec<MemoMatch>, LightWalletError> {
+    let mut conn = open_connection(config.data_file)?;
+    Ok(crate::memo_index::find_by_memo(
+        &mut conn, account_id, &memo_data, prefix,
+    )?)
+}
+
+pub fn set_transaction_label(
+    config: DbInit,
+    txid: Vec<u8>,
+    label: Option<String>,
+) -> Result<(), LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::annotations::set_transaction_label(
+        &conn,
+        &txid,
+        label.as_deref(),
+    )?)
+}
+
+pub fn verify_transaction_inclusion(
+    config: DbInit,
+    uri: String,
+    txid: Vec<u8>,
+) -> Result<InclusionVerification, LightWalletError> {
+    let uri = parse_server_uri(&uri)?;
+    let txid =
+        TxId::from_bytes(
+            txid.try_into()
+                .map_err(|_| LightWalletError::InvalidArgument {
+                    message: "A transaction ID is 32 bytes.".to_string(),
+                })?,
+        );
+    let min_confirmations = NonZeroU32::try_from(config.min_confirmations).map_err(|_| {
+        LightWalletError::InvalidArgument {
+            message: "A positive integer is required.".to_string(),
+        }
+    })?;
+    RT.block_on(async move {
+        let mut db = Db::load(&config.data_file, config.network.into())?;
+        let conn = open_connection(&config.data_file)?;
+        Ok(
+            crate::inclusion::verify_inclusion(&mut db, &conn, uri, txid, min_confirmations)
+                .await?,
+        )
+    })
+}
+
+pub fn create_tag(config: DbInit, name: String) -> Result<Tag, LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::annotations::create_tag(&conn, &name)?)
+}
+
+pub fn get_tags(config: DbInit) -> Result<Vec<Tag>, LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::annotations::get_tags(&conn)?)
+}
+
+pub fn rename_tag(config: DbInit, tag_id: u32, name: String) -> Result<(), LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::annotations::rename_tag(&conn, tag_id, &name)?)
+}
+
+pub fn delete_tag(config: DbInit, tag_id: u32) -> Result<(), LightWalletError> {
+    let mut conn = open_connection(config.data_file)?;
+    Ok(crate::annotations::delete_tag(&mut conn, tag_id)?)
+}
+
+pub fn add_transaction_tag(
+    config: DbInit,
+    txid: Vec<u8>,
+    tag_id: u32,
+) -> Result<(), LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::annotations::add_transaction_tag(
+        &conn, &txid, tag_id,
+    )?)
+}
+
+pub fn remove_transaction_tag(
+    config: DbInit,
+    txid: Vec<u8>,
+    tag_id: u32,
+) -> Result<(), LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::annotations::remove_transaction_tag(
+        &conn, &txid, tag_id,
+    )?)
+}
+
+pub fn get_transactions_by_tag(
+    config: DbInit,
+    account_id: u32,
+    tag_id: u32,
+) -> Result<Vec<Transaction>, LightWalletError> {
+    let network: Network = config.network.into();
+    let mut db = Db::load(config.data_file.clone(), network)?;
+    let mut conn = open_connection(config.data_file)?;
+    Ok(crate::annotations::get_transactions_by_tag(
+        &mut db, &mut conn, &network, account_id, tag_id,
     )?)
 }
 
@@ -466,8 +1358,116 @@ pub fn get_user_balances(
     )?)
 }
 
+pub fn get_wallet_summary(config: DbInit) -> Result<Option<WalletSummary>, LightWalletError> {
+    use crate::analysis::get_wallet_summary;
+    Ok(get_wallet_summary(&config)?)
+}
+
+pub fn get_tax_report(
+    config: DbInit,
+    account_id: u32,
+    year: i32,
+    method: CostBasisMethod,
+    currency: String,
+) -> Result<TaxReport, LightWalletError> {
+    use crate::reporting::get_tax_report;
+    Ok(get_tax_report(config, account_id, year, method, &currency)?)
+}
+
+pub fn get_fee_stats(
+    config: DbInit,
+    account_id: u32,
+    window: TimeWindow,
+) -> Result<FeeStats, LightWalletError> {
+    use crate::reporting::get_fee_stats;
+    Ok(get_fee_stats(config, account_id, window)?)
+}
+
+pub fn get_user_balances_fiat(
+    config: DbInit,
+    account_id: u32,
+    currency: String,
+) -> Result<FiatUserBalances, LightWalletError> {
+    use crate::prices::get_user_balances_fiat;
+    Ok(get_user_balances_fiat(
+        &config,
+        account_id.into(),
+        NonZeroU32::try_from(config.min_confirmations)
+            .map_err(|_| Error::InvalidArgument("A positive integer is required.".to_string()))?,
+        &currency,
+    )?)
+}
+
+pub fn set_prices(
+    config: DbInit,
+    currency: String,
+    prices: Vec<PricePoint>,
+) -> Result<(), LightWalletError> {
+    let mut conn = open_connection(config.data_file)?;
+    Ok(crate::prices::set_prices(&mut conn, &currency, &prices)?)
+}
+
+pub fn get_prices(
+    config: DbInit,
+    currency: String,
+    window: TimeWindow,
+) -> Result<Vec<PricePoint>, LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::prices::get_prices(&conn, &currency, &window)?)
+}
+
+pub fn backfill_prices(
+    config: DbInit,
+    currency: String,
+    provider: Box<dyn PriceProvider>,
+) -> Result<u32, LightWalletError> {
+    let mut conn = open_connection(config.data_file)?;
+    Ok(crate::prices::backfill_prices(
+        &mut conn,
+        &currency,
+        provider.as_ref(),
+    )?)
+}
+
+pub fn set_connection_options(options: ConnectionOptions) {
+    crate::grpc::set_connection_options(options)
+}
+
+pub fn set_name_resolver(resolver: Option<Box<dyn NameResolver>>) {
+    crate::grpc::set_name_resolver(resolver)
+}
+
+pub fn set_spend_approver(approver: Option<Box<dyn SpendApprover>>) {
+    crate::spending_policy::set_spend_approver(approver)
+}
+
+pub fn set_spending_policy(
+    config: DbInit,
+    account_id: u32,
+    policy: SpendingPolicy,
+) -> Result<(), LightWalletError> {
+    let mut conn = open_connection(config.data_file)?;
+    Ok(crate::spending_policy::set_spending_policy(
+        &mut conn,
+        &config.network.into(),
+        account_id.into(),
+        &policy,
+    )?)
+}
+
+pub fn get_spending_policy(
+    config: DbInit,
+    account_id: u32,
+) -> Result<SpendingPolicy, LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::spending_policy::get_spending_policy(
+        &conn,
+        account_id.into(),
+    )?)
+}
+
 pub fn disconnect_server(uri: String) -> Result<bool, LightWalletError> {
-    let uri: Uri = uri.parse()?;
+    let uri: Uri = parse_server_uri(&uri)?;
     RT.block_on(async move { Ok(destroy_channel(uri)) })
 }
 
@@ -483,6 +1483,7 @@ pub fn simulate_send(
     config: DbInit,
     ufvk: String,
     send_details: Vec<TransactionSendDetail>,
+    options: SendOptions,
 ) -> Result<SendDetails, LightWalletError> {
     let network = config.network.into();
     let mut db = Db::init(config.data_file, network)?;
@@ -493,7 +1494,14 @@ pub fn simulate_send(
             message: "A positive integer is required.".to_string(),
         }
     })?;
-    let proposal = create_send_proposal(&mut db, network, &ufvk, min_confirmations, send_details)?;
+    let proposal = create_send_proposal(
+        &mut db,
+        network,
+        &ufvk,
+        min_confirmations,
+        send_details,
+        &options,
+    )?;
 
     Ok(SendDetails {
         fee: proposal
@@ -504,19 +1512,29 @@ pub fn simulate_send(
     })
 }
 
+/// Sends from a wallet opened with [`open_wallet`].
+pub fn send_from_wallet(
+    handle: u32,
+    uri: String,
+    usk: Vec<u8>,
+    send_details: Vec<TransactionSendDetail>,
+    options: SendOptions,
+) -> Result<Vec<SendTransactionResult>, LightWalletError> {
+    send(get_wallet_config(handle)?, uri, usk, send_details, options)
+}
+
 pub fn send(
     config: DbInit,
     uri: String,
     usk: Vec<u8>,
     send_details: Vec<TransactionSendDetail>,
+    options: SendOptions,
 ) -> Result<Vec<SendTransactionResult>, LightWalletError> {
-    let uri: Uri = uri.parse()?;
-    let usk = UnifiedSpendingKey::from_bytes(Era::Orchard, &usk).map_err(|_| {
-        LightWalletError::InvalidArgument {
-            message: "Failure when parsing USK.".to_string(),
-        }
-    })?;
+    let uri: Uri = parse_server_uri(&uri)?;
+    let usk = parse_usk(usk)?;
     RT.block_on(async move {
+        let locks = wallet_locks(&config.data_file);
+        let _write_lock = locks.write.lock().await;
         let result = send_transaction(
             config.data_file,
             uri,
@@ -526,6 +1544,7 @@ pub fn send(
                 Error::InvalidArgument("A positive integer is required.".to_string())
             })?,
             send_details,
+            &options,
         )
         .await?;
         Ok(result
@@ -537,6 +1556,274 @@ pub fn send(
     })
 }
 
+pub fn enqueue_send(
+    config: DbInit,
+    account_id: u32,
+    send_details: Vec<TransactionSendDetail>,
+) -> Result<Vec<u32>, LightWalletError> {
+    let mut conn = open_connection(config.data_file)?;
+    Ok(crate::send_queue::enqueue_send(
+        &mut conn,
+        account_id.into(),
+        &send_details,
+    )?)
+}
+
+pub fn get_send_queue(
+    config: DbInit,
+    account_id: u32,
+) -> Result<Vec<QueuedPayment>, LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::send_queue::get_send_queue(&conn, account_id.into())?)
+}
+
+pub fn cancel_queued_send(config: DbInit, payment_id: u32) -> Result<bool, LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::send_queue::cancel_queued_send(&conn, payment_id)?)
+}
+
+pub fn process_send_queue(
+    config: DbInit,
+    uri: String,
+    usk: Vec<u8>,
+    options: SendOptions,
+) -> Result<SendQueueResult, LightWalletError> {
+    let uri: Uri = parse_server_uri(&uri)?;
+    let usk = parse_usk(usk)?;
+    RT.block_on(async move {
+        let locks = wallet_locks(&config.data_file);
+        let _write_lock = locks.write.lock().await;
+        Ok(crate::send_queue::process_send_queue(
+            &config,
+            uri,
+            &usk,
+            NonZeroU32::try_from(config.min_confirmations).map_err(|_| {
+                Error::InvalidArgument("A positive integer is required.".to_string())
+            })?,
+            &options,
+        )
+        .await?)
+    })
+}
+
+pub fn create_recurring_payment(
+    config: DbInit,
+    account_id: u32,
+    send_detail: TransactionSendDetail,
+    start: SystemTime,
+    interval_seconds: u64,
+    end: Option<SystemTime>,
+) -> Result<RecurringPayment, LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::recurring::create_recurring_payment(
+        &conn,
+        account_id.into(),
+        &send_detail,
+        start,
+        interval_seconds,
+        end,
+    )?)
+}
+
+pub fn get_recurring_payments(
+    config: DbInit,
+    account_id: u32,
+) -> Result<Vec<RecurringPayment>, LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::recurring::get_recurring_payments(
+        &conn,
+        account_id.into(),
+    )?)
+}
+
+pub fn cancel_recurring_payment(config: DbInit, id: u32) -> Result<bool, LightWalletError> {
+    let conn = open_connection(config.data_file)?;
+    Ok(crate::recurring::cancel_recurring_payment(&conn, id)?)
+}
+
+pub fn migrate_to_orchard(
+    config: DbInit,
+    uri: String,
+    usk: Vec<u8>,
+    account_id: u32,
+    progress: Option<Box<dyn MigrationProgress>>,
+) -> Result<Vec<SendTransactionResult>, LightWalletError> {
+    use crate::migrate::migrate_to_orchard;
+    let uri: Uri = parse_server_uri(&uri)?;
+    let usk = parse_usk(usk)?;
+    let min_confirmations = NonZeroU32::try_from(config.min_confirmations).map_err(|_| {
+        LightWalletError::InvalidArgument {
+            message: "A positive integer is required.".to_string(),
+        }
+    })?;
+    RT.block_on(async move {
+        let locks = wallet_locks(&config.data_file);
+        let _write_lock = locks.write.lock().await;
+        Ok(migrate_to_orchard(
+            config.data_file,
+            uri,
+            config.network.into(),
+            &usk,
+            account_id.into(),
+            min_confirmations,
+            progress.as_deref(),
+        )
+        .await?
+        .into_iter()
+        .map(|r| SendTransactionResult {
+            txid: r.txid.as_ref().to_vec(),
+        })
+        .collect())
+    })
+}
+
+pub fn rotate_to_new_seed(
+    config: DbInit,
+    uri: String,
+    old_usk: Vec<u8>,
+    new_seed: Vec<u8>,
+    birthday_height: Option<u32>,
+    progress: Option<Box<dyn MigrationProgress>>,
+) -> Result<SeedRotation, LightWalletError> {
+    use crate::lightclient::get_block_height;
+    use crate::rotate::rotate_to_new_seed;
+    let uri: Uri = parse_server_uri(&uri)?;
+    let old_usk = parse_usk(old_usk)?;
+    let min_confirmations = NonZeroU32::try_from(config.min_confirmations).map_err(|_| {
+        LightWalletError::InvalidArgument {
+            message: "A positive integer is required.".to_string(),
+        }
+    })?;
+    RT.block_on(async move {
+        let locks = wallet_locks(&config.data_file);
+        let _write_lock = locks.write.lock().await;
+        let birthday_height = match birthday_height {
+            Some(v) => v,
+            None => get_block_height(uri.clone(), CancellationToken::new()).await?,
+        };
+        Ok(rotate_to_new_seed(
+            config.data_file,
+            uri,
+            config.network.into(),
+            &old_usk,
+            &SecretVec::new(new_seed),
+            birthday_height,
+            min_confirmations,
+            progress.as_deref(),
+        )
+        .await?)
+    })
+}
+
+pub fn decode_transaction(
+    network: ChainType,
+    data: Vec<u8>,
+) -> Result<DecodedTransaction, LightWalletError> {
+    use crate::decode::decode_transaction;
+    Ok(decode_transaction(&network.into(), &data)?)
+}
+
+pub fn format_zec(zatoshis: i64) -> String {
+    crate::amount::format_zec(zatoshis)
+}
+
+pub fn parse_zec(value: String) -> Result<u64, LightWalletError> {
+    Ok(crate::amount::parse_zec(&value)?)
+}
+
+pub fn derive_key_along_path(
+    seed: Vec<u8>,
+    pool: Pool,
+    path: Vec<u32>,
+) -> Result<Vec<u8>, LightWalletError> {
+    let seed = SecretVec::new(seed);
+    Ok(crate::derivation::derive_key_along_path(
+        seed.expose_secret(),
+        pool,
+        &path,
+    )?)
+}
+
+pub fn build_ufvk(
+    network: ChainType,
+    transparent: Option<Vec<u8>>,
+    sapling: Option<Vec<u8>>,
+    orchard: Option<Vec<u8>>,
+) -> Result<String, LightWalletError> {
+    let ufvk = crate::viewing_keys::build_ufvk(
+        transparent.as_deref(),
+        sapling.as_deref(),
+        orchard.as_deref(),
+    )?;
+    Ok(ufvk.encode(&network.into()))
+}
+
+pub fn decrypt_diversifier(
+    network: ChainType,
+    viewing_key: String,
+    address: String,
+) -> Result<Option<AddressOwnership>, LightWalletError> {
+    Ok(crate::viewing_keys::decrypt_diversifier(
+        &network.into(),
+        &viewing_key,
+        &address,
+    )?)
+}
+
+/// Signs a message to prove that the signer can spend funds sent to a sapling address.
+///
+/// As with [`parse_usk`], the key's bytes are zeroized once parsed, but the parsed key is merely dropped.
+pub fn sign_message_with_sapling_address(
+    network: ChainType,
+    expanded_spending_key: Vec<u8>,
+    address: String,
+    message: Vec<u8>,
+) -> Result<Vec<u8>, LightWalletError> {
+    use crate::sapling::sign_message;
+    let expanded_spending_key = Zeroizing::new(expanded_spending_key);
+    let expsk = ExpandedSpendingKey::from_bytes(&expanded_spending_key).map_err(|_| {
+        LightWalletError::InvalidArgument {
+            message: "Invalid expanded spending key.".to_string(),
+        }
+    })?;
+    let address = decode_sapling_address(&network.into(), &address)?;
+    Ok(sign_message(&expsk, &address, &message)?.to_vec())
+}
+
+/// Verifies a signature produced by [`sign_message_with_sapling_address`].
+pub fn verify_message(
+    network: ChainType,
+    address: String,
+    message: Vec<u8>,
+    signature: Vec<u8>,
+) -> Result<bool, LightWalletError> {
+    let address = decode_sapling_address(&network.into(), &address)?;
+    Ok(crate::sapling::verify_message(
+        &address, &message, &signature,
+    )?)
+}
+
+/// Decodes a sapling address, or the sapling receiver of a unified address.
+fn decode_sapling_address(
+    network: &Network,
+    address: &str,
+) -> Result<PaymentAddress, LightWalletError> {
+    match Address::decode(network, address) {
+        Some(Address::Sapling(a)) => Ok(a),
+        Some(Address::Unified(ua)) => {
+            ua.sapling()
+                .copied()
+                .ok_or(LightWalletError::InvalidArgument {
+                    message: "The address has no sapling receiver.".to_string(),
+                })
+        }
+        Some(_) => Err(LightWalletError::InvalidArgument {
+            message: "The address has no sapling receiver.".to_string(),
+        }),
+        None => Err(Error::InvalidAddress.into()),
+    }
+}
+
 pub fn get_unshielded_utxos(
     config: DbInit,
     account_id: u32,
@@ -550,26 +1837,32 @@ pub fn shield(
     uri: String,
     usk: Vec<u8>,
     address: String,
+    min_confirmations: u32,
+    memo: Option<Vec<u8>>,
 ) -> Result<Vec<SendTransactionResult>, LightWalletError> {
-    let uri: Uri = uri.parse()?;
-    let usk = UnifiedSpendingKey::from_bytes(Era::Orchard, &usk).map_err(|_| {
-        LightWalletError::InvalidArgument {
-            message: "Failure when parsing USK.".to_string(),
-        }
-    })?;
+    let uri: Uri = parse_server_uri(&uri)?;
+    let usk = parse_usk(usk)?;
     let network = Network::from(config.network);
     let address =
         TransparentAddress::decode(&network, &address[..]).map_err(|_| Error::InvalidAddress)?;
     RT.block_on(async move {
-        Ok(
-            shield_funds_at_address(config.data_file, uri, network, &usk, address)
-                .await?
-                .map(|r| SendTransactionResult {
-                    txid: r.txid.as_ref().to_vec(),
-                })
-                .into_iter()
-                .collect::<Vec<_>>(),
+        let locks = wallet_locks(&config.data_file);
+        let _write_lock = locks.write.lock().await;
+        Ok(shield_funds_at_address(
+            config.data_file,
+            uri,
+            network,
+            &usk,
+            address,
+            min_confirmations,
+            memo,
         )
+        .await?
+        .map(|r| SendTransactionResult {
+            txid: r.txid.as_ref().to_vec(),
+        })
+        .into_iter()
+        .collect::<Vec<_>>())
     })
 }
 
@@ -583,11 +1876,91 @@ mod tests {
         static ref LIGHTSERVER_URI: Uri = crate::test_constants::TESTNET_LIGHTSERVER_URI.to_owned();
     }
 
+    #[test]
+    fn test_parse_usk() {
+        let usk =
+            UnifiedSpendingKey::from_seed(&Network::TestNetwork, &[1; 32], zip32::AccountId::ZERO)
+                .unwrap();
+        let parsed = parse_usk(usk.to_bytes(Era::Orchard)).unwrap();
+        assert_eq!(
+            usk.to_unified_full_viewing_key()
+                .encode(&Network::TestNetwork),
+            parsed
+                .to_unified_full_viewing_key()
+                .encode(&Network::TestNetwork)
+        );
+
+        assert!(matches!(
+            parse_usk(vec![0; 10]),
+            Err(LightWalletError::InvalidArgument { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_server_uri() {
+        assert_eq!(
+            Uri::from_static("https://zec.rocks:443"),
+            parse_server_uri("https://zec.rocks:443").unwrap()
+        );
+        assert!(matches!(
+            parse_server_uri("zec.rocks:443"),
+            Err(LightWalletError::InvalidUri)
+        ));
+        assert!(matches!(
+            parse_server_uri("ftp://zec.rocks"),
+            Err(LightWalletError::InvalidUri)
+        ));
+
+        #[cfg(unix)]
+        {
+            let uri = parse_server_uri("unix:///run/lightwalletd.sock").unwrap();
+            assert_eq!(Some(UNIX_SCHEME), uri.scheme_str());
+            assert_eq!("/run/lightwalletd.sock", uri.path());
+        }
+        assert!(matches!(
+            parse_server_uri("unix://run/lightwalletd.sock"),
+            Err(LightWalletError::InvalidArgument { .. })
+        ));
+    }
+
     #[test]
     fn test_get_transactions_empty() {
         let setup = RT.block_on(async move { setup_test().await });
-        let transactions = get_transactions(setup.db_init, 0, 0).unwrap();
+        let transactions =
+            get_transactions(setup.db_init, None, None, None, ChangeNotes::Include).unwrap();
 
         assert!(transactions.is_empty());
     }
+
+    #[test]
+    fn test_get_accounts_spending_key_available() {
+        let mut setup = RT.block_on(async move { setup_test().await });
+        let (_, birthday, _, usk) = RT.block_on(setup.create_account()).unwrap();
+
+        let accounts = get_accounts(setup.db_init.clone()).unwrap();
+        assert_eq!(1, accounts.len());
+        assert!(accounts[0].spending_key_available);
+        assert!(!accounts[0].imported);
+        assert_eq!(Some(0), accounts[0].zip32_account_index);
+        assert_eq!(
+            Some(32),
+            accounts[0].seed_fingerprint.as_ref().map(|f| f.len())
+        );
+
+        let mut view_only = RT.block_on(async move { setup_test().await });
+        RT.block_on(view_only.db.import_account_ufvk(
+            &usk.to_unified_full_viewing_key(),
+            false,
+            birthday,
+            &mut view_only.client,
+        ))
+        .unwrap();
+
+        let accounts = get_accounts(view_only.db_init.clone()).unwrap();
+        assert_eq!(1, accounts.len());
+        assert!(!accounts[0].spending_key_available);
+        assert!(accounts[0].imported);
+        assert_eq!(None, accounts[0].zip32_account_index);
+        assert_eq!(None, accounts[0].seed_fingerprint);
+    }
 }
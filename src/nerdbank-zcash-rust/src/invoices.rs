@@ -0,0 +1,303 @@
+use std::time::SystemTime;
+
+use rusqlite::{named_params, Connection, OptionalExtension, Row};
+use time::OffsetDateTime;
+use zcash_client_backend::{data_api::WalletRead, encoding::AddressCodec, PoolType};
+use zcash_client_sqlite::AccountId;
+use zcash_keys::address::Address;
+use zcash_primitives::consensus::Network;
+
+use crate::{
+    allocations::allocate_payment_address,
+    backing_store::Db,
+    error::Error,
+    sql_statements::{CREATE_INVOICE, GET_INVOICE, GET_PAYMENTS_TO_RECEIVERS},
+    sync::is_change,
+};
+
+/// A request for payment to an address dedicated to it.
+#[derive(Debug, Clone)]
+pub struct Invoice {
+    pub id: u32,
+    pub account_id: u32,
+    /// The unified address allocated to this invoice.
+    pub address: String,
+    /// The little-endian diversifier index of `address`.
+    pub diversifier_index: Vec<u8>,
+    /// The amount requested, in zatoshis.
+    pub amount: u64,
+    /// The memo the payer is asked to include.
+    pub memo: Option<Vec<u8>>,
+    pub created: SystemTime,
+    /// The time after which the invoice is no longer payable, if any.
+    pub expiry: Option<SystemTime>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InvoiceState {
+    /// Less than the requested amount has been received.
+    Unpaid,
+    /// At least the requested amount has been received, but not all of it has enough confirmations.
+    Pending,
+    /// At least the requested amount has been received with enough confirmations.
+    Paid,
+    /// The invoice expired before the requested amount was received.
+    Expired,
+}
+
+/// The payment status of an invoice, based on the payments detected by sync.
+#[derive(Debug, Clone)]
+pub struct InvoiceStatus {
+    pub invoice: Invoice,
+    pub state: InvoiceState,
+    /// The sum of all payments to the invoice's address, including unconfirmed ones.
+    pub received: u64,
+    /// The sum of the payments to the invoice's address that have enough confirmations.
+    pub confirmed: u64,
+}
+
+/// Creates an invoice, allocating a new diversified address of the account for it.
+pub fn create_invoice(
+    db: &mut Db,
+    conn: &Connection,
+    network: &Network,
+    account_id: AccountId,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    expiry: Option<SystemTime>,
+) -> Result<Invoice, Error> {
+    if amount == 0 {
+        return Err(Error::InvalidArgument(
+            "An invoice must request a non-zero amount.".to_string(),
+        ));
+    }
+    if memo.as_ref().is_some_and(|m| m.len() > 512) {
+        return Err(Error::InvalidArgument(
+            "A memo may not exceed 512 bytes.".to_string(),
+        ));
+    }
+
+    let allocation = allocate_payment_address(db, conn, network, account_id, "invoice")?;
+
+    let created = allocation.allocated;
+    let unix_time = |t: SystemTime| OffsetDateTime::from(t).unix_timestamp();
+    conn.execute(
+        CREATE_INVOICE,
+        named_params! {
+            ":account_id": u32::from(account_id),
+            ":address": allocation.address,
+            ":diversifier_index": allocation.diversifier_index,
+            ":amount": amount,
+            ":memo": memo,
+            ":created": unix_time(created),
+            ":expiry": expiry.map(unix_time),
+        },
+    )?;
+
+    get_invoice(conn, conn.last_insert_rowid() as u32)
+}
+
+/// Gets an invoice and works out whether it has been paid.
+pub fn get_invoice_status(
+    db: &Db,
+    conn: &Connection,
+    network: &Network,
+    min_confirmations: u32,
+    invoice_id: u32,
+) -> Result<InvoiceStatus, Error> {
+    let invoice = get_invoice(conn, invoice_id)?;
+    let ua = match Address::decode(network, &invoice.address) {
+        Some(Address::Unified(ua)) => ua,
+        _ => {
+            return Err(Error::Internal(format!(
+                "Invoice {invoice_id} has an invalid address."
+            )))
+        }
+    };
+
+    let tip = db.data.chain_height()?.map(u32::from);
+    let (mut received, mut confirmed) = (0, 0);
+    let mut stmt = conn.prepare(GET_PAYMENTS_TO_RECEIVERS)?;
+    let mut rows = stmt.query(named_params! {
+        ":account_id": invoice.account_id,
+        ":transparent": ua.transparent().map(|t| t.encode(network)),
+        ":sapling": ua.sapling().map(|s| s.diversifier().0.to_vec()),
+        ":orchard": ua.orchard().map(|o| o.diversifier().as_array().to_vec()),
+    })?;
+    while let Some(row) = rows.next()? {
+        let output_pool = match row.get::<_, u32>("output_pool")? {
+            0 => PoolType::Transparent,
+            2 => PoolType::SAPLING,
+            _ => PoolType::ORCHARD,
+        };
+        let memo: Option<Vec<u8>> = row.get("memo")?;
+        if is_change(
+            row.get("from_account_id")?,
+            Some(invoice.account_id),
+            output_pool,
+            &memo.unwrap_or_default(),
+        ) {
+            continue;
+        }
+
+        let value: u64 = row.get("value")?;
+        let mined_height: Option<u32> = row.get("mined_height")?;
+        let confirmations = match (mined_height, tip) {
+            (Some(h), Some(tip)) if tip >= h => tip - h + 1,
+            _ => 0,
+        };
+        received += value;
+        if confirmations >= min_confirmations.max(1) {
+            confirmed += value;
+        }
+    }
+
+    let state = invoice_state(&invoice, received, confirmed, SystemTime::now());
+    Ok(InvoiceStatus {
+        invoice,
+        state,
+        received,
+        confirmed,
+    })
+}
+
+fn invoice_state(
+    invoice: &Invoice,
+    received: u64,
+    confirmed: u64,
+    now: SystemTime,
+) -> InvoiceState {
+    if confirmed >= invoice.amount {
+        InvoiceState::Paid
+    } else if received >= invoice.amount {
+        InvoiceState::Pending
+    } else if invoice.expiry.is_some_and(|e| now > e) {
+        InvoiceState::Expired
+    } else {
+        InvoiceState::Unpaid
+    }
+}
+
+fn get_invoice(conn: &Connection, invoice_id: u32) -> Result<Invoice, Error> {
+    conn.query_row(
+        GET_INVOICE,
+        named_params! { ":id": invoice_id },
+        read_invoice,
+    )
+    .optional()?
+    .ok_or_else(|| Error::InvalidArgument(format!("No invoice has the ID {invoice_id}.")))?
+}
+
+fn read_invoice(row: &Row) -> rusqlite::Result<Result<Invoice, Error>> {
+    let to_time = |t: i64| {
+        OffsetDateTime::from_unix_timestamp(t)
+            .map(SystemTime::from)
+            .map_err(|e| Error::Internal(format!("Invalid invoice time: {}", e)))
+    };
+    let created: i64 = row.get("created")?;
+    let expiry: Option<i64> = row.get("expiry")?;
+    let id = row.get("id")?;
+    let account_id = row.get("account_id")?;
+    let address = row.get("address")?;
+    let diversifier_index = row.get("diversifier_index")?;
+    let amount = row.get("amount")?;
+    let memo = row.get("memo")?;
+
+    Ok((|| {
+        Ok(Invoice {
+            id,
+            account_id,
+            address,
+            diversifier_index,
+            amount,
+            memo,
+            created: to_time(created)?,
+            expiry: expiry.map(to_time).transpose()?,
+        })
+    })())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{backing_store::open_connection, test_constants::setup_local_test};
+
+    use super::*;
+
+    #[test]
+    fn test_create_invoice() {
+        let mut setup = setup_local_test();
+        let (_, _, account_id, _) = setup.create_account().unwrap();
+        let conn = open_connection(&setup.data_file).unwrap();
+
+        let first = create_invoice(
+            &mut setup.db,
+            &conn,
+            &setup.network,
+            account_id,
+            100_000,
+            Some(b"Order 1".to_vec()),
+            None,
+        )
+        .unwrap();
+        let second = create_invoice(
+            &mut setup.db,
+            &conn,
+            &setup.network,
+            account_id,
+            200_000,
+            None,
+            Some(SystemTime::now() - Duration::from_secs(60)),
+        )
+        .unwrap();
+        assert_ne!(first.address, second.address);
+        assert_ne!(first.id, second.id);
+        assert_eq!(Some(b"Order 1".to_vec()), first.memo);
+
+        let status = get_invoice_status(&setup.db, &conn, &setup.network, 1, first.id).unwrap();
+        assert_eq!(InvoiceState::Unpaid, status.state);
+        assert_eq!(0, status.received);
+        assert_eq!(first.address, status.invoice.address);
+
+        let status = get_invoice_status(&setup.db, &conn, &setup.network, 1, second.id).unwrap();
+        assert_eq!(InvoiceState::Expired, status.state);
+
+        assert!(get_invoice_status(&setup.db, &conn, &setup.network, 1, 1000).is_err());
+        assert!(create_invoice(
+            &mut setup.db,
+            &conn,
+            &setup.network,
+            account_id,
+            0,
+            None,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_invoice_state() {
+        let now = SystemTime::now();
+        let invoice = Invoice {
+            id: 1,
+            account_id: 0,
+            address: String::new(),
+            diversifier_index: vec![0; 11],
+            amount: 100,
+            memo: None,
+            created: now,
+            expiry: Some(now + Duration::from_secs(60)),
+        };
+        assert_eq!(InvoiceState::Unpaid, invoice_state(&invoice, 50, 50, now));
+        assert_eq!(InvoiceState::Pending, invoice_state(&invoice, 100, 50, now));
+        assert_eq!(InvoiceState::Paid, invoice_state(&invoice, 150, 100, now));
+
+        let later = now + Duration::from_secs(120);
+        assert_eq!(InvoiceState::Expired, invoice_state(&invoice, 50, 0, later));
+        assert_eq!(
+            InvoiceState::Pending,
+            invoice_state(&invoice, 100, 0, later)
+        );
+    }
+}
@@ -3,31 +3,104 @@ uniffi::include_scaffolding!("ffi");
 #[macro_use]
 extern crate lazy_static;
 
+mod account_uuid;
+mod allocations;
+mod amount;
 mod analysis;
+mod annotations;
+mod audit_log;
 mod backing_store;
 mod block_source;
+mod chunk_journal;
+mod chunk_sizer;
+mod decode;
+mod derivation;
 mod error;
+mod estimate;
 mod grpc;
+mod inclusion;
+mod input_selection;
 mod interop;
+mod invoices;
 mod lightclient;
+mod memo_fields;
+mod memo_index;
+mod migrate;
+#[cfg(any(test, feature = "mock-lightwalletd"))]
+pub mod mock_lightwalletd;
 mod orchard;
+mod pause;
+mod payments;
+mod prices;
 mod prover;
+mod recurring;
+#[cfg(feature = "regtest")]
+pub mod regtest;
+mod reporting;
 mod resilience;
+mod rotate;
 mod sapling;
 mod send;
+mod send_queue;
 mod shield;
+mod spending_policy;
 mod sql_statements;
+mod state_diff;
 mod sync;
+mod tree_states;
+mod viewing_keys;
+mod watch;
 
 #[cfg(test)]
 mod test_constants;
 
-use analysis::{BirthdayHeights, UserBalances};
+use allocations::AddressAllocation;
+use analysis::{
+    AccountBalance, AccountSyncState, BirthdayHeights, MaturityEvent, PoolBalance, ProgressRatio,
+    UserBalances, WalletSummary,
+};
+use annotations::Tag;
+use audit_log::{AuditLogEntry, AuditOperation};
+use decode::{DecodedTransaction, DecodedTransparentInput, DecodedTransparentOutput};
+use estimate::{ScanBenchmark, SyncEstimate};
+use inclusion::{InclusionVerification, NoteInclusion};
 use interop::{
-    add_account, add_diversifier, cancel, disconnect_server, get_accounts, get_birthday_height,
-    get_birthday_heights, get_block_height, get_sync_height, get_transactions,
-    get_unshielded_utxos, get_user_balances, import_account_ufvk, init, send, shield,
-    simulate_send, sync, AccountInfo, CancellationSource, ChainType, DbInit, LightWalletError,
-    Pool, SendDetails, SendTransactionResult, SyncUpdate, SyncUpdateData, Transaction,
-    TransactionNote, TransactionSendDetail, TransparentNote,
+    add_account, add_diversifier, add_transaction_tag, allocate_payment_address, audit_addresses,
+    backfill_prices, benchmark_scan, build_ufvk, cancel, cancel_queued_send,
+    cancel_recurring_payment, close_wallet, create_invoice, create_recurring_payment, create_tag,
+    decode_transaction, decrypt_diversifier, delete_tag, derive_key_along_path, disconnect_server,
+    enqueue_send, estimate_sync, export_state_diff, export_wallet_state, find_transactions_by_memo,
+    format_zec, get_account_id, get_account_sync_state, get_account_uivk, get_accounts,
+    get_audit_log, get_birthday_height, get_birthday_heights, get_block_height, get_fee_stats,
+    get_incoming_payments, get_invoice_status, get_prices, get_recurring_payments, get_scan_ranges,
+    get_send_queue, get_spending_policy, get_sync_height, get_tags, get_tax_report,
+    get_transactions, get_transactions_by_tag, get_unshielded_utxos, get_user_balances,
+    get_user_balances_fiat, get_wallet_config, get_wallet_summary, import_account_ufvk,
+    import_state_diff, import_wallet_state, init, migrate_to_orchard, open_wallet, parse_zec,
+    pause, process_send_queue, remove_transaction_tag, rename_tag, resume, rotate_to_new_seed,
+    send, send_from_wallet, set_connection_options, set_name_resolver, set_prices,
+    set_spend_approver, set_spending_policy, set_transaction_label, shield,
+    sign_message_with_sapling_address, simulate_send, sync, sync_wallet, verify_message,
+    verify_transaction_inclusion, watch_address, AccountInfo, AddressWatcher, CancellationSource,
+    ChainType, ChangeNotes, ConnectionOptions, DbInit, IpPreference, LightWalletError,
+    MigrationProgress, NameResolver, NoteSelectionStrategy, Pool, PriceProvider, PrivacyPolicy,
+    RemainingBlocks, ScanPriority, ScanRange, ScanRangePhase, SendDetails, SendOptions,
+    SendTransactionResult, SpendApprover, SyncMode, SyncOptions, SyncUpdate, SyncUpdateData,
+    Transaction, TransactionNote, TransactionSendDetail, TransparentInput, TransparentNote,
+    TransparentOutput,
+};
+use invoices::{Invoice, InvoiceState, InvoiceStatus};
+use memo_index::MemoMatch;
+use payments::{
+    IncomingPayment, IncomingPaymentsCursor, IncomingPaymentsFilter, IncomingPaymentsPage,
+};
+use prices::FiatUserBalances;
+use recurring::RecurringPayment;
+use reporting::{
+    CostBasisMethod, FeeStats, MonthlyFees, PricePoint, TaxReport, TaxReportEntry, TimeWindow,
 };
+use rotate::SeedRotation;
+use send_queue::{QueuedPayment, SendQueueResult};
+use spending_policy::SpendingPolicy;
+use state_diff::{DiffAddress, DiffNote, DiffTransaction, WalletStateDiff};
+use viewing_keys::{AddressMismatch, AddressOwnership, KeyScope};
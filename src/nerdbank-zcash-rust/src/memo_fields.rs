@@ -0,0 +1,139 @@
+use zcash_client_backend::zip321::TransactionRequest;
+use zcash_keys::address::Address;
+use zcash_primitives::{consensus::Network, memo::Memo};
+
+/// The prefix of a line (or a line of its own) that introduces the address to reply to.
+/// Several wallets append this to the memos they send, followed by the sender's address.
+const REPLY_TO_PREFIX: &str = "reply-to:";
+
+/// The scheme of a ZIP-321 payment request URI.
+const ZIP321_SCHEME: &str = "zcash:";
+
+/// The conventional structures found in a text memo.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoFields {
+    /// The address the sender asked to be replied (or refunded) to.
+    pub reply_to: Option<String>,
+    /// The label of a ZIP-321 payment request included in the memo.
+    pub label: Option<String>,
+    /// The message of a ZIP-321 payment request included in the memo.
+    pub message: Option<String>,
+}
+
+/// Looks for a reply-to address and a ZIP-321 payment request in a memo.
+///
+/// Two conventions are recognized:
+/// * A `Reply-To:` line, with the address after the colon or on the following line.
+/// * A `zcash:` payment request URI, whose label and message are taken, and whose address is used
+///   as the reply-to address when no `Reply-To:` line is present.
+///
+/// Only addresses that are valid on the given network are returned.
+pub fn parse_memo(network: &Network, memo: &[u8]) -> MemoFields {
+    let text = match Memo::from_bytes(memo) {
+        Ok(Memo::Text(text)) => text,
+        _ => return MemoFields::default(),
+    };
+
+    let mut fields = MemoFields::default();
+    let mut lines = text.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        if fields.reply_to.is_none() && starts_with_ignore_case(line, REPLY_TO_PREFIX) {
+            let rest = line[REPLY_TO_PREFIX.len()..].trim();
+            let candidate = if rest.is_empty() {
+                lines.next().unwrap_or_default()
+            } else {
+                rest
+            };
+            fields.reply_to = valid_address(network, candidate);
+        } else if fields.label.is_none()
+            && fields.message.is_none()
+            && starts_with_ignore_case(line, ZIP321_SCHEME)
+        {
+            if let Ok(request) = TransactionRequest::from_uri(line) {
+                if let Some(payment) = request.payments().values().next() {
+                    fields.label = payment.label().cloned();
+                    fields.message = payment.message().cloned();
+                    if fields.reply_to.is_none() {
+                        fields.reply_to =
+                            valid_address(network, &payment.recipient_address().encode());
+                    }
+                }
+            }
+        }
+    }
+
+    fields
+}
+
+fn starts_with_ignore_case(line: &str, prefix: &str) -> bool {
+    line.get(..prefix.len())
+        .is_some_and(|p| p.eq_ignore_ascii_case(prefix))
+}
+
+fn valid_address(network: &Network, address: &str) -> Option<String> {
+    Address::decode(network, address).map(|_| address.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use zcash_client_backend::keys::UnifiedSpendingKey;
+
+    use super::*;
+
+    fn address(network: &Network) -> String {
+        let usk = UnifiedSpendingKey::from_seed(network, &[0; 32], zip32::AccountId::ZERO).unwrap();
+        let (_, address) = usk
+            .to_unified_full_viewing_key()
+            .sapling()
+            .unwrap()
+            .default_address();
+        Address::Sapling(address).encode(network)
+    }
+
+    fn memo(text: &str) -> Vec<u8> {
+        Memo::from_str(text).unwrap().encode().as_slice().to_vec()
+    }
+
+    #[test]
+    fn test_reply_to() {
+        let network = Network::TestNetwork;
+        let address = address(&network);
+
+        let fields = parse_memo(&network, &memo(&format!("Thanks!\nReply-To:\n{address}")));
+        assert_eq!(Some(address.clone()), fields.reply_to);
+
+        let fields = parse_memo(&network, &memo(&format!("reply-to: {address}")));
+        assert_eq!(Some(address.clone()), fields.reply_to);
+
+        // Addresses for another network are ignored.
+        let fields = parse_memo(
+            &Network::MainNetwork,
+            &memo(&format!("Reply-To: {address}")),
+        );
+        assert_eq!(None, fields.reply_to);
+
+        assert_eq!(
+            MemoFields::default(),
+            parse_memo(&network, &memo("Reply-To: nobody"))
+        );
+        assert_eq!(MemoFields::default(), parse_memo(&network, &[0xf6]));
+    }
+
+    #[test]
+    fn test_zip321_request() {
+        let network = Network::TestNetwork;
+        let address = address(&network);
+
+        let fields = parse_memo(
+            &network,
+            &memo(&format!(
+                "For order 42\nzcash:{address}?amount=1&label=Coffee%20shop&message=Order%2042"
+            )),
+        );
+        assert_eq!(Some(address), fields.reply_to);
+        assert_eq!(Some("Coffee shop".to_string()), fields.label);
+        assert_eq!(Some("Order 42".to_string()), fields.message);
+    }
+}
@@ -0,0 +1,203 @@
+use rusqlite::{named_params, Connection};
+use zcash_primitives::memo::Memo;
+
+use crate::{
+    error::Error,
+    interop::Pool,
+    sql_statements::{
+        DELETE_ORPHANED_MEMO_INDEX_ENTRIES, FIND_MEMO_INDEX_ENTRIES, GET_UNINDEXED_MEMOS,
+        GET_UNINDEXED_TRANSACTIONS, INSERT_MEMO_INDEX_ENTRY, MARK_MEMOS_INDEXED,
+    },
+};
+
+/// An output whose memo matched a lookup.
+#[derive(Debug, Clone)]
+pub struct MemoMatch {
+    /// The account that received the output, or that sent it if no account received it.
+    pub account_id: u32,
+    pub txid: Vec<u8>,
+    pub mined_height: Option<u32>,
+    pub pool: Pool,
+    pub output_index: u32,
+    pub value: u64,
+    /// The decoded content of the memo: the UTF-8 bytes of a text memo,
+    /// or the arbitrary data of a ZIP-302 arbitrary data memo with trailing zeros removed.
+    pub memo_data: Vec<u8>,
+}
+
+/// Adds the memos of outputs that the wallet has learned about since the index was last updated,
+/// and drops the entries for transactions that are no longer in the wallet.
+pub(crate) fn update_memo_index(conn: &mut Connection) -> Result<(), Error> {
+    let tx = conn.transaction()?;
+
+    // Read everything first, since we're about to add rows to tables these queries read from.
+    let transactions = tx
+        .prepare(GET_UNINDEXED_TRANSACTIONS)?
+        .query_map([], |row| row.get::<_, Vec<u8>>("txid"))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let unindexed = tx
+        .prepare(GET_UNINDEXED_MEMOS)?
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, Vec<u8>>("txid")?,
+                row.get::<_, u32>("output_pool")?,
+                row.get::<_, u32>("output_index")?,
+                row.get::<_, Option<u32>>("from_account_id")?,
+                row.get::<_, Option<u32>>("to_account_id")?,
+                row.get::<_, u64>("value")?,
+                row.get::<_, Vec<u8>>("memo")?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut insert = tx.prepare(INSERT_MEMO_INDEX_ENTRY)?;
+    for (txid, output_pool, output_index, from_account_id, to_account_id, value, memo) in unindexed
+    {
+        // Outputs with memos that can't be looked up are still recorded (without a key),
+        // so that they aren't decoded again on every update.
+        insert.execute(named_params! {
+            ":txid": txid,
+            ":output_pool": output_pool,
+            ":output_index": output_index,
+            ":from_account_id": from_account_id,
+            ":to_account_id": to_account_id,
+            ":value": value,
+            ":memo_key": memo_key(&memo),
+        })?;
+    }
+    drop(insert);
+
+    let mut mark = tx.prepare(MARK_MEMOS_INDEXED)?;
+    for txid in transactions {
+        mark.execute(named_params! { ":txid": txid })?;
+    }
+    drop(mark);
+
+    tx.execute_batch(DELETE_ORPHANED_MEMO_INDEX_ENTRIES)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Finds the outputs whose memos match the given data, optionally limited to those sent or received by one account.
+///
+/// When `prefix` is `true`, memos that start with `memo_data` match. Otherwise the memo must match exactly.
+/// The index is brought up to date before the lookup.
+pub fn find_by_memo(
+    conn: &mut Connection,
+    account_id: Option<u32>,
+    memo_data: &[u8],
+    prefix: bool,
+) -> Result<Vec<MemoMatch>, Error> {
+    update_memo_index(conn)?;
+
+    // An exact match is a prefix match that excludes every longer key.
+    let upper_bound = if prefix {
+        prefix_upper_bound(memo_data)
+    } else {
+        let mut bound = memo_data.to_vec();
+        bound.push(0);
+        Some(bound)
+    };
+
+    let mut matches = Vec::new();
+    let mut stmt = conn.prepare(FIND_MEMO_INDEX_ENTRIES)?;
+    let mut rows = stmt.query(named_params! {
+        ":lower_bound": memo_data,
+        ":upper_bound": upper_bound,
+        ":account_id": account_id,
+    })?;
+    while let Some(row) = rows.next()? {
+        let output_pool: u32 = row.get("output_pool")?;
+        matches.push(MemoMatch {
+            account_id: row.get("account_id")?,
+            txid: row.get("txid")?,
+            mined_height: row.get("mined_height")?,
+            pool: match output_pool {
+                0 => Pool::Transparent,
+                2 => Pool::Sapling,
+                3 => Pool::Orchard,
+                _ => {
+                    return Err(Error::Internal(format!(
+                        "Unknown output pool type: {}",
+                        output_pool
+                    )))
+                }
+            },
+            output_index: row.get("output_index")?,
+            value: row.get("value")?,
+            memo_data: row.get("memo_key")?,
+        });
+    }
+
+    Ok(matches)
+}
+
+/// Gets the key a memo is indexed by, or `None` if the memo has no content that can be looked up.
+fn memo_key(memo: &[u8]) -> Option<Vec<u8>> {
+    match Memo::from_bytes(memo) {
+        Ok(Memo::Text(text)) => Some(text.as_bytes().to_vec()),
+        Ok(Memo::Arbitrary(data)) => {
+            let len = data.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+            Some(data[..len].to_vec())
+        }
+        _ => None,
+    }
+}
+
+/// Gets the smallest key that is greater than every key starting with the given prefix,
+/// or `None` if there is no such key.
+fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut bound = prefix.to_vec();
+    while let Some(last) = bound.pop() {
+        if last < u8::MAX {
+            bound.push(last + 1);
+            return Some(bound);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::{backing_store::open_connection, test_constants::setup_test};
+
+    use super::*;
+
+    #[test]
+    fn test_memo_key() {
+        let text = Memo::from_str("order 42").unwrap().encode();
+        assert_eq!(Some(b"order 42".to_vec()), memo_key(text.as_slice()));
+
+        let mut arbitrary = [0u8; 512];
+        arbitrary[0] = 0xff;
+        arbitrary[1..4].copy_from_slice(&[1, 2, 3]);
+        assert_eq!(Some(vec![1, 2, 3]), memo_key(&arbitrary));
+
+        assert_eq!(None, memo_key(Memo::Empty.encode().as_slice()));
+    }
+
+    #[test]
+    fn test_prefix_upper_bound() {
+        assert_eq!(Some(vec![1, 3]), prefix_upper_bound(&[1, 2]));
+        assert_eq!(Some(vec![2]), prefix_upper_bound(&[1, 0xff]));
+        assert_eq!(None, prefix_upper_bound(&[0xff, 0xff]));
+        assert_eq!(None, prefix_upper_bound(&[]));
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_find_by_memo_empty_wallet() {
+        let mut setup = setup_test().await;
+        setup.create_account().await.unwrap();
+        let mut conn = open_connection(&setup.data_file).unwrap();
+
+        assert!(find_by_memo(&mut conn, None, b"order", true)
+            .unwrap()
+            .is_empty());
+        assert!(find_by_memo(&mut conn, Some(0), b"order", false)
+            .unwrap()
+            .is_empty());
+    }
+}
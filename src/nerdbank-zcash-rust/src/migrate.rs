@@ -0,0 +1,332 @@
+use std::{num::NonZeroU32, path::Path};
+
+use http::Uri;
+use orchard::keys::Scope;
+use zcash_address::ZcashAddress;
+use zcash_client_backend::{
+    address::UnifiedAddress,
+    data_api::{
+        wallet::{
+            create_proposed_transactions, input_selection::GreedyInputSelectorError,
+            propose_transfer,
+        },
+        Account, InputSource, WalletRead,
+    },
+    fees::{zip317::SingleOutputChangeStrategy, ChangeStrategy},
+    keys::UnifiedSpendingKey,
+    wallet::OvkPolicy,
+    zip321::{Payment, TransactionRequest},
+    ShieldedProtocol,
+};
+use zcash_client_sqlite::{AccountId, ReceivedNoteId};
+use zcash_primitives::{
+    consensus::Network,
+    transaction::{
+        components::amount::{NonNegativeAmount, MAX_MONEY},
+        fees::zip317::FeeRule,
+    },
+};
+
+use crate::{
+    backing_store::Db,
+    error::Error,
+    input_selection::WalletInputSelector,
+    interop::{MigrationProgress, Pool},
+    prover::get_prover,
+    send::{transmit_transaction, SendTransactionResult},
+};
+
+/// The most Sapling notes to spend in one migration transaction,
+/// which keeps the size of each transaction and the time it takes to prove reasonable.
+const MAX_NOTES_PER_TRANSACTION: usize = 50;
+
+/// Moves all of an account's spendable Sapling funds into the Orchard pool,
+/// using as many transactions as it takes.
+///
+/// Sapling notes worth less than the fee to spend them are left behind.
+/// Progress is reported after each transaction is sent.
+pub async fn migrate_to_orchard<P: AsRef<Path>>(
+    data_file: P,
+    server_uri: Uri,
+    network: Network,
+    usk: &UnifiedSpendingKey,
+    account_id: AccountId,
+    min_confirmations: NonZeroU32,
+    progress: Option<&dyn MigrationProgress>,
+) -> Result<Vec<SendTransactionResult>, Error> {
+    let mut db = Db::init(data_file, network)?;
+    let ufvk = usk.to_unified_full_viewing_key();
+    let account = db
+        .data
+        .get_account_for_ufvk(&ufvk)?
+        .ok_or(Error::KeyNotRecognized)?;
+    if account.id() != account_id {
+        return Err(Error::InvalidArgument(
+            "The spending key does not belong to the account.".to_string(),
+        ));
+    }
+
+    // The funds go to the account's internal Orchard address,
+    // which marks them as moving within the wallet rather than being received from elsewhere.
+    let orchard = ufvk.orchard().ok_or_else(|| {
+        Error::InvalidArgument("The account has no Orchard key to migrate funds to.".to_string())
+    })?;
+    let recipient =
+        UnifiedAddress::from_receivers(Some(orchard.address_at(0u32, Scope::Internal)), None, None)
+            .ok_or_else(|| Error::Internal("Failed to create an Orchard address.".to_string()))?;
+    let recipient = ZcashAddress::try_from_encoded(&recipient.encode(&network))
+        .map_err(|_| Error::InvalidAddress)?;
+
+    sweep_notes(
+        &mut db,
+        &server_uri,
+        network,
+        usk,
+        account_id,
+        ShieldedProtocol::Sapling,
+        &recipient,
+        min_confirmations,
+        progress,
+    )
+    .await
+}
+
+/// Sends all of an account's spendable notes in one pool to a single recipient,
+/// using as many transactions as it takes.
+///
+/// Change goes to Orchard, so nothing is left behind in the pool but notes worth less than the fee to spend them.
+/// Progress is reported after each transaction is sent.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn sweep_notes(
+    db: &mut Db,
+    server_uri: &Uri,
+    network: Network,
+    usk: &UnifiedSpendingKey,
+    account_id: AccountId,
+    protocol: ShieldedProtocol,
+    recipient: &ZcashAddress,
+    min_confirmations: NonZeroU32,
+    progress: Option<&dyn MigrationProgress>,
+) -> Result<Vec<SendTransactionResult>, Error> {
+    let pool = match protocol {
+        ShieldedProtocol::Sapling => Pool::Sapling,
+        ShieldedProtocol::Orchard => Pool::Orchard,
+    };
+    let input_selector =
+        WalletInputSelector::new(Some(pool))?.with_fallback_change_pool(ShieldedProtocol::Orchard);
+
+    let mut results = Vec::new();
+    let mut migrated = 0;
+    let mut notes = spendable_notes(db, account_id, protocol, min_confirmations)?;
+    let prover = get_prover()?;
+    while let Some(amount) = next_batch_amount(&notes, protocol) {
+        let request = TransactionRequest::new(vec![Payment::new(
+            recipient.clone(),
+            NonNegativeAmount::from_u64(amount).map_err(|_| Error::InvalidAmount)?,
+            None,
+            None,
+            None,
+            Vec::new(),
+        )
+        .ok_or(Error::MemoNotAllowed)?])?;
+        let proposal = propose_transfer::<_, _, _, Error>(
+            &mut db.data,
+            &network,
+            account_id,
+            &input_selector,
+            request,
+            min_confirmations,
+        )?;
+        let txids = create_proposed_transactions::<
+            _,
+            _,
+            GreedyInputSelectorError<
+                <SingleOutputChangeStrategy as ChangeStrategy>::Error,
+                ReceivedNoteId,
+            >,
+            _,
+            _,
+        >(
+            &mut db.data,
+            &network,
+            &prover,
+            &prover,
+            usk,
+            OvkPolicy::Sender,
+            &proposal,
+        )?;
+
+        migrated += amount;
+        notes = spendable_notes(db, account_id, protocol, min_confirmations)?;
+        for txid in txids {
+            let result = transmit_transaction(txid, server_uri.clone(), db).await?;
+            if let Some(progress) = progress {
+                progress.transaction_sent(
+                    result.txid.as_ref().to_vec(),
+                    migrated,
+                    notes.iter().sum(),
+                );
+            }
+            results.push(result);
+        }
+    }
+
+    Ok(results)
+}
+
+/// Gets the total that [`sweep_notes`] would send from an account's spendable notes in a pool.
+pub(crate) fn sweep_amount(
+    db: &Db,
+    account_id: AccountId,
+    protocol: ShieldedProtocol,
+    min_confirmations: NonZeroU32,
+) -> Result<u64, Error> {
+    Ok(
+        spendable_notes(db, account_id, protocol, min_confirmations)?
+            .chunks(MAX_NOTES_PER_TRANSACTION)
+            .filter_map(|batch| next_batch_amount(batch, protocol))
+            .sum(),
+    )
+}
+
+/// Gets the values of the account's spendable notes in a pool, in the order the input selector considers them.
+fn spendable_notes(
+    db: &Db,
+    account_id: AccountId,
+    protocol: ShieldedProtocol,
+    min_confirmations: NonZeroU32,
+) -> Result<Vec<u64>, Error> {
+    let (_, anchor_height) = db
+        .data
+        .get_target_and_anchor_heights(min_confirmations)?
+        .ok_or(Error::SyncFirst)?;
+    let notes = db.data.select_spendable_notes(
+        account_id,
+        NonNegativeAmount::const_from_u64(MAX_MONEY),
+        &[protocol],
+        anchor_height,
+        &[],
+    )?;
+
+    Ok(match protocol {
+        ShieldedProtocol::Sapling => notes
+            .sapling()
+            .iter()
+            .map(|n| n.note().value().inner())
+            .collect(),
+        ShieldedProtocol::Orchard => notes
+            .orchard()
+            .iter()
+            .map(|n| n.note().value().inner())
+            .collect(),
+    })
+}
+
+/// Gets the amount to send in the next transaction of a sweep, which spends as many of the given notes as one transaction may,
+/// or `None` if those notes aren't worth the fee to spend them.
+fn next_batch_amount(notes: &[u64], protocol: ShieldedProtocol) -> Option<u64> {
+    let batch = &notes[..notes.len().min(MAX_NOTES_PER_TRANSACTION)];
+    batch
+        .iter()
+        .sum::<u64>()
+        .checked_sub(sweep_fee(protocol, batch.len()))
+        .filter(|amount| *amount > 0)
+}
+
+/// Gets a ZIP-317 fee that covers spending the given number of notes from a pool
+/// into an Orchard output and an Orchard change output.
+fn sweep_fee(protocol: ShieldedProtocol, notes: usize) -> u64 {
+    let fee_rule = FeeRule::standard();
+
+    // Orchard bundles are padded to two actions, each of which spends one note and makes one output.
+    // Spending from Sapling adds a Sapling bundle, which is padded to two outputs.
+    let logical_actions = match protocol {
+        ShieldedProtocol::Sapling => notes.max(2) + 2,
+        ShieldedProtocol::Orchard => notes.max(2),
+    };
+    u64::from(fee_rule.marginal_fee()) * logical_actions.max(fee_rule.grace_actions()) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+
+    use crate::test_constants::{
+        setup_local_test, LocalTestSetup, LIGHTSERVER_URI, MIN_CONFIRMATIONS,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_next_batch_amount() {
+        let sapling = ShieldedProtocol::Sapling;
+        assert_eq!(None, next_batch_amount(&[], sapling));
+        assert_eq!(None, next_batch_amount(&[15_000], sapling));
+        assert_eq!(None, next_batch_amount(&[20_000], sapling));
+        assert_eq!(Some(10_000), next_batch_amount(&[30_000], sapling));
+        assert_eq!(
+            Some(10_000),
+            next_batch_amount(&[10_000], ShieldedProtocol::Orchard)
+        );
+
+        // Only as many notes as fit in one transaction are spent at a time.
+        let notes = vec![100_000; MAX_NOTES_PER_TRANSACTION + 10];
+        assert_eq!(
+            Some(
+                100_000 * MAX_NOTES_PER_TRANSACTION as u64
+                    - sweep_fee(sapling, MAX_NOTES_PER_TRANSACTION)
+            ),
+            next_batch_amount(&notes, sapling)
+        );
+    }
+
+    async fn migrate(
+        setup: &LocalTestSetup,
+        usk: &UnifiedSpendingKey,
+        account_id: AccountId,
+    ) -> Result<Vec<SendTransactionResult>, Error> {
+        migrate_to_orchard(
+            &setup.data_file,
+            LIGHTSERVER_URI.to_owned(),
+            setup.network,
+            usk,
+            account_id,
+            NonZeroU32::new(MIN_CONFIRMATIONS).unwrap(),
+            None,
+        )
+        .await
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_migrate_to_orchard_checks_account() {
+        let mut setup = setup_local_test();
+        let (_, _, account_id, usk) = setup.create_account().unwrap();
+
+        let stranger =
+            UnifiedSpendingKey::from_seed(&setup.network, &[1; 32], zip32::AccountId::ZERO)
+                .unwrap();
+        assert_matches!(
+            migrate(&setup, &stranger, account_id).await,
+            Err(Error::KeyNotRecognized)
+        );
+        assert_matches!(
+            migrate(&setup, &usk, AccountId::from(u32::from(account_id) + 1)).await,
+            Err(Error::InvalidArgument(_))
+        );
+
+        // The wallet has never synced, so there are no spendable notes to find yet.
+        assert_matches!(
+            migrate(&setup, &usk, account_id).await,
+            Err(Error::SyncFirst)
+        );
+    }
+
+    #[test]
+    fn test_sweep_fee() {
+        assert_eq!(20_000, sweep_fee(ShieldedProtocol::Sapling, 1));
+        assert_eq!(20_000, sweep_fee(ShieldedProtocol::Sapling, 2));
+        assert_eq!(60_000, sweep_fee(ShieldedProtocol::Sapling, 10));
+        assert_eq!(10_000, sweep_fee(ShieldedProtocol::Orchard, 1));
+        assert_eq!(50_000, sweep_fee(ShieldedProtocol::Orchard, 10));
+    }
+}
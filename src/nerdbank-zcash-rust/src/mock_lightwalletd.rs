@@ -0,0 +1,451 @@
+//! An in-process lightwalletd that serves canned chain data,
+//! so that behavior that depends on the server (retries, reorgs, cancellation) can be tested deterministically and offline.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::{
+    future::{ready, Ready},
+    stream::{self, Iter},
+};
+use http::Uri;
+use tokio::net::TcpListener;
+use tokio_util::sync::{CancellationToken, DropGuard};
+use tonic::{
+    body::BoxBody,
+    codec::ProstCodec,
+    codegen::{Body, BoxFuture, Context, Poll, Service, StdError},
+    server::{Grpc, NamedService, ServerStreamingService, UnaryService},
+    transport::{server::TcpIncoming, Server},
+    Code, Status,
+};
+use zcash_client_backend::proto::{
+    compact_formats::CompactBlock,
+    service::{
+        AddressList, Balance, BlockId, BlockRange, ChainSpec, Empty, GetSubtreeRootsArg,
+        LightdInfo, RawTransaction, SendResponse, ShieldedProtocol, SubtreeRoot,
+        TransparentAddressBlockFilter, TreeState, TxFilter,
+    },
+};
+
+use crate::error::Error;
+
+/// The chain data served by a [`MockLightwalletd`].
+///
+/// Tests may change it while the server runs, e.g. to mine blocks or simulate a reorg.
+#[derive(Debug, Default)]
+pub struct MockChain {
+    /// The `chain_name` reported by `GetLightdInfo`, such as `main` or `test`.
+    pub chain_name: String,
+    pub blocks: BTreeMap<u64, CompactBlock>,
+    pub tree_states: BTreeMap<u64, TreeState>,
+    pub sapling_subtree_roots: Vec<SubtreeRoot>,
+    pub orchard_subtree_roots: Vec<SubtreeRoot>,
+    /// Transactions that `GetTransaction` can find, keyed by txid.
+    /// Their `height` field identifies their block, or is 0 for transactions in the mempool.
+    pub transactions: HashMap<Vec<u8>, RawTransaction>,
+    /// The txids of transactions that involve each transparent address.
+    pub taddress_txids: HashMap<String, Vec<Vec<u8>>>,
+    pub taddress_balances: HashMap<String, i64>,
+    pub mempool: Vec<RawTransaction>,
+    /// Transactions submitted with `SendTransaction`.
+    pub sent: Vec<RawTransaction>,
+    /// Failures to return from the next requests, in order, regardless of which method is called.
+    pub injected_failures: VecDeque<Code>,
+}
+
+impl MockChain {
+    pub fn new(chain_name: &str) -> Self {
+        Self {
+            chain_name: chain_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn tip_height(&self) -> u64 {
+        self.blocks.keys().next_back().copied().unwrap_or(0)
+    }
+
+    pub fn push_block(&mut self, block: CompactBlock) {
+        self.blocks.insert(block.height, block);
+    }
+
+    /// Removes all blocks (and their tree states) above the given height, as happens in a reorg.
+    pub fn truncate(&mut self, height: u64) {
+        self.blocks.split_off(&(height + 1));
+        self.tree_states.split_off(&(height + 1));
+    }
+
+    fn lightd_info(&self) -> LightdInfo {
+        LightdInfo {
+            chain_name: self.chain_name.clone(),
+            block_height: self.tip_height(),
+            vendor: "Nerdbank mock".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn latest_block(&self) -> BlockId {
+        BlockId {
+            height: self.tip_height(),
+            hash: self
+                .blocks
+                .values()
+                .next_back()
+                .map(|b| b.hash.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn block(&self, height: u64) -> Result<CompactBlock, Status> {
+        self.blocks
+            .get(&height)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("No block at height {height}")))
+    }
+
+    fn block_range(&self, range: BlockRange) -> Result<Vec<CompactBlock>, Status> {
+        let start = range.start.map_or(0, |b| b.height);
+        let end = range.end.map_or(0, |b| b.height);
+        if start <= end {
+            (start..=end).map(|h| self.block(h)).collect()
+        } else {
+            (end..=start).rev().map(|h| self.block(h)).collect()
+        }
+    }
+
+    fn tree_state(&self, height: u64) -> Result<TreeState, Status> {
+        self.tree_states
+            .get(&height)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("No tree state at height {height}")))
+    }
+
+    fn subtree_roots(&self, request: GetSubtreeRootsArg) -> Vec<SubtreeRoot> {
+        let roots = match request.shielded_protocol() {
+            ShieldedProtocol::Sapling => &self.sapling_subtree_roots,
+            ShieldedProtocol::Orchard => &self.orchard_subtree_roots,
+        };
+        let max_entries = match request.max_entries {
+            0 => usize::MAX,
+            n => n as usize,
+        };
+        roots
+            .iter()
+            .skip(request.start_index as usize)
+            .take(max_entries)
+            .cloned()
+            .collect()
+    }
+
+    fn transaction(&self, filter: TxFilter) -> Result<RawTransaction, Status> {
+        self.transactions
+            .get(&filter.hash)
+            .cloned()
+            .ok_or_else(|| Status::not_found("Transaction not found"))
+    }
+
+    fn taddress_transactions(&self, filter: TransparentAddressBlockFilter) -> Vec<RawTransaction> {
+        let (start, end) = match filter.range {
+            Some(range) => (
+                range.start.map_or(0, |b| b.height),
+                range.end.map_or(u64::MAX, |b| b.height),
+            ),
+            None => (0, u64::MAX),
+        };
+        self.taddress_txids
+            .get(&filter.address)
+            .into_iter()
+            .flatten()
+            .filter_map(|txid| self.transactions.get(txid))
+            .filter(|tx| tx.height >= start && tx.height <= end)
+            .cloned()
+            .collect()
+    }
+
+    fn taddress_balance(&self, addresses: AddressList) -> Balance {
+        Balance {
+            value_zat: addresses
+                .addresses
+                .iter()
+                .filter_map(|a| self.taddress_balances.get(a))
+                .sum(),
+        }
+    }
+}
+
+/// A lightwalletd server running in this process. It stops when dropped.
+pub struct MockLightwalletd {
+    pub uri: Uri,
+    pub chain: Arc<Mutex<MockChain>>,
+    _shutdown: DropGuard,
+}
+
+impl MockLightwalletd {
+    /// Starts serving the given chain on a local port.
+    pub async fn start(chain: MockChain) -> Result<Self, Error> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let uri = format!("http://{}", listener.local_addr()?)
+            .parse()
+            .map_err(|e: http::uri::InvalidUri| Error::Internal(e.to_string()))?;
+        let incoming = TcpIncoming::from_listener(listener, true, None)
+            .map_err(|e| Error::Internal(e.to_string()))?;
+
+        let chain = Arc::new(Mutex::new(chain));
+        let shutdown = CancellationToken::new();
+        let server = Server::builder()
+            .add_service(MockCompactTxStreamer {
+                chain: chain.clone(),
+            })
+            .serve_with_incoming_shutdown(incoming, shutdown.clone().cancelled_owned());
+        tokio::spawn(server);
+
+        Ok(Self {
+            uri,
+            chain,
+            _shutdown: shutdown.drop_guard(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MockCompactTxStreamer {
+    chain: Arc<Mutex<MockChain>>,
+}
+
+impl NamedService for MockCompactTxStreamer {
+    const NAME: &'static str = "cash.z.wallet.sdk.rpc.CompactTxStreamer";
+}
+
+impl<B> Service<http::Request<B>> for MockCompactTxStreamer
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let chain = self.chain.clone();
+        let method = req
+            .uri()
+            .path()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        Box::pin(async move {
+            let injected_failure = chain.lock().unwrap().injected_failures.pop_front();
+            if let Some(code) = injected_failure {
+                return Ok(Status::new(code, "Injected failure").to_http());
+            }
+
+            Ok(match method.as_str() {
+                "GetLightdInfo" => {
+                    unary(req, |_: Empty| Ok(chain.lock().unwrap().lightd_info())).await
+                }
+                "GetLatestBlock" => {
+                    unary(req, |_: ChainSpec| Ok(chain.lock().unwrap().latest_block())).await
+                }
+                "GetBlock" => unary(req, |b: BlockId| chain.lock().unwrap().block(b.height)).await,
+                "GetBlockRange" => {
+                    streaming(req, |r: BlockRange| chain.lock().unwrap().block_range(r)).await
+                }
+                "GetTreeState" => {
+                    unary(req, |b: BlockId| chain.lock().unwrap().tree_state(b.height)).await
+                }
+                "GetSubtreeRoots" => {
+                    streaming(req, |r: GetSubtreeRootsArg| {
+                        Ok(chain.lock().unwrap().subtree_roots(r))
+                    })
+                    .await
+                }
+                "GetTransaction" => {
+                    unary(req, |f: TxFilter| chain.lock().unwrap().transaction(f)).await
+                }
+                "GetTaddressTxids" => {
+                    streaming(req, |f: TransparentAddressBlockFilter| {
+                        Ok(chain.lock().unwrap().taddress_transactions(f))
+                    })
+                    .await
+                }
+                "GetTaddressBalance" => {
+                    unary(req, |a: AddressList| {
+                        Ok(chain.lock().unwrap().taddress_balance(a))
+                    })
+                    .await
+                }
+                "GetMempoolStream" => {
+                    streaming(req, |_: Empty| Ok(chain.lock().unwrap().mempool.clone())).await
+                }
+                "SendTransaction" => {
+                    unary(req, |tx: RawTransaction| {
+                        chain.lock().unwrap().sent.push(tx);
+                        Ok(SendResponse::default())
+                    })
+                    .await
+                }
+                _ => Status::unimplemented(method).to_http(),
+            })
+        })
+    }
+}
+
+async fn unary<Req, Resp, B, F>(req: http::Request<B>, f: F) -> http::Response<BoxBody>
+where
+    Req: prost::Message + Default + Send + 'static,
+    Resp: prost::Message + Send + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+    F: FnOnce(Req) -> Result<Resp, Status>,
+{
+    Grpc::new(ProstCodec::<Resp, Req>::default())
+        .unary(UnaryFn(Some(f)), req)
+        .await
+}
+
+async fn streaming<Req, Resp, B, F>(req: http::Request<B>, f: F) -> http::Response<BoxBody>
+where
+    Req: prost::Message + Default + Send + 'static,
+    Resp: prost::Message + Send + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+    F: FnOnce(Req) -> Result<Vec<Resp>, Status>,
+{
+    Grpc::new(ProstCodec::<Resp, Req>::default())
+        .server_streaming(StreamingFn(Some(f)), req)
+        .await
+}
+
+/// Adapts a function that is called once into a [`UnaryService`].
+struct UnaryFn<F>(Option<F>);
+
+impl<Req, Resp, F> UnaryService<Req> for UnaryFn<F>
+where
+    F: FnOnce(Req) -> Result<Resp, Status>,
+{
+    type Response = Resp;
+    type Future = Ready<Result<tonic::Response<Resp>, Status>>;
+
+    fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+        let f = self.0.take().expect("A request is only handled once.");
+        ready(f(request.into_inner()).map(tonic::Response::new))
+    }
+}
+
+/// Adapts a function that is called once into a [`ServerStreamingService`] that streams the items it returns.
+struct StreamingFn<F>(Option<F>);
+
+impl<Req, Resp, F> ServerStreamingService<Req> for StreamingFn<F>
+where
+    Resp: Send + 'static,
+    F: FnOnce(Req) -> Result<Vec<Resp>, Status>,
+{
+    type Response = Resp;
+    type ResponseStream = Iter<std::vec::IntoIter<Result<Resp, Status>>>;
+    type Future = Ready<Result<tonic::Response<Self::ResponseStream>, Status>>;
+
+    fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+        let f = self.0.take().expect("A request is only handled once.");
+        ready(f(request.into_inner()).map(|items| {
+            tonic::Response::new(stream::iter(
+                items.into_iter().map(Ok).collect::<Vec<_>>().into_iter(),
+            ))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        grpc::get_client, lightclient::get_block_height, resilience::webrequest_with_retry,
+    };
+
+    use super::*;
+
+    async fn start_with_blocks(heights: std::ops::RangeInclusive<u64>) -> MockLightwalletd {
+        let mut chain = MockChain::new("test");
+        for height in heights {
+            chain.push_block(CompactBlock {
+                height,
+                hash: vec![height as u8; 32],
+                ..Default::default()
+            });
+        }
+
+        MockLightwalletd::start(chain).await.unwrap()
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_get_block_height() {
+        let server = start_with_blocks(100..=110).await;
+        let height = get_block_height(server.uri.clone(), CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(110, height);
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_block_range_after_reorg() {
+        let server = start_with_blocks(100..=110).await;
+        server.chain.lock().unwrap().truncate(105);
+
+        let mut client = get_client(server.uri.clone()).await.unwrap();
+        let range = BlockRange {
+            start: Some(BlockId {
+                height: 103,
+                ..Default::default()
+            }),
+            end: Some(BlockId {
+                height: 105,
+                ..Default::default()
+            }),
+        };
+        let mut stream = client.get_block_range(range).await.unwrap().into_inner();
+        let mut heights = Vec::new();
+        while let Some(block) = stream.message().await.unwrap() {
+            heights.push(block.height);
+        }
+        assert_eq!(vec![103, 104, 105], heights);
+
+        let status = client
+            .get_block(BlockId {
+                height: 106,
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(Code::NotFound, status.code());
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_retry_recovers_from_transient_failures() {
+        let server = start_with_blocks(100..=100).await;
+        server
+            .chain
+            .lock()
+            .unwrap()
+            .injected_failures
+            .extend([Code::Unavailable, Code::Unavailable]);
+
+        let client = get_client(server.uri.clone()).await.unwrap();
+        let info = webrequest_with_retry(
+            || async { Ok(client.clone().get_lightd_info(Empty {}).await?.into_inner()) },
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!("test", info.chain_name);
+    }
+}
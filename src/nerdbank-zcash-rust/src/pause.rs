@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use tokio::{select, sync::watch};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::Error;
+
+/// A token that allows a long-running operation to be suspended and later resumed
+/// without losing the work it has in progress.
+#[derive(Debug, Clone)]
+pub(crate) struct PauseToken {
+    paused: Arc<watch::Sender<bool>>,
+}
+
+impl Default for PauseToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PauseToken {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Self {
+            paused: Arc::new(sender),
+        }
+    }
+
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Returns immediately if the token is not paused.
+    /// Otherwise waits until it is resumed, or fails if cancellation is signaled first.
+    pub async fn wait_while_paused(
+        &self,
+        cancellation_token: &CancellationToken,
+    ) -> Result<(), Error> {
+        let mut receiver = self.paused.subscribe();
+        select! {
+            // The sender lives as long as self, so waiting can never fail.
+            _ = receiver.wait_for(|paused| !paused) => Ok(()),
+            _ = cancellation_token.cancelled() => Err(Error::Canceled),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio_shared_rt::test]
+    async fn wait_while_paused_returns_when_resumed() {
+        let token = PauseToken::new();
+        token
+            .wait_while_paused(&CancellationToken::new())
+            .await
+            .unwrap();
+
+        token.pause();
+        assert!(token.is_paused());
+        let resumer = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            resumer.resume();
+        });
+        token
+            .wait_while_paused(&CancellationToken::new())
+            .await
+            .unwrap();
+        assert!(!token.is_paused());
+    }
+
+    #[tokio_shared_rt::test]
+    async fn wait_while_paused_fails_when_canceled() {
+        let token = PauseToken::new();
+        token.pause();
+        let cancellation_token = CancellationToken::new();
+        cancellation_token.cancel();
+        assert!(matches!(
+            token.wait_while_paused(&cancellation_token).await,
+            Err(Error::Canceled)
+        ));
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::{named_params, Connection};
+use zcash_client_backend::{data_api::WalletRead, encoding::AddressCodec, PoolType};
+use zcash_client_sqlite::{error::SqliteClientError, AccountId};
+use zcash_keys::address::Address;
+use zcash_primitives::consensus::Network;
+
+use crate::{
+    backing_store::Db,
+    error::Error,
+    interop::{Pool, SyncUpdate, Transaction},
+    sql_statements::{GET_INCOMING_PAYMENTS, GET_RECEIVING_ADDRESSES},
+    sync::{is_change, regenerate_address},
+};
+
+/// A payment received by one of the wallet's accounts.
+#[derive(Debug, Clone)]
+pub struct IncomingPayment {
+    pub account_id: u32,
+    pub txid: Vec<u8>,
+    /// The address the payment was sent to.
+    /// When the receiver belongs to an address the wallet handed out, this is that (unified) address
+    /// rather than the individual receiver.
+    pub address: String,
+    /// The little-endian diversifier index of `address`, when it is one of the account's generated addresses.
+    pub diversifier_index: Option<Vec<u8>>,
+    pub pool: Pool,
+    pub value: u64,
+    pub memo: Option<Vec<u8>>,
+    pub mined_height: Option<u32>,
+    /// The number of blocks in the chain from the one that mined the payment through the tip,
+    /// or 0 if the payment has not been mined.
+    pub confirmations: u32,
+}
+
+/// Selects which incoming payments to get.
+#[derive(Debug, Clone, Default)]
+pub struct IncomingPaymentsFilter {
+    /// The lowest block height to include payments from.
+    pub starting_block: u32,
+    /// The highest block height to include payments from.
+    /// When set, unmined payments are excluded.
+    pub ending_block: Option<u32>,
+    /// Whether to exclude payments with fewer than the wallet's minimum confirmations, including unmined ones.
+    pub confirmed_only: bool,
+    /// The most payments to return.
+    pub limit: Option<u32>,
+    /// The cursor returned with the previous page, to get the payments that follow it.
+    pub after: Option<IncomingPaymentsCursor>,
+}
+
+/// The position of a payment in the order payments are returned in.
+/// Its fields are only meaningful to the wallet, and should be passed back as they were received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IncomingPaymentsCursor {
+    pub height: u32,
+    pub index: i64,
+    pub output_pool: u32,
+    pub output_index: u32,
+}
+
+/// A page of incoming payments.
+#[derive(Debug, Clone)]
+pub struct IncomingPaymentsPage {
+    pub payments: Vec<IncomingPayment>,
+    /// The cursor to get the next page with, or `None` if there are no more payments.
+    pub next: Option<IncomingPaymentsCursor>,
+}
+
+/// An address the wallet has generated for an account.
+#[derive(Debug, Clone)]
+struct ReceivingAddress {
+    address: String,
+    /// The little-endian diversifier index.
+    diversifier_index: Vec<u8>,
+}
+
+/// The wallet's addresses, keyed by account, output pool, and receiver.
+/// Shielded receivers are identified by their diversifier, and transparent receivers by their encoding.
+type Receivers = HashMap<(u32, u32, Vec<u8>), ReceivingAddress>;
+
+/// Gets the payments received by the wallet's accounts, ordered by height with unmined payments last.
+///
+/// Change is excluded.
+pub fn get_incoming_payments(
+    db: &Db,
+    conn: &Connection,
+    network: &Network,
+    min_confirmations: u32,
+    filter: &IncomingPaymentsFilter,
+) -> Result<IncomingPaymentsPage, Error> {
+    let ufvkeys = db.data.get_unified_full_viewing_keys()?;
+    let tip = db.data.chain_height()?.map(u32::from);
+    let receivers = get_receivers(conn, network)?;
+
+    let mut ending_block = filter.ending_block;
+    if filter.confirmed_only {
+        let confirmed_height = match tip.and_then(|t| (t + 1).checked_sub(min_confirmations.max(1)))
+        {
+            Some(h) => h,
+            None => {
+                return Ok(IncomingPaymentsPage {
+                    payments: Vec::new(),
+                    next: None,
+                })
+            }
+        };
+        ending_block = Some(ending_block.map_or(confirmed_height, |e| e.min(confirmed_height)));
+    }
+
+    let limit = filter.limit.map_or(usize::MAX, |l| l as usize);
+    let after = filter.after.as_ref();
+    let mut page = IncomingPaymentsPage {
+        payments: Vec::new(),
+        next: filter.after,
+    };
+    let mut more = false;
+    let mut stmt = conn.prepare(GET_INCOMING_PAYMENTS)?;
+    let mut rows = stmt.query(named_params! {
+        ":starting_block": filter.starting_block,
+        ":ending_block": ending_block,
+        ":include_unmined": ending_block.is_none(),
+        ":after_height": after.map(|a| a.height),
+        ":after_index": after.map(|a| a.index),
+        ":after_pool": after.map(|a| a.output_pool),
+        ":after_output_index": after.map(|a| a.output_index),
+    })?;
+    while let Some(row) = rows.next()? {
+        let cursor = IncomingPaymentsCursor {
+            height: row.get("sort_height")?,
+            index: row.get("sort_index")?,
+            output_pool: row.get("output_pool")?,
+            output_index: row.get("output_index")?,
+        };
+        let account_id: u32 = row.get("account_id")?;
+        let from_account_id: Option<u32> = row.get("from_account_id")?;
+        let output_pool: u32 = row.get("output_pool")?;
+        let memo: Option<Vec<u8>> = row.get("memo")?;
+        let memo = memo.unwrap_or_default();
+
+        let (pool_type, pool) = match output_pool {
+            0 => (PoolType::Transparent, Pool::Transparent),
+            2 => (PoolType::SAPLING, Pool::Sapling),
+            3 => (PoolType::ORCHARD, Pool::Orchard),
+            _ => {
+                return Err(Error::SqliteClient(SqliteClientError::CorruptedData(
+                    format!("Unknown output pool type: {}", output_pool),
+                )))
+            }
+        };
+
+        if is_change(from_account_id, Some(account_id), pool_type, &memo) {
+            continue;
+        }
+
+        let to_address: Option<String> = row.get("to_address")?;
+        let diversifier: Option<Vec<u8>> = row.get("diversifier")?;
+        let receiver = match pool_type {
+            PoolType::Transparent => to_address.clone().map(String::into_bytes),
+            _ => diversifier.clone(),
+        };
+
+        let (address, diversifier_index) =
+            match receiver.and_then(|r| receivers.get(&(account_id, output_pool, r))) {
+                Some(r) => (r.address.clone(), Some(r.diversifier_index.clone())),
+                None => {
+                    let address = to_address.or_else(|| {
+                        diversifier.and_then(|d| {
+                            regenerate_address(
+                                ufvkeys.get(&AccountId::from(account_id)),
+                                pool_type,
+                                d,
+                                network,
+                            )
+                        })
+                    });
+                    match address {
+                        Some(a) => (a, None),
+                        // We can't say where the payment came in, which is the whole point of reporting it.
+                        None => continue,
+                    }
+                }
+            };
+
+        let mined_height: Option<u32> = row.get("mined_height")?;
+        let confirmations = match (mined_height, tip) {
+            (Some(h), Some(tip)) if tip >= h => tip - h + 1,
+            _ => 0,
+        };
+
+        if page.payments.len() == limit {
+            more = true;
+            break;
+        }
+
+        page.payments.push(IncomingPayment {
+            account_id,
+            txid: row.get("txid")?,
+            address,
+            diversifier_index,
+            pool,
+            value: row.get("value")?,
+            memo: if memo.is_empty() { None } else { Some(memo) },
+            mined_height,
+            confirmations,
+        });
+        page.next = Some(cursor);
+    }
+
+    if !more {
+        page.next = None;
+    }
+
+    Ok(page)
+}
+
+/// Reports the payments received by the given transactions.
+pub(crate) fn report_incoming_payments(
+    sink: &dyn SyncUpdate,
+    db: &Db,
+    conn: &Connection,
+    network: &Network,
+    transactions: &[Transaction],
+) -> Result<(), Error> {
+    let txids: HashSet<&[u8]> = transactions
+        .iter()
+        .filter(|t| !t.incoming.is_empty())
+        .map(|t| &t.txid[..])
+        .collect();
+    if txids.is_empty() {
+        return Ok(());
+    }
+
+    // Unmined payments are always included, so only mined transactions limit the range.
+    let starting_block = transactions
+        .iter()
+        .filter_map(|t| t.mined_height)
+        .min()
+        .unwrap_or(u32::MAX);
+    let filter = IncomingPaymentsFilter {
+        starting_block,
+        ..Default::default()
+    };
+    let payments: Vec<IncomingPayment> = get_incoming_payments(db, conn, network, 0, &filter)?
+        .payments
+        .into_iter()
+        .filter(|p| txids.contains(&p.txid[..]))
+        .collect();
+    if !payments.is_empty() {
+        sink.report_incoming_payments(payments);
+    }
+
+    Ok(())
+}
+
+fn get_receivers(conn: &Connection, network: &Network) -> Result<Receivers, Error> {
+    let mut receivers = Receivers::new();
+    let mut stmt = conn.prepare(GET_RECEIVING_ADDRESSES)?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let account_id: u32 = row.get("account_id")?;
+        let address: String = row.get("address")?;
+        let mut diversifier_index: Vec<u8> = row.get("diversifier_index_be")?;
+        diversifier_index.reverse();
+
+        let mut insert = |pool: u32, receiver: Vec<u8>| {
+            receivers.insert(
+                (account_id, pool, receiver),
+                ReceivingAddress {
+                    address: address.clone(),
+                    diversifier_index: diversifier_index.clone(),
+                },
+            );
+        };
+        match Address::decode(network, &address) {
+            Some(Address::Unified(ua)) => {
+                if let Some(t) = ua.transparent() {
+                    insert(0, t.encode(network).into_bytes());
+                }
+                if let Some(s) = ua.sapling() {
+                    insert(2, s.diversifier().0.to_vec());
+                }
+                if let Some(o) = ua.orchard() {
+                    insert(3, o.diversifier().as_array().to_vec());
+                }
+            }
+            Some(Address::Sapling(s)) => insert(2, s.diversifier().0.to_vec()),
+            _ => {}
+        }
+    }
+
+    Ok(receivers)
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_primitives::zip32::DiversifierIndex;
+
+    use crate::{backing_store::open_connection, test_constants::setup_test};
+
+    use super::*;
+
+    #[tokio_shared_rt::test]
+    async fn test_get_receivers() {
+        let mut setup = setup_test().await;
+        let (_, _, account_id, _) = setup.create_account().await.unwrap();
+        let ua = setup
+            .db
+            .add_diversifier(account_id, DiversifierIndex::from(5u32))
+            .unwrap();
+        let conn = open_connection(&setup.data_file).unwrap();
+
+        let receivers = get_receivers(&conn, &setup.network).unwrap();
+        let sapling = receivers
+            .get(&(
+                account_id.into(),
+                2,
+                ua.sapling().unwrap().diversifier().0.to_vec(),
+            ))
+            .unwrap();
+        assert_eq!(ua.encode(&setup.network), sapling.address);
+        assert_eq!(5, sapling.diversifier_index[0]);
+        assert!(sapling.diversifier_index[1..].iter().all(|b| *b == 0));
+
+        let filter = IncomingPaymentsFilter {
+            limit: Some(10),
+            ..Default::default()
+        };
+        let page = get_incoming_payments(&setup.db, &conn, &setup.network, 1, &filter).unwrap();
+        assert!(page.payments.is_empty());
+        assert!(page.next.is_none());
+    }
+}
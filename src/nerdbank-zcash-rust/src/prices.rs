@@ -0,0 +1,315 @@
+use std::{num::NonZeroU32, time::SystemTime};
+
+use rusqlite::{named_params, Connection, OptionalExtension};
+use time::OffsetDateTime;
+use zcash_client_sqlite::AccountId;
+
+use crate::{
+    analysis::{get_user_balances, UserBalances},
+    backing_store::open_connection,
+    error::Error,
+    interop::{DbInit, PriceProvider},
+    reporting::{to_fiat, PricePoint, TimeWindow},
+    sql_statements::{
+        GET_CACHED_PRICES, GET_DAYS_MISSING_PRICES, GET_LATEST_CACHED_PRICE, SET_CACHED_PRICE,
+    },
+};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// An account's balances, along with their value in a fiat currency at one price.
+#[derive(Debug, Clone)]
+pub struct FiatUserBalances {
+    pub balances: UserBalances,
+    /// The stored price the fiat values were computed with.
+    pub price: PricePoint,
+    pub spendable: f64,
+    pub immature_change: f64,
+    pub minimum_fees: f64,
+    pub immature_income: f64,
+    pub dust: f64,
+    pub incoming: f64,
+    pub incoming_dust: f64,
+}
+
+/// Stores the daily prices of ZEC in the given currency, replacing any already stored for the same (UTC) day.
+///
+/// Only one price is kept per day, so each price is recorded against the start of the day its time falls on.
+pub fn set_prices(
+    conn: &mut Connection,
+    currency: &str,
+    prices: &[PricePoint],
+) -> Result<(), Error> {
+    let currency = validate_currency(currency)?;
+    let tx = conn.transaction()?;
+    for price in prices {
+        if !price.price.is_finite() || price.price < 0.0 {
+            return Err(Error::InvalidArgument(format!(
+                "Invalid price: {}",
+                price.price
+            )));
+        }
+
+        tx.execute(
+            SET_CACHED_PRICE,
+            named_params! {
+                ":currency": currency,
+                ":day": day_of(price.time),
+                ":price": price.price,
+            },
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Gets the stored daily prices of ZEC in the given currency for the days that start within the window,
+/// in chronological order.
+pub fn get_prices(
+    conn: &Connection,
+    currency: &str,
+    window: &TimeWindow,
+) -> Result<Vec<PricePoint>, Error> {
+    let currency = validate_currency(currency)?;
+    let unix_time = |t: SystemTime| OffsetDateTime::from(t).unix_timestamp();
+    let mut stmt = conn.prepare(GET_CACHED_PRICES)?;
+    let prices = stmt
+        .query_map(
+            named_params! {
+                ":currency": currency,
+                ":start": window.start.map(unix_time),
+                ":end": window.end.map(unix_time),
+            },
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?)),
+        )?
+        .map(|row| {
+            let (day, price) = row?;
+            Ok(PricePoint {
+                time: start_of(day)?,
+                price,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(prices)
+}
+
+/// Asks the provider for the prices of ZEC on each day that the wallet has a mined transaction
+/// but no stored price in the given currency, and stores the prices it returns.
+///
+/// Returns the number of those days that now have a price,
+/// which may be fewer than were asked for if the provider doesn't know them all.
+pub fn backfill_prices(
+    conn: &mut Connection,
+    currency: &str,
+    provider: &dyn PriceProvider,
+) -> Result<u32, Error> {
+    let currency = validate_currency(currency)?;
+    let days = days_missing_prices(conn, &currency)?;
+    if days.is_empty() {
+        return Ok(0);
+    }
+
+    let missing = days.len();
+    let days = days
+        .into_iter()
+        .map(start_of)
+        .collect::<Result<Vec<_>, _>>()?;
+    let prices = provider.get_prices(currency.clone(), days);
+    set_prices(conn, &currency, &prices)?;
+
+    // The provider may skip days, or return prices for days we didn't ask about.
+    let filled = missing - days_missing_prices(conn, &currency)?.len();
+    Ok(filled as u32)
+}
+
+fn days_missing_prices(conn: &Connection, currency: &str) -> Result<Vec<i64>, Error> {
+    Ok(conn
+        .prepare(GET_DAYS_MISSING_PRICES)?
+        .query_map(named_params! { ":currency": currency }, |row| {
+            row.get::<_, i64>(0)
+        })?
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Gets an account's balances valued at the most recent stored price of ZEC in the given currency.
+pub fn get_user_balances_fiat(
+    config: &DbInit,
+    account_id: AccountId,
+    min_confirmations: NonZeroU32,
+    currency: &str,
+) -> Result<FiatUserBalances, Error> {
+    let currency = validate_currency(currency)?;
+    let (day, price) = open_connection(&config.data_file)?
+        .query_row(
+            GET_LATEST_CACHED_PRICE,
+            named_params! { ":currency": currency },
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?)),
+        )
+        .optional()?
+        .ok_or_else(|| Error::InvalidArgument(format!("No {currency} price has been stored.")))?;
+    let balances = get_user_balances(config, account_id, min_confirmations)?;
+
+    Ok(FiatUserBalances {
+        price: PricePoint {
+            time: start_of(day)?,
+            price,
+        },
+        spendable: to_fiat(balances.spendable, price),
+        immature_change: to_fiat(balances.immature_change, price),
+        minimum_fees: to_fiat(balances.minimum_fees, price),
+        immature_income: to_fiat(balances.immature_income, price),
+        dust: to_fiat(balances.dust, price),
+        incoming: to_fiat(balances.incoming, price),
+        incoming_dust: to_fiat(balances.incoming_dust, price),
+        balances,
+    })
+}
+
+/// Currencies are stored as upper case ISO 4217 codes (e.g. "USD").
+fn validate_currency(currency: &str) -> Result<String, Error> {
+    let currency = currency.trim().to_ascii_uppercase();
+    if currency.is_empty() {
+        return Err(Error::InvalidArgument(
+            "A currency code is required.".to_string(),
+        ));
+    }
+
+    Ok(currency)
+}
+
+/// Gets the number of the (UTC) day that the given time falls on, counting from the Unix epoch.
+fn day_of(time: SystemTime) -> i64 {
+    OffsetDateTime::from(time)
+        .unix_timestamp()
+        .div_euclid(SECONDS_PER_DAY)
+}
+
+fn start_of(day: i64) -> Result<SystemTime, Error> {
+    Ok(OffsetDateTime::from_unix_timestamp(day * SECONDS_PER_DAY)
+        .map_err(|e| Error::Internal(format!("Invalid price date: {}", e)))?
+        .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        backing_store::open_connection,
+        test_constants::{setup_local_test, setup_test},
+    };
+
+    use super::*;
+
+    /// 2023-01-01T00:00:00Z
+    const JAN_1_2023: u64 = 1_672_531_200;
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(JAN_1_2023 + seconds)
+    }
+
+    #[derive(Debug)]
+    struct NoPrices;
+
+    impl PriceProvider for NoPrices {
+        fn get_prices(&self, _currency: String, _days: Vec<SystemTime>) -> Vec<PricePoint> {
+            panic!("No prices should be requested.");
+        }
+    }
+
+    #[test]
+    fn test_set_and_get_prices() {
+        let setup = setup_local_test();
+        let mut conn = open_connection(&setup.data_file).unwrap();
+        let day = SECONDS_PER_DAY as u64;
+
+        set_prices(
+            &mut conn,
+            "usd",
+            &[
+                PricePoint {
+                    time: at(3600),
+                    price: 20.0,
+                },
+                PricePoint {
+                    time: at(day + 3600),
+                    price: 21.0,
+                },
+            ],
+        )
+        .unwrap();
+
+        // A later price for the same day replaces the first.
+        set_prices(
+            &mut conn,
+            "USD",
+            &[PricePoint {
+                time: at(day + 7200),
+                price: 22.0,
+            }],
+        )
+        .unwrap();
+
+        let prices = get_prices(&conn, " USD ", &TimeWindow::default()).unwrap();
+        assert_eq!(2, prices.len());
+        assert_eq!(at(0), prices[0].time);
+        assert_eq!(20.0, prices[0].price);
+        assert_eq!(at(day), prices[1].time);
+        assert_eq!(22.0, prices[1].price);
+
+        let window = TimeWindow {
+            start: Some(at(1)),
+            end: None,
+        };
+        assert_eq!(1, get_prices(&conn, "USD", &window).unwrap().len());
+        assert!(get_prices(&conn, "EUR", &TimeWindow::default())
+            .unwrap()
+            .is_empty());
+
+        assert!(set_prices(
+            &mut conn,
+            "USD",
+            &[PricePoint {
+                time: at(0),
+                price: f64::NAN,
+            }],
+        )
+        .is_err());
+        assert!(get_prices(&conn, "", &TimeWindow::default()).is_err());
+
+        // There are no transactions to find prices for.
+        assert_eq!(0, backfill_prices(&mut conn, "USD", &NoPrices).unwrap());
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_get_user_balances_fiat() {
+        let mut setup = setup_test().await;
+        let (_, _, account_id, _) = setup.create_account().await.unwrap();
+        let min_confirmations = NonZeroU32::new(3).unwrap();
+        assert!(
+            get_user_balances_fiat(&setup.db_init, account_id, min_confirmations, "USD").is_err()
+        );
+
+        let mut conn = open_connection(&setup.data_file).unwrap();
+        set_prices(
+            &mut conn,
+            "USD",
+            &[
+                PricePoint {
+                    time: at(0),
+                    price: 20.0,
+                },
+                PricePoint {
+                    time: at(SECONDS_PER_DAY as u64),
+                    price: 30.0,
+                },
+            ],
+        )
+        .unwrap();
+
+        let fiat =
+            get_user_balances_fiat(&setup.db_init, account_id, min_confirmations, "usd").unwrap();
+        assert_eq!(30.0, fiat.price.price);
+        assert_eq!(0.0, fiat.spendable);
+    }
+}
@@ -0,0 +1,313 @@
+use std::time::{Duration, SystemTime};
+
+use rusqlite::{named_params, Connection, Row};
+use time::OffsetDateTime;
+use zcash_client_sqlite::AccountId;
+
+use crate::{
+    error::Error,
+    interop::TransactionSendDetail,
+    send_queue::{queue_payment, to_payment, QueuedPayment},
+    sql_statements::{
+        ADVANCE_RECURRING_PAYMENT, CANCEL_RECURRING_PAYMENT, CREATE_RECURRING_PAYMENT,
+        GET_DUE_RECURRING_PAYMENTS, GET_RECURRING_PAYMENTS,
+    },
+};
+
+/// The longest interval a recurring payment may have.
+const MAX_INTERVAL_SECONDS: u64 = 10 * 366 * 24 * 60 * 60;
+
+/// The most payments a recurring payment adds to the send queue at once.
+/// A wallet that hasn't been opened in a long time skips the rest rather than paying them all at once.
+const MAX_MISSED_PAYMENTS: u64 = 12;
+
+/// A payment that is added to the send queue at a regular interval.
+#[derive(Debug, Clone)]
+pub struct RecurringPayment {
+    pub id: u32,
+    pub account_id: u32,
+    pub recipient: String,
+    pub value: u64,
+    pub memo: Option<Vec<u8>>,
+    /// The number of seconds between payments.
+    pub interval_seconds: u64,
+    /// When the next payment is due.
+    pub next_due: SystemTime,
+    /// The time after which no more payments are made, if any.
+    pub end: Option<SystemTime>,
+}
+
+/// Schedules a payment to be made every `interval_seconds`, starting at `start`.
+pub fn create_recurring_payment(
+    conn: &Connection,
+    account_id: AccountId,
+    detail: &TransactionSendDetail,
+    start: SystemTime,
+    interval_seconds: u64,
+    end: Option<SystemTime>,
+) -> Result<RecurringPayment, Error> {
+    to_payment(&detail.recipient, detail.value, detail.memo.as_deref())?;
+    if interval_seconds == 0 {
+        return Err(Error::InvalidArgument(
+            "The interval must be at least one second.".to_string(),
+        ));
+    }
+    if interval_seconds > MAX_INTERVAL_SECONDS {
+        return Err(Error::InvalidArgument(format!(
+            "The interval may be at most {} seconds.",
+            MAX_INTERVAL_SECONDS
+        )));
+    }
+    if end.is_some_and(|e| e < start) {
+        return Err(Error::InvalidArgument(
+            "The schedule cannot end before it starts.".to_string(),
+        ));
+    }
+
+    conn.execute(
+        CREATE_RECURRING_PAYMENT,
+        named_params! {
+            ":account_id": u32::from(account_id),
+            ":recipient": detail.recipient,
+            ":value": detail.value,
+            ":memo": detail.memo,
+            ":interval_seconds": interval_seconds,
+            ":next_due": unix_time(start),
+            ":end_time": end.map(unix_time),
+        },
+    )?;
+
+    Ok(RecurringPayment {
+        id: conn.last_insert_rowid() as u32,
+        account_id: account_id.into(),
+        recipient: detail.recipient.clone(),
+        value: detail.value,
+        memo: detail.memo.clone(),
+        interval_seconds,
+        next_due: start,
+        end,
+    })
+}
+
+/// Gets an account's recurring payments.
+pub fn get_recurring_payments(
+    conn: &Connection,
+    account_id: AccountId,
+) -> Result<Vec<RecurringPayment>, Error> {
+    conn.prepare(GET_RECURRING_PAYMENTS)?
+        .query_map(
+            named_params! { ":account_id": u32::from(account_id) },
+            read_recurring_payment,
+        )?
+        .map(|row| row?)
+        .collect()
+}
+
+/// Stops a recurring payment. Payments it already added to the send queue stay there.
+///
+/// Returns `true` if the recurring payment existed.
+pub fn cancel_recurring_payment(conn: &Connection, id: u32) -> Result<bool, Error> {
+    Ok(conn.execute(CANCEL_RECURRING_PAYMENT, named_params! { ":id": id })? > 0)
+}
+
+/// Adds a payment to the send queue for each time a recurring payment has come due, and schedules the next.
+///
+/// A recurring payment that came due more than once since this was last called queues a payment for each time,
+/// up to [`MAX_MISSED_PAYMENTS`]. Returns the payments that were queued.
+pub fn enqueue_due_payments(
+    conn: &mut Connection,
+    now: SystemTime,
+) -> Result<Vec<QueuedPayment>, Error> {
+    let tx = conn.transaction()?;
+    let due = tx
+        .prepare(GET_DUE_RECURRING_PAYMENTS)?
+        .query_map(
+            named_params! { ":now": unix_time(now) },
+            read_recurring_payment,
+        )?
+        .map(|row| row?)
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut queued = Vec::new();
+    for schedule in due {
+        let detail = TransactionSendDetail {
+            recipient: schedule.recipient.clone(),
+            value: schedule.value,
+            memo: schedule.memo.clone(),
+        };
+        let interval = schedule.interval_seconds.max(1);
+        let periods_since = |time: SystemTime| {
+            time.duration_since(schedule.next_due)
+                .map_or(0, |elapsed| elapsed.as_secs() / interval + 1)
+        };
+
+        // Pay for each period that has come due before the schedule ends, and skip past the rest.
+        let periods = periods_since(now);
+        let payments = schedule
+            .end
+            .map_or(periods, |end| periods.min(periods_since(end)))
+            .min(MAX_MISSED_PAYMENTS);
+        for _ in 0..payments {
+            queued.push(queue_payment(&tx, schedule.account_id, &detail, now)?);
+        }
+
+        let next_due = periods
+            .checked_mul(interval)
+            .and_then(|secs| schedule.next_due.checked_add(Duration::from_secs(secs)));
+        match next_due {
+            Some(next_due) => tx.execute(
+                ADVANCE_RECURRING_PAYMENT,
+                named_params! { ":id": schedule.id, ":next_due": unix_time(next_due) },
+            )?,
+            // The schedule can never come due again.
+            None => tx.execute(
+                CANCEL_RECURRING_PAYMENT,
+                named_params! { ":id": schedule.id },
+            )?,
+        };
+    }
+
+    tx.commit()?;
+    Ok(queued)
+}
+
+fn unix_time(time: SystemTime) -> i64 {
+    OffsetDateTime::from(time).unix_timestamp()
+}
+
+fn read_recurring_payment(row: &Row) -> rusqlite::Result<Result<RecurringPayment, Error>> {
+    let to_time = |t: i64| {
+        OffsetDateTime::from_unix_timestamp(t)
+            .map(SystemTime::from)
+            .map_err(|e| Error::Internal(format!("Invalid schedule time: {}", e)))
+    };
+    let next_due: i64 = row.get("next_due")?;
+    let end: Option<i64> = row.get("end_time")?;
+    let id = row.get("id")?;
+    let account_id = row.get("account_id")?;
+    let recipient = row.get("recipient")?;
+    let value = row.get("value")?;
+    let memo = row.get("memo")?;
+    let interval_seconds = row.get("interval_seconds")?;
+
+    Ok((|| {
+        Ok(RecurringPayment {
+            id,
+            account_id,
+            recipient,
+            value,
+            memo,
+            interval_seconds,
+            next_due: to_time(next_due)?,
+            end: end.map(to_time).transpose()?,
+        })
+    })())
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_keys::address::Address;
+
+    use crate::{
+        backing_store::open_connection, send_queue::get_send_queue,
+        test_constants::setup_local_test,
+    };
+
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn test_recurring_payments() {
+        let mut setup = setup_local_test();
+        let (_, _, account_id, usk) = setup.create_account().unwrap();
+        let mut conn = open_connection(&setup.data_file).unwrap();
+        let (_, address) = usk
+            .to_unified_full_viewing_key()
+            .sapling()
+            .unwrap()
+            .default_address();
+        let detail = TransactionSendDetail {
+            recipient: Address::Sapling(address).encode(&setup.network),
+            value: 10_000,
+            memo: None,
+        };
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let schedule = create_recurring_payment(
+            &conn,
+            account_id,
+            &detail,
+            start,
+            DAY,
+            Some(start + Duration::from_secs(2 * DAY)),
+        )
+        .unwrap();
+
+        assert!(
+            enqueue_due_payments(&mut conn, start - Duration::from_secs(1))
+                .unwrap()
+                .is_empty()
+        );
+
+        // Two payments have come due by the second day.
+        let queued = enqueue_due_payments(&mut conn, start + Duration::from_secs(DAY + 1)).unwrap();
+        assert_eq!(2, queued.len());
+        assert_eq!(
+            start + Duration::from_secs(2 * DAY),
+            get_recurring_payments(&conn, account_id).unwrap()[0].next_due
+        );
+
+        // The last payment is on the day the schedule ends.
+        let queued =
+            enqueue_due_payments(&mut conn, start + Duration::from_secs(10 * DAY)).unwrap();
+        assert_eq!(1, queued.len());
+        assert_eq!(3, get_send_queue(&conn, account_id).unwrap().len());
+
+        assert!(cancel_recurring_payment(&conn, schedule.id).unwrap());
+        assert!(get_recurring_payments(&conn, account_id)
+            .unwrap()
+            .is_empty());
+
+        assert!(create_recurring_payment(&conn, account_id, &detail, start, 0, None).is_err());
+        assert!(create_recurring_payment(
+            &conn,
+            account_id,
+            &detail,
+            start,
+            MAX_INTERVAL_SECONDS + 1,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_enqueue_due_payments_skips_long_missed_periods() {
+        let mut setup = setup_local_test();
+        let (_, _, account_id, usk) = setup.create_account().unwrap();
+        let mut conn = open_connection(&setup.data_file).unwrap();
+        let (_, address) = usk
+            .to_unified_full_viewing_key()
+            .sapling()
+            .unwrap()
+            .default_address();
+        let detail = TransactionSendDetail {
+            recipient: Address::Sapling(address).encode(&setup.network),
+            value: 10_000,
+            memo: None,
+        };
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        create_recurring_payment(&conn, account_id, &detail, start, 1, None).unwrap();
+
+        // A year of missed payments queues only a few, and resumes from now.
+        let now = start + Duration::from_secs(365 * DAY);
+        let queued = enqueue_due_payments(&mut conn, now).unwrap();
+        assert_eq!(MAX_MISSED_PAYMENTS as usize, queued.len());
+        assert_eq!(
+            now + Duration::from_secs(1),
+            get_recurring_payments(&conn, account_id).unwrap()[0].next_due
+        );
+        assert!(enqueue_due_payments(&mut conn, now).unwrap().is_empty());
+    }
+}
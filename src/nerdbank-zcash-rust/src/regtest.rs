@@ -0,0 +1,198 @@
+//! Helpers for tests that drive a local regtest zcashd and the lightwalletd that fronts it,
+//! so that they can mine blocks and fund addresses on demand instead of depending on a public testnet server.
+//!
+//! The node is located with these environment variables:
+//! - `ZCASHD_RPC_URI` (e.g. `http://127.0.0.1:18232`)
+//! - `ZCASHD_RPC_USER` and `ZCASHD_RPC_PASSWORD`
+//! - `LIGHTWALLETD_URI` (e.g. `http://127.0.0.1:9067`)
+
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use http::Uri;
+use serde_json::{json, Value};
+use zcash_client_backend::proto::service::TxFilter;
+
+use crate::{error::Error, grpc::get_client};
+
+/// How often to check on something we're waiting for.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A regtest zcashd node and the lightwalletd server that serves its chain.
+#[derive(Debug, Clone)]
+pub struct RegtestNode {
+    rpc_uri: String,
+    authorization: String,
+    pub lightwalletd_uri: Uri,
+}
+
+impl RegtestNode {
+    /// Locates the regtest node from environment variables,
+    /// or returns `None` if they are not set so that tests can skip themselves.
+    pub fn from_env() -> Option<Self> {
+        let rpc_uri = std::env::var("ZCASHD_RPC_URI").ok()?;
+        let user = std::env::var("ZCASHD_RPC_USER").unwrap_or_default();
+        let password = std::env::var("ZCASHD_RPC_PASSWORD").unwrap_or_default();
+        let lightwalletd_uri = std::env::var("LIGHTWALLETD_URI").ok()?.parse().ok()?;
+        Some(Self::new(rpc_uri, &user, &password, lightwalletd_uri))
+    }
+
+    pub fn new(rpc_uri: String, user: &str, password: &str, lightwalletd_uri: Uri) -> Self {
+        Self {
+            rpc_uri,
+            authorization: format!("Basic {}", STANDARD.encode(format!("{user}:{password}"))),
+            lightwalletd_uri,
+        }
+    }
+
+    /// Mines the given number of blocks, and returns their hashes.
+    pub async fn generate(&self, blocks: u32) -> Result<Vec<String>, Error> {
+        let result = self.rpc("generate", json!([blocks])).await?;
+        serde_json::from_value(result).map_err(|e| Error::Internal(e.to_string()))
+    }
+
+    /// Sends funds from the node's wallet to the given address, and returns the txid.
+    ///
+    /// The node must already have mature coinbase funds, which can be arranged by generating at least 101 blocks.
+    pub async fn fund_address(&self, address: &str, zatoshis: u64) -> Result<String, Error> {
+        let amount = zatoshis as f64 / 100_000_000.0;
+        let operation_id = self
+            .rpc(
+                "z_sendmany",
+                json!([
+                    "ANY_TADDR",
+                    [{ "address": address, "amount": amount }],
+                    1,
+                    null,
+                    "AllowRevealedSenders",
+                ]),
+            )
+            .await?;
+
+        // The send runs as an asynchronous operation on the node.
+        loop {
+            let results = self
+                .rpc("z_getoperationresult", json!([[operation_id]]))
+                .await?;
+            if let Some(result) = results.as_array().and_then(|r| r.first()) {
+                return match result["status"].as_str() {
+                    Some("success") => result["result"]["txid"]
+                        .as_str()
+                        .map(str::to_string)
+                        .ok_or_else(|| Error::Internal("No txid in operation result.".to_string())),
+                    _ => Err(Error::Internal(format!(
+                        "Funding {} failed: {}",
+                        address, result["error"]
+                    ))),
+                };
+            }
+
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Waits until lightwalletd reports that a transaction has been mined, and returns the height of its block.
+    ///
+    /// This does not mine any blocks itself, so [`RegtestNode::generate`] should typically be called first.
+    pub async fn await_inclusion(&self, txid: &str, timeout: Duration) -> Result<u64, Error> {
+        // RPC displays txids in reverse byte order.
+        let mut hash = (0..txid.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&txid[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| Error::InvalidArgument(format!("Invalid txid: {txid}")))?;
+        hash.reverse();
+
+        let mut client = get_client(self.lightwalletd_uri.clone()).await?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let response = client
+                .get_transaction(TxFilter {
+                    hash: hash.clone(),
+                    ..Default::default()
+                })
+                .await;
+            if let Ok(tx) = response {
+                let height = tx.into_inner().height;
+                if height > 0 && height != u64::MAX {
+                    return Ok(height);
+                }
+            }
+
+            if Instant::now() > deadline {
+                return Err(Error::Internal(format!(
+                    "Transaction {txid} was not mined within {timeout:?}."
+                )));
+            }
+
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Invokes a JSON-RPC method on zcashd.
+    async fn rpc(&self, method: &str, params: Value) -> Result<Value, Error> {
+        let request = json!({
+            "jsonrpc": "1.0",
+            "id": "nerdbank-zcash-rust",
+            "method": method,
+            "params": params,
+        });
+
+        // minreq blocks, so it gets a thread of its own rather than one of the runtime's workers.
+        let rpc_uri = self.rpc_uri.clone();
+        let authorization = self.authorization.clone();
+        let response = tokio::task::spawn_blocking(move || {
+            minreq::post(rpc_uri)
+                .with_header("Authorization", authorization)
+                .with_header("Content-Type", "application/json")
+                .with_body(request.to_string())
+                .send()
+        })
+        .await??;
+        let mut body: Value =
+            serde_json::from_str(response.as_str()?).map_err(|e| Error::Internal(e.to_string()))?;
+        if !body["error"].is_null() {
+            return Err(Error::Internal(format!(
+                "{} failed: {}",
+                method, body["error"]
+            )));
+        }
+
+        Ok(body["result"].take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_util::sync::CancellationToken;
+
+    use crate::lightclient::get_block_height;
+
+    use super::*;
+
+    #[tokio_shared_rt::test]
+    async fn test_fund_address() {
+        let Some(node) = RegtestNode::from_env() else {
+            // There's no regtest node to test against.
+            return;
+        };
+
+        // Mature some coinbase funds to send from.
+        node.generate(101).await.unwrap();
+        let address = node.rpc("getnewaddress", json!([])).await.unwrap();
+        let txid = node
+            .fund_address(address.as_str().unwrap(), 100_000)
+            .await
+            .unwrap();
+        node.generate(1).await.unwrap();
+
+        let height = node
+            .await_inclusion(&txid, Duration::from_secs(60))
+            .await
+            .unwrap();
+        let tip = get_block_height(node.lightwalletd_uri.clone(), CancellationToken::new())
+            .await
+            .unwrap();
+        assert!(u64::from(tip) >= height);
+    }
+}
@@ -0,0 +1,470 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::SystemTime,
+};
+
+use rusqlite::named_params;
+use time::OffsetDateTime;
+use zcash_primitives::consensus::Network;
+
+use crate::{
+    amount::COIN,
+    backing_store::{open_connection, Db},
+    error::Error,
+    interop::{DbInit, Transaction},
+    prices::get_prices,
+    sql_statements::{GET_FEES_PAID, GET_SPENT_NOTE_SOURCES},
+};
+
+/// The fiat price of one ZEC as of some point in time.
+#[derive(Debug, Clone)]
+pub struct PricePoint {
+    pub time: SystemTime,
+    pub price: f64,
+}
+
+/// How the cost basis of spent ZEC is determined.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CostBasisMethod {
+    /// ZEC is spent in the order it was received.
+    Fifo,
+    /// ZEC is spent from the transactions that received the notes that were actually spent,
+    /// falling back to FIFO for any remainder.
+    SpecificIdentification,
+}
+
+/// A transaction that changed the account's balance, valued in fiat.
+#[derive(Debug, Clone)]
+pub struct TaxReportEntry {
+    pub txid: Vec<u8>,
+    pub block_time: SystemTime,
+    /// The net change to the account's balance, in zatoshis. Disposals include the fee.
+    pub account_balance_delta: i64,
+    /// The fiat price of one ZEC at the time of the transaction.
+    pub price: f64,
+    /// The fiat value of `account_balance_delta`.
+    pub fiat_value: f64,
+    /// The fiat cost of the ZEC disposed of by this transaction, if it was a disposal.
+    pub cost_basis: Option<f64>,
+    /// The fiat gain (or loss, when negative) realized by this transaction, if it was a disposal.
+    pub realized_gain: Option<f64>,
+}
+
+/// The taxable events for an account in one calendar year (UTC).
+#[derive(Debug, Clone)]
+pub struct TaxReport {
+    pub year: i32,
+    pub method: CostBasisMethod,
+    pub entries: Vec<TaxReportEntry>,
+    /// The fiat value of all ZEC disposed of during the year.
+    pub total_proceeds: f64,
+    pub total_cost_basis: f64,
+    pub total_realized_gain: f64,
+}
+
+/// A span of time to summarize. Either end may be left open.
+#[derive(Debug, Clone, Default)]
+pub struct TimeWindow {
+    /// The (inclusive) start of the window.
+    pub start: Option<SystemTime>,
+    /// The (exclusive) end of the window.
+    pub end: Option<SystemTime>,
+}
+
+/// The fees an account paid in one calendar month (UTC).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonthlyFees {
+    pub year: i32,
+    /// The month, from 1 to 12.
+    pub month: u8,
+    pub total_fees: u64,
+    pub transaction_count: u32,
+}
+
+/// A summary of the fees an account paid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeStats {
+    pub total_fees: u64,
+    pub transaction_count: u32,
+    /// The average fee per transaction, rounded down. 0 when no fees were paid.
+    pub average_fee: u64,
+    /// The fees paid in each month that had any, in chronological order.
+    pub by_month: Vec<MonthlyFees>,
+}
+
+/// ZEC acquired by one transaction that has not yet been disposed of.
+struct Lot {
+    txid: Vec<u8>,
+    remaining: u64,
+    price: f64,
+}
+
+pub fn get_tax_report(
+    config: DbInit,
+    account_id: u32,
+    year: i32,
+    method: CostBasisMethod,
+    currency: &str,
+) -> Result<TaxReport, Error> {
+    let network: Network = config.network.into();
+    let mut db = Db::load(&config.data_file, network)?;
+    let mut conn = open_connection(&config.data_file)?;
+    let prices = get_prices(&conn, currency, &TimeWindow::default())?;
+    let transactions =
+        crate::sync::get_transactions(&mut db, &mut conn, &network, Some(account_id), None, None)?;
+
+    // Map each spending transaction to the transactions that received the notes it spent.
+    let mut spent_sources: HashMap<Vec<u8>, Vec<Vec<u8>>> = HashMap::new();
+    let mut stmt = conn.prepare(GET_SPENT_NOTE_SOURCES)?;
+    let rows = stmt.query_map(named_params! { ":account_id": account_id }, |row| {
+        Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+    })?;
+    for row in rows {
+        let (spending_txid, source_txid) = row?;
+        spent_sources
+            .entry(spending_txid)
+            .or_default()
+            .push(source_txid);
+    }
+
+    compute_tax_report(&transactions, &spent_sources, &prices, year, method)
+}
+
+/// Summarizes the fees the account paid for transactions mined within the given window.
+pub fn get_fee_stats(
+    config: DbInit,
+    account_id: u32,
+    window: TimeWindow,
+) -> Result<FeeStats, Error> {
+    let conn = open_connection(&config.data_file)?;
+    let unix_time = |t: SystemTime| OffsetDateTime::from(t).unix_timestamp();
+    let mut stmt = conn.prepare(GET_FEES_PAID)?;
+    let fees = stmt
+        .query_map(
+            named_params! {
+                ":account_id": account_id,
+                ":start": window.start.map(unix_time),
+                ":end": window.end.map(unix_time),
+            },
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, u64>(1)?)),
+        )?
+        .map(|row| {
+            let (block_time, fee) = row?;
+            let block_time = OffsetDateTime::from_unix_timestamp(block_time)
+                .map_err(|e| Error::Internal(format!("Invalid block time: {}", e)))?;
+            Ok((block_time, fee))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(summarize_fees(&fees))
+}
+
+/// Summarizes fees, which must be in chronological order.
+fn summarize_fees(fees: &[(OffsetDateTime, u64)]) -> FeeStats {
+    let mut stats = FeeStats {
+        total_fees: 0,
+        transaction_count: 0,
+        average_fee: 0,
+        by_month: Vec::new(),
+    };
+
+    for (block_time, fee) in fees {
+        let (year, month) = (block_time.year(), u8::from(block_time.month()));
+        match stats.by_month.last_mut() {
+            Some(m) if m.year == year && m.month == month => {
+                m.total_fees += fee;
+                m.transaction_count += 1;
+            }
+            _ => stats.by_month.push(MonthlyFees {
+                year,
+                month,
+                total_fees: *fee,
+                transaction_count: 1,
+            }),
+        }
+
+        stats.total_fees += fee;
+        stats.transaction_count += 1;
+    }
+
+    if stats.transaction_count > 0 {
+        stats.average_fee = stats.total_fees / u64::from(stats.transaction_count);
+    }
+
+    stats
+}
+
+fn compute_tax_report(
+    transactions: &[Transaction],
+    spent_sources: &HashMap<Vec<u8>, Vec<Vec<u8>>>,
+    prices: &[PricePoint],
+    year: i32,
+    method: CostBasisMethod,
+) -> Result<TaxReport, Error> {
+    let mut report = TaxReport {
+        year,
+        method,
+        entries: Vec::new(),
+        total_proceeds: 0.0,
+        total_cost_basis: 0.0,
+        total_realized_gain: 0.0,
+    };
+    let mut lots: VecDeque<Lot> = VecDeque::new();
+
+    // Transactions are already ordered by height. Unmined transactions have no taxable date yet.
+    for tx in transactions {
+        let block_time = match tx.block_time {
+            Some(t) => t,
+            None => continue,
+        };
+        let tx_year = OffsetDateTime::from(block_time).year();
+        if tx_year > year {
+            break;
+        }
+
+        let price = price_at(prices, block_time)?;
+        let fiat_value = to_fiat(tx.account_balance_delta.unsigned_abs(), price)
+            * tx.account_balance_delta.signum() as f64;
+
+        let cost_basis = if tx.account_balance_delta > 0 {
+            lots.push_back(Lot {
+                txid: tx.txid.clone(),
+                remaining: tx.account_balance_delta as u64,
+                price,
+            });
+            None
+        } else if tx.account_balance_delta < 0 {
+            let sources = match method {
+                CostBasisMethod::Fifo => None,
+                CostBasisMethod::SpecificIdentification => {
+                    spent_sources.get(&tx.txid).map(Vec::as_slice)
+                }
+            };
+            Some(dispose(
+                &mut lots,
+                tx.account_balance_delta.unsigned_abs(),
+                sources,
+            ))
+        } else {
+            None
+        };
+
+        if tx_year == year {
+            let realized_gain = cost_basis.map(|basis| -fiat_value - basis);
+            if let (Some(basis), Some(gain)) = (cost_basis, realized_gain) {
+                report.total_proceeds += -fiat_value;
+                report.total_cost_basis += basis;
+                report.total_realized_gain += gain;
+            }
+
+            report.entries.push(TaxReportEntry {
+                txid: tx.txid.clone(),
+                block_time,
+                account_balance_delta: tx.account_balance_delta,
+                price,
+                fiat_value,
+                cost_basis,
+                realized_gain,
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+/// Removes the given amount from the lots, and returns the fiat cost basis of what was removed.
+///
+/// Lots that received the spent notes are drawn from first when `sources` is given.
+/// Any amount beyond what the lots hold is treated as having no cost basis.
+fn dispose(lots: &mut VecDeque<Lot>, mut amount: u64, sources: Option<&[Vec<u8>]>) -> f64 {
+    let mut basis = 0.0;
+    let mut take = |lot: &mut Lot, amount: &mut u64| {
+        let taken = lot.remaining.min(*amount);
+        lot.remaining -= taken;
+        *amount -= taken;
+        basis += to_fiat(taken, lot.price);
+    };
+
+    for source in sources.into_iter().flatten() {
+        if let Some(lot) = lots.iter_mut().find(|l| &l.txid == source) {
+            take(lot, &mut amount);
+        }
+    }
+
+    for lot in lots.iter_mut() {
+        take(lot, &mut amount);
+    }
+
+    lots.retain(|l| l.remaining > 0);
+    basis
+}
+
+/// Gets the most recent price at or before the given time.
+fn price_at(prices: &[PricePoint], time: SystemTime) -> Result<f64, Error> {
+    let index = prices.partition_point(|p| p.time <= time);
+    match index {
+        0 => Err(Error::InvalidArgument(format!(
+            "No price is known as of {}.",
+            OffsetDateTime::from(time)
+        ))),
+        i => Ok(prices[i - 1].price),
+    }
+}
+
+pub(crate) fn to_fiat(zatoshis: u64, price: f64) -> f64 {
+    zatoshis as f64 / COIN as f64 * price
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// 2023-01-01T00:00:00Z
+    const JAN_1_2023: u64 = 1_672_531_200;
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn at(days: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(JAN_1_2023 + days * DAY)
+    }
+
+    fn tx(id: u8, days: u64, delta: i64) -> Transaction {
+        Transaction {
+            account_id: 0,
+            account_uuid: Vec::new(),
+            txid: vec![id],
+            block_time: Some(at(days)),
+            mined_height: Some(days as u32),
+            expiry_height: None,
+            blocks_until_expiry: None,
+            expired_unmined: false,
+            account_balance_delta: delta,
+            fee: None,
+            version: None,
+            size: None,
+            consensus_branch_id: None,
+            outgoing_recovered: false,
+            label: None,
+            tags: Vec::new(),
+            outgoing: Vec::new(),
+            incoming: Vec::new(),
+            change: Vec::new(),
+            transparent_inputs: Vec::new(),
+            transparent_outputs: Vec::new(),
+        }
+    }
+
+    fn prices() -> Vec<PricePoint> {
+        vec![
+            PricePoint {
+                time: at(0),
+                price: 10.0,
+            },
+            PricePoint {
+                time: at(10),
+                price: 20.0,
+            },
+            PricePoint {
+                time: at(400),
+                price: 40.0,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_fifo_and_specific_identification() {
+        let transactions = [
+            tx(1, 1, COIN as i64),
+            tx(2, 11, COIN as i64),
+            tx(3, 12, -(COIN as i64) / 2),
+        ];
+        let sources = HashMap::from([(vec![3], vec![vec![2]])]);
+
+        let fifo = compute_tax_report(
+            &transactions,
+            &sources,
+            &prices(),
+            2023,
+            CostBasisMethod::Fifo,
+        )
+        .unwrap();
+        assert_eq!(3, fifo.entries.len());
+        assert_eq!(Some(5.0), fifo.entries[2].cost_basis);
+        assert_eq!(10.0, fifo.total_proceeds);
+        assert_eq!(5.0, fifo.total_realized_gain);
+
+        let specific = compute_tax_report(
+            &transactions,
+            &sources,
+            &prices(),
+            2023,
+            CostBasisMethod::SpecificIdentification,
+        )
+        .unwrap();
+        assert_eq!(Some(10.0), specific.entries[2].cost_basis);
+        assert_eq!(0.0, specific.total_realized_gain);
+    }
+
+    #[test]
+    fn test_lots_carry_into_later_years() {
+        let transactions = [tx(1, 1, COIN as i64), tx(2, 401, -(COIN as i64))];
+        let report = compute_tax_report(
+            &transactions,
+            &HashMap::new(),
+            &prices(),
+            2024,
+            CostBasisMethod::Fifo,
+        )
+        .unwrap();
+        assert_eq!(1, report.entries.len());
+        assert_eq!(40.0, report.total_proceeds);
+        assert_eq!(10.0, report.total_cost_basis);
+        assert_eq!(30.0, report.total_realized_gain);
+    }
+
+    #[test]
+    fn test_summarize_fees() {
+        let fees = [
+            (OffsetDateTime::from(at(1)), 10_000),
+            (OffsetDateTime::from(at(2)), 15_000),
+            (OffsetDateTime::from(at(40)), 20_001),
+        ];
+        let stats = summarize_fees(&fees);
+        assert_eq!(45_001, stats.total_fees);
+        assert_eq!(3, stats.transaction_count);
+        assert_eq!(15_000, stats.average_fee);
+        assert_eq!(
+            vec![
+                MonthlyFees {
+                    year: 2023,
+                    month: 1,
+                    total_fees: 25_000,
+                    transaction_count: 2,
+                },
+                MonthlyFees {
+                    year: 2023,
+                    month: 2,
+                    total_fees: 20_001,
+                    transaction_count: 1,
+                },
+            ],
+            stats.by_month
+        );
+
+        assert_eq!(0, summarize_fees(&[]).average_fee);
+    }
+
+    #[test]
+    fn test_missing_price() {
+        let transactions = [tx(1, 0, COIN as i64)];
+        assert!(compute_tax_report(
+            &transactions,
+            &HashMap::new(),
+            &prices()[1..],
+            2023,
+            CostBasisMethod::Fifo,
+        )
+        .is_err());
+    }
+}
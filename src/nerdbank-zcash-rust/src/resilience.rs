@@ -1,14 +1,112 @@
-use std::time::Duration;
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use futures_util::Future;
 use tokio::select;
 use tokio_util::sync::CancellationToken;
-use tonic::Status;
+use tonic::{Code, Status};
 use tracing::warn;
 
-const ATTEMPT_LIMIT: u32 = 3;
+pub(crate) const ATTEMPT_LIMIT: u32 = 3;
 const DELAY_BETWEEN_RETRIES: Duration = Duration::from_secs(2);
 
+/// The longest we will wait before retrying a request, even if the server asks for longer.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// The most times the size of downloads may be halved in response to the server pushing back.
+const MAX_BACKPRESSURE_LEVEL: u32 = 4;
+
+/// Checks whether the server failed a request because it is rate-limiting us.
+///
+/// `UNAVAILABLE` is not included, because it usually means the connection dropped,
+/// which is no reason to shrink downloads or wait longer before reconnecting.
+pub(crate) fn is_throttled(status: &Status) -> bool {
+    status.code() == Code::ResourceExhausted
+}
+
+/// Checks whether a request failed for a reason that may not recur, such as a dropped connection,
+/// as opposed to a problem with the request itself.
+pub(crate) fn is_transient(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Unavailable
+            | Code::ResourceExhausted
+            | Code::DeadlineExceeded
+            | Code::Aborted
+            | Code::Unknown
+            | Code::Internal
+    )
+}
+
+/// Gets how long to wait before retrying a failed request.
+///
+/// A delay suggested by the server is honored when present.
+/// Otherwise throttled requests back off exponentially, and other failures are retried after a fixed delay.
+pub(crate) fn retry_delay(status: &Status, failure_count: u32) -> Duration {
+    if let Some(delay) = server_retry_delay(status) {
+        return delay.min(MAX_RETRY_DELAY);
+    }
+
+    if is_throttled(status) {
+        2u32.checked_pow(failure_count.saturating_sub(1))
+            .and_then(|factor| DELAY_BETWEEN_RETRIES.checked_mul(factor))
+            .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+    } else {
+        DELAY_BETWEEN_RETRIES
+    }
+}
+
+/// Reads the delay the server asked us to wait before retrying, from the gRPC retry pushback metadata
+/// or a `retry-after` header (in seconds).
+fn server_retry_delay(status: &Status) -> Option<Duration> {
+    let metadata = status.metadata();
+    let read = |key: &str| {
+        metadata
+            .get(key)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+    };
+
+    read("grpc-retry-pushback-ms")
+        .map(Duration::from_millis)
+        .or_else(|| read("retry-after").map(Duration::from_secs))
+}
+
+/// Tracks how hard the server has been pushing back during a sync,
+/// so that downloads can be made smaller until it recovers.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Backpressure(Arc<AtomicU32>);
+
+impl Backpressure {
+    /// Records that the server throttled a request.
+    pub(crate) fn throttled(&self) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |level| {
+                Some((level + 1).min(MAX_BACKPRESSURE_LEVEL))
+            });
+    }
+
+    /// Records that a request succeeded, relaxing any backpressure a step at a time.
+    pub(crate) fn succeeded(&self) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |level| {
+                Some(level.saturating_sub(1))
+            });
+    }
+
+    /// Halves the given size once for each level of backpressure.
+    pub(crate) fn scale(&self, size: usize) -> usize {
+        (size >> self.0.load(Ordering::SeqCst)).max(1)
+    }
+}
+
 pub(crate) async fn _webrequest_with_logged_retry<
     TResult,
     FRequest: FnMut() -> FResult,
@@ -33,8 +131,9 @@ pub(crate) async fn _webrequest_with_logged_retry<
                     return Err(status);
                 } else {
                     failure_count += 1;
-                    on_retry(status, DELAY_BETWEEN_RETRIES, failure_count);
-                    tokio::time::sleep(DELAY_BETWEEN_RETRIES).await;
+                    let delay = retry_delay(&status, failure_count);
+                    on_retry(status, delay, failure_count);
+                    tokio::time::sleep(delay).await;
                 }
             }
         }
@@ -67,7 +166,7 @@ pub(crate) async fn webrequest_with_retry<
                 } else {
                     failure_count += 1;
                     select! {
-                        _ = tokio::time::sleep(DELAY_BETWEEN_RETRIES) => {},
+                        _ = tokio::time::sleep(retry_delay(&status, failure_count)) => {},
                         _ = cancellation_token.cancelled() => {
                             return Err(Status::cancelled("Request cancelled"));
                         },
@@ -77,3 +176,62 @@ pub(crate) async fn webrequest_with_retry<
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tonic::metadata::MetadataValue;
+
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let exhausted = Status::resource_exhausted("busy");
+        assert_eq!(DELAY_BETWEEN_RETRIES, retry_delay(&exhausted, 1));
+        assert_eq!(DELAY_BETWEEN_RETRIES * 4, retry_delay(&exhausted, 3));
+        assert_eq!(MAX_RETRY_DELAY, retry_delay(&exhausted, 30));
+
+        let unavailable = Status::unavailable("connection reset");
+        assert_eq!(DELAY_BETWEEN_RETRIES, retry_delay(&unavailable, 3));
+
+        let internal = Status::internal("oops");
+        assert_eq!(DELAY_BETWEEN_RETRIES, retry_delay(&internal, 3));
+
+        let mut pushback = Status::resource_exhausted("slow down");
+        pushback
+            .metadata_mut()
+            .insert("grpc-retry-pushback-ms", MetadataValue::from_static("1500"));
+        assert_eq!(Duration::from_millis(1500), retry_delay(&pushback, 1));
+    }
+
+    #[test]
+    fn test_is_throttled() {
+        assert!(is_throttled(&Status::resource_exhausted("slow down")));
+        assert!(!is_throttled(&Status::unavailable("connection reset")));
+        assert!(!is_throttled(&Status::internal("h2 protocol error")));
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&Status::unavailable("connection reset")));
+        assert!(is_transient(&Status::internal("h2 protocol error")));
+        assert!(!is_transient(&Status::invalid_argument("bad range")));
+        assert!(!is_transient(&Status::cancelled("cancelled")));
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let backpressure = Backpressure::default();
+        assert_eq!(1000, backpressure.scale(1000));
+        backpressure.throttled();
+        backpressure.throttled();
+        assert_eq!(250, backpressure.scale(1000));
+        for _ in 0..10 {
+            backpressure.throttled();
+        }
+        assert_eq!(1000 >> MAX_BACKPRESSURE_LEVEL, backpressure.scale(1000));
+        for _ in 0..10 {
+            backpressure.succeeded();
+        }
+        assert_eq!(1000, backpressure.scale(1000));
+    }
+}
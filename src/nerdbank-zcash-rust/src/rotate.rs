@@ -0,0 +1,305 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    num::NonZeroU32,
+    path::Path,
+    time::SystemTime,
+};
+
+use http::Uri;
+use orchard::keys::Scope;
+use rusqlite::{named_params, Connection};
+use secrecy::{ExposeSecret, SecretVec};
+use time::OffsetDateTime;
+use zcash_address::ZcashAddress;
+use zcash_client_backend::{
+    address::UnifiedAddress,
+    data_api::{Account, WalletRead},
+    encoding::AddressCodec,
+    keys::UnifiedSpendingKey,
+    ShieldedProtocol,
+};
+use zcash_client_sqlite::AccountId;
+use zcash_primitives::{consensus::Network, legacy::TransparentAddress};
+
+use crate::{
+    analysis::get_user_balances,
+    backing_store::{open_connection, Db},
+    error::Error,
+    grpc::get_client,
+    interop::{DbInit, MigrationProgress, TransactionSendDetail},
+    migrate::{sweep_amount, sweep_notes},
+    shield::{get_unshielded_utxos, shield_funds_at_address},
+    spending_policy::{check_spending_policy, log_spend},
+    sql_statements::{GET_ACCOUNT_SUCCESSORS, RETIRE_ACCOUNT},
+};
+
+/// The outcome of moving an account's funds to an account from a new seed.
+#[derive(Debug, Clone)]
+pub struct SeedRotation {
+    /// The account derived from the new seed.
+    pub new_account_id: u32,
+    /// The transactions that shielded or moved funds.
+    pub transactions: Vec<Vec<u8>>,
+    /// The funds left in the old account, such as freshly shielded funds or change that is not yet spendable.
+    /// Rotating again after syncing moves whatever has become spendable.
+    pub remaining: u64,
+}
+
+/// Moves the funds of the account that `old_usk` belongs to into the first account of a new seed,
+/// and marks the old account as retired once they have been sent.
+///
+/// The new account is added to the wallet with the given birthday, unless it is already there.
+/// Transparent funds are shielded (into the old account, since only it can spend them),
+/// and all spendable shielded funds are sent to the new account.
+/// Sending them is subject to the old account's spending policy, like any other send.
+/// Shielded funds and change need confirmations before they can be moved,
+/// so this should be called again after syncing for as long as funds are `remaining`.
+/// Notes worth less than the fee to spend them are left behind.
+pub async fn rotate_to_new_seed<P: AsRef<Path>>(
+    data_file: P,
+    server_uri: Uri,
+    network: Network,
+    old_usk: &UnifiedSpendingKey,
+    new_seed: &SecretVec<u8>,
+    birthday_height: u32,
+    min_confirmations: NonZeroU32,
+    progress: Option<&dyn MigrationProgress>,
+) -> Result<SeedRotation, Error> {
+    let mut db = Db::init(&data_file, network)?;
+    let old_account_id = db
+        .data
+        .get_account_for_ufvk(&old_usk.to_unified_full_viewing_key())?
+        .ok_or(Error::KeyNotRecognized)?
+        .id();
+
+    let new_usk =
+        UnifiedSpendingKey::from_seed(&network, new_seed.expose_secret(), zip32::AccountId::ZERO)
+            .map_err(|_| Error::InvalidArgument("Invalid seed.".to_string()))?;
+    let new_ufvk = new_usk.to_unified_full_viewing_key();
+    let new_account_id = match db.data.get_account_for_ufvk(&new_ufvk)? {
+        Some(account) => account.id(),
+        None => {
+            let mut client = get_client(server_uri.clone()).await?;
+            db.add_account(
+                new_seed,
+                zip32::AccountId::ZERO,
+                birthday_height.into(),
+                &mut client,
+            )
+            .await?
+            .0
+            .id()
+        }
+    };
+    if new_account_id == old_account_id {
+        return Err(Error::InvalidArgument(
+            "The new seed is the seed of the account being rotated away from.".to_string(),
+        ));
+    }
+
+    let conn = open_connection(&data_file)?;
+    let config = DbInit {
+        data_file: data_file.as_ref().to_string_lossy().into_owned(),
+        network: network.into(),
+        min_confirmations: min_confirmations.get(),
+    };
+    let mut transactions = Vec::new();
+
+    // Transparent funds can only be spent by shielding them, which sends them to the old account.
+    let addresses = get_unshielded_utxos(config.clone(), old_account_id)?
+        .into_iter()
+        .map(|utxo| utxo.recipient)
+        .collect::<BTreeSet<_>>();
+    for address in addresses {
+        let address =
+            TransparentAddress::decode(&network, &address).map_err(|_| Error::InvalidAddress)?;
+        match shield_funds_at_address(
+            &data_file,
+            server_uri.clone(),
+            network,
+            old_usk,
+            address,
+            min_confirmations.get(),
+            None,
+        )
+        .await
+        {
+            Ok(results) => {
+                transactions.extend(results.into_iter().map(|r| r.txid.as_ref().to_vec()))
+            }
+            // What's at this address isn't worth the fee to shield it (or isn't confirmed yet).
+            Err(Error::InsufficientFunds { .. }) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let orchard = new_ufvk.orchard().ok_or_else(|| {
+        Error::Internal("The new account has no Orchard key to send funds to.".to_string())
+    })?;
+    let recipient =
+        UnifiedAddress::from_receivers(Some(orchard.address_at(0u32, Scope::External)), None, None)
+            .ok_or_else(|| Error::Internal("Failed to create an Orchard address.".to_string()))?;
+    let recipient = recipient.encode(&network);
+
+    let mut protocols = Vec::new();
+    let mut total = 0;
+    for protocol in [ShieldedProtocol::Orchard, ShieldedProtocol::Sapling] {
+        let amount = sweep_amount(&db, old_account_id, protocol, min_confirmations)?;
+        if amount > 0 {
+            protocols.push(protocol);
+            total += amount;
+        }
+    }
+    if total > 0 {
+        check_spending_policy(
+            &conn,
+            &network,
+            old_account_id,
+            &[TransactionSendDetail {
+                recipient: recipient.clone(),
+                value: total,
+                memo: None,
+            }],
+            SystemTime::now(),
+        )?;
+    }
+
+    let recipient =
+        ZcashAddress::try_from_encoded(&recipient).map_err(|_| Error::InvalidAddress)?;
+    for protocol in protocols {
+        let results = sweep_notes(
+            &mut db,
+            &server_uri,
+            network,
+            old_usk,
+            old_account_id,
+            protocol,
+            &recipient,
+            min_confirmations,
+            progress,
+        )
+        .await?;
+        transactions.extend(results.into_iter().map(|r| r.txid.as_ref().to_vec()));
+    }
+
+    let now = SystemTime::now();
+    if total > 0 {
+        log_spend(&conn, old_account_id, total, now)?;
+    }
+    retire_account(&conn, old_account_id, new_account_id, now)?;
+
+    let balances = get_user_balances(&config, old_account_id, min_confirmations)?;
+    Ok(SeedRotation {
+        new_account_id: new_account_id.into(),
+        transactions,
+        remaining: balances.spendable + balances.immature_change + balances.immature_income,
+    })
+}
+
+/// Records that an account's funds are being moved to a successor account.
+fn retire_account(
+    conn: &Connection,
+    account_id: AccountId,
+    successor_account_id: AccountId,
+    retired: SystemTime,
+) -> Result<(), Error> {
+    conn.execute(
+        RETIRE_ACCOUNT,
+        named_params! {
+            ":account_id": u32::from(account_id),
+            ":successor_account_id": u32::from(successor_account_id),
+            ":retired": OffsetDateTime::from(retired).unix_timestamp(),
+        },
+    )?;
+    Ok(())
+}
+
+/// Gets the successor of each retired account, keyed by the retired account.
+pub(crate) fn get_account_successors(conn: &Connection) -> Result<HashMap<u32, u32>, Error> {
+    Ok(conn
+        .prepare(GET_ACCOUNT_SUCCESSORS)?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+    use zcash_client_backend::data_api::WalletWrite;
+    use zcash_primitives::consensus::BlockHeight;
+
+    use crate::test_constants::{
+        setup_local_test, LocalTestSetup, LIGHTSERVER_URI, LOCAL_BIRTHDAY_HEIGHT, MIN_CONFIRMATIONS,
+    };
+
+    use super::*;
+
+    async fn rotate(
+        setup: &LocalTestSetup,
+        old_usk: &UnifiedSpendingKey,
+        new_seed: &SecretVec<u8>,
+    ) -> Result<SeedRotation, Error> {
+        rotate_to_new_seed(
+            &setup.data_file,
+            LIGHTSERVER_URI.to_owned(),
+            setup.network,
+            old_usk,
+            new_seed,
+            LOCAL_BIRTHDAY_HEIGHT as u32,
+            NonZeroU32::new(MIN_CONFIRMATIONS).unwrap(),
+            None,
+        )
+        .await
+    }
+
+    fn successors(setup: &LocalTestSetup) -> HashMap<u32, u32> {
+        get_account_successors(&open_connection(&setup.data_file).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_retire_account() {
+        let mut setup = setup_local_test();
+        let (_, _, old, _) = setup.create_account().unwrap();
+        let (_, _, new, _) = setup.create_account().unwrap();
+        let conn = open_connection(&setup.data_file).unwrap();
+        assert!(get_account_successors(&conn).unwrap().is_empty());
+
+        retire_account(&conn, old, new, SystemTime::now()).unwrap();
+        let successors = get_account_successors(&conn).unwrap();
+        assert_eq!(Some(&u32::from(new)), successors.get(&u32::from(old)));
+        assert_eq!(1, successors.len());
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_rotate_to_new_seed_retires_only_after_sweeping() {
+        let mut setup = setup_local_test();
+        let (old_seed, _, old, old_usk) = setup.create_account().unwrap();
+        let (new_seed, _, new, _) = setup.create_account().unwrap();
+
+        assert_matches!(
+            rotate(&setup, &old_usk, &old_seed).await,
+            Err(Error::InvalidArgument(_))
+        );
+
+        // Funds can't be swept before the wallet has synced, so the old account stays in service.
+        assert_matches!(
+            rotate(&setup, &old_usk, &new_seed).await,
+            Err(Error::SyncFirst)
+        );
+        assert!(successors(&setup).is_empty());
+
+        setup
+            .db
+            .data
+            .update_chain_tip(BlockHeight::from_u32(LOCAL_BIRTHDAY_HEIGHT as u32 + 5))
+            .unwrap();
+        let rotation = rotate(&setup, &old_usk, &new_seed).await.unwrap();
+        assert_eq!(u32::from(new), rotation.new_account_id);
+        assert!(rotation.transactions.is_empty());
+        assert_eq!(0, rotation.remaining);
+        assert_eq!(
+            Some(&u32::from(new)),
+            successors(&setup).get(&u32::from(old))
+        );
+    }
+}
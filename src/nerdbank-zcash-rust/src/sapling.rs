@@ -1,12 +1,178 @@
+use std::sync::OnceLock;
+
+use bellman::groth16;
+use blake2b_simd::Params;
+use ff::Field;
+use rand_core::OsRng;
 use sapling::{
     keys::{ExpandedSpendingKey, FullViewingKey},
+    prover::SpendProver,
+    value::{NoteValue, ValueCommitTrapdoor, ValueCommitment},
+    verifier::SaplingVerificationContext,
     zip32::{
         sapling_derive_internal_fvk, DiversifiableFullViewingKey, DiversifierKey,
         ExtendedSpendingKey,
     },
-    PaymentAddress, SaplingIvk,
+    CommitmentTree, IncrementalWitness, Node, Note, PaymentAddress, Rseed, SaplingIvk,
 };
 use zcash_primitives::zip32::{ChildIndex, DiversifierIndex, Scope};
+use zcash_proofs::{download_sapling_parameters, load_parameters, ZcashParameters};
+use zeroize::Zeroizing;
+
+use crate::{error::Error, prover::get_prover};
+
+/// The length of a signature produced by [`sign_message`].
+/// It consists of the nullifier, rk, value commitment, spend proof and spend authorization signature.
+pub const MESSAGE_SIGNATURE_LENGTH: usize = 32 + 32 + 32 + 192 + 64;
+
+/// Signs a message in a way that proves the signer can spend funds sent to a sapling address,
+/// without revealing anything else about the signer's keys.
+///
+/// This follows the construction of ZIP-304: the signer produces a spend proof for a zero-valued note
+/// sent to the address, and a spend authorization signature over a digest of that proof and the message.
+pub fn sign_message(
+    expsk: &ExpandedSpendingKey,
+    address: &PaymentAddress,
+    message: &[u8],
+) -> Result<[u8; MESSAGE_SIGNATURE_LENGTH], Error> {
+    let proof_generation_key = expsk.proof_generation_key();
+    let viewing_key = proof_generation_key.to_viewing_key();
+    if viewing_key.ivk().to_payment_address(*address.diversifier()) != Some(*address) {
+        return Err(Error::KeyNotRecognized);
+    }
+
+    let note = message_note(address);
+    let witness = message_note_witness(&note);
+    let merkle_path = witness
+        .path()
+        .ok_or_else(|| Error::Internal("No path to the message note.".to_string()))?;
+    let nullifier = note.nf(&viewing_key.nk, 0);
+
+    let mut rng = OsRng;
+    let alpha = jubjub::Fr::random(&mut rng);
+    let rsk = expsk.ask.randomize(&alpha);
+    let rk = redjubjub::VerificationKey::from(&rsk);
+    let rcv = ValueCommitTrapdoor::random(&mut rng);
+    let cv = ValueCommitment::derive(NoteValue::from_raw(0), rcv.clone());
+
+    let prover = get_prover()?;
+    let circuit = <zcash_proofs::prover::LocalTxProver as SpendProver>::prepare_circuit(
+        proof_generation_key,
+        *address.diversifier(),
+        *note.rseed(),
+        note.value(),
+        alpha,
+        rcv,
+        witness.root().into(),
+        merkle_path,
+    )
+    .ok_or_else(|| Error::Internal("Unable to construct the spend circuit.".to_string()))?;
+    let zkproof = <zcash_proofs::prover::LocalTxProver as SpendProver>::encode_proof(
+        prover.create_proof(circuit, &mut rng),
+    );
+
+    let digest = message_digest(&zkproof, message);
+    let rk_bytes: [u8; 32] = rk.into();
+    let spend_auth_sig: [u8; 64] = rsk
+        .sign(&mut rng, &[&rk_bytes[..], &digest[..]].concat())
+        .into();
+
+    let mut signature = [0u8; MESSAGE_SIGNATURE_LENGTH];
+    signature[..32].copy_from_slice(&nullifier.0);
+    signature[32..64].copy_from_slice(&rk_bytes);
+    signature[64..96].copy_from_slice(&cv.to_bytes());
+    signature[96..288].copy_from_slice(&zkproof);
+    signature[288..].copy_from_slice(&spend_auth_sig);
+    Ok(signature)
+}
+
+/// Verifies a signature produced by [`sign_message`].
+///
+/// Returns `false` if the signature is malformed or was not produced by the holder of the address's spending key.
+pub fn verify_message(
+    address: &PaymentAddress,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<bool, Error> {
+    if signature.len() != MESSAGE_SIGNATURE_LENGTH {
+        return Ok(false);
+    }
+
+    let nullifier: [u8; 32] = signature[..32].try_into().unwrap();
+    let rk_bytes: [u8; 32] = signature[32..64].try_into().unwrap();
+    let cv_bytes: [u8; 32] = signature[64..96].try_into().unwrap();
+    let zkproof = &signature[96..288];
+    let spend_auth_sig: [u8; 64] = signature[288..].try_into().unwrap();
+
+    let rk = match redjubjub::VerificationKey::try_from(rk_bytes) {
+        Ok(rk) => rk,
+        Err(_) => return Ok(false),
+    };
+    let cv = match Option::from(ValueCommitment::from_bytes_not_small_order(&cv_bytes)) {
+        Some(cv) => cv,
+        None => return Ok(false),
+    };
+    let proof = match groth16::Proof::read(zkproof) {
+        Ok(proof) => proof,
+        Err(_) => return Ok(false),
+    };
+
+    let parameters = sapling_parameters()?;
+
+    let anchor = message_note_witness(&message_note(address)).root();
+    let digest = message_digest(zkproof, message);
+    Ok(SaplingVerificationContext::new().check_spend(
+        &cv,
+        anchor.into(),
+        &nullifier,
+        rk,
+        &digest,
+        redjubjub::Signature::from(spend_auth_sig),
+        proof,
+        &parameters.spend_vk,
+    ))
+}
+
+/// Gets the sapling parameters, which are only loaded the first time they are needed
+/// since loading them takes several seconds.
+fn sapling_parameters() -> Result<&'static ZcashParameters, Error> {
+    static PARAMETERS: OnceLock<ZcashParameters> = OnceLock::new();
+    if let Some(parameters) = PARAMETERS.get() {
+        return Ok(parameters);
+    }
+
+    let parameter_paths = download_sapling_parameters(None)?;
+    let parameters = load_parameters(&parameter_paths.spend, &parameter_paths.output, None);
+    Ok(PARAMETERS.get_or_init(|| parameters))
+}
+
+/// The note whose spend is proven in a message signature.
+/// Its contents are all public, so its commitment randomness is fixed so that verifiers can reproduce it.
+fn message_note(address: &PaymentAddress) -> Note {
+    address.create_note(
+        NoteValue::from_raw(0),
+        Rseed::BeforeZip212(jubjub::Fr::zero()),
+    )
+}
+
+/// A witness for the message note in a commitment tree that contains only that note.
+fn message_note_witness(note: &Note) -> IncrementalWitness {
+    let mut tree = CommitmentTree::empty();
+    tree.append(Node::from_cmu(&note.cmu()))
+        .expect("An empty tree has room for a note.");
+    IncrementalWitness::from_tree(tree)
+}
+
+fn message_digest(zkproof: &[u8], message: &[u8]) -> [u8; 32] {
+    let hash = Params::new()
+        .hash_length(32)
+        .personal(b"ZIP304Signed")
+        .to_state()
+        .update(zkproof)
+        .update(message)
+        .finalize();
+    hash.as_bytes().try_into().unwrap()
+}
 
 #[no_mangle]
 pub extern "C" fn derive_sapling_ivk_from_fvk(fvk: *const [u8; 96], ivk: *mut [u8; 32]) -> i32 {
@@ -124,7 +290,7 @@ pub extern "C" fn derive_sapling_child(
     match ExtendedSpendingKey::from_bytes(ext_sk) {
         Ok(sk) => {
             let derived_child = sk.derive_child(child_index);
-            child_bytes.copy_from_slice(&derived_child.to_bytes());
+            child_bytes.copy_from_slice(&*Zeroizing::new(derived_child.to_bytes()));
             0
         }
         Err(_) => -1,
@@ -168,7 +334,7 @@ pub extern "C" fn derive_internal_sk_sapling(
 
     if let Ok(ext_sk) = ExtendedSpendingKey::from_bytes(ext_sk) {
         let internal = ext_sk.derive_internal();
-        internal_ext_sk.copy_from_slice(&internal.to_bytes()[..]);
+        internal_ext_sk.copy_from_slice(&Zeroizing::new(internal.to_bytes())[..]);
         0
     } else {
         -1
@@ -181,7 +347,7 @@ pub extern "C" fn get_sapling_expanded_sk(sk: *const [u8; 32], expsk: *mut [u8;
     let expsk_bytes = unsafe { &mut *expsk };
 
     let expsk = ExpandedSpendingKey::from_spending_key(sk);
-    expsk_bytes.copy_from_slice(&expsk.to_bytes());
+    expsk_bytes.copy_from_slice(&*Zeroizing::new(expsk.to_bytes()));
 }
 
 #[no_mangle]
@@ -237,3 +403,36 @@ pub extern "C" fn get_sapling_receiver(
         -1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key_and_address(seed: u8) -> (ExpandedSpendingKey, PaymentAddress) {
+        let expsk = ExpandedSpendingKey::from_spending_key(&[seed; 32]);
+        let ivk = FullViewingKey::from_expanded_spending_key(&expsk).vk.ivk();
+        let address = (0..=u8::MAX)
+            .find_map(|i| ivk.to_payment_address(sapling::Diversifier([i; 11])))
+            .unwrap();
+        (expsk, address)
+    }
+
+    #[test]
+    fn test_sign_and_verify_message() {
+        let (expsk, address) = test_key_and_address(7);
+        let signature = sign_message(&expsk, &address, b"hello").unwrap();
+        assert!(verify_message(&address, b"hello", &signature).unwrap());
+        assert!(!verify_message(&address, b"goodbye", &signature).unwrap());
+        assert!(!verify_message(&address, b"hello", &signature[1..]).unwrap());
+    }
+
+    #[test]
+    fn test_sign_message_with_foreign_address() {
+        let (expsk, _) = test_key_and_address(7);
+        let (_, other_address) = test_key_and_address(8);
+        assert!(matches!(
+            sign_message(&expsk, &other_address, b"hello"),
+            Err(Error::KeyNotRecognized)
+        ));
+    }
+}
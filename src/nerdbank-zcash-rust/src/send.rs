@@ -1,27 +1,27 @@
-use std::{num::NonZeroU32, path::Path};
+use std::{collections::HashSet, num::NonZeroU32, path::Path, time::SystemTime};
 
 use http::Uri;
 use nonempty::NonEmpty;
 use rusqlite::Connection;
+use tracing::warn;
 use zcash_address::ZcashAddress;
 use zcash_client_backend::{
     data_api::{
         wallet::{
-            create_proposed_transactions,
-            input_selection::{GreedyInputSelector, GreedyInputSelectorError},
+            create_proposed_transactions, input_selection::GreedyInputSelectorError,
             propose_transfer,
         },
         Account, WalletRead,
     },
     fees::{zip317::SingleOutputChangeStrategy, ChangeStrategy},
     keys::UnifiedSpendingKey,
-    proposal::Proposal,
+    proposal::{Proposal, Step},
     proto::service,
     wallet::OvkPolicy,
     zip321::{Payment, TransactionRequest},
-    ShieldedProtocol,
+    PoolType, ShieldedProtocol,
 };
-use zcash_client_sqlite::{ReceivedNoteId, WalletDb};
+use zcash_client_sqlite::ReceivedNoteId;
 use zcash_keys::keys::UnifiedFullViewingKey;
 use zcash_primitives::{
     consensus::Network,
@@ -30,8 +30,14 @@ use zcash_primitives::{
 };
 
 use crate::{
-    backing_store::Db, error::Error, grpc::get_client, interop::TransactionSendDetail,
+    audit_log::{self, AuditOperation},
+    backing_store::{open_connection, Db},
+    error::Error,
+    grpc::get_client,
+    input_selection::WalletInputSelector,
+    interop::{PrivacyPolicy, SendOptions, TransactionSendDetail},
     prover::get_prover,
+    spending_policy::{check_spending_policy, log_spend},
 };
 
 #[derive(Debug)]
@@ -45,12 +51,11 @@ pub fn create_send_proposal(
     account_ufvk: &UnifiedFullViewingKey,
     min_confirmations: NonZeroU32,
     details: Vec<TransactionSendDetail>,
+    options: &SendOptions,
 ) -> Result<Proposal<FeeRule, ReceivedNoteId>, Error> {
     // TODO: revise this to a smarter change strategy that avoids unnecessarily crossing the turnstile.
-    let input_selector = GreedyInputSelector::new(
-        SingleOutputChangeStrategy::new(FeeRule::standard(), None, ShieldedProtocol::Sapling),
-        Default::default(),
-    );
+    let input_selector =
+        WalletInputSelector::new(options.source_pool)?.with_strategy(options.note_selection);
 
     let mut payments = Vec::new();
     for detail in details.iter() {
@@ -78,14 +83,125 @@ pub fn create_send_proposal(
         .get_account_for_ufvk(account_ufvk)?
         .ok_or(Error::KeyNotRecognized)?;
 
-    Ok(propose_transfer::<_, _, _, Error>(
+    let proposal = propose_transfer::<_, _, _, Error>(
         &mut db.data,
         &network,
         account.id(),
         &input_selector,
         request,
         min_confirmations,
-    )?)
+    )?;
+    check_privacy_policy(&proposal, options.privacy_policy)?;
+
+    Ok(proposal)
+}
+
+/// Fails with [`Error::PrivacyPolicyViolation`] if carrying out the proposal
+/// would reveal more than the given policy allows.
+fn check_privacy_policy<NoteRef>(
+    proposal: &Proposal<FeeRule, NoteRef>,
+    policy: PrivacyPolicy,
+) -> Result<(), Error> {
+    check_disclosures(proposal.steps().iter().map(StepDisclosure::of), policy)
+}
+
+/// What one step of a proposal reveals, as far as privacy policies are concerned.
+#[derive(Debug, Default)]
+struct StepDisclosure {
+    /// The number of distinct transparent addresses the step spends from.
+    transparent_senders: usize,
+    /// Whether any recipient is paid with transparent funds.
+    transparent_recipient: bool,
+    /// The shielded pools that the step draws value from or sends value to.
+    pools: HashSet<ShieldedProtocol>,
+}
+
+impl StepDisclosure {
+    fn of<NoteRef>(step: &Step<NoteRef>) -> Self {
+        let senders: HashSet<_> = step
+            .transparent_inputs()
+            .iter()
+            .map(|i| *i.recipient_address())
+            .collect();
+
+        let mut pools = HashSet::new();
+        if let Some(inputs) = step.shielded_inputs() {
+            pools.extend(inputs.notes().iter().map(|n| n.note().protocol()));
+        }
+        pools.extend(step.payment_pools().values().filter_map(|p| match p {
+            PoolType::Shielded(p) => Some(*p),
+            PoolType::Transparent => None,
+        }));
+        pools.extend(
+            step.balance()
+                .proposed_change()
+                .iter()
+                .map(|c| c.output_pool()),
+        );
+
+        StepDisclosure {
+            transparent_senders: senders.len(),
+            transparent_recipient: step
+                .payment_pools()
+                .values()
+                .any(|p| *p == PoolType::Transparent),
+            pools,
+        }
+    }
+}
+
+/// Fails with [`Error::PrivacyPolicyViolation`] if any of the given disclosures goes beyond the given policy.
+/// The reason given is for the disclosure that needs the least restrictive policy.
+fn check_disclosures(
+    disclosures: impl IntoIterator<Item = StepDisclosure>,
+    policy: PrivacyPolicy,
+) -> Result<(), Error> {
+    let mut required = PrivacyPolicy::FullPrivacy;
+    let mut reason = String::new();
+    let mut require = |p: PrivacyPolicy, r: String| {
+        if p > required {
+            required = p;
+            reason = r;
+        }
+    };
+
+    for disclosure in disclosures {
+        if disclosure.transparent_senders > 1 {
+            require(
+                PrivacyPolicy::AllowLinkingAccountAddresses,
+                format!(
+                    "The transaction would spend from {} transparent addresses, publicly linking them.",
+                    disclosure.transparent_senders
+                ),
+            );
+        }
+
+        if disclosure.transparent_recipient {
+            require(
+                PrivacyPolicy::AllowRevealedRecipients,
+                "A recipient can only receive transparent funds, which would make the recipient and the amount public."
+                    .to_string(),
+            );
+        }
+
+        // Whenever more than one shielded pool is involved, value crosses between them,
+        // and the amount that crosses is public.
+        if disclosure.pools.len() > 1 {
+            require(
+                PrivacyPolicy::AllowRevealedAmounts,
+                format!(
+                    "Funds would move between the {:?} pools, which would make the amount moved public.",
+                    disclosure.pools
+                ),
+            );
+        }
+    }
+
+    if required > policy {
+        return Err(Error::PrivacyPolicyViolation { required, reason });
+    }
+
+    Ok(())
 }
 
 pub async fn send_transaction<P: AsRef<Path>>(
@@ -95,14 +211,55 @@ pub async fn send_transaction<P: AsRef<Path>>(
     usk: &UnifiedSpendingKey,
     min_confirmations: NonZeroU32,
     details: Vec<TransactionSendDetail>,
+    options: &SendOptions,
 ) -> Result<NonEmpty<SendTransactionResult>, Error> {
-    let mut db = Db::init(data_file, network)?;
-    let proposal = create_send_proposal(
+    let mut db = Db::init(&data_file, network)?;
+    let conn = open_connection(&data_file)?;
+    let txids = create_send_transactions(
         &mut db,
+        &conn,
+        network,
+        usk,
+        min_confirmations,
+        details,
+        options,
+    )?;
+
+    let mut result = Vec::new();
+    for txid in txids {
+        result.push(transmit_transaction(txid, server_uri.clone(), &mut db).await?);
+    }
+
+    Ok(NonEmpty::from_vec(result).unwrap())
+}
+
+/// Creates and stores the transactions that make the given payments, without broadcasting them.
+pub(crate) fn create_send_transactions(
+    db: &mut Db,
+    conn: &Connection,
+    network: Network,
+    usk: &UnifiedSpendingKey,
+    min_confirmations: NonZeroU32,
+    details: Vec<TransactionSendDetail>,
+    options: &SendOptions,
+) -> Result<NonEmpty<TxId>, Error> {
+    let account_ufvk = usk.to_unified_full_viewing_key();
+    let account_id = db
+        .data
+        .get_account_for_ufvk(&account_ufvk)?
+        .ok_or(Error::KeyNotRecognized)?
+        .id();
+    let now = SystemTime::now();
+    check_spending_policy(conn, &network, account_id, &details, now)?;
+    let value = details.iter().map(|d| d.value).sum();
+
+    let proposal = create_send_proposal(
+        db,
         network,
-        &usk.to_unified_full_viewing_key(),
+        &account_ufvk,
         min_confirmations,
         details,
+        options,
     )?;
 
     let prover = get_prover()?;
@@ -124,22 +281,19 @@ pub async fn send_transaction<P: AsRef<Path>>(
         OvkPolicy::Sender,
         &proposal,
     )?;
+    log_spend(conn, account_id, value, now)?;
 
-    let mut result = Vec::new();
-    for txid in txids {
-        result.push(transmit_transaction(txid, server_uri.clone(), &mut db.data).await?);
-    }
-
-    Ok(NonEmpty::from_vec(result).unwrap())
+    Ok(txids)
 }
 
 pub(crate) async fn transmit_transaction(
     txid: TxId,
     server_uri: Uri,
-    db: &mut WalletDb<Connection, Network>,
+    db: &mut Db,
 ) -> Result<SendTransactionResult, Error> {
     let mut client = get_client(server_uri).await?;
     let raw_tx = db
+        .data
         .get_transaction(txid)?
         .ok_or(Error::Internal("Transaction not found".to_string()))
         .map(|tx| {
@@ -154,6 +308,20 @@ pub(crate) async fn transmit_transaction(
             reason: response.error_message,
         })
     } else {
+        // The transaction is on the network now, so failing to log it mustn't fail the send.
+        if let Err(e) = open_connection(&db.data_file).and_then(|conn| {
+            audit_log::record(
+                &conn,
+                AuditOperation::SendBroadcast,
+                None,
+                &txid.to_string(),
+            )
+        }) {
+            warn!(
+                "Failed to record the broadcast of {} in the audit log: {}",
+                txid, e
+            );
+        }
         Ok(SendTransactionResult { txid })
     }
 }
@@ -164,12 +332,93 @@ mod tests {
     use tokio_util::sync::CancellationToken;
 
     use crate::{
+        interop::SyncOptions,
+        pause::PauseToken,
         sync::sync,
         test_constants::{setup_test, MIN_CONFIRMATIONS, VALID_SAPLING_TESTNET},
     };
 
     use super::*;
 
+    fn required_policy(disclosures: Vec<StepDisclosure>) -> PrivacyPolicy {
+        match check_disclosures(disclosures, PrivacyPolicy::FullPrivacy) {
+            Ok(()) => PrivacyPolicy::FullPrivacy,
+            Err(Error::PrivacyPolicyViolation { required, .. }) => required,
+            Err(e) => panic!("Unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_check_disclosures() {
+        let within_sapling = StepDisclosure {
+            pools: HashSet::from([ShieldedProtocol::Sapling]),
+            ..Default::default()
+        };
+        let across_pools = StepDisclosure {
+            pools: HashSet::from([ShieldedProtocol::Sapling, ShieldedProtocol::Orchard]),
+            ..Default::default()
+        };
+        let to_transparent = StepDisclosure {
+            transparent_recipient: true,
+            ..Default::default()
+        };
+        let from_two_taddrs = StepDisclosure {
+            transparent_senders: 2,
+            ..Default::default()
+        };
+
+        assert_eq!(PrivacyPolicy::FullPrivacy, required_policy(vec![]));
+        assert_eq!(
+            PrivacyPolicy::FullPrivacy,
+            required_policy(vec![within_sapling])
+        );
+        assert_eq!(
+            PrivacyPolicy::FullPrivacy,
+            required_policy(vec![StepDisclosure {
+                transparent_senders: 1,
+                ..Default::default()
+            }])
+        );
+        assert_eq!(
+            PrivacyPolicy::AllowRevealedAmounts,
+            required_policy(vec![across_pools])
+        );
+        assert_eq!(
+            PrivacyPolicy::AllowRevealedRecipients,
+            required_policy(vec![to_transparent])
+        );
+        assert_eq!(
+            PrivacyPolicy::AllowLinkingAccountAddresses,
+            required_policy(vec![from_two_taddrs])
+        );
+    }
+
+    #[test]
+    fn test_check_disclosures_strictest_step_wins() {
+        let steps = || {
+            vec![
+                StepDisclosure {
+                    transparent_recipient: true,
+                    ..Default::default()
+                },
+                StepDisclosure {
+                    pools: HashSet::from([ShieldedProtocol::Sapling, ShieldedProtocol::Orchard]),
+                    ..Default::default()
+                },
+            ]
+        };
+
+        assert_matches!(
+            check_disclosures(steps(), PrivacyPolicy::AllowRevealedAmounts),
+            Err(Error::PrivacyPolicyViolation {
+                required: PrivacyPolicy::AllowRevealedRecipients,
+                reason,
+            }) if reason.contains("transparent")
+        );
+        assert!(check_disclosures(steps(), PrivacyPolicy::AllowRevealedRecipients).is_ok());
+        assert!(check_disclosures(steps(), PrivacyPolicy::AllowLinkingAccountAddresses).is_ok());
+    }
+
     #[tokio_shared_rt::test]
     async fn test_send_insufficient_funds() {
         let mut setup = setup_test().await;
@@ -180,6 +429,8 @@ mod tests {
             None,
             setup.db_init.min_confirmations,
             false,
+            SyncOptions::default(),
+            PauseToken::default(),
             CancellationToken::new(),
         )
         .await
@@ -195,6 +446,7 @@ mod tests {
                 memo: None,
                 recipient: VALID_SAPLING_TESTNET.to_string(),
             }],
+            &SendOptions::default(),
         )
         .await
         .unwrap_err();
@@ -0,0 +1,536 @@
+use std::{num::NonZeroU32, time::SystemTime};
+
+use http::Uri;
+use rusqlite::{named_params, Connection, Row};
+use time::OffsetDateTime;
+use tracing::warn;
+use zcash_address::ZcashAddress;
+use zcash_client_backend::{
+    data_api::{Account, WalletRead},
+    keys::UnifiedSpendingKey,
+    zip321::Payment,
+};
+use zcash_client_sqlite::AccountId;
+use zcash_primitives::{
+    consensus::Network,
+    memo::MemoBytes,
+    transaction::{components::amount::NonNegativeAmount, fees::zip317::FeeRule, TxId},
+};
+
+use crate::{
+    analysis::get_user_balances,
+    backing_store::{open_connection, Db},
+    error::Error,
+    interop::{DbInit, SendOptions, TransactionSendDetail},
+    send::{create_send_transactions, transmit_transaction},
+    sql_statements::{
+        CANCEL_QUEUED_PAYMENT, DELETE_SEND_QUEUE_TRANSACTION, ENQUEUE_PAYMENT, GET_QUEUED_PAYMENTS,
+        GET_UNBROADCAST_SEND_QUEUE_TRANSACTIONS, RECORD_SEND_QUEUE_TRANSACTION, REQUEUE_PAYMENTS,
+        SET_QUEUED_PAYMENT_SENT, SET_SEND_QUEUE_TRANSACTION_BROADCAST,
+    },
+};
+
+/// The most payments to batch into one transaction.
+/// Each payment adds an output (and to the fee), so this keeps transactions to a reasonable size.
+const MAX_PAYMENTS_PER_TRANSACTION: usize = 50;
+
+/// A payment waiting in the send queue, or one that the queue has sent.
+#[derive(Debug, Clone)]
+pub struct QueuedPayment {
+    pub id: u32,
+    pub account_id: u32,
+    pub recipient: String,
+    pub value: u64,
+    pub memo: Option<Vec<u8>>,
+    pub enqueued: SystemTime,
+    /// The ID of the transaction that made this payment, once it has been sent.
+    pub txid: Option<Vec<u8>>,
+}
+
+/// The outcome of processing the send queue.
+#[derive(Debug, Clone, Default)]
+pub struct SendQueueResult {
+    /// The payments that were sent.
+    pub sent: Vec<QueuedPayment>,
+    /// The number of payments that remain in the queue.
+    pub remaining: u32,
+}
+
+/// Adds payments to an account's send queue, to be sent by [`process_send_queue`].
+///
+/// The payments are checked up front, so that one that could never be sent doesn't hold up the queue.
+/// Returns the IDs of the queued payments.
+pub fn enqueue_send(
+    conn: &mut Connection,
+    account_id: AccountId,
+    details: &[TransactionSendDetail],
+) -> Result<Vec<u32>, Error> {
+    for detail in details {
+        to_payment(&detail.recipient, detail.value, detail.memo.as_deref())?;
+    }
+
+    let enqueued = SystemTime::now();
+    let tx = conn.transaction()?;
+    let mut ids = Vec::with_capacity(details.len());
+    for detail in details {
+        ids.push(queue_payment(&tx, account_id.into(), detail, enqueued)?.id);
+    }
+    tx.commit()?;
+    Ok(ids)
+}
+
+/// Adds a payment that has already been checked to the send queue.
+pub(crate) fn queue_payment(
+    conn: &Connection,
+    account_id: u32,
+    detail: &TransactionSendDetail,
+    enqueued: SystemTime,
+) -> Result<QueuedPayment, Error> {
+    conn.execute(
+        ENQUEUE_PAYMENT,
+        named_params! {
+            ":account_id": account_id,
+            ":recipient": detail.recipient,
+            ":value": detail.value,
+            ":memo": detail.memo,
+            ":enqueued": OffsetDateTime::from(enqueued).unix_timestamp(),
+        },
+    )?;
+
+    Ok(QueuedPayment {
+        id: conn.last_insert_rowid() as u32,
+        account_id,
+        recipient: detail.recipient.clone(),
+        value: detail.value,
+        memo: detail.memo.clone(),
+        enqueued,
+        txid: None,
+    })
+}
+
+/// Gets the payments in an account's send queue that have not been sent yet, oldest first.
+pub fn get_send_queue(
+    conn: &Connection,
+    account_id: AccountId,
+) -> Result<Vec<QueuedPayment>, Error> {
+    conn.prepare(GET_QUEUED_PAYMENTS)?
+        .query_map(
+            named_params! { ":account_id": u32::from(account_id) },
+            read_queued_payment,
+        )?
+        .map(|row| row?)
+        .collect()
+}
+
+/// Removes a payment from the send queue, if it has not been sent yet.
+///
+/// Returns `true` if the payment was removed.
+pub fn cancel_queued_send(conn: &Connection, payment_id: u32) -> Result<bool, Error> {
+    Ok(conn.execute(CANCEL_QUEUED_PAYMENT, named_params! { ":id": payment_id })? > 0)
+}
+
+/// Sends as many of the payments in the account's send queue as its spendable balance allows,
+/// batching them into a shared transaction to save on fees.
+///
+/// Payments are sent in the order they were queued, and a payment is never sent ahead of one queued before it.
+/// Nothing is sent until the wallet has synced far enough to spend funds.
+/// Payments that remain in the queue are tried again the next time this is called,
+/// so it is meant to be called after each sync.
+///
+/// A transaction is recorded against its payments before it is broadcast.
+/// One that was created but never broadcast (e.g. because the app closed) is broadcast on the next call,
+/// or its payments go back in the queue once it has expired.
+pub async fn process_send_queue(
+    config: &DbInit,
+    server_uri: Uri,
+    usk: &UnifiedSpendingKey,
+    min_confirmations: NonZeroU32,
+    options: &SendOptions,
+) -> Result<SendQueueResult, Error> {
+    let network: Network = config.network.into();
+    let mut db = Db::load(&config.data_file, network)?;
+    let account_id = db
+        .data
+        .get_account_for_ufvk(&usk.to_unified_full_viewing_key())?
+        .ok_or(Error::KeyNotRecognized)?
+        .id();
+    let mut conn = open_connection(&config.data_file)?;
+
+    broadcast_unbroadcast_transactions(&mut db, &conn, server_uri.clone(), account_id).await?;
+
+    let mut result = SendQueueResult::default();
+    let mut queue = get_send_queue(&conn, account_id)?;
+    while !queue.is_empty() {
+        let balances = get_user_balances(config, account_id, min_confirmations)?;
+        if !balances.synced {
+            break;
+        }
+
+        // Take as many payments as the spendable balance looks like it can cover, and fewer if that turns out not to be so.
+        let mut batch = affordable_batch(&queue, balances.spendable);
+        let txids = loop {
+            if batch.is_empty() {
+                break None;
+            }
+
+            let details = batch
+                .iter()
+                .map(|p| TransactionSendDetail {
+                    recipient: p.recipient.clone(),
+                    value: p.value,
+                    memo: p.memo.clone(),
+                })
+                .collect();
+            match create_send_transactions(
+                &mut db,
+                &conn,
+                network,
+                usk,
+                min_confirmations,
+                details,
+                options,
+            ) {
+                Ok(txids) => break Some(txids),
+                Err(Error::InsufficientFunds { .. }) => {
+                    batch.pop();
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        let txids = match txids {
+            Some(txids) => txids,
+            None => break,
+        };
+
+        // The payments are made by the last transaction; any before it only shield funds for it.
+        let txid = *txids.last();
+        let tx = conn.transaction()?;
+        for id in txids.iter() {
+            tx.execute(
+                RECORD_SEND_QUEUE_TRANSACTION,
+                named_params! { ":txid": id.as_ref(), ":account_id": u32::from(account_id) },
+            )?;
+        }
+        for payment in batch.iter_mut() {
+            tx.execute(
+                SET_QUEUED_PAYMENT_SENT,
+                named_params! { ":id": payment.id, ":txid": txid.as_ref() },
+            )?;
+            payment.txid = Some(txid.as_ref().to_vec());
+        }
+        tx.commit()?;
+
+        for id in txids {
+            transmit_transaction(id, server_uri.clone(), &mut db).await?;
+            conn.execute(
+                SET_SEND_QUEUE_TRANSACTION_BROADCAST,
+                named_params! { ":txid": id.as_ref() },
+            )?;
+        }
+
+        queue.drain(..batch.len());
+        result.sent.extend(batch);
+    }
+
+    result.remaining = get_send_queue(&conn, account_id)?.len() as u32;
+    Ok(result)
+}
+
+/// Broadcasts the transactions the send queue created but did not get to broadcast,
+/// and puts the payments made by any that expired in the meantime back in the queue.
+async fn broadcast_unbroadcast_transactions(
+    db: &mut Db,
+    conn: &Connection,
+    server_uri: Uri,
+    account_id: AccountId,
+) -> Result<(), Error> {
+    let tip = db.data.chain_height()?.map(u32::from);
+    let unbroadcast = conn
+        .prepare(GET_UNBROADCAST_SEND_QUEUE_TRANSACTIONS)?
+        .query_map(
+            named_params! { ":account_id": u32::from(account_id) },
+            |row| {
+                Ok((
+                    TxId::from_bytes(row.get("txid")?),
+                    row.get::<_, bool>("stored")?,
+                    row.get::<_, Option<u32>>("mined_height")?,
+                    row.get::<_, Option<u32>>("expiry_height")?,
+                ))
+            },
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (txid, stored, mined_height, expiry_height) in unbroadcast {
+        let expired = match (tip, expiry_height) {
+            (Some(tip), Some(expiry)) => expiry > 0 && tip > expiry,
+            _ => false,
+        };
+        if !stored || mined_height.is_some() {
+            // Either it made it to the chain after all, or the wallet no longer has it to broadcast.
+            // Sending its payments again could pay them twice, so leave them be.
+            if !stored {
+                warn!("Queued transaction {} is missing from the wallet.", txid);
+            }
+        } else if expired {
+            conn.execute(REQUEUE_PAYMENTS, named_params! { ":txid": txid.as_ref() })?;
+            conn.execute(
+                DELETE_SEND_QUEUE_TRANSACTION,
+                named_params! { ":txid": txid.as_ref() },
+            )?;
+            continue;
+        } else {
+            transmit_transaction(txid, server_uri.clone(), db).await?;
+        }
+
+        conn.execute(
+            SET_SEND_QUEUE_TRANSACTION_BROADCAST,
+            named_params! { ":txid": txid.as_ref() },
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Takes payments from the front of the queue for as long as their sum, with a rough fee, fits in the balance.
+fn affordable_batch(queue: &[QueuedPayment], spendable: u64) -> Vec<QueuedPayment> {
+    let marginal_fee: u64 = FeeRule::standard().marginal_fee().into();
+    let grace_actions: u64 = FeeRule::standard().grace_actions() as u64;
+
+    let mut batch = Vec::new();
+    let mut total = 0;
+    for payment in queue.iter().take(MAX_PAYMENTS_PER_TRANSACTION) {
+        // Every payment adds an output, and there is change and at least one input besides.
+        let fee = marginal_fee * (batch.len() as u64 + 2).max(grace_actions);
+        if total + payment.value + fee > spendable {
+            break;
+        }
+
+        total += payment.value;
+        batch.push(payment.clone());
+    }
+
+    batch
+}
+
+pub(crate) fn to_payment(
+    recipient: &str,
+    value: u64,
+    memo: Option<&[u8]>,
+) -> Result<Payment, Error> {
+    let memo = memo.map(MemoBytes::from_bytes).transpose()?;
+    Payment::new(
+        ZcashAddress::try_from_encoded(recipient).map_err(|_| Error::InvalidAddress)?,
+        NonNegativeAmount::from_u64(value).map_err(|_| Error::InvalidAmount)?,
+        memo,
+        None,
+        None,
+        Vec::new(),
+    )
+    .ok_or(Error::MemoNotAllowed)
+}
+
+fn read_queued_payment(row: &Row) -> rusqlite::Result<Result<QueuedPayment, Error>> {
+    let enqueued: i64 = row.get("enqueued")?;
+    let payment = QueuedPayment {
+        id: row.get("id")?,
+        account_id: row.get("account_id")?,
+        recipient: row.get("recipient")?,
+        value: row.get("value")?,
+        memo: row.get("memo")?,
+        enqueued: SystemTime::UNIX_EPOCH,
+        txid: row.get("txid")?,
+    };
+
+    Ok(OffsetDateTime::from_unix_timestamp(enqueued)
+        .map(|t| QueuedPayment {
+            enqueued: t.into(),
+            ..payment
+        })
+        .map_err(|e| Error::Internal(format!("Invalid queue time: {}", e))))
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::WalletWrite;
+    use zcash_primitives::consensus::BlockHeight;
+
+    use crate::{
+        backing_store::open_connection,
+        test_constants::{setup_local_test, LIGHTSERVER_URI, LOCAL_BIRTHDAY_HEIGHT},
+    };
+
+    use super::*;
+
+    fn payment(value: u64) -> QueuedPayment {
+        QueuedPayment {
+            id: 0,
+            account_id: 0,
+            recipient: String::new(),
+            value,
+            memo: None,
+            enqueued: SystemTime::now(),
+            txid: None,
+        }
+    }
+
+    #[test]
+    fn test_enqueue_send() {
+        let mut setup = setup_local_test();
+        let (_, _, account_id, usk) = setup.create_account().unwrap();
+        let mut conn = open_connection(&setup.data_file).unwrap();
+        let (_, address) = usk
+            .to_unified_full_viewing_key()
+            .sapling()
+            .unwrap()
+            .default_address();
+        let recipient = zcash_keys::address::Address::Sapling(address).encode(&setup.network);
+
+        let ids = enqueue_send(
+            &mut conn,
+            account_id,
+            &[
+                TransactionSendDetail {
+                    recipient: recipient.clone(),
+                    value: 10_000,
+                    memo: Some(b"first".to_vec()),
+                },
+                TransactionSendDetail {
+                    recipient: recipient.clone(),
+                    value: 20_000,
+                    memo: None,
+                },
+            ],
+        )
+        .unwrap();
+        assert_eq!(2, ids.len());
+
+        let queue = get_send_queue(&conn, account_id).unwrap();
+        assert_eq!(ids, queue.iter().map(|p| p.id).collect::<Vec<_>>());
+        assert_eq!(Some(b"first".to_vec()), queue[0].memo);
+
+        assert!(cancel_queued_send(&conn, ids[0]).unwrap());
+        assert!(!cancel_queued_send(&conn, ids[0]).unwrap());
+        assert_eq!(1, get_send_queue(&conn, account_id).unwrap().len());
+
+        // A bad payment is rejected without queuing any of the others.
+        assert!(enqueue_send(
+            &mut conn,
+            account_id,
+            &[
+                TransactionSendDetail {
+                    recipient: recipient.clone(),
+                    value: 10_000,
+                    memo: None,
+                },
+                TransactionSendDetail {
+                    recipient: "bogus".to_string(),
+                    value: 10_000,
+                    memo: None,
+                },
+            ],
+        )
+        .is_err());
+        assert_eq!(1, get_send_queue(&conn, account_id).unwrap().len());
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_process_send_queue_requeues_expired_transaction() {
+        let mut setup = setup_local_test();
+        let (_, _, account_id, usk) = setup.create_account().unwrap();
+        let mut conn = open_connection(&setup.data_file).unwrap();
+        let (_, address) = usk
+            .to_unified_full_viewing_key()
+            .sapling()
+            .unwrap()
+            .default_address();
+        let recipient = zcash_keys::address::Address::Sapling(address).encode(&setup.network);
+        let detail = TransactionSendDetail {
+            recipient,
+            value: 10_000,
+            memo: None,
+        };
+        let ids = enqueue_send(&mut conn, account_id, &[detail.clone(), detail]).unwrap();
+        let min_confirmations = NonZeroU32::new(1).unwrap();
+
+        // Nothing is sent before the wallet has synced.
+        let result = process_send_queue(
+            &setup.db_init,
+            LIGHTSERVER_URI.clone(),
+            &usk,
+            min_confirmations,
+            &SendOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert!(result.sent.is_empty());
+        assert_eq!(2, result.remaining);
+
+        // Simulate a transaction that paid both but was never broadcast, and has since expired.
+        let txid = [7u8; 32];
+        for id in &ids {
+            conn.execute(
+                SET_QUEUED_PAYMENT_SENT,
+                named_params! { ":id": id, ":txid": txid },
+            )
+            .unwrap();
+        }
+        conn.execute(
+            RECORD_SEND_QUEUE_TRANSACTION,
+            named_params! { ":txid": txid, ":account_id": u32::from(account_id) },
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO transactions (txid, expiry_height) VALUES (?, ?)",
+            (txid, LOCAL_BIRTHDAY_HEIGHT + 1),
+        )
+        .unwrap();
+        setup
+            .db
+            .data
+            .update_chain_tip(BlockHeight::from_u32(LOCAL_BIRTHDAY_HEIGHT as u32 + 5))
+            .unwrap();
+        assert!(get_send_queue(&conn, account_id).unwrap().is_empty());
+
+        // The payments go back in the queue, ready to be sent in a new transaction.
+        let result = process_send_queue(
+            &setup.db_init,
+            LIGHTSERVER_URI.clone(),
+            &usk,
+            min_confirmations,
+            &SendOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert!(result.sent.is_empty());
+        assert_eq!(2, result.remaining);
+        assert_eq!(
+            ids,
+            get_send_queue(&conn, account_id)
+                .unwrap()
+                .iter()
+                .map(|p| p.id)
+                .collect::<Vec<_>>()
+        );
+        let in_flight: u32 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM nerdbank_send_queue_transactions",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(0, in_flight);
+    }
+
+    #[test]
+    fn test_affordable_batch() {
+        let queue = vec![payment(50_000), payment(30_000), payment(1)];
+        assert!(affordable_batch(&queue, 50_000).is_empty());
+        assert_eq!(1, affordable_batch(&queue, 70_000).len());
+        assert_eq!(3, affordable_batch(&queue, 1_000_000).len());
+
+        // A later payment that would fit is not sent ahead of an earlier one that doesn't.
+        let queue = vec![payment(100_000), payment(1)];
+        assert!(affordable_batch(&queue, 50_000).is_empty());
+    }
+}
@@ -2,7 +2,7 @@ use std::path::Path;
 
 use http::Uri;
 use nonempty::NonEmpty;
-use rusqlite::{named_params, Connection};
+use rusqlite::named_params;
 use zcash_client_backend::{
     data_api::wallet::{
         create_proposed_transactions,
@@ -18,11 +18,12 @@ use zcash_client_sqlite::{AccountId, ReceivedNoteId};
 use zcash_primitives::{
     consensus::Network,
     legacy::TransparentAddress,
+    memo::MemoBytes,
     transaction::fees::zip317::{FeeRule, MINIMUM_FEE},
 };
 
 use crate::{
-    backing_store::Db,
+    backing_store::{open_connection, Db},
     error::Error,
     interop::{DbInit, TransparentNote},
     prover::get_prover,
@@ -30,21 +31,40 @@ use crate::{
     sql_statements::GET_UNSPENT_TRANSPARENT_NOTES,
 };
 
+/// Shields the funds received at a transparent address.
+///
+/// `min_confirmations` is the number of confirmations a UTXO needs before it is shielded.
+/// 0 shields UTXOs as soon as they appear in the mempool, at the risk of paying fees
+/// for a transaction that fails if those UTXOs are dropped.
+///
+/// `memo` is attached to the shielded output, so the transaction can be recognized later.
 pub async fn shield_funds_at_address<P: AsRef<Path>>(
     data_file: P,
     server_uri: Uri,
     network: Network,
     usk: &UnifiedSpendingKey,
     address: TransparentAddress,
+    min_confirmations: u32,
+    memo: Option<Vec<u8>>,
 ) -> Result<NonEmpty<SendTransactionResult>, Error> {
-    let mut db = Db::init(data_file, network)?;
+    // The wallet only derives P2PKH addresses, and the transaction builder can only sign inputs
+    // that spend from them. Spending from a P2SH address would take its redeem script and
+    // whatever signatures it requires, which we have no way to produce.
+    if let TransparentAddress::ScriptHash(_) = address {
+        return Err(Error::InvalidArgument(
+            "Funds sent to a P2SH address cannot be shielded by this wallet.".to_string(),
+        ));
+    }
 
-    // We want to be able to shield as soon as UTXOs appear in the mempool.
-    let min_confirmations = 0;
+    let memo = match memo {
+        Some(m) => Some(MemoBytes::from_bytes(&m[..])?),
+        None => None,
+    };
+    let mut db = Db::init(data_file, network)?;
 
     let prover = get_prover()?;
     let input_selector = GreedyInputSelector::new(
-        SingleOutputChangeStrategy::new(FeeRule::standard(), None, ShieldedProtocol::Sapling),
+        SingleOutputChangeStrategy::new(FeeRule::standard(), memo, ShieldedProtocol::Sapling),
         Default::default(),
     );
     let proposal = propose_shielding::<_, _, _, zcash_client_sqlite::wallet::commitment_tree::Error>(
@@ -76,7 +96,7 @@ pub async fn shield_funds_at_address<P: AsRef<Path>>(
 
     let mut result = Vec::new();
     for txid in txids {
-        result.push(transmit_transaction(txid, server_uri.clone(), &mut db.data).await?);
+        result.push(transmit_transaction(txid, server_uri.clone(), &mut db).await?);
     }
 
     Ok(NonEmpty::from_vec(result).unwrap())
@@ -88,7 +108,7 @@ pub fn get_unshielded_utxos(
     config: DbInit,
     account_id: AccountId,
 ) -> Result<Vec<TransparentNote>, Error> {
-    let conn = Connection::open(config.data_file)?;
+    let conn = open_connection(config.data_file)?;
     let mut balances_query = conn.prepare(GET_UNSPENT_TRANSPARENT_NOTES)?;
     let mut rows = balances_query.query(named_params! {
         ":account_id": u32::from(account_id),
@@ -109,10 +129,28 @@ pub fn get_unshielded_utxos(
 
 #[cfg(test)]
 mod tests {
-    use crate::test_constants::setup_test;
+    use crate::test_constants::{setup_test, LIGHTSERVER_URI};
 
     use super::*;
 
+    #[tokio_shared_rt::test]
+    async fn test_shield_p2sh_address() {
+        let network = Network::TestNetwork;
+        let usk =
+            UnifiedSpendingKey::from_seed(&network, &[0; 32], zip32::AccountId::ZERO).unwrap();
+        let result = shield_funds_at_address(
+            "unused.sqlite",
+            LIGHTSERVER_URI.to_owned(),
+            network,
+            &usk,
+            TransparentAddress::ScriptHash([0; 20]),
+            0,
+            None,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
     #[tokio_shared_rt::test(flavor = "multi_thread")]
     async fn test_get_unshielded_utxos() {
         let mut setup = setup_test().await;
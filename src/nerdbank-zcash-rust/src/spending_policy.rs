@@ -0,0 +1,367 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use rusqlite::{named_params, Connection, OptionalExtension};
+use time::OffsetDateTime;
+use zcash_client_sqlite::AccountId;
+use zcash_keys::address::Address;
+use zcash_primitives::consensus::Network;
+
+use crate::{
+    error::Error,
+    interop::{SpendApprover, TransactionSendDetail},
+    sql_statements::{
+        CLEAR_SPENDING_POLICY_RECIPIENTS, GET_SPENDING_POLICY, GET_SPENDING_POLICY_RECIPIENTS,
+        GET_SPENT_SINCE, LOG_SPEND, SET_SPENDING_POLICY, SET_SPENDING_POLICY_RECIPIENT,
+    },
+};
+
+/// The period that [`SpendingPolicy::daily_limit`] applies to.
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+lazy_static! {
+    static ref SPEND_APPROVER: Mutex<Option<Arc<dyn SpendApprover>>> = Mutex::new(None);
+}
+
+/// Limits on what an account may send, which are enforced whenever it sends.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpendingPolicy {
+    /// The most the account may send (excluding fees) in any 24 hour period.
+    pub daily_limit: Option<u64>,
+    /// Sends of more than this amount (excluding fees) must be approved by the [`SpendApprover`].
+    pub approval_threshold: Option<u64>,
+    /// The only addresses the account may send to. When empty, any address not in `denied_recipients` is allowed.
+    /// A unified address is only allowed if each of its receivers is in one of these addresses.
+    pub allowed_recipients: Vec<String>,
+    /// Addresses the account may not send to.
+    /// Any address that shares a receiver with one of these is denied as well.
+    pub denied_recipients: Vec<String>,
+}
+
+/// Sets the callback that approves sends above an account's approval threshold.
+pub(crate) fn set_spend_approver(approver: Option<Box<dyn SpendApprover>>) {
+    *SPEND_APPROVER.lock().unwrap() = approver.map(Arc::from);
+}
+
+/// Replaces the spending policy of an account.
+pub fn set_spending_policy(
+    conn: &mut Connection,
+    network: &Network,
+    account_id: AccountId,
+    policy: &SpendingPolicy,
+) -> Result<(), Error> {
+    for address in policy
+        .allowed_recipients
+        .iter()
+        .chain(&policy.denied_recipients)
+    {
+        receivers(network, address)?;
+    }
+
+    let tx = conn.transaction()?;
+    tx.execute(
+        SET_SPENDING_POLICY,
+        named_params! {
+            ":account_id": u32::from(account_id),
+            ":daily_limit": policy.daily_limit,
+            ":approval_threshold": policy.approval_threshold,
+        },
+    )?;
+    tx.execute(
+        CLEAR_SPENDING_POLICY_RECIPIENTS,
+        named_params! { ":account_id": u32::from(account_id) },
+    )?;
+    for (addresses, allowed) in [
+        (&policy.allowed_recipients, true),
+        (&policy.denied_recipients, false),
+    ] {
+        for address in addresses {
+            tx.execute(
+                SET_SPENDING_POLICY_RECIPIENT,
+                named_params! {
+                    ":account_id": u32::from(account_id),
+                    ":address": address,
+                    ":allowed": allowed,
+                },
+            )?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Gets the spending policy of an account. An account without one has no limits.
+pub fn get_spending_policy(
+    conn: &Connection,
+    account_id: AccountId,
+) -> Result<SpendingPolicy, Error> {
+    let mut policy = conn
+        .query_row(
+            GET_SPENDING_POLICY,
+            named_params! { ":account_id": u32::from(account_id) },
+            |row| {
+                Ok(SpendingPolicy {
+                    daily_limit: row.get("daily_limit")?,
+                    approval_threshold: row.get("approval_threshold")?,
+                    ..Default::default()
+                })
+            },
+        )
+        .optional()?
+        .unwrap_or_default();
+
+    let mut stmt = conn.prepare(GET_SPENDING_POLICY_RECIPIENTS)?;
+    let mut rows = stmt.query(named_params! { ":account_id": u32::from(account_id) })?;
+    while let Some(row) = rows.next()? {
+        let address: String = row.get("address")?;
+        if row.get("allowed")? {
+            policy.allowed_recipients.push(address);
+        } else {
+            policy.denied_recipients.push(address);
+        }
+    }
+
+    Ok(policy)
+}
+
+/// Fails with [`Error::SpendingPolicyViolation`] if the account's spending policy does not allow the send.
+///
+/// The [`SpendApprover`] is only consulted once the send has passed every other check.
+pub(crate) fn check_spending_policy(
+    conn: &Connection,
+    network: &Network,
+    account_id: AccountId,
+    details: &[TransactionSendDetail],
+    now: SystemTime,
+) -> Result<(), Error> {
+    let policy = get_spending_policy(conn, account_id)?;
+    let all_receivers = |addresses: &[String]| -> Result<Vec<Receiver>, Error> {
+        let mut all = Vec::new();
+        for address in addresses {
+            all.extend(receivers(network, address)?);
+        }
+        Ok(all)
+    };
+    let allowed = all_receivers(&policy.allowed_recipients)?;
+    let denied = all_receivers(&policy.denied_recipients)?;
+    for detail in details {
+        // Compare receivers rather than address strings, so that an address can't slip past the policy
+        // by being encoded differently or bundled into a unified address with other receivers.
+        let recipient = receivers(network, &detail.recipient)?;
+        if recipient.iter().any(|r| denied.contains(r))
+            || !(policy.allowed_recipients.is_empty()
+                || recipient.iter().all(|r| allowed.contains(r)))
+        {
+            return Err(Error::SpendingPolicyViolation(format!(
+                "Sending to {} is not allowed.",
+                detail.recipient
+            )));
+        }
+    }
+
+    let total: u64 = details.iter().map(|d| d.value).sum();
+    if let Some(limit) = policy.daily_limit {
+        let spent: u64 = conn.query_row(
+            GET_SPENT_SINCE,
+            named_params! {
+                ":account_id": u32::from(account_id),
+                ":since": unix_time(now - DAY),
+            },
+            |row| row.get(0),
+        )?;
+        if spent + total > limit {
+            return Err(Error::SpendingPolicyViolation(format!(
+                "The send would exceed the daily limit of {} ZATs, of which {} ZATs have been spent.",
+                limit, spent
+            )));
+        }
+    }
+
+    if policy.approval_threshold.is_some_and(|t| total > t) {
+        let approver = SPEND_APPROVER.lock().unwrap().clone();
+        let approved = approver.is_some_and(|a| a.approve(account_id.into(), details.to_vec()));
+        if !approved {
+            return Err(Error::SpendingPolicyViolation(
+                "The send was not approved.".to_string(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Records a send against the account's daily limit.
+pub(crate) fn log_spend(
+    conn: &Connection,
+    account_id: AccountId,
+    value: u64,
+    now: SystemTime,
+) -> Result<(), Error> {
+    conn.execute(
+        LOG_SPEND,
+        named_params! {
+            ":account_id": u32::from(account_id),
+            ":time": unix_time(now),
+            ":value": value,
+        },
+    )?;
+    Ok(())
+}
+
+/// A receiver, as its pool (using the same numbering as `v_tx_outputs.output_pool`) and its encoding.
+type Receiver = (u8, Vec<u8>);
+
+/// Gets the receivers that an address is made of.
+fn receivers(network: &Network, address: &str) -> Result<Vec<Receiver>, Error> {
+    match Address::decode(network, address).ok_or(Error::InvalidAddress)? {
+        Address::Unified(ua) => {
+            let mut receivers = Vec::new();
+            if let Some(t) = ua.transparent() {
+                receivers.push((0, t.encode(network).into_bytes()));
+            }
+            if let Some(s) = ua.sapling() {
+                receivers.push((2, s.to_bytes().to_vec()));
+            }
+            if let Some(o) = ua.orchard() {
+                receivers.push((3, o.to_raw_address_bytes().to_vec()));
+            }
+            Ok(receivers)
+        }
+        Address::Sapling(s) => Ok(vec![(2, s.to_bytes().to_vec())]),
+        Address::Transparent(t) => Ok(vec![(0, t.encode(network).into_bytes())]),
+    }
+}
+
+fn unix_time(time: SystemTime) -> i64 {
+    OffsetDateTime::from(time).unix_timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+    use orchard::keys::Scope;
+    use zcash_client_backend::{address::UnifiedAddress, keys::UnifiedSpendingKey};
+
+    use crate::{backing_store::open_connection, test_constants::setup_local_test};
+
+    use super::*;
+
+    fn send(recipient: &str, value: u64) -> Vec<TransactionSendDetail> {
+        vec![TransactionSendDetail {
+            recipient: recipient.to_string(),
+            value,
+            memo: None,
+        }]
+    }
+
+    #[test]
+    fn test_spending_policy() {
+        let mut setup = setup_local_test();
+        let (_, _, account_id, _) = setup.create_account().unwrap();
+        let mut conn = open_connection(&setup.data_file).unwrap();
+        let network = setup.network;
+        let now = SystemTime::now();
+
+        let other = UnifiedSpendingKey::from_seed(&network, &[1; 32], zip32::AccountId::ZERO)
+            .unwrap()
+            .to_unified_full_viewing_key();
+        let orchard = |i: u32| other.orchard().unwrap().address_at(i, Scope::External);
+        let sapling = other.sapling().unwrap().default_address().1;
+        let address = |orchard, sapling| {
+            UnifiedAddress::from_receivers(orchard, sapling, None)
+                .unwrap()
+                .encode(&network)
+        };
+        let thief = address(Some(orchard(1)), None);
+        let friend = address(Some(orchard(2)), None);
+        let stranger = address(Some(orchard(3)), None);
+
+        assert_eq!(
+            SpendingPolicy::default(),
+            get_spending_policy(&conn, account_id).unwrap()
+        );
+        check_spending_policy(
+            &conn,
+            &network,
+            account_id,
+            &send(&stranger, 1_000_000),
+            now,
+        )
+        .unwrap();
+
+        let policy = SpendingPolicy {
+            daily_limit: Some(100_000),
+            approval_threshold: None,
+            allowed_recipients: Vec::new(),
+            denied_recipients: vec![thief.clone()],
+        };
+        set_spending_policy(&mut conn, &network, account_id, &policy).unwrap();
+        assert_eq!(policy, get_spending_policy(&conn, account_id).unwrap());
+
+        assert_matches!(
+            check_spending_policy(&conn, &network, account_id, &send(&thief, 1), now),
+            Err(Error::SpendingPolicyViolation(_))
+        );
+
+        // The thief's receiver is denied in any address that contains it.
+        let disguised = address(Some(orchard(1)), Some(sapling));
+        assert_matches!(
+            check_spending_policy(&conn, &network, account_id, &send(&disguised, 1), now),
+            Err(Error::SpendingPolicyViolation(_))
+        );
+
+        check_spending_policy(&conn, &network, account_id, &send(&friend, 60_000), now).unwrap();
+        log_spend(&conn, account_id, 60_000, now).unwrap();
+        assert_matches!(
+            check_spending_policy(&conn, &network, account_id, &send(&friend, 60_000), now),
+            Err(Error::SpendingPolicyViolation(_))
+        );
+
+        // The limit applies to a rolling 24 hour period.
+        check_spending_policy(
+            &conn,
+            &network,
+            account_id,
+            &send(&friend, 60_000),
+            now + DAY,
+        )
+        .unwrap();
+
+        let policy = SpendingPolicy {
+            allowed_recipients: vec![friend.clone()],
+            approval_threshold: Some(10_000),
+            ..Default::default()
+        };
+        set_spending_policy(&mut conn, &network, account_id, &policy).unwrap();
+        assert_matches!(
+            check_spending_policy(&conn, &network, account_id, &send(&stranger, 1), now),
+            Err(Error::SpendingPolicyViolation(_))
+        );
+        check_spending_policy(&conn, &network, account_id, &send(&friend, 10_000), now).unwrap();
+
+        // An allowed receiver doesn't let other receivers bundled with it through.
+        let bundled = address(Some(orchard(2)), Some(sapling));
+        assert_matches!(
+            check_spending_policy(&conn, &network, account_id, &send(&bundled, 1), now),
+            Err(Error::SpendingPolicyViolation(_))
+        );
+
+        // With no approver, sends above the threshold are refused.
+        assert_matches!(
+            check_spending_policy(&conn, &network, account_id, &send(&friend, 10_001), now),
+            Err(Error::SpendingPolicyViolation(_))
+        );
+
+        // Addresses that can't be decoded are rejected up front.
+        let policy = SpendingPolicy {
+            denied_recipients: vec!["thief".to_string()],
+            ..Default::default()
+        };
+        assert_matches!(
+            set_spending_policy(&mut conn, &network, account_id, &policy),
+            Err(Error::InvalidAddress)
+        );
+    }
+}
@@ -10,6 +10,8 @@ pub(crate) const GET_TRANSACTIONS_SQL: &str = r#"
 		t.fee_paid,
 		t.block_time,
 		t.expired_unmined,
+		length(tx.raw) AS raw_size,
+		substr(tx.raw, 1, 22) AS raw_header, -- decode::HEADER_PREFIX_LENGTH
 		txo.output_pool,
 		coalesce(
 			txo.from_account_id,
@@ -32,7 +34,10 @@ pub(crate) const GET_TRANSACTIONS_SQL: &str = r#"
 		(SELECT to_address FROM v_tx_outputs vtxo WHERE vtxo.txid = t.txid AND vtxo.output_pool = txo.output_pool AND vtxo.output_index = txo.output_index AND to_address IS NOT NULL) AS to_address,
 		coalesce(s.diversifier, o.diversifier) AS diversifier,
 		txo.value,
-		txo.memo
+		txo.memo,
+		EXISTS(SELECT 1 FROM sent_notes sn WHERE sn.tx = tx.id_tx AND sn.from_account_id = t.account_id) AS outgoing_recovered,
+		(SELECT label FROM nerdbank_transaction_labels l WHERE l.txid = t.txid) AS label,
+		(SELECT group_concat(tag_id) FROM nerdbank_transaction_tags tt WHERE tt.txid = t.txid) AS tags
 	FROM v_transactions t
 	LEFT OUTER JOIN v_tx_outputs txo ON t.txid = txo.txid
 	LEFT OUTER JOIN transactions tx ON tx.txid = t.txid
@@ -47,20 +52,32 @@ pub(crate) const GET_TRANSACTIONS_SQL: &str = r#"
 	ORDER BY t.account_id, t.mined_height, t.tx_index, txo.output_pool, txo.output_index -- ensure rows that get squashed together are next to each other
 "#;
 
-// TODO: update this to consider UTXOs in "Block with first unspent note" column.
+// When :account_id is NULL, this produces a row for every account.
 // Note that WalletDb::get_min_unspent_height provides the rebirth height at the wallet level (instead of the account level).
 pub(crate) const GET_BIRTHDAY_HEIGHTS: &str = r#"
 	SELECT
-		(SELECT birthday_height FROM accounts WHERE id = :account_id) AS "Original birthday height",
-		(SELECT MIN(mined_height) FROM v_transactions WHERE account_id = :account_id) AS "Block with first note",
+		a.id AS account_id,
+		a.birthday_height AS "Original birthday height",
+		(SELECT MIN(mined_height) FROM v_transactions WHERE account_id = a.id) AS "Block with first note",
 		(SELECT MIN(t.block)
-			FROM transactions t 
-			LEFT OUTER JOIN sapling_received_notes s ON s.tx = t.id_tx
+			FROM transactions t
+			INNER JOIN sapling_received_notes s ON s.tx = t.id_tx
 			LEFT OUTER JOIN sapling_received_note_spends ss ON ss.sapling_received_note_id = s.id
-			LEFT OUTER JOIN orchard_received_notes o ON o.tx = t.id_tx
+			WHERE s.account_id = a.id AND ss.transaction_id IS NULL
+		) AS "Block with first unspent sapling note",
+		(SELECT MIN(t.block)
+			FROM transactions t
+			INNER JOIN orchard_received_notes o ON o.tx = t.id_tx
 			LEFT OUTER JOIN orchard_received_note_spends os ON os.orchard_received_note_id = o.id
-			WHERE (s.account_id = :account_id AND ss.transaction_id IS NULL) OR (o.account_id = :account_id AND os.transaction_id IS NULL)
-		) AS "Block with first unspent note"
+			WHERE o.account_id = a.id AND os.transaction_id IS NULL
+		) AS "Block with first unspent orchard note",
+		(SELECT MIN(utxos.height)
+			FROM utxos
+			LEFT OUTER JOIN transparent_received_output_spends j ON utxos.id = j.transparent_received_output_id
+			WHERE received_by_account_id = a.id AND j.transaction_id IS NULL
+		) AS "Block with first unspent UTXO"
+	FROM accounts a
+	WHERE :account_id IS NULL OR a.id = :account_id
 "#;
 
 // The v_tx_outputs view doesn't include transparent UTXOs, so we filter them out (for good measure) and add them via UNION with the utxos table.
@@ -88,6 +105,18 @@ pub(crate) const GET_UNSPENT_NOTES: &str = r#"
 	FROM utxos
 	LEFT OUTER JOIN transparent_received_output_spends j ON utxos.id = j.transparent_received_output_id
 	WHERE received_by_account_id = :account_id AND j.transaction_id IS NULL
+
+	UNION ALL
+
+	-- Transparent payments seen in the mempool, until they are mined and downloaded as UTXOs.
+	SELECT
+		NULL, -- block
+		value,
+		0, -- output_pool
+		0  -- is_change
+	FROM nerdbank_mempool_receives m
+	WHERE account_id = :account_id
+		AND NOT EXISTS (SELECT 1 FROM utxos u WHERE u.prevout_txid = m.txid AND u.prevout_idx = m.output_index)
 "#;
 
 pub(crate) const GET_UNSPENT_TRANSPARENT_NOTES: &str = r#"
@@ -100,3 +129,841 @@ pub(crate) const GET_UNSPENT_TRANSPARENT_NOTES: &str = r#"
 	WHERE received_by_account_id = :account_id AND j.transaction_id IS NULL
 	ORDER BY height
 "#;
+
+pub(crate) const GET_UNSPENT_TRANSPARENT_BALANCE: &str = r#"
+	SELECT COALESCE(SUM(value_zat), 0)
+	FROM utxos
+	LEFT OUTER JOIN transparent_received_output_spends j ON utxos.id = j.transparent_received_output_id
+	WHERE j.transaction_id IS NULL
+"#;
+
+pub(crate) const GET_ACCOUNT_KEY_INFO: &str = r#"
+	SELECT has_spend_key, account_kind, hd_seed_fingerprint, hd_account_index
+	FROM accounts
+	WHERE id = :account_id
+"#;
+
+// Pairs each transaction that spent one of the account's notes or UTXOs with the transaction that received it.
+pub(crate) const GET_SPENT_NOTE_SOURCES: &str = r#"
+	SELECT spending.txid, source.txid
+	FROM sapling_received_note_spends ss
+	INNER JOIN sapling_received_notes s ON s.id = ss.sapling_received_note_id
+	INNER JOIN transactions spending ON spending.id_tx = ss.transaction_id
+	INNER JOIN transactions source ON source.id_tx = s.tx
+	WHERE s.account_id = :account_id
+
+	UNION ALL
+
+	SELECT spending.txid, source.txid
+	FROM orchard_received_note_spends os
+	INNER JOIN orchard_received_notes o ON o.id = os.orchard_received_note_id
+	INNER JOIN transactions spending ON spending.id_tx = os.transaction_id
+	INNER JOIN transactions source ON source.id_tx = o.tx
+	WHERE o.account_id = :account_id
+
+	UNION ALL
+
+	SELECT spending.txid, utxos.prevout_txid
+	FROM transparent_received_output_spends j
+	INNER JOIN utxos ON utxos.id = j.transparent_received_output_id
+	INNER JOIN transactions spending ON spending.id_tx = j.transaction_id
+	WHERE utxos.received_by_account_id = :account_id
+"#;
+
+pub(crate) const GET_TRANSACTION_RAW: &str = r#"
+	SELECT raw
+	FROM transactions
+	WHERE txid = :txid
+"#;
+
+pub(crate) const GET_UTXO_VALUE: &str = r#"
+	SELECT value_zat
+	FROM utxos
+	WHERE prevout_txid = :txid AND prevout_idx = :index
+"#;
+
+// Transactions that a rewind to the given height would orphan.
+pub(crate) const GET_TXIDS_MINED_ABOVE: &str = r#"
+	SELECT txid
+	FROM transactions
+	WHERE block > :height
+"#;
+
+// Transactions that a reorg orphaned, to be reported again once the blocks up to the height the reorg
+// was detected at have been scanned again. They survive the sync that detected the reorg ending early.
+pub(crate) const CREATE_PENDING_REORGS: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_pending_reorgs (
+		txid BLOB NOT NULL PRIMARY KEY,
+		detected_at INTEGER NOT NULL
+	);
+"#;
+
+pub(crate) const RECORD_PENDING_REORG: &str = r#"
+	INSERT INTO nerdbank_pending_reorgs (txid, detected_at)
+	VALUES (:txid, :detected_at)
+	ON CONFLICT (txid) DO UPDATE SET detected_at = max(detected_at, excluded.detected_at)
+"#;
+
+pub(crate) const GET_RESCANNED_PENDING_REORGS: &str = r#"
+	SELECT txid
+	FROM nerdbank_pending_reorgs
+	WHERE detected_at <= :scanned_to
+"#;
+
+pub(crate) const DELETE_RESCANNED_PENDING_REORGS: &str = r#"
+	DELETE FROM nerdbank_pending_reorgs
+	WHERE detected_at <= :scanned_to
+"#;
+
+// Transactions mined at or above a height, and those not yet mined, for sharing with another device.
+pub(crate) const GET_DIFF_TRANSACTIONS: &str = r#"
+	SELECT raw, block AS mined_height
+	FROM transactions
+	WHERE block IS NULL OR block >= :since_height
+	ORDER BY block
+"#;
+
+// Records the height of a transaction learned from another device, unless this wallet already knows it.
+pub(crate) const SET_DIFF_TRANSACTION_HEIGHT: &str = r#"
+	UPDATE transactions
+	SET block = :height
+	WHERE txid = :txid AND block IS NULL
+"#;
+
+// The positions and nullifiers of the notes received by the transactions in a diff.
+// Notes that the wallet has only decrypted (rather than scanned) don't know these yet, so they are left out.
+pub(crate) const GET_DIFF_NOTES: &str = r#"
+	SELECT tx.txid, 2 AS output_pool, n.output_index, n.commitment_tree_position, n.nf
+	FROM sapling_received_notes n
+	INNER JOIN transactions tx ON tx.id_tx = n.tx
+	WHERE (tx.block IS NULL OR tx.block >= :since_height)
+		AND n.commitment_tree_position IS NOT NULL AND n.nf IS NOT NULL
+	UNION ALL
+	SELECT tx.txid, 3 AS output_pool, n.action_index, n.commitment_tree_position, n.nf
+	FROM orchard_received_notes n
+	INNER JOIN transactions tx ON tx.id_tx = n.tx
+	WHERE (tx.block IS NULL OR tx.block >= :since_height)
+		AND n.commitment_tree_position IS NOT NULL AND n.nf IS NOT NULL
+"#;
+
+// Records the position and nullifier of a note learned from another device, unless this wallet already knows them.
+pub(crate) const SET_DIFF_SAPLING_NOTE: &str = r#"
+	UPDATE sapling_received_notes
+	SET commitment_tree_position = :position, nf = :nf
+	WHERE tx = (SELECT id_tx FROM transactions WHERE txid = :txid)
+		AND output_index = :output_index
+		AND commitment_tree_position IS NULL
+"#;
+
+pub(crate) const SET_DIFF_ORCHARD_NOTE: &str = r#"
+	UPDATE orchard_received_notes
+	SET commitment_tree_position = :position, nf = :nf
+	WHERE tx = (SELECT id_tx FROM transactions WHERE txid = :txid)
+		AND action_index = :output_index
+		AND commitment_tree_position IS NULL
+"#;
+
+pub(crate) const GET_DIFF_ADDRESSES: &str = r#"
+	SELECT account_id, diversifier_index_be
+	FROM addresses
+"#;
+
+pub(crate) const GET_DIFF_ADDRESS: &str = r#"
+	SELECT 1
+	FROM addresses
+	WHERE account_id = :account_id AND diversifier_index_be = :index
+"#;
+
+// Notes and UTXOs received by the wallet's accounts, with what is needed to work out which address received them.
+// Rows are ordered by (sort_height, sort_index, output_pool, output_index), which is also the key for resuming after a cursor.
+// Unmined transactions sort last, in the order they were added.
+pub(crate) const GET_INCOMING_PAYMENTS: &str = r#"
+	SELECT
+		coalesce(tx.block, 4294967295) AS sort_height,
+		coalesce(tx.tx_index, tx.id_tx) AS sort_index,
+		txo.output_index,
+		txo.to_account_id AS account_id,
+		txo.from_account_id,
+		txo.txid,
+		tx.block AS mined_height,
+		txo.output_pool,
+		txo.to_address,
+		coalesce(s.diversifier, o.diversifier) AS diversifier,
+		txo.value,
+		txo.memo
+	FROM v_tx_outputs txo
+	INNER JOIN transactions tx ON tx.txid = txo.txid
+	LEFT OUTER JOIN sapling_received_notes s ON txo.output_pool = 2 AND s.tx = tx.id_tx AND s.output_index = txo.output_index
+	LEFT OUTER JOIN orchard_received_notes o ON txo.output_pool = 3 AND o.tx = tx.id_tx AND o.action_index = txo.output_index
+	WHERE txo.to_account_id IS NOT NULL
+		AND (tx.block IS NULL OR tx.block >= :starting_block)
+		AND (tx.block IS NOT NULL OR :include_unmined)
+		AND (tx.block IS NULL OR :ending_block IS NULL OR tx.block <= :ending_block)
+		AND (:after_height IS NULL OR (sort_height, sort_index, txo.output_pool, txo.output_index) > (:after_height, :after_index, :after_pool, :after_output_index))
+	ORDER BY sort_height, sort_index, txo.output_pool, txo.output_index
+"#;
+
+// The payments an account received at any of the receivers of one address.
+// Transparent receivers are matched by their encoding, and shielded ones by their diversifier.
+pub(crate) const GET_PAYMENTS_TO_RECEIVERS: &str = r#"
+	SELECT
+		tx.block AS mined_height,
+		txo.from_account_id,
+		txo.output_pool,
+		txo.value,
+		txo.memo
+	FROM v_tx_outputs txo
+	INNER JOIN transactions tx ON tx.txid = txo.txid
+	LEFT OUTER JOIN sapling_received_notes s ON txo.output_pool = 2 AND s.tx = tx.id_tx AND s.output_index = txo.output_index
+	LEFT OUTER JOIN orchard_received_notes o ON txo.output_pool = 3 AND o.tx = tx.id_tx AND o.action_index = txo.output_index
+	WHERE txo.to_account_id = :account_id
+		AND (
+			(txo.output_pool = 0 AND txo.to_address = :transparent)
+			OR (txo.output_pool = 2 AND s.diversifier = :sapling)
+			OR (txo.output_pool = 3 AND o.diversifier = :orchard)
+		)
+"#;
+
+pub(crate) const GET_RECEIVING_ADDRESSES: &str = r#"
+	SELECT account_id, diversifier_index_be, address
+	FROM addresses
+"#;
+
+// Every address stored for an account, whether librustzcash or an allocation recorded it.
+pub(crate) const GET_ACCOUNT_ADDRESSES: &str = r#"
+	SELECT diversifier_index_be, address
+	FROM addresses
+	WHERE account_id = :account_id
+	UNION
+	SELECT diversifier_index_be, address
+	FROM nerdbank_address_allocations
+	WHERE account_id = :account_id AND address IS NOT NULL
+	ORDER BY diversifier_index_be
+"#;
+
+// A side index of memo content, so that memos can be looked up without decoding every memo in the wallet.
+// Tables this crate creates are prefixed to keep them clear of librustzcash's migrations.
+pub(crate) const CREATE_MEMO_INDEX: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_memo_index (
+		txid BLOB NOT NULL,
+		output_pool INTEGER NOT NULL,
+		output_index INTEGER NOT NULL,
+		from_account_id INTEGER,
+		to_account_id INTEGER,
+		value INTEGER NOT NULL,
+		memo_key BLOB,
+		PRIMARY KEY (txid, output_pool, output_index)
+	);
+	CREATE INDEX IF NOT EXISTS nerdbank_memo_index_key ON nerdbank_memo_index (memo_key);
+	CREATE TABLE IF NOT EXISTS nerdbank_memo_indexed_transactions (
+		txid BLOB NOT NULL PRIMARY KEY
+	);
+"#;
+
+// Memos are only stored along with the full transaction, so a transaction needs indexing once its raw data arrives,
+// and never again after that.
+pub(crate) const GET_UNINDEXED_MEMOS: &str = r#"
+	SELECT txo.txid, txo.output_pool, txo.output_index, txo.from_account_id, txo.to_account_id, txo.value, txo.memo
+	FROM v_tx_outputs txo
+	WHERE txo.memo IS NOT NULL AND txo.txid IN (
+		SELECT tx.txid
+		FROM transactions tx
+		LEFT OUTER JOIN nerdbank_memo_indexed_transactions i ON i.txid = tx.txid
+		WHERE tx.raw IS NOT NULL AND i.txid IS NULL
+	)
+"#;
+
+pub(crate) const GET_UNINDEXED_TRANSACTIONS: &str = r#"
+	SELECT tx.txid
+	FROM transactions tx
+	LEFT OUTER JOIN nerdbank_memo_indexed_transactions i ON i.txid = tx.txid
+	WHERE tx.raw IS NOT NULL AND i.txid IS NULL
+"#;
+
+pub(crate) const INSERT_MEMO_INDEX_ENTRY: &str = r#"
+	INSERT OR IGNORE INTO nerdbank_memo_index (txid, output_pool, output_index, from_account_id, to_account_id, value, memo_key)
+	VALUES (:txid, :output_pool, :output_index, :from_account_id, :to_account_id, :value, :memo_key)
+"#;
+
+pub(crate) const MARK_MEMOS_INDEXED: &str = r#"
+	INSERT OR IGNORE INTO nerdbank_memo_indexed_transactions (txid)
+	VALUES (:txid)
+"#;
+
+pub(crate) const DELETE_ORPHANED_MEMO_INDEX_ENTRIES: &str = r#"
+	DELETE FROM nerdbank_memo_index
+	WHERE txid NOT IN (SELECT txid FROM transactions);
+	DELETE FROM nerdbank_memo_indexed_transactions
+	WHERE txid NOT IN (SELECT txid FROM transactions);
+"#;
+
+pub(crate) const FIND_MEMO_INDEX_ENTRIES: &str = r#"
+	SELECT
+		coalesce(m.to_account_id, m.from_account_id) AS account_id,
+		m.txid,
+		tx.block AS mined_height,
+		m.output_pool,
+		m.output_index,
+		m.value,
+		m.memo_key
+	FROM nerdbank_memo_index m
+	INNER JOIN transactions tx ON tx.txid = m.txid
+	WHERE m.memo_key >= :lower_bound
+		AND (:upper_bound IS NULL OR m.memo_key < :upper_bound)
+		AND (:account_id IS NULL OR m.to_account_id = :account_id OR m.from_account_id = :account_id)
+	ORDER BY tx.block IS NULL, tx.block, m.output_pool, m.output_index
+"#;
+
+// The user's labels for transactions, keyed by txid so they survive the wallet forgetting and rediscovering a transaction.
+pub(crate) const CREATE_TRANSACTION_LABELS: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_transaction_labels (
+		txid BLOB NOT NULL PRIMARY KEY,
+		label TEXT NOT NULL
+	);
+"#;
+
+pub(crate) const SET_TRANSACTION_LABEL: &str = r#"
+	INSERT INTO nerdbank_transaction_labels (txid, label)
+	VALUES (:txid, :label)
+	ON CONFLICT (txid) DO UPDATE SET label = excluded.label
+"#;
+
+pub(crate) const DELETE_TRANSACTION_LABEL: &str = r#"
+	DELETE FROM nerdbank_transaction_labels
+	WHERE txid = :txid
+"#;
+
+// Tags that the user can apply to transactions to categorize them.
+pub(crate) const CREATE_TAGS: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_tags (
+		id INTEGER NOT NULL PRIMARY KEY,
+		name TEXT NOT NULL UNIQUE
+	);
+	CREATE TABLE IF NOT EXISTS nerdbank_transaction_tags (
+		txid BLOB NOT NULL,
+		tag_id INTEGER NOT NULL REFERENCES nerdbank_tags(id),
+		PRIMARY KEY (txid, tag_id)
+	);
+	CREATE INDEX IF NOT EXISTS nerdbank_transaction_tags_tag ON nerdbank_transaction_tags (tag_id);
+"#;
+
+pub(crate) const CREATE_TAG: &str = r#"
+	INSERT INTO nerdbank_tags (name)
+	VALUES (:name)
+	ON CONFLICT (name) DO NOTHING
+"#;
+
+pub(crate) const GET_TAG_BY_NAME: &str = r#"
+	SELECT id, name
+	FROM nerdbank_tags
+	WHERE name = :name
+"#;
+
+pub(crate) const GET_TAGS: &str = r#"
+	SELECT id, name
+	FROM nerdbank_tags
+	ORDER BY name
+"#;
+
+pub(crate) const RENAME_TAG: &str = r#"
+	UPDATE nerdbank_tags
+	SET name = :name
+	WHERE id = :tag_id
+"#;
+
+pub(crate) const DELETE_TAG_ASSIGNMENTS: &str = r#"
+	DELETE FROM nerdbank_transaction_tags
+	WHERE tag_id = :tag_id
+"#;
+
+pub(crate) const DELETE_TAG: &str = r#"
+	DELETE FROM nerdbank_tags
+	WHERE id = :tag_id
+"#;
+
+pub(crate) const ADD_TRANSACTION_TAG: &str = r#"
+	INSERT INTO nerdbank_transaction_tags (txid, tag_id)
+	SELECT :txid, id FROM nerdbank_tags WHERE id = :tag_id
+	ON CONFLICT (txid, tag_id) DO NOTHING
+"#;
+
+pub(crate) const REMOVE_TRANSACTION_TAG: &str = r#"
+	DELETE FROM nerdbank_transaction_tags
+	WHERE txid = :txid AND tag_id = :tag_id
+"#;
+
+pub(crate) const GET_TAGGED_TXIDS: &str = r#"
+	SELECT txid
+	FROM nerdbank_transaction_tags
+	WHERE tag_id = :tag_id
+"#;
+
+// The fees paid by an account, i.e. for mined transactions that reduced its balance.
+pub(crate) const GET_FEES_PAID: &str = r#"
+	SELECT block_time, fee_paid
+	FROM v_transactions
+	WHERE account_id = :account_id
+		AND fee_paid IS NOT NULL
+		AND account_balance_delta < 0
+		AND block_time IS NOT NULL
+		AND (:start IS NULL OR block_time >= :start)
+		AND (:end IS NULL OR block_time < :end)
+	ORDER BY block_time
+"#;
+
+// Daily prices of ZEC in fiat currencies, so that fiat history survives reinstalling the app.
+// Each day is the number of (UTC) days since the Unix epoch.
+pub(crate) const CREATE_PRICES: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_prices (
+		currency TEXT NOT NULL,
+		day INTEGER NOT NULL,
+		price REAL NOT NULL,
+		PRIMARY KEY (currency, day)
+	);
+"#;
+
+pub(crate) const SET_CACHED_PRICE: &str = r#"
+	INSERT INTO nerdbank_prices (currency, day, price)
+	VALUES (:currency, :day, :price)
+	ON CONFLICT (currency, day) DO UPDATE SET price = excluded.price
+"#;
+
+pub(crate) const GET_CACHED_PRICES: &str = r#"
+	SELECT day, price
+	FROM nerdbank_prices
+	WHERE currency = :currency
+		AND (:start IS NULL OR day * 86400 >= :start)
+		AND (:end IS NULL OR day * 86400 < :end)
+	ORDER BY day
+"#;
+
+pub(crate) const GET_LATEST_CACHED_PRICE: &str = r#"
+	SELECT day, price
+	FROM nerdbank_prices
+	WHERE currency = :currency
+	ORDER BY day DESC
+	LIMIT 1
+"#;
+
+// The days on which the wallet has mined transactions but no price in the given currency.
+pub(crate) const GET_DAYS_MISSING_PRICES: &str = r#"
+	SELECT DISTINCT t.block_time / 86400 AS day
+	FROM v_transactions t
+	WHERE t.block_time IS NOT NULL
+		AND NOT EXISTS (
+			SELECT 1 FROM nerdbank_prices p
+			WHERE p.currency = :currency AND p.day = t.block_time / 86400
+		)
+	ORDER BY day
+"#;
+
+// Invoices, each with an address of its own so that payments to it can be told apart.
+// The diversifier index is little-endian, unlike in librustzcash's addresses table.
+// Times are Unix timestamps.
+pub(crate) const CREATE_INVOICES: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_invoices (
+		id INTEGER NOT NULL PRIMARY KEY,
+		account_id INTEGER NOT NULL,
+		address TEXT NOT NULL,
+		diversifier_index BLOB NOT NULL,
+		amount INTEGER NOT NULL,
+		memo BLOB,
+		created INTEGER NOT NULL,
+		expiry INTEGER
+	);
+"#;
+
+pub(crate) const CREATE_INVOICE: &str = r#"
+	INSERT INTO nerdbank_invoices (account_id, address, diversifier_index, amount, memo, created, expiry)
+	VALUES (:account_id, :address, :diversifier_index, :amount, :memo, :created, :expiry)
+"#;
+
+pub(crate) const GET_INVOICE: &str = r#"
+	SELECT id, account_id, address, diversifier_index, amount, memo, created, expiry
+	FROM nerdbank_invoices
+	WHERE id = :id
+"#;
+
+// Diversifier indexes reserved for addresses that were handed out for a purpose.
+// The primary key keeps two callers from reserving the same index.
+// The address is NULL when the index doesn't produce a valid address.
+pub(crate) const CREATE_ADDRESS_ALLOCATIONS: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_address_allocations (
+		account_id INTEGER NOT NULL,
+		diversifier_index_be BLOB NOT NULL,
+		address TEXT,
+		purpose TEXT NOT NULL,
+		allocated INTEGER NOT NULL,
+		PRIMARY KEY (account_id, diversifier_index_be)
+	);
+"#;
+
+// The highest diversifier index the account has used or reserved.
+// Indexes are stored big-endian, so comparing the blobs compares the numbers.
+pub(crate) const GET_MAX_ALLOCATED_DIVERSIFIER_INDEX: &str = r#"
+	SELECT MAX(diversifier_index_be)
+	FROM (
+		SELECT diversifier_index_be FROM addresses WHERE account_id = :account_id
+		UNION ALL
+		SELECT diversifier_index_be FROM nerdbank_address_allocations WHERE account_id = :account_id
+	)
+"#;
+
+pub(crate) const RESERVE_DIVERSIFIER_INDEX: &str = r#"
+	INSERT INTO nerdbank_address_allocations (account_id, diversifier_index_be, purpose, allocated)
+	VALUES (:account_id, :diversifier_index_be, :purpose, :allocated)
+	ON CONFLICT (account_id, diversifier_index_be) DO NOTHING
+"#;
+
+pub(crate) const SET_ALLOCATED_ADDRESS: &str = r#"
+	UPDATE nerdbank_address_allocations
+	SET address = :address
+	WHERE account_id = :account_id AND diversifier_index_be = :diversifier_index_be
+"#;
+
+// Payments waiting to be sent, and the transactions that sent them.
+// Times are Unix timestamps.
+pub(crate) const CREATE_SEND_QUEUE: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_send_queue (
+		id INTEGER NOT NULL PRIMARY KEY,
+		account_id INTEGER NOT NULL,
+		recipient TEXT NOT NULL,
+		value INTEGER NOT NULL,
+		memo BLOB,
+		enqueued INTEGER NOT NULL,
+		txid BLOB
+	);
+"#;
+
+pub(crate) const ENQUEUE_PAYMENT: &str = r#"
+	INSERT INTO nerdbank_send_queue (account_id, recipient, value, memo, enqueued)
+	VALUES (:account_id, :recipient, :value, :memo, :enqueued)
+"#;
+
+pub(crate) const GET_QUEUED_PAYMENTS: &str = r#"
+	SELECT id, account_id, recipient, value, memo, enqueued, txid
+	FROM nerdbank_send_queue
+	WHERE account_id = :account_id AND txid IS NULL
+	ORDER BY id
+"#;
+
+pub(crate) const CANCEL_QUEUED_PAYMENT: &str = r#"
+	DELETE FROM nerdbank_send_queue
+	WHERE id = :id AND txid IS NULL
+"#;
+
+pub(crate) const SET_QUEUED_PAYMENT_SENT: &str = r#"
+	UPDATE nerdbank_send_queue
+	SET txid = :txid
+	WHERE id = :id
+"#;
+
+// Every transaction the send queue has created, recorded before it is broadcast,
+// so that one left unbroadcast (e.g. because the app closed) is broadcast later rather than paid again.
+pub(crate) const CREATE_SEND_QUEUE_TRANSACTIONS: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_send_queue_transactions (
+		txid BLOB NOT NULL PRIMARY KEY,
+		account_id INTEGER NOT NULL,
+		broadcast INTEGER NOT NULL DEFAULT 0
+	);
+"#;
+
+pub(crate) const RECORD_SEND_QUEUE_TRANSACTION: &str = r#"
+	INSERT OR IGNORE INTO nerdbank_send_queue_transactions (txid, account_id)
+	VALUES (:txid, :account_id)
+"#;
+
+pub(crate) const GET_UNBROADCAST_SEND_QUEUE_TRANSACTIONS: &str = r#"
+	SELECT q.txid, tx.id_tx IS NOT NULL AS stored, tx.block AS mined_height, tx.expiry_height
+	FROM nerdbank_send_queue_transactions q
+	LEFT OUTER JOIN transactions tx ON tx.txid = q.txid
+	WHERE q.account_id = :account_id AND q.broadcast = 0
+	ORDER BY q.rowid
+"#;
+
+pub(crate) const SET_SEND_QUEUE_TRANSACTION_BROADCAST: &str = r#"
+	UPDATE nerdbank_send_queue_transactions
+	SET broadcast = 1
+	WHERE txid = :txid
+"#;
+
+pub(crate) const DELETE_SEND_QUEUE_TRANSACTION: &str = r#"
+	DELETE FROM nerdbank_send_queue_transactions
+	WHERE txid = :txid
+"#;
+
+// Puts the payments made by a transaction that can no longer be mined back in the queue.
+pub(crate) const REQUEUE_PAYMENTS: &str = r#"
+	UPDATE nerdbank_send_queue
+	SET txid = NULL
+	WHERE txid = :txid
+"#;
+
+// Payments that were scheduled on one device shouldn't be sent again by another that imports its state.
+pub(crate) const CLEAR_SCHEDULED_PAYMENTS: &str = r#"
+	DELETE FROM nerdbank_send_queue;
+	DELETE FROM nerdbank_send_queue_transactions;
+	DELETE FROM nerdbank_recurring_payments;
+"#;
+
+// Payments that are added to the send queue at a regular interval.
+// Times are Unix timestamps.
+pub(crate) const CREATE_RECURRING_PAYMENTS: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_recurring_payments (
+		id INTEGER NOT NULL PRIMARY KEY,
+		account_id INTEGER NOT NULL,
+		recipient TEXT NOT NULL,
+		value INTEGER NOT NULL,
+		memo BLOB,
+		interval_seconds INTEGER NOT NULL,
+		next_due INTEGER NOT NULL,
+		end_time INTEGER
+	);
+"#;
+
+pub(crate) const CREATE_RECURRING_PAYMENT: &str = r#"
+	INSERT INTO nerdbank_recurring_payments (account_id, recipient, value, memo, interval_seconds, next_due, end_time)
+	VALUES (:account_id, :recipient, :value, :memo, :interval_seconds, :next_due, :end_time)
+"#;
+
+pub(crate) const GET_RECURRING_PAYMENTS: &str = r#"
+	SELECT id, account_id, recipient, value, memo, interval_seconds, next_due, end_time
+	FROM nerdbank_recurring_payments
+	WHERE account_id = :account_id
+	ORDER BY id
+"#;
+
+pub(crate) const GET_DUE_RECURRING_PAYMENTS: &str = r#"
+	SELECT id, account_id, recipient, value, memo, interval_seconds, next_due, end_time
+	FROM nerdbank_recurring_payments
+	WHERE next_due <= :now AND (end_time IS NULL OR next_due <= end_time)
+	ORDER BY next_due, id
+"#;
+
+pub(crate) const ADVANCE_RECURRING_PAYMENT: &str = r#"
+	UPDATE nerdbank_recurring_payments
+	SET next_due = :next_due
+	WHERE id = :id
+"#;
+
+pub(crate) const CANCEL_RECURRING_PAYMENT: &str = r#"
+	DELETE FROM nerdbank_recurring_payments
+	WHERE id = :id
+"#;
+
+// Limits on what each account may send.
+// The spend log records what each account has sent, to enforce the daily limit. Times are Unix timestamps.
+pub(crate) const CREATE_SPENDING_POLICIES: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_spending_policies (
+		account_id INTEGER NOT NULL PRIMARY KEY,
+		daily_limit INTEGER,
+		approval_threshold INTEGER
+	);
+	CREATE TABLE IF NOT EXISTS nerdbank_spending_policy_recipients (
+		account_id INTEGER NOT NULL,
+		address TEXT NOT NULL,
+		allowed INTEGER NOT NULL,
+		PRIMARY KEY (account_id, address)
+	);
+	CREATE TABLE IF NOT EXISTS nerdbank_spend_log (
+		account_id INTEGER NOT NULL,
+		time INTEGER NOT NULL,
+		value INTEGER NOT NULL
+	);
+	CREATE INDEX IF NOT EXISTS nerdbank_spend_log_account_time ON nerdbank_spend_log (account_id, time);
+"#;
+
+pub(crate) const SET_SPENDING_POLICY: &str = r#"
+	INSERT INTO nerdbank_spending_policies (account_id, daily_limit, approval_threshold)
+	VALUES (:account_id, :daily_limit, :approval_threshold)
+	ON CONFLICT (account_id) DO UPDATE SET
+		daily_limit = excluded.daily_limit,
+		approval_threshold = excluded.approval_threshold
+"#;
+
+pub(crate) const GET_SPENDING_POLICY: &str = r#"
+	SELECT daily_limit, approval_threshold
+	FROM nerdbank_spending_policies
+	WHERE account_id = :account_id
+"#;
+
+pub(crate) const CLEAR_SPENDING_POLICY_RECIPIENTS: &str = r#"
+	DELETE FROM nerdbank_spending_policy_recipients
+	WHERE account_id = :account_id
+"#;
+
+pub(crate) const SET_SPENDING_POLICY_RECIPIENT: &str = r#"
+	INSERT INTO nerdbank_spending_policy_recipients (account_id, address, allowed)
+	VALUES (:account_id, :address, :allowed)
+	ON CONFLICT (account_id, address) DO UPDATE SET allowed = excluded.allowed
+"#;
+
+pub(crate) const GET_SPENDING_POLICY_RECIPIENTS: &str = r#"
+	SELECT address, allowed
+	FROM nerdbank_spending_policy_recipients
+	WHERE account_id = :account_id
+	ORDER BY address
+"#;
+
+pub(crate) const LOG_SPEND: &str = r#"
+	INSERT INTO nerdbank_spend_log (account_id, time, value)
+	VALUES (:account_id, :time, :value)
+"#;
+
+pub(crate) const GET_SPENT_SINCE: &str = r#"
+	SELECT COALESCE(SUM(value), 0)
+	FROM nerdbank_spend_log
+	WHERE account_id = :account_id AND time > :since
+"#;
+
+// The values of transparent outputs that were spent by the wallet's transactions but never belonged to the wallet,
+// which are needed to calculate those transactions' fees.
+pub(crate) const CREATE_PREVOUT_VALUES: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_prevout_values (
+		txid BLOB NOT NULL,
+		output_index INTEGER NOT NULL,
+		value INTEGER NOT NULL,
+		PRIMARY KEY (txid, output_index)
+	);
+"#;
+
+pub(crate) const SET_PREVOUT_VALUE: &str = r#"
+	INSERT OR REPLACE INTO nerdbank_prevout_values (txid, output_index, value)
+	VALUES (:txid, :output_index, :value)
+"#;
+
+pub(crate) const GET_PREVOUT_VALUE: &str = r#"
+	SELECT value_zat FROM utxos WHERE prevout_txid = :txid AND prevout_idx = :idx
+	UNION ALL
+	SELECT value FROM nerdbank_prevout_values WHERE txid = :txid AND output_index = :idx
+	LIMIT 1
+"#;
+
+// Mined transactions whose prevout values could not all be backfilled,
+// so that the backfill doesn't download or parse the same transactions again on every sync.
+pub(crate) const CREATE_PREVOUT_BACKFILL_FAILURES: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_prevout_backfill_failures (
+		txid BLOB NOT NULL PRIMARY KEY
+	);
+"#;
+
+pub(crate) const RECORD_PREVOUT_BACKFILL_FAILURE: &str = r#"
+	INSERT OR IGNORE INTO nerdbank_prevout_backfill_failures (txid)
+	VALUES (:txid)
+"#;
+
+// Mined transactions whose fees are unknown and whose prevout values have not failed to backfill before.
+pub(crate) const GET_PREVOUT_BACKFILL_CANDIDATES: &str = r#"
+	SELECT txid
+	FROM transactions t
+	WHERE fee IS NULL AND block IS NOT NULL
+		AND NOT EXISTS (SELECT 1 FROM nerdbank_prevout_backfill_failures f WHERE f.txid = t.txid)
+"#;
+
+// Transparent payments to the wallet that have been seen in the mempool but not yet mined.
+// They are kept out of the utxos table, which requires the height of the block that mined them.
+// Rows are removed once the transaction is mined or expires.
+pub(crate) const CREATE_MEMPOOL_RECEIVES: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_mempool_receives (
+		txid BLOB NOT NULL,
+		output_index INTEGER NOT NULL,
+		account_id INTEGER NOT NULL,
+		value INTEGER NOT NULL,
+		expiry_height INTEGER NOT NULL,
+		PRIMARY KEY (txid, output_index)
+	);
+"#;
+
+pub(crate) const RECORD_MEMPOOL_RECEIVE: &str = r#"
+	INSERT OR IGNORE INTO nerdbank_mempool_receives (txid, output_index, account_id, value, expiry_height)
+	VALUES (:txid, :output_index, :account_id, :value, :expiry_height)
+"#;
+
+// An expiry height of 0 means the transaction never expires, so it stays until it is mined.
+pub(crate) const EXPIRE_MEMPOOL_RECEIVES: &str = r#"
+	DELETE FROM nerdbank_mempool_receives
+	WHERE (expiry_height > 0 AND expiry_height <= :tip_height)
+		OR txid IN (SELECT txid FROM transactions WHERE block IS NOT NULL)
+"#;
+
+pub(crate) const CREATE_RETIRED_ACCOUNTS: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_retired_accounts (
+		account_id INTEGER NOT NULL PRIMARY KEY,
+		successor_account_id INTEGER NOT NULL,
+		retired INTEGER NOT NULL
+	);
+"#;
+
+pub(crate) const RETIRE_ACCOUNT: &str = r#"
+	INSERT INTO nerdbank_retired_accounts (account_id, successor_account_id, retired)
+	VALUES (:account_id, :successor_account_id, :retired)
+	ON CONFLICT (account_id) DO UPDATE SET
+		successor_account_id = excluded.successor_account_id
+"#;
+
+pub(crate) const GET_ACCOUNT_SUCCESSORS: &str = r#"
+	SELECT account_id, successor_account_id
+	FROM nerdbank_retired_accounts
+"#;
+
+pub(crate) const CREATE_AUDIT_LOG: &str = r#"
+	CREATE TABLE IF NOT EXISTS nerdbank_audit_log (
+		id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+		time INTEGER NOT NULL,
+		operation INTEGER NOT NULL,
+		account_id INTEGER,
+		details TEXT NOT NULL
+	);
+	CREATE TRIGGER IF NOT EXISTS nerdbank_audit_log_no_update BEFORE UPDATE ON nerdbank_audit_log
+	BEGIN
+		SELECT RAISE(ABORT, 'The audit log is append-only.');
+	END;
+	CREATE TRIGGER IF NOT EXISTS nerdbank_audit_log_no_delete BEFORE DELETE ON nerdbank_audit_log
+	BEGIN
+		SELECT RAISE(ABORT, 'The audit log is append-only.');
+	END;
+"#;
+
+pub(crate) const APPEND_AUDIT_LOG: &str = r#"
+	INSERT INTO nerdbank_audit_log (time, operation, account_id, details)
+	VALUES (:time, :operation, :account_id, :details)
+"#;
+
+pub(crate) const TRANSACTION_EXISTS: &str = r#"
+	SELECT EXISTS (SELECT 1 FROM transactions WHERE txid = :txid)
+"#;
+
+// The wallet's notes from a transaction, with whether each has been spent.
+pub(crate) const GET_TRANSACTION_NOTES: &str = r#"
+	SELECT 2 AS output_pool, n.output_index, n.value, n.commitment_tree_position, tx.block AS mined_height,
+		EXISTS (SELECT 1 FROM sapling_received_note_spends s WHERE s.sapling_received_note_id = n.id) AS spent
+	FROM sapling_received_notes n
+	INNER JOIN transactions tx ON tx.id_tx = n.tx
+	WHERE tx.txid = :txid
+	UNION ALL
+	SELECT 3 AS output_pool, n.action_index, n.value, n.commitment_tree_position, tx.block AS mined_height,
+		EXISTS (SELECT 1 FROM orchard_received_note_spends s WHERE s.orchard_received_note_id = n.id) AS spent
+	FROM orchard_received_notes n
+	INNER JOIN transactions tx ON tx.id_tx = n.tx
+	WHERE tx.txid = :txid
+	ORDER BY output_pool, output_index
+"#;
+
+// The audit log records what was done on one device, so it is left out of the snapshots that another adopts.
+// Dropping the table bypasses the triggers that keep its rows from being deleted.
+pub(crate) const DROP_AUDIT_LOG: &str = r#"
+	DROP TABLE IF EXISTS nerdbank_audit_log;
+"#;
+
+pub(crate) const COPY_AUDIT_LOG: &str = r#"
+	INSERT INTO nerdbank_audit_log (id, time, operation, account_id, details)
+	SELECT id, time, operation, account_id, details
+	FROM source.nerdbank_audit_log
+	ORDER BY id
+"#;
+
+pub(crate) const GET_AUDIT_LOG: &str = r#"
+	SELECT id, time, operation, account_id, details
+	FROM nerdbank_audit_log
+	ORDER BY id
+"#;
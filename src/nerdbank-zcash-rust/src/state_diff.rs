@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+
+use rusqlite::{named_params, OptionalExtension};
+use zcash_client_backend::data_api::{wallet::decrypt_and_store_transaction, WalletRead};
+use zcash_primitives::{
+    consensus::{BlockHeight, BranchId, Network},
+    transaction::Transaction,
+    zip32::DiversifierIndex,
+};
+
+use crate::{
+    backing_store::{open_connection, Db},
+    error::Error,
+    interop::Pool,
+    sql_statements::{
+        GET_DIFF_ADDRESS, GET_DIFF_ADDRESSES, GET_DIFF_NOTES, GET_DIFF_TRANSACTIONS,
+        SET_DIFF_ORCHARD_NOTE, SET_DIFF_SAPLING_NOTE, SET_DIFF_TRANSACTION_HEIGHT,
+    },
+    sync::store_transparent_receives,
+};
+
+/// A transaction to be shared with another device.
+#[derive(Debug, Clone)]
+pub struct DiffTransaction {
+    pub data: Vec<u8>,
+    pub mined_height: Option<u32>,
+}
+
+/// An address that was generated for an account.
+#[derive(Debug, Clone)]
+pub struct DiffAddress {
+    /// The UFVK of the account the address belongs to, which identifies the account across devices.
+    pub ufvk: String,
+    /// The little-endian diversifier index of the address.
+    pub diversifier_index: Vec<u8>,
+}
+
+/// Where a shielded note sits in its pool's note commitment tree, and the nullifier that spends it.
+///
+/// A wallet that only decrypts a transaction (rather than scanning its block) can't work these out,
+/// and without the nullifier it can't tell when the note is spent.
+#[derive(Debug, Clone)]
+pub struct DiffNote {
+    pub txid: Vec<u8>,
+    pub pool: Pool,
+    pub output_index: u32,
+    pub commitment_tree_position: u64,
+    pub nullifier: Vec<u8>,
+}
+
+/// The changes to a wallet since some height,
+/// which can be applied to another wallet sharing the same viewing keys.
+#[derive(Debug, Clone)]
+pub struct WalletStateDiff {
+    pub transactions: Vec<DiffTransaction>,
+    pub notes: Vec<DiffNote>,
+    pub addresses: Vec<DiffAddress>,
+    /// The height to export the next diff from, so that it picks up where this one left off.
+    pub next_height: u32,
+}
+
+/// Collects the transactions mined at or above the given height (and all unmined ones),
+/// along with every address the wallet has generated.
+pub fn export_diff(
+    data_file: &str,
+    network: Network,
+    since_height: u32,
+) -> Result<WalletStateDiff, Error> {
+    let db = Db::load(data_file, network)?;
+    let conn = open_connection(data_file)?;
+    let ufvks: HashMap<u32, String> = db
+        .data
+        .get_unified_full_viewing_keys()?
+        .into_iter()
+        .map(|(id, ufvk)| (id.into(), ufvk.encode(&network)))
+        .collect();
+
+    let mut transactions = Vec::new();
+    let mut next_height = since_height;
+    let mut undownloaded_height: Option<u32> = None;
+    let mut stmt = conn.prepare(GET_DIFF_TRANSACTIONS)?;
+    let mut rows = stmt.query(named_params! { ":since_height": since_height })?;
+    while let Some(row) = rows.next()? {
+        let data: Option<Vec<u8>> = row.get("raw")?;
+        let mined_height: Option<u32> = row.get("mined_height")?;
+        if let Some(h) = mined_height {
+            next_height = next_height.max(h + 1);
+        }
+
+        match data {
+            Some(data) => transactions.push(DiffTransaction { data, mined_height }),
+            // Sync hasn't downloaded this transaction yet, so the next diff should start early enough to include it.
+            None => {
+                if let Some(h) = mined_height {
+                    undownloaded_height = Some(undownloaded_height.map_or(h, |u| u.min(h)));
+                }
+            }
+        }
+    }
+
+    let mut notes = Vec::new();
+    let mut stmt = conn.prepare(GET_DIFF_NOTES)?;
+    let mut rows = stmt.query(named_params! { ":since_height": since_height })?;
+    while let Some(row) = rows.next()? {
+        notes.push(DiffNote {
+            txid: row.get("txid")?,
+            pool: match row.get::<_, u32>("output_pool")? {
+                2 => Pool::Sapling,
+                _ => Pool::Orchard,
+            },
+            output_index: row.get("output_index")?,
+            commitment_tree_position: row.get("commitment_tree_position")?,
+            nullifier: row.get("nf")?,
+        });
+    }
+
+    let mut addresses = Vec::new();
+    let mut stmt = conn.prepare(GET_DIFF_ADDRESSES)?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let account_id: u32 = row.get("account_id")?;
+        let mut diversifier_index: Vec<u8> = row.get("diversifier_index_be")?;
+        diversifier_index.reverse();
+        if let Some(ufvk) = ufvks.get(&account_id) {
+            addresses.push(DiffAddress {
+                ufvk: ufvk.clone(),
+                diversifier_index,
+            });
+        }
+    }
+
+    Ok(WalletStateDiff {
+        transactions,
+        notes,
+        addresses,
+        next_height: undownloaded_height.map_or(next_height, |h| h.min(next_height)),
+    })
+}
+
+/// Applies a diff exported from another wallet with the same viewing keys.
+///
+/// Transactions and addresses that this wallet already has are left as they are,
+/// so diffs may be applied more than once, in any order, and in both directions between two devices.
+/// Notes discovered this way can only be spent after this wallet has scanned the blocks that contain them,
+/// but their spends are recognized right away.
+///
+/// The whole diff is validated before the wallet is changed, so a malformed diff changes nothing.
+/// librustzcash stores each transaction in a database transaction of its own,
+/// so the rest of the import is done in one database transaction after those are stored.
+/// If that fails, importing the diff again completes it.
+pub fn import_diff(data_file: &str, network: Network, diff: WalletStateDiff) -> Result<(), Error> {
+    let mut db = Db::load(data_file, network)?;
+    let mut conn = open_connection(data_file)?;
+    let accounts: HashMap<String, u32> = db
+        .data
+        .get_unified_full_viewing_keys()?
+        .into_iter()
+        .map(|(id, ufvk)| (ufvk.encode(&network), id.into()))
+        .collect();
+
+    let transactions = diff
+        .transactions
+        .iter()
+        .map(|tx| {
+            // v4 and earlier transactions only need a branch ID to compute their signature hash, which we don't do here.
+            // v5 and later transactions record their own.
+            let height = tx.mined_height.map(BlockHeight::from_u32);
+            let branch_id = height.map_or(BranchId::Nu5, |h| BranchId::for_height(&network, h));
+            Ok((Transaction::read(&tx.data[..], branch_id)?, height))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let addresses = diff
+        .addresses
+        .into_iter()
+        .filter_map(|a| accounts.get(&a.ufvk).map(|id| (*id, a.diversifier_index)))
+        .map(|(account_id, diversifier_index)| {
+            let diversifier_index: [u8; 11] = diversifier_index
+                .try_into()
+                .map_err(|_| Error::InvalidArgument("Bad diversifier".to_string()))?;
+            Ok((account_id, diversifier_index))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    if diff
+        .notes
+        .iter()
+        .any(|n| matches!(n.pool, Pool::Transparent))
+    {
+        return Err(Error::InvalidArgument(
+            "Transparent outputs are not notes.".to_string(),
+        ));
+    }
+
+    for (transaction, height) in &transactions {
+        decrypt_and_store_transaction(&network, &mut db.data, transaction)?;
+        if let Some(height) = height {
+            store_transparent_receives(&mut db, transaction, *height)?;
+        }
+    }
+
+    let db_transaction = conn.transaction()?;
+    for (transaction, height) in &transactions {
+        if let Some(height) = height {
+            db_transaction.execute(
+                SET_DIFF_TRANSACTION_HEIGHT,
+                named_params! {
+                    ":height": u32::from(*height),
+                    ":txid": &transaction.txid().as_ref()[..],
+                },
+            )?;
+        }
+    }
+    for note in &diff.notes {
+        db_transaction.execute(
+            match note.pool {
+                Pool::Sapling => SET_DIFF_SAPLING_NOTE,
+                _ => SET_DIFF_ORCHARD_NOTE,
+            },
+            named_params! {
+                ":position": note.commitment_tree_position,
+                ":nf": note.nullifier,
+                ":txid": note.txid,
+                ":output_index": note.output_index,
+            },
+        )?;
+    }
+    db_transaction.commit()?;
+
+    // A transaction that spends a note is only linked to it if the note's nullifier is known when the transaction is stored,
+    // so the transactions are stored again now that the notes have their nullifiers.
+    if !diff.notes.is_empty() {
+        for (transaction, _) in &transactions {
+            decrypt_and_store_transaction(&network, &mut db.data, transaction)?;
+        }
+    }
+
+    for (account_id, diversifier_index) in addresses {
+        let mut diversifier_index_be = diversifier_index;
+        diversifier_index_be.reverse();
+        let exists = conn
+            .query_row(
+                GET_DIFF_ADDRESS,
+                named_params! {
+                    ":account_id": account_id,
+                    ":index": &diversifier_index_be[..],
+                },
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if !exists {
+            db.add_diversifier(account_id.into(), DiversifierIndex::from(diversifier_index))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_primitives::{
+        legacy::TransparentAddress,
+        transaction::{
+            components::{
+                amount::NonNegativeAmount,
+                transparent::{self, TxOut},
+            },
+            Authorized, TransactionData, TxVersion,
+        },
+    };
+
+    use crate::test_constants::setup_test;
+
+    use super::*;
+
+    fn payment_to(address: TransparentAddress) -> Vec<u8> {
+        let tx = TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            BranchId::Nu5,
+            0,
+            BlockHeight::from(0),
+            Some(transparent::Bundle {
+                vin: vec![],
+                vout: vec![TxOut {
+                    value: NonNegativeAmount::const_from_u64(50_000),
+                    script_pubkey: address.script(),
+                }],
+                authorization: transparent::Authorized,
+            }),
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap();
+        let mut data = Vec::new();
+        tx.write(&mut data).unwrap();
+        data
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_export_import_addresses() {
+        let mut setup = setup_test().await;
+        let (_, birthday, account_id, usk) = setup.create_account().await.unwrap();
+        setup
+            .db
+            .add_diversifier(account_id, DiversifierIndex::from(5u32))
+            .unwrap();
+        let data_file = setup.data_file.to_str().unwrap();
+
+        let diff = export_diff(data_file, setup.network, 0).unwrap();
+        assert!(diff.transactions.is_empty());
+        assert_eq!(0, diff.next_height);
+        assert!(diff.addresses.len() >= 2);
+
+        let other_file = setup.data_file.with_file_name("other.sqlite");
+        let mut other = Db::init(&other_file, setup.network).unwrap();
+        other
+            .import_account_ufvk(
+                &usk.to_unified_full_viewing_key(),
+                false,
+                birthday,
+                &mut setup.client,
+            )
+            .await
+            .unwrap();
+        drop(other);
+
+        let other_file = other_file.to_str().unwrap();
+        import_diff(other_file, setup.network, diff.clone()).unwrap();
+        import_diff(other_file, setup.network, diff.clone()).unwrap();
+
+        let imported = export_diff(other_file, setup.network, 0).unwrap();
+        assert_eq!(diff.addresses.len(), imported.addresses.len());
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_import_transaction() {
+        let mut setup = setup_test().await;
+        let (_, birthday, _, usk) = setup.create_account().await.unwrap();
+        let (address, _) = usk
+            .to_unified_full_viewing_key()
+            .transparent()
+            .unwrap()
+            .derive_external_ivk()
+            .unwrap()
+            .default_address();
+        let height = u32::from(birthday.height()) + 1;
+        let data_file = setup.data_file.to_str().unwrap();
+
+        let diff = WalletStateDiff {
+            transactions: vec![DiffTransaction {
+                data: payment_to(address),
+                mined_height: Some(height),
+            }],
+            notes: vec![],
+            addresses: vec![],
+            next_height: height + 1,
+        };
+        import_diff(data_file, setup.network, diff.clone()).unwrap();
+        import_diff(data_file, setup.network, diff.clone()).unwrap();
+
+        let exported = export_diff(data_file, setup.network, 0).unwrap();
+        assert_eq!(1, exported.transactions.len());
+        assert_eq!(diff.transactions[0].data, exported.transactions[0].data);
+        assert_eq!(Some(height), exported.transactions[0].mined_height);
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_import_malformed_diff_changes_nothing() {
+        let mut setup = setup_test().await;
+        let (_, _, _, usk) = setup.create_account().await.unwrap();
+        let (address, _) = usk
+            .to_unified_full_viewing_key()
+            .transparent()
+            .unwrap()
+            .derive_external_ivk()
+            .unwrap()
+            .default_address();
+        let data_file = setup.data_file.to_str().unwrap();
+
+        let diff = WalletStateDiff {
+            transactions: vec![
+                DiffTransaction {
+                    data: payment_to(address),
+                    mined_height: None,
+                },
+                DiffTransaction {
+                    data: vec![1, 2, 3],
+                    mined_height: None,
+                },
+            ],
+            notes: vec![],
+            addresses: vec![],
+            next_height: 0,
+        };
+        assert!(import_diff(data_file, setup.network, diff).is_err());
+
+        let exported = export_diff(data_file, setup.network, 0).unwrap();
+        assert!(exported.transactions.is_empty());
+    }
+}
@@ -2,20 +2,27 @@ use futures_util::TryStreamExt;
 use http::Uri;
 use orchard::{keys::Scope, tree::MerkleHashOrchard};
 use prost::bytes::Buf;
-use rusqlite::{named_params, Connection};
-use std::{borrow::Borrow, collections::HashMap, ops::Range, path::Path, sync::Arc};
+use rusqlite::{named_params, Connection, OptionalExtension};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap, HashSet},
+    num::NonZeroU32,
+    ops::Range,
+    path::Path,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant, SystemTime},
+};
 use tokio::{
     select,
-    sync::{mpsc, Mutex},
+    sync::{mpsc, Mutex, OwnedSemaphorePermit},
 };
 use tokio_util::sync::CancellationToken;
 use tonic::{transport::Channel, Status};
-use tracing::{debug, info};
-use uniffi::deps::anyhow;
+use tracing::{debug, info, warn};
 use zcash_client_sqlite::{error::SqliteClientError, AccountId, WalletDb};
-use zcash_keys::address::UnifiedAddress;
+use zcash_keys::{address::UnifiedAddress, keys::UnifiedFullViewingKey};
 use zcash_primitives::{
-    consensus::{BlockHeight, BranchId, Network, NetworkUpgrade, Parameters},
+    consensus::{BlockHeight, BranchId, Network, Parameters},
     legacy::TransparentAddress,
     memo::Memo,
     merkle_tree::HashSer,
@@ -45,14 +52,39 @@ use zcash_client_backend::{
 };
 
 use crate::{
-    backing_store::Db,
-    block_source::BlockCacheError,
+    account_uuid::account_uuids,
+    analysis::{compute_user_balances, UserBalances},
+    audit_log::{self, AuditOperation},
+    backing_store::{open_connection, wallet_locks, Db},
+    block_source::{BlockCacheBudget, BlockCacheError},
+    chunk_journal::ChunkJournal,
+    chunk_sizer::ChunkSizer,
+    decode::{decode_transaction, read_header},
     error::Error,
-    grpc::get_client,
-    interop::{Pool, SyncUpdate, SyncUpdateData, TransactionNote},
+    grpc::{get_client, is_metered},
+    interop::{
+        self, parse_server_uri, ChangeNotes, Pool, RemainingBlocks, ScanRangePhase, SyncMode,
+        SyncOptions, SyncUpdate, SyncUpdateData, TransactionNote, TransparentInput,
+        TransparentOutput,
+    },
     lightclient::parse_network,
-    resilience::webrequest_with_retry,
-    sql_statements::GET_TRANSACTIONS_SQL,
+    memo_fields::parse_memo,
+    memo_index::update_memo_index,
+    pause::PauseToken,
+    payments::{report_incoming_payments, IncomingPayment},
+    recurring::enqueue_due_payments,
+    resilience::{
+        is_throttled, is_transient, retry_delay, webrequest_with_retry, Backpressure, ATTEMPT_LIMIT,
+    },
+    sql_statements::{
+        DELETE_RESCANNED_PENDING_REORGS, EXPIRE_MEMPOOL_RECEIVES, GET_PREVOUT_BACKFILL_CANDIDATES,
+        GET_PREVOUT_VALUE, GET_RESCANNED_PENDING_REORGS, GET_TRANSACTIONS_SQL, GET_TRANSACTION_RAW,
+        GET_TXIDS_MINED_ABOVE, GET_UNSPENT_TRANSPARENT_BALANCE, GET_UTXO_VALUE,
+        RECORD_MEMPOOL_RECEIVE, RECORD_PENDING_REORG, RECORD_PREVOUT_BACKFILL_FAILURE,
+        SET_PREVOUT_VALUE,
+    },
+    tree_states::{get_tree_state, remember_tree_state},
+    watch::get_tip_height,
 };
 
 type ChainError =
@@ -63,26 +95,61 @@ const TADDR_INDEX_GAP_LIMIT: u32 = 20;
 /// The number of sapling spends+outputs and orchard actions that should be in memory at any time.
 const BLOCK_ACTIONS_MEMORY_LIMIT: usize = 500_000;
 
+/// The most sapling spends+outputs and orchard actions that may be downloaded ahead of scanning,
+/// unless [`SyncOptions::max_cached_actions`] says otherwise.
+const DEFAULT_MAX_CACHED_ACTIONS: u32 = 2_000_000;
+
+/// A chunk of downloaded blocks, the chain state as of the block before them,
+/// and the room they take up in the block cache budget.
+type DownloadedChunk = (Vec<CompactBlock>, ChainState, OwnedSemaphorePermit);
+
 /// The capacity of the channel that receives vectors of CompactBlock.
 ///
 /// This should be a relatively low number for scanning efficiency, but
 /// high enough that we don't wait too long for download before starting to scan.
 const CHUNK_CHANNEL_CAPACITY: usize = 10;
 
-/// The approximate number of actions for each chunk that we submit to the downloaded channel.
+/// The approximate number of actions for each chunk that we submit to the downloaded channel
+/// at the start of a sync, before [`ChunkSizer`] has adapted it to the device.
 /// We want this to contain at least (num_threads - 1) * 100 + 1 outputs in order to maximize throughput
 /// during trial decryption and other stages.
 /// https://discord.com/channels/809218587167293450/1250828701864693761/1250942856198230086
 const BLOCKS_CHUNK_THRESHOLD: usize = BLOCK_ACTIONS_MEMORY_LIMIT / CHUNK_CHANNEL_CAPACITY;
 
+/// The maximum length of each piece that historic scan ranges are split into
+/// when syncing in [`SyncMode::FastRecovery`] mode.
+const FAST_RECOVERY_RANGE_LENGTH: u32 = 10_000;
+
+/// How often a continual sync asks the server for the chain tip while it waits for the next block.
+const CHAIN_TIP_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long a continual sync waits before trying again after it first fails.
+const CONTINUAL_SYNC_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The longest a continual sync waits before trying again, however many times it has failed.
+const MAX_CONTINUAL_SYNC_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
 pub async fn sync<P: AsRef<Path>>(
     uri: Uri,
     data_file: P,
     progress: Option<Box<dyn SyncUpdate>>,
     min_confirmations: u32,
     continually: bool,
+    options: SyncOptions,
+    pause_token: PauseToken,
     cancellation_token: CancellationToken,
 ) -> Result<SyncUpdateData, Error> {
+    if options.max_cached_actions == Some(0) {
+        return Err(Error::InvalidArgument(
+            "The block cache must have room for at least one action.".to_string(),
+        ));
+    }
+    if options.skip_transparent && options.mode == SyncMode::TransparentOnly {
+        return Err(Error::InvalidArgument(
+            "Transparent scanning cannot be skipped in transparent-only mode.".to_string(),
+        ));
+    }
+
     let mut client = get_client(uri.clone()).await?;
     let info = webrequest_with_retry(
         || async {
@@ -96,138 +163,285 @@ pub async fn sync<P: AsRef<Path>>(
     )
     .await?;
     let state = SyncState {
+        server_uri: uri.clone(),
         cancellation_token,
         min_confirmations,
         network: parse_network(&info)?,
         progress: Arc::new(progress),
+        options,
+        pause_token,
+        journal: ChunkJournal::for_wallet(&data_file),
+        backpressure: Backpressure::default(),
+        chunk_sizer: ChunkSizer::new(BLOCKS_CHUNK_THRESHOLD, CHUNK_CHANNEL_CAPACITY),
+        reported_balances: Arc::new(StdMutex::new(HashMap::new())),
     };
 
+    // One database handle and connection serve the whole sync, including the scanner, which borrows the handle for each range.
     let mut db = Db::load(&data_file, state.network)?;
-    let conn = Connection::open(&data_file)?;
+    let mut conn = open_connection(&data_file)?;
 
     // 1) Download note commitment tree data from lightwalletd
     // 2) Pass the commitment tree data to the database.
-    update_subtree_roots(&mut client.clone(), &mut db.data).await?;
+    if state.options.mode != SyncMode::TransparentOnly {
+        let mut verifier = match &state.options.verification_server {
+            Some(uri) => Some(
+                get_client(parse_server_uri(uri).map_err(|_| {
+                    Error::InvalidArgument("Invalid verification server URI.".to_string())
+                })?)
+                .await?,
+            ),
+            None => None,
+        };
+        update_subtree_roots(&mut client.clone(), verifier.as_mut(), &mut db.data).await?;
+    }
 
     let mut status = SyncUpdateData {
         current_step: 0,
         total_steps: 0,
+        sapling_outputs_scanned: 0,
+        orchard_actions_scanned: 0,
         last_fully_scanned_block: None,
         tip_height: 0,
+        transparent_balance: None,
         last_error: None,
+        scanning_priority: None,
+        remaining_blocks: Vec::new(),
     };
 
+    let locks = wallet_locks(&data_file);
+    let mut failures = 0;
     loop {
-        // 3) Download chain tip metadata from lightwalletd
-        status.tip_height = webrequest_with_retry(
-            || async {
-                Ok(client
-                    .clone()
-                    .get_latest_block(service::ChainSpec::default())
-                    .await?
-                    .get_ref()
-                    .height)
-            },
-            state.cancellation_token.clone(),
-        )
-        .await?
-        .try_into()
-        .map_err(|e| Error::Internal(format!("Invalid block height: {}", e)))?;
+        // Each pass is serialized with sends and other writes to the wallet,
+        // but a continual sync lets them proceed while it waits for the next block.
+        let pass = {
+            let _write_lock = select! {
+                lock = locks.write.lock() => lock,
+                _ = state.cancellation_token.cancelled() => return Err(Error::Canceled),
+            };
+            sync_pass(&mut client, db, conn, &state, &mut status).await
+        };
+        let result = match pass {
+            Ok(pass) => {
+                db = pass.db;
+                conn = pass.conn;
+                status.last_error = None;
+                if !pass.caught_up {
+                    continue;
+                }
 
-        // 4) Notify the wallet of the updated chain tip.
-        db.data.update_chain_tip(status.tip_height.into())?;
-
-        fn report_new_transactions<P: AsRef<Path>>(
-            txids: Vec<TxId>,
-            progress: &Option<Box<dyn SyncUpdate>>,
-            data_file: &P,
-            db: &mut Db,
-            conn: &Connection,
-            network: Network,
-        ) -> Result<(), Error> {
-            if !txids.is_empty() {
-                initialize_transaction_fees(db, conn)?;
-                if let Some(sink) = progress.as_ref() {
-                    let mut conn = Connection::open(data_file)?;
-                    let new_transactions =
-                        get_transactions(db, &mut conn, &network, None, None, None)?
-                            .iter()
-                            .filter(|r| {
-                                TryInto::<[u8; 32]>::try_into(r.txid.clone())
-                                    .map(|a| txids.contains(&TxId::from_bytes(a)))
-                                    .unwrap_or(false)
-                            })
-                            .cloned()
-                            .collect::<Vec<_>>();
-                    if !new_transactions.is_empty() {
-                        sink.report_transactions(new_transactions);
+                if !continually {
+                    return Ok(status);
+                }
+
+                report_status(&status, &state.progress);
+
+                // We'll loop around again when the next block is mined.
+                // In the meantime, the mempool is watched for incoming transparent payments.
+                let tip_client = client.clone();
+                let next_block =
+                    wait_for_next_block(&tip_client, status.tip_height, &state.cancellation_token);
+                let mempool = watch_mempool(
+                    &mut client,
+                    &mut db,
+                    &mut conn,
+                    &state,
+                    &pass.watched_taddrs,
+                    status.tip_height,
+                );
+                tokio::pin!(next_block, mempool);
+                select! {
+                    r = &mut next_block => r,
+                    r = &mut mempool => {
+                        // Watching the mempool only gives early notice of payments, so it isn't worth interrupting the sync over.
+                        if let Err(e) = r {
+                            warn!("Stopped watching the mempool: {}", e);
+                        }
+                        next_block.await
                     }
                 }
             }
+            Err(e) => {
+                if !continually || !is_recoverable(&e) {
+                    return Err(e);
+                }
 
-            Ok(())
-        }
+                // The failed pass took the database handle and connection down with it, so we need fresh ones to try again.
+                db = Db::load(&data_file, state.network)?;
+                conn = open_connection(&data_file)?;
+                Err(e)
+            }
+        };
 
-        fn report_transactions_in_range<P: AsRef<Path>>(
-            range: &Range<BlockHeight>,
-            progress: &Option<Box<dyn SyncUpdate>>,
-            data_file: &P,
-            db: &mut Db,
-            conn: &Connection,
-            network: &Network,
-        ) -> Result<(), Error> {
-            initialize_transaction_fees(db, conn)?;
-            if let Some(sink) = progress.as_ref() {
-                let mut conn = Connection::open(data_file)?;
-                let new_transactions = get_transactions(
-                    db,
-                    &mut conn,
-                    network,
-                    None,
-                    Some(range.start.into()),
-                    Some((range.end - 1).into()),
-                )?
-                .to_vec();
-                if !new_transactions.is_empty() {
-                    sink.report_transactions(new_transactions);
+        // When syncing continually, a failure that may not recur is reported to the host
+        // and the sync is tried again after a delay, rather than giving up on it.
+        match result {
+            Ok(()) => failures = 0,
+            Err(e) if is_recoverable(&e) => {
+                failures += 1;
+                warn!(
+                    "Sync failed {} time(s) in a row and will be retried: {}",
+                    failures, e
+                );
+                status.last_error = Some(e.to_string());
+                report_status(&status, &state.progress);
+                select! {
+                    _ = state.cancellation_token.cancelled() => return Err(Error::Canceled),
+                    _ = tokio::time::sleep(continual_sync_retry_delay(failures)) => {}
                 }
             }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The outcome of one pass of a sync over the chain.
+struct SyncPass {
+    db: Db,
+    conn: Connection,
+    /// Whether every block there was to scan has been scanned.
+    caught_up: bool,
+    /// The transparent addresses the wallet has handed out, and the accounts they belong to.
+    watched_taddrs: HashMap<TransparentAddress, AccountId>,
+}
+
+/// Brings the wallet up to the current chain tip, downloading its transparent transactions
+/// and then scanning the blocks the wallet suggests.
+///
+/// The pass ends early (without being caught up) if the suggested scan ranges change along the way.
+async fn sync_pass(
+    client: &mut CompactTxStreamerClient<Channel>,
+    mut db: Db,
+    mut conn: Connection,
+    state: &SyncState,
+    status: &mut SyncUpdateData,
+) -> Result<SyncPass, Error> {
+    // 3) Download chain tip metadata from lightwalletd
+    status.tip_height = get_tip_height(client, &state.cancellation_token)
+        .await?
+        .try_into()
+        .map_err(|e| Error::Internal(format!("Invalid block height: {}", e)))?;
 
-            Ok(())
+    // 4) Notify the wallet of the updated chain tip.
+    db.data.update_chain_tip(status.tip_height.into())?;
+
+    // The blocks replacing those of a reorg that an earlier sync detected may have been scanned before it ended.
+    report_reorged_transactions(&mut db, &mut conn, state)?;
+
+    fn report_transactions_in_range(
+        range: &Range<BlockHeight>,
+        progress: &Option<Box<dyn SyncUpdate>>,
+        db: &mut Db,
+        conn: &mut Connection,
+        network: &Network,
+    ) -> Result<(), Error> {
+        initialize_transaction_fees(db, conn)?;
+        update_memo_index(conn)?;
+        if let Some(sink) = progress.as_ref() {
+            let new_transactions = get_transactions(
+                db,
+                conn,
+                network,
+                None,
+                Some(range.start.into()),
+                Some((range.end - 1).into()),
+            )?
+            .to_vec();
+            if !new_transactions.is_empty() {
+                report_incoming_payments(sink.as_ref(), db, conn, network, &new_transactions)?;
+                sink.report_transactions(new_transactions);
+            }
         }
 
-        // Download all the transparent ops related to the wallet first.
-        // We don't need batches for this as that would just multiply the number of LWD requests we have to make.
-        let mut taddrs = db.data.get_transparent_addresses_and_sync_heights()?;
-        let mut taddrs_to_scan = taddrs.clone();
-        while !taddrs_to_scan.is_empty() {
-            for addr_info in taddrs.iter_mut().filter(|a| taddrs_to_scan.contains(a)) {
-                let txids = download_transparent_transactions(
-                    &mut client,
-                    &mut db,
-                    &state.network,
-                    &addr_info.address,
-                    Some(addr_info.height),
-                    status.tip_height.into(),
-                    state.cancellation_token.clone(),
-                )
+        Ok(())
+    }
+
+    // Download all the transparent ops related to the wallet first.
+    // We don't need batches for this as that would just multiply the number of LWD requests we have to make.
+    let mut taddrs = if state.options.skip_transparent {
+        Vec::new()
+    } else {
+        db.data.get_transparent_addresses_and_sync_heights()?
+    };
+    let mut taddrs_to_scan = taddrs.clone();
+
+    // Downloading transparent transactions can take a while for a wallet with a long history,
+    // so on the first pass we ask the server for the balance so the user sees it right away.
+    if status.transparent_balance.is_none() && !taddrs.is_empty() {
+        let addresses = taddrs
+            .iter()
+            .map(|a| a.address.encode(&state.network))
+            .collect::<Vec<_>>();
+        let balance = webrequest_with_retry(
+            || async {
+                Ok(client
+                    .clone()
+                    .get_taddress_balance(service::AddressList {
+                        addresses: addresses.clone(),
+                    })
+                    .await?
+                    .into_inner())
+            },
+            state.cancellation_token.clone(),
+        )
+        .await?;
+        status.transparent_balance = Some(balance.value_zat.try_into().unwrap_or(0));
+        report_status(status, &state.progress);
+    }
+
+    let birthdays = db
+        .data
+        .get_account_ids()?
+        .into_iter()
+        .map(|id| Ok((id, db.data.get_account_birthday(id)?)))
+        .collect::<Result<HashMap<_, _>, Error>>()?;
+
+    while !taddrs_to_scan.is_empty() {
+        for addr_info in taddrs.iter_mut().filter(|a| taddrs_to_scan.contains(a)) {
+            state
+                .pause_token
+                .wait_while_paused(&state.cancellation_token)
                 .await?;
-                if !txids.is_empty() {
-                    addr_info.used = true;
-                    report_new_transactions(
-                        txids,
-                        state.progress.borrow(),
-                        &data_file,
-                        &mut db,
-                        &conn,
-                        state.network,
-                    )?;
-                }
+            let start = transparent_start_height(
+                state.options.transparent_start_height,
+                addr_info.height,
+                birthdays.get(&addr_info.account_id).copied(),
+            );
+            let txids = download_transparent_transactions(
+                client,
+                &mut db,
+                &state.network,
+                &addr_info.address,
+                start,
+                status.tip_height.into(),
+                state.cancellation_token.clone(),
+            )
+            .await?;
+            if !txids.is_empty() {
+                addr_info.used = true;
+                report_new_transactions(
+                    txids,
+                    state.progress.borrow(),
+                    &mut db,
+                    &mut conn,
+                    state.network,
+                )?;
             }
-
-            taddrs_to_scan = fill_in_taddrs_to_gap_limit(&mut taddrs, &mut db.data)?;
         }
 
+        taddrs_to_scan = fill_in_taddrs_to_gap_limit(&mut taddrs, &mut db.data)?;
+    }
+
+    // Now that the transparent transactions are downloaded, the wallet's own records are authoritative.
+    if !taddrs.is_empty() {
+        status.transparent_balance =
+            Some(conn.query_row(GET_UNSPENT_TRANSPARENT_BALANCE, [], |row| {
+                row.get::<_, u64>(0)
+            })?);
+        report_balance_changes(&db, &conn, state);
+    }
+
+    let mut caught_up = true;
+    if state.options.mode != SyncMode::TransparentOnly {
         // 5) Get the suggested scan ranges from the wallet database
         let mut scan_ranges = db.data.suggest_scan_ranges()?;
 
@@ -238,16 +452,17 @@ pub async fn sync<P: AsRef<Path>>(
             // the first element of the vector of suggested ranges.
             match scan_ranges.first() {
                 Some(scan_range) if scan_range.priority() == ScanPriority::Verify => {
+                    status.scanning_priority = Some(scan_range.priority().into());
+                    status.remaining_blocks = remaining_blocks(&scan_ranges);
+                    report_status(status, &state.progress);
+
                     // Download and scan the blocks and check for scanning errors that indicate that the wallet's chain tip
                     // is out of sync with blockchain history.
-                    let scan_result = download_and_scan_blocks(
-                        &mut client,
-                        Db::load(&data_file, state.network)?,
-                        &state,
-                        None,
-                        scan_range,
-                    )
-                    .await?;
+                    let scan_result =
+                        download_and_scan_blocks(client, db, conn, state, None, scan_range).await?;
+                    db = scan_result.db;
+                    conn = scan_result.conn;
+                    report_reorged_transactions(&mut db, &mut conn, state)?;
 
                     if scan_result.priorities_changed {
                         // The suggested scan ranges have been updated, so we re-request.
@@ -278,34 +493,49 @@ pub async fn sync<P: AsRef<Path>>(
         // appropriate, and for ranges with priority `Historic` it can be useful to download and
         // scan the range in reverse order (to discover more recent unspent notes sooner), or from
         // the start and end of the range inwards.
-        let scan_ranges = db.data.suggest_scan_ranges()?;
+        scan_journaled_chunks(&mut db, &conn, state)?;
+        let mut scan_ranges = db.data.suggest_scan_ranges()?;
+        if state.options.mode == SyncMode::FastRecovery {
+            scan_ranges = order_for_fast_recovery(scan_ranges);
+        }
+        if is_metered() {
+            scan_ranges.retain(|r| !is_deferred_when_metered(r));
+        }
         debug!("Suggested ranges: {:?}", scan_ranges);
 
         // The total_steps is the sum of the current_step and the sum of the lengths of the scan_ranges.
         status.total_steps =
             status.current_step + scan_ranges.iter().map(|r| r.len()).sum::<usize>() as u64;
 
-        update_and_report_status(&mut status, &db.data, min_confirmations, &state.progress)?;
+        update_and_report_status(status, &db.data, state.min_confirmations, &state.progress)?;
 
-        let mut caught_up = true;
-        for scan_range in scan_ranges.into_iter() {
-            let scan_result = download_and_scan_blocks(
-                &mut client,
-                Db::load(&data_file, state.network)?,
-                &state,
-                Some(&status),
-                &scan_range,
-            )
-            .await?;
+        for (i, scan_range) in scan_ranges.iter().enumerate() {
+            state
+                .pause_token
+                .wait_while_paused(&state.cancellation_token)
+                .await?;
+
+            // The connection may have become metered since the ranges were chosen.
+            if is_metered() && is_deferred_when_metered(scan_range) {
+                continue;
+            }
+
+            status.scanning_priority = Some(scan_range.priority().into());
+            status.remaining_blocks = remaining_blocks(&scan_ranges[i..]);
+            let scan_result =
+                download_and_scan_blocks(client, db, conn, state, Some(&*status), scan_range)
+                    .await?;
+            db = scan_result.db;
+            conn = scan_result.conn;
             if let Some(s) = scan_result.status {
-                status = s;
+                *status = s;
             }
 
             // Download and decrypt the full transactions we found in the compact blocks
             // so we can save their memos to the database.
             download_full_shielded_transactions(
-                &mut client,
-                &data_file,
+                client,
+                &conn,
                 &mut db,
                 &state.network,
                 state.cancellation_token.clone(),
@@ -319,13 +549,16 @@ pub async fn sync<P: AsRef<Path>>(
             report_transactions_in_range(
                 scan_range.block_range(),
                 &state.progress,
-                &data_file,
                 &mut db,
-                &conn,
+                &mut conn,
                 &state.network,
             )?;
+            report_reorged_transactions(&mut db, &mut conn, state)?;
+
+            report_scan_range_event(&state.progress, scan_range, ScanRangePhase::Completed);
+            status.remaining_blocks = remaining_blocks(&scan_ranges[i + 1..]);
 
-            update_and_report_status(&mut status, &db.data, min_confirmations, &state.progress)?;
+            update_and_report_status(status, &db.data, state.min_confirmations, &state.progress)?;
 
             if scan_result.priorities_changed {
                 // The suggested scan ranges have been updated (either due to a continuity
@@ -334,26 +567,103 @@ pub async fn sync<P: AsRef<Path>>(
                 break;
             }
         }
+    }
+
+    status.scanning_priority = None;
+    if caught_up {
+        status.remaining_blocks.clear();
+        // Downloading other people's transactions would tell the server which ones the wallet cares about.
+        if !state.options.skip_transparent {
+            backfill_prevout_values(client, &mut db, &conn, state.cancellation_token.clone())
+                .await?;
+        }
+        initialize_transaction_fees(&mut db, &conn)?;
+        update_status(status, &db.data, state.min_confirmations)?;
+        report_due_payments(&mut conn, &state.progress)?;
+    }
 
-        if caught_up {
-            update_status(&mut status, &db.data, min_confirmations)?;
+    let watched_taddrs = taddrs
+        .iter()
+        .map(|a| (a.address, a.account_id))
+        .collect::<HashMap<_, _>>();
+    Ok(SyncPass {
+        db,
+        conn,
+        caught_up,
+        watched_taddrs,
+    })
+}
 
-            if !continually {
-                return Ok(status);
-            }
+/// Gets the height to start looking for an address's transparent transactions at.
+///
+/// An explicit `start_height` can move the start back, so that history an earlier sync skipped can be searched again,
+/// but never forward past the address's `sync_height`, which would skip blocks that were never searched.
+/// Otherwise the search resumes at the `sync_height`, but never before the account existed,
+/// since the address can't have received funds before then.
+fn transparent_start_height(
+    start_height: Option<u32>,
+    sync_height: BlockHeight,
+    birthday: Option<BlockHeight>,
+) -> BlockHeight {
+    match start_height {
+        Some(height) => sync_height.min(BlockHeight::from(height)),
+        None => birthday.map_or(sync_height, |b| sync_height.max(b)),
+    }
+}
 
-            report_status(&status, &state.progress);
+/// Checks whether a sync failed for a reason that may not recur, such as the server or network being down,
+/// so that a continual sync may try again.
+fn is_recoverable(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::Transport(_)
+            | Error::TonicStatus(_)
+            | Error::Io(_)
+            | Error::Minreq(_)
+            | Error::BlockSource(_)
+            | Error::Scan(_)
+    )
+}
+
+/// Gets how long a continual sync waits before trying again, after failing `failures` times in a row.
+///
+/// The delay doubles with each failure, up to [`MAX_CONTINUAL_SYNC_RETRY_DELAY`].
+fn continual_sync_retry_delay(failures: u32) -> Duration {
+    2u32.checked_pow(failures.saturating_sub(1))
+        .and_then(|factor| CONTINUAL_SYNC_RETRY_DELAY.checked_mul(factor))
+        .map_or(MAX_CONTINUAL_SYNC_RETRY_DELAY, |delay| {
+            delay.min(MAX_CONTINUAL_SYNC_RETRY_DELAY)
+        })
+}
 
-            // We'll loop around again when the next block is mined.
-            // Eventually we should actually do something with the transactions in the mempool too.
-            // WARNING: This is vulnerable to a race condition, because if a new block has *already* been mined
-            // but not noticed above, we'll end up waiting for yet *another* block to be mined.
-            select! {
-                _ = state.cancellation_token.cancelled() => Err(Status::cancelled("Request cancelled")),
-                _ = watch_mempool(&mut client) => Ok(()),
-            }?;
+fn report_new_transactions(
+    txids: Vec<TxId>,
+    progress: &Option<Box<dyn SyncUpdate>>,
+    db: &mut Db,
+    conn: &mut Connection,
+    network: Network,
+) -> Result<(), Error> {
+    if !txids.is_empty() {
+        initialize_transaction_fees(db, conn)?;
+        update_memo_index(conn)?;
+        if let Some(sink) = progress.as_ref() {
+            let new_transactions = get_transactions(db, conn, &network, None, None, None)?
+                .iter()
+                .filter(|r| {
+                    TryInto::<[u8; 32]>::try_into(r.txid.clone())
+                        .map(|a| txids.contains(&TxId::from_bytes(a)))
+                        .unwrap_or(false)
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            if !new_transactions.is_empty() {
+                report_incoming_payments(sink.as_ref(), db, conn, &network, &new_transactions)?;
+                sink.report_transactions(new_transactions);
+            }
         }
     }
+
+    Ok(())
 }
 
 fn update_status<'a>(
@@ -384,6 +694,145 @@ fn report_status(status: &SyncUpdateData, progress: &Option<Box<dyn SyncUpdate>>
     }
 }
 
+fn report_scan_range_event(
+    progress: &Option<Box<dyn SyncUpdate>>,
+    range: &ScanRange,
+    phase: ScanRangePhase,
+) {
+    if let Some(sink) = progress.as_ref() {
+        sink.scan_range_event(range.into(), phase);
+    }
+}
+
+fn report_reorg(
+    progress: &Option<Box<dyn SyncUpdate>>,
+    rewind_height: BlockHeight,
+    affected_txids: &[TxId],
+) {
+    if let Some(sink) = progress.as_ref() {
+        sink.report_reorg(
+            rewind_height.into(),
+            affected_txids.iter().map(|t| t.as_ref().to_vec()).collect(),
+        );
+    }
+}
+
+/// Reports the transactions that reorgs orphaned again, once the blocks that replaced them have been scanned,
+/// so that the host learns which were mined again (and at what height) and which went back to being unconfirmed.
+///
+/// The orphaned transactions are kept in the wallet until they are reported,
+/// so those of a reorg detected by an earlier sync that ended too soon are reported by a later one.
+fn report_reorged_transactions(
+    db: &mut Db,
+    conn: &mut Connection,
+    state: &SyncState,
+) -> Result<(), Error> {
+    let scanned_to = match db.data.block_max_scanned()? {
+        Some(block) => block.block_height(),
+        None => return Ok(()),
+    };
+    let txids = get_rescanned_reorged_txids(conn, scanned_to)?;
+
+    if !txids.is_empty() {
+        if let Some(sink) = state.progress.as_ref() {
+            let transactions = get_transactions(db, conn, &state.network, None, None, None)?
+                .into_iter()
+                .filter(|t| {
+                    TryInto::<[u8; 32]>::try_into(t.txid.clone())
+                        .map(|a| txids.contains(&TxId::from_bytes(a)))
+                        .unwrap_or(false)
+                })
+                .collect::<Vec<_>>();
+            if !transactions.is_empty() {
+                sink.report_transactions(transactions);
+            }
+        }
+
+        conn.execute(
+            DELETE_RESCANNED_PENDING_REORGS,
+            named_params! { ":scanned_to": u32::from(scanned_to) },
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Remembers the transactions that a reorg detected at `detected_at` orphaned,
+/// so that they are reported again once the blocks up to that height have been scanned again.
+fn record_pending_reorg(
+    conn: &Connection,
+    detected_at: BlockHeight,
+    txids: &[TxId],
+) -> Result<(), Error> {
+    let mut stmt = conn.prepare(RECORD_PENDING_REORG)?;
+    for txid in txids {
+        stmt.execute(named_params! {
+            ":txid": txid.as_ref(),
+            ":detected_at": u32::from(detected_at),
+        })?;
+    }
+    Ok(())
+}
+
+/// Gets the orphaned transactions of the reorgs whose blocks have been scanned again, up to `scanned_to`.
+fn get_rescanned_reorged_txids(
+    conn: &Connection,
+    scanned_to: BlockHeight,
+) -> Result<HashSet<TxId>, Error> {
+    conn.prepare(GET_RESCANNED_PENDING_REORGS)?
+        .query_map(
+            named_params! { ":scanned_to": u32::from(scanned_to) },
+            |r| r.get::<_, [u8; 32]>(0).map(TxId::from_bytes),
+        )?
+        .map(|txid| Ok(txid?))
+        .collect()
+}
+
+/// Adds the recurring payments that have come due to the send queue, and reports them.
+fn report_due_payments(
+    conn: &mut Connection,
+    progress: &Option<Box<dyn SyncUpdate>>,
+) -> Result<(), Error> {
+    let payments = enqueue_due_payments(conn, SystemTime::now())?;
+    if let Some(sink) = progress.as_ref() {
+        if !payments.is_empty() {
+            sink.report_scheduled_payments(payments);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reports the balances of each account whose balances have changed since they were last reported.
+///
+/// This is best-effort: a failure is logged rather than failing the sync,
+/// since the balances will be reported again after the next change.
+fn report_balance_changes(db: &Db, conn: &Connection, state: &SyncState) {
+    if let Err(e) = try_report_balance_changes(db, conn, state) {
+        warn!("Failed to report balance changes: {}", e);
+    }
+}
+
+fn try_report_balance_changes(db: &Db, conn: &Connection, state: &SyncState) -> Result<(), Error> {
+    let sink = match state.progress.as_ref() {
+        Some(sink) => sink,
+        None => return Ok(()),
+    };
+
+    let min_confirmations = NonZeroU32::new(state.min_confirmations).unwrap_or(NonZeroU32::MIN);
+    let mut reported = state.reported_balances.lock().unwrap();
+    for account_id in db.data.get_account_ids()? {
+        let balances = compute_user_balances(db, conn, account_id, min_confirmations)?;
+        let id = u32::from(account_id);
+        if reported.get(&id) != Some(&balances) {
+            sink.report_balances(id, balances.clone());
+            reported.insert(id, balances);
+        }
+    }
+
+    Ok(())
+}
+
 fn update_and_report_status(
     status: &mut SyncUpdateData,
     data: &WalletDb<Connection, Network>,
@@ -394,9 +843,64 @@ fn update_and_report_status(
     Ok(())
 }
 
+/// Reorders the suggested scan ranges so that historic ranges are scanned from the most recent blocks backward.
+///
+/// The wallet records the nullifiers it sees in scanned blocks, so a note that is discovered in an older range
+/// is still recognized as spent by a newer range that was scanned first.
+/// Scanning history newest-first therefore discovers the wallet's unspent funds much sooner than a linear scan,
+/// which is what matters most to a user restoring a wallet with a long history.
+fn order_for_fast_recovery(scan_ranges: Vec<ScanRange>) -> Vec<ScanRange> {
+    let (historic, mut result): (Vec<_>, Vec<_>) = scan_ranges
+        .into_iter()
+        .partition(|r| r.priority() == ScanPriority::Historic);
+
+    let mut pieces = Vec::new();
+    for range in historic {
+        let mut remaining = range;
+        while remaining.len() > FAST_RECOVERY_RANGE_LENGTH as usize {
+            let split_height = remaining.block_range().end - FAST_RECOVERY_RANGE_LENGTH;
+            match remaining.split_at(split_height) {
+                Some((older, newer)) => {
+                    pieces.push(newer);
+                    remaining = older;
+                }
+                None => break,
+            }
+        }
+        pieces.push(remaining);
+    }
+
+    pieces.sort_by_key(|r| std::cmp::Reverse(r.block_range().end));
+    result.extend(pieces);
+    result
+}
+
+/// Gets whether a range is left unscanned while the connection is metered.
+/// Only the blocks needed to keep up with the chain tip are scanned then.
+fn is_deferred_when_metered(scan_range: &ScanRange) -> bool {
+    scan_range.priority() < ScanPriority::ChainTip
+}
+
+/// Sums the lengths of the given ranges by priority, highest priority first.
+fn remaining_blocks(scan_ranges: &[ScanRange]) -> Vec<RemainingBlocks> {
+    let mut blocks = BTreeMap::<interop::ScanPriority, u64>::new();
+    for range in scan_ranges {
+        *blocks.entry(range.priority().into()).or_default() += range.len() as u64;
+    }
+    blocks
+        .into_iter()
+        .rev()
+        .map(|(priority, blocks)| RemainingBlocks { priority, blocks })
+        .collect()
+}
+
 struct DownloadAndScanResult {
     priorities_changed: bool,
     status: Option<SyncUpdateData>,
+    /// The database handle that was lent to the scanner.
+    db: Db,
+    /// The connection that was lent to the scanner along with `db`.
+    conn: Connection,
 }
 
 fn fill_in_taddrs_to_gap_limit(
@@ -463,25 +967,27 @@ fn fill_in_taddrs_to_gap_limit(
 /// Calculates the fee for some transaction.
 ///
 /// Returns `Error::OutPointMissing` if any UTXO consumed by the transaction is not already in the `utxos` table.
-fn calculate_transaction_fee(transaction: Transaction, conn: &Connection) -> Result<Amount, Error> {
-    fn get_prevout_value(outpoint: &OutPoint, conn: &Connection) -> Result<Amount, Error> {
-        Ok(Amount::try_from(
-            conn.query_row(
-                "SELECT value_zat FROM utxos WHERE prevout_txid = :txid AND prevout_idx = :idx",
-                named_params! {
-                    ":txid": outpoint.hash(),
-                    ":idx": outpoint.n(),
-                },
-                |row| row.get::<_, i64>(0),
-            )
-            .map_err(|e| match e {
-                rusqlite::Error::QueryReturnedNoRows => Error::OutPointMissing,
-                e => e.into(),
-            })?,
+/// Gets the value of a transparent output that a transaction spends,
+/// if it belonged to the wallet or its value was recorded by [`backfill_prevout_values`].
+fn get_prevout_value(outpoint: &OutPoint, conn: &Connection) -> Result<Amount, Error> {
+    Ok(Amount::try_from(
+        conn.query_row(
+            GET_PREVOUT_VALUE,
+            named_params! {
+                ":txid": outpoint.hash(),
+                ":idx": outpoint.n(),
+            },
+            |row| row.get::<_, i64>(0),
         )
-        .unwrap())
-    }
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => Error::OutPointMissing,
+            e => e.into(),
+        })?,
+    )
+    .unwrap())
+}
 
+fn calculate_transaction_fee(transaction: Transaction, conn: &Connection) -> Result<Amount, Error> {
     let transparent_value_balance = transaction
         .transparent_bundle()
         .map_or(Ok(Amount::zero()), |b| {
@@ -514,10 +1020,8 @@ fn initialize_transaction_fees(db: &mut Db, conn: &Connection) -> Result<(), Err
                 .ok_or(Error::Internal("Transaction not found.".to_string()))?;
 
             // Some fees we'll fail to calculate because we're missing UTXOs.
-            // that should only happen when it's an incoming transparent transaction from a spent UTXO,
-            // but if it's incoming, the user didn't pay the fee anyway so it's not a big deal to not display the fee.
-            // If we want to predict whether transactions in the mempool have a ZIP-317 sufficient fee, we'll have to
-            // add a way to fetch those UTXO values.
+            // That should only happen when it's an incoming transparent transaction from a spent UTXO,
+            // until backfill_prevout_values has fetched the values of the outputs it spends.
             if let Ok(fee) = calculate_transaction_fee(tx, conn) {
                 let txid: [u8; 32] = txid.into();
                 conn.execute(
@@ -533,96 +1037,261 @@ fn initialize_transaction_fees(db: &mut Db, conn: &Connection) -> Result<(), Err
     Ok(())
 }
 
-async fn download_full_shielded_transactions<P: AsRef<Path> + Clone>(
+/// Records the values of the transparent outputs spent by transactions whose fees can't be calculated
+/// because those outputs never belonged to the wallet (e.g. funds sent to the wallet from a transparent address).
+///
+/// The transactions that created those outputs are taken from the wallet when it has them,
+/// or else downloaded from the server.
+///
+/// Transactions whose prevout values can't all be found are remembered, and not tried again.
+/// The fee is only informational, so such failures don't fail the sync.
+async fn backfill_prevout_values(
     client: &mut CompactTxStreamerClient<Channel>,
-    data_file: P,
     db: &mut Db,
-    network: &Network,
+    conn: &Connection,
     cancellation_token: CancellationToken,
-) -> Result<Vec<TxId>, Error> {
-    let client = Arc::new(Mutex::new(client));
-    // Scope the database connection so it's closed before we use the db argument,
-    // to avoid 'database is locked' errors.
-    let txids;
-    {
-        let conn = Connection::open(data_file.clone())?;
-        let mut stmt = conn.prepare("SELECT txid FROM transactions WHERE raw IS NULL")?;
-        txids = stmt
-            .query_map([], |r| r.get::<_, [u8; 32]>(0).map(TxId::from_bytes))?
-            .collect::<Result<Vec<_>, _>>()?;
-    }
-
-    for txid in txids.iter() {
-        let raw_tx = webrequest_with_retry(
-            || async {
-                Ok(client
-                    .lock()
-                    .await
-                    .get_transaction(TxFilter {
-                        hash: txid.as_ref().to_vec(),
-                        ..Default::default()
-                    })
-                    .await?
-                    .into_inner())
-            },
-            cancellation_token.clone(),
-        )
-        .await?;
+) -> Result<(), Error> {
+    let txids = conn
+        .prepare(GET_PREVOUT_BACKFILL_CANDIDATES)?
+        .query_map([], |r| r.get::<_, [u8; 32]>(0).map(TxId::from_bytes))?
+        .collect::<Result<Vec<_>, _>>()?;
 
-        // The consensus branch ID passed in here does not matter:
-        // - v4 and below cache it internally, but all we do with this transaction while
-        //   it is in memory is decryption and serialization, neither of which use the
-        //   consensus branch ID.
-        // - v5 and above transactions ignore the argument, and parse the correct value
-        //   from their encoding.
-        let tx = Transaction::read(raw_tx.data.reader(), BranchId::Sapling)?;
-        decrypt_and_store_transaction(network, &mut db.data, &tx)?;
+    for txid in txids {
+        if !backfill_transaction_prevout_values(client, db, conn, txid, &cancellation_token).await?
+        {
+            conn.execute(
+                RECORD_PREVOUT_BACKFILL_FAILURE,
+                named_params! { ":txid": txid.as_ref() },
+            )?;
+        }
     }
 
-    Ok(txids)
+    Ok(())
 }
 
-async fn update_subtree_roots<P: Parameters>(
+/// Records the values of the transparent outputs spent by one transaction,
+/// and returns whether they are now all known.
+async fn backfill_transaction_prevout_values(
     client: &mut CompactTxStreamerClient<Channel>,
-    db_data: &mut WalletDb<rusqlite::Connection, P>,
-) -> Result<(), anyhow::Error> {
-    // Update sapling subtree roots
-    let mut request = service::GetSubtreeRootsArg::default();
-    request.set_shielded_protocol(service::ShieldedProtocol::Sapling);
-    let roots: Vec<CommitmentTreeRoot<sapling::Node>> = client
-        .get_subtree_roots(request)
-        .await?
-        .into_inner()
-        .and_then(|root| async move {
-            let root_hash = sapling::Node::read(&root.root_hash[..])?;
-            Ok(CommitmentTreeRoot::from_parts(
-                BlockHeight::from_u32(root.completing_block_height as u32),
-                root_hash,
-            ))
+    db: &mut Db,
+    conn: &Connection,
+    txid: TxId,
+    cancellation_token: &CancellationToken,
+) -> Result<bool, Error> {
+    let tx = match db.data.get_transaction(txid) {
+        Ok(Some(tx)) => tx,
+        Ok(None) => return Ok(false),
+        Err(e) => {
+            warn!("Failed to read transaction {}: {}", txid, e);
+            return Ok(false);
+        }
+    };
+    let inputs = match tx.transparent_bundle() {
+        Some(b) => &b.vin,
+        None => return Ok(false),
+    };
+
+    let mut complete = true;
+    for input in inputs {
+        if get_prevout_value(&input.prevout, conn).is_ok() {
+            continue;
+        }
+
+        let prevout_txid = TxId::from_bytes(*input.prevout.hash());
+        let prevout_tx = match db.data.get_transaction(prevout_txid) {
+            Ok(Some(tx)) => Some(tx),
+            Ok(None) => {
+                let raw_tx = webrequest_with_retry(
+                    || async {
+                        Ok(client
+                            .clone()
+                            .get_transaction(TxFilter {
+                                hash: prevout_txid.as_ref().to_vec(),
+                                ..Default::default()
+                            })
+                            .await?
+                            .into_inner())
+                    },
+                    cancellation_token.clone(),
+                )
+                .await;
+                match raw_tx {
+                    // As in download_full_shielded_transactions, the branch ID doesn't matter for reading outputs.
+                    Ok(raw_tx) => {
+                        match Transaction::read(raw_tx.data.reader(), BranchId::Sapling) {
+                            Ok(tx) => Some(tx),
+                            Err(e) => {
+                                warn!("Failed to parse transaction {}: {}", prevout_txid, e);
+                                None
+                            }
+                        }
+                    }
+                    Err(_) if cancellation_token.is_cancelled() => return Err(Error::Canceled),
+                    Err(e) => {
+                        warn!("Failed to download transaction {}: {}", prevout_txid, e);
+                        None
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Failed to read transaction {}: {}", prevout_txid, e);
+                None
+            }
+        };
+
+        let output = prevout_tx.as_ref().and_then(|t| {
+            t.transparent_bundle()
+                .and_then(|b| b.vout.get(input.prevout.n() as usize))
+        });
+        match output {
+            Some(output) => {
+                conn.execute(
+                    SET_PREVOUT_VALUE,
+                    named_params! {
+                        ":txid": prevout_txid.as_ref(),
+                        ":output_index": input.prevout.n(),
+                        ":value": u64::from(output.value),
+                    },
+                )?;
+            }
+            None => complete = false,
+        }
+    }
+
+    Ok(complete)
+}
+
+async fn download_full_shielded_transactions(
+    client: &mut CompactTxStreamerClient<Channel>,
+    conn: &Connection,
+    db: &mut Db,
+    network: &Network,
+    cancellation_token: CancellationToken,
+) -> Result<Vec<TxId>, Error> {
+    let client = Arc::new(Mutex::new(client));
+    let txids = conn
+        .prepare("SELECT txid FROM transactions WHERE raw IS NULL")?
+        .query_map([], |r| r.get::<_, [u8; 32]>(0).map(TxId::from_bytes))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for txid in txids.iter() {
+        let raw_tx = webrequest_with_retry(
+            || async {
+                Ok(client
+                    .lock()
+                    .await
+                    .get_transaction(TxFilter {
+                        hash: txid.as_ref().to_vec(),
+                        ..Default::default()
+                    })
+                    .await?
+                    .into_inner())
+            },
+            cancellation_token.clone(),
+        )
+        .await?;
+
+        // The consensus branch ID passed in here does not matter:
+        // - v4 and below cache it internally, but all we do with this transaction while
+        //   it is in memory is decryption and serialization, neither of which use the
+        //   consensus branch ID.
+        // - v5 and above transactions ignore the argument, and parse the correct value
+        //   from their encoding.
+        let tx = Transaction::read(raw_tx.data.reader(), BranchId::Sapling)?;
+
+        // This decrypts with the full viewing keys of all accounts, including view-only ones,
+        // so the outgoing viewing keys recover the recipients of funds spent by accounts we cannot spend from.
+        decrypt_and_store_transaction(network, &mut db.data, &tx)?;
+    }
+
+    Ok(txids)
+}
+
+/// Downloads the subtree roots of the note commitment trees and stores them in the wallet.
+///
+/// When a `verifier` is given, the roots are also downloaded from it,
+/// nothing is stored unless the two servers agree on every root they both have,
+/// and only the roots that the verifier has too are stored.
+async fn update_subtree_roots<P: Parameters>(
+    client: &mut CompactTxStreamerClient<Channel>,
+    mut verifier: Option<&mut CompactTxStreamerClient<Channel>>,
+    db_data: &mut WalletDb<rusqlite::Connection, P>,
+) -> Result<(), Error> {
+    // Update sapling subtree roots
+    let mut roots = get_subtree_roots(client, service::ShieldedProtocol::Sapling, |h| {
+        sapling::Node::read(h)
+    })
+    .await?;
+    if let Some(verifier) = verifier.as_deref_mut() {
+        let other = get_subtree_roots(verifier, service::ShieldedProtocol::Sapling, |h| {
+            sapling::Node::read(h)
         })
-        .try_collect()
         .await?;
-    db_data.put_sapling_subtree_roots(0, &roots)?;
+        roots.truncate(check_subtree_roots("Sapling", &roots, &other)?);
+    }
+    db_data
+        .put_sapling_subtree_roots(0, &roots)
+        .map_err(SqliteClientError::CommitmentTree)?;
 
     // Update orchard subtree roots
-    request = service::GetSubtreeRootsArg::default();
-    request.set_shielded_protocol(service::ShieldedProtocol::Orchard);
-    let roots: Vec<CommitmentTreeRoot<MerkleHashOrchard>> = client
+    let mut roots = get_subtree_roots(client, service::ShieldedProtocol::Orchard, |h| {
+        MerkleHashOrchard::read(h)
+    })
+    .await?;
+    if let Some(verifier) = verifier {
+        let other = get_subtree_roots(verifier, service::ShieldedProtocol::Orchard, |h| {
+            MerkleHashOrchard::read(h)
+        })
+        .await?;
+        roots.truncate(check_subtree_roots("Orchard", &roots, &other)?);
+    }
+    db_data
+        .put_orchard_subtree_roots(0, roots.as_slice())
+        .map_err(SqliteClientError::CommitmentTree)?;
+
+    Ok(())
+}
+
+async fn get_subtree_roots<H>(
+    client: &mut CompactTxStreamerClient<Channel>,
+    protocol: service::ShieldedProtocol,
+    read: fn(&[u8]) -> std::io::Result<H>,
+) -> Result<Vec<CommitmentTreeRoot<H>>, Error> {
+    let mut request = service::GetSubtreeRootsArg::default();
+    request.set_shielded_protocol(protocol);
+    Ok(client
         .get_subtree_roots(request)
         .await?
         .into_inner()
         .and_then(|root| async move {
-            let root_hash = MerkleHashOrchard::read(&root.root_hash[..])?;
+            let root_hash = read(&root.root_hash[..])?;
             Ok(CommitmentTreeRoot::from_parts(
                 BlockHeight::from_u32(root.completing_block_height as u32),
                 root_hash,
             ))
         })
         .try_collect()
-        .await?;
-    db_data.put_orchard_subtree_roots(0, roots.as_slice())?;
+        .await?)
+}
 
-    Ok(())
+/// Fails with [`Error::SubtreeRootMismatch`] unless two servers' subtree roots agree.
+///
+/// A server that is behind the other may have fewer roots, so only the roots both have are compared.
+/// Returns how many of `roots` were verified, which excludes any the other server doesn't have yet.
+fn check_subtree_roots<H: PartialEq>(
+    pool: &str,
+    roots: &[CommitmentTreeRoot<H>],
+    other: &[CommitmentTreeRoot<H>],
+) -> Result<usize, Error> {
+    match roots.iter().zip(other).position(|(a, b)| {
+        a.subtree_end_height() != b.subtree_end_height() || a.root_hash() != b.root_hash()
+    }) {
+        Some(index) => Err(Error::SubtreeRootMismatch(format!(
+            "The servers report different {} subtree roots at index {}.",
+            pool, index
+        ))),
+        None => Ok(roots.len().min(other.len())),
+    }
 }
 
 async fn download_transparent_transactions(
@@ -630,7 +1299,7 @@ async fn download_transparent_transactions(
     db: &mut Db,
     network: &Network,
     address: &TransparentAddress,
-    start: Option<BlockHeight>,
+    start: BlockHeight,
     end: BlockHeight,
     cancellation_token: CancellationToken,
 ) -> Result<Vec<TxId>, Error> {
@@ -645,11 +1314,7 @@ async fn download_transparent_transactions(
                     address: address.encode(network),
                     range: Some(BlockRange {
                         start: Some(BlockId {
-                            height: start
-                                .unwrap_or_else(|| {
-                                    network.activation_height(NetworkUpgrade::Sapling).unwrap()
-                                })
-                                .into(),
+                            height: start.into(),
                             ..Default::default()
                         }),
                         end: Some(BlockId {
@@ -675,20 +1340,7 @@ async fn download_transparent_transactions(
         decrypt_and_store_transaction(network, &mut db.data, &tx)?;
 
         // Record receives.
-        if let Some(t) = tx.transparent_bundle() {
-            for (txout_index, txout) in t.vout.iter().enumerate() {
-                let outpoint = OutPoint::new(tx.txid().as_ref().to_owned(), txout_index as u32);
-                if let Some(output) =
-                    WalletTransparentOutput::from_parts(outpoint, txout.to_owned(), height)
-                {
-                    match db.data.put_received_transparent_utxo(&output) {
-                        Ok(_) => (),
-                        Err(SqliteClientError::AddressNotRecognized(_)) => (), // Not all tx outputs will be for our wallet. We only want to record the ones that are.
-                        Err(x) => return Err(x.into()),
-                    };
-                }
-            }
-        }
+        store_transparent_receives(db, &tx, height)?;
     }
 
     db.data
@@ -697,24 +1349,67 @@ async fn download_transparent_transactions(
     Ok(txids_for_new_transparent_transactions)
 }
 
+/// Records the outputs of a transaction that send funds to the wallet's transparent addresses.
+pub(crate) fn store_transparent_receives(
+    db: &mut Db,
+    tx: &Transaction,
+    height: BlockHeight,
+) -> Result<(), Error> {
+    if let Some(t) = tx.transparent_bundle() {
+        for (txout_index, txout) in t.vout.iter().enumerate() {
+            let outpoint = OutPoint::new(tx.txid().as_ref().to_owned(), txout_index as u32);
+            if let Some(output) =
+                WalletTransparentOutput::from_parts(outpoint, txout.to_owned(), height)
+            {
+                match db.data.put_received_transparent_utxo(&output) {
+                    Ok(_) => (),
+                    Err(SqliteClientError::AddressNotRecognized(_)) => (), // Not all tx outputs will be for our wallet. We only want to record the ones that are.
+                    Err(x) => return Err(x.into()),
+                };
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 struct SyncState {
+    /// The server being synced from, which the tree states it provides are cached for.
+    server_uri: Uri,
     network: Network,
     progress: Arc<Option<Box<dyn SyncUpdate>>>,
     min_confirmations: u32,
+    options: SyncOptions,
+    pause_token: PauseToken,
+    journal: ChunkJournal,
+    backpressure: Backpressure,
+    chunk_sizer: ChunkSizer,
+    /// The balances last reported for each account, so that only changes are reported.
+    reported_balances: Arc<StdMutex<HashMap<u32, UserBalances>>>,
     cancellation_token: CancellationToken,
 }
 
+/// Downloads and scans a range of blocks.
+///
+/// The scanner runs on its own task, so it takes ownership of the database handle and connection for the duration,
+/// and hands them back in the result.
 async fn download_and_scan_blocks(
     client: &mut CompactTxStreamerClient<Channel>,
     mut db: Db,
+    conn: Connection,
     state: &SyncState,
     status: Option<&SyncUpdateData>,
     block_range: &ScanRange,
 ) -> Result<DownloadAndScanResult, Error> {
     info!("Received instructions to download [{})", block_range);
-    let (send, mut receive) =
-        mpsc::channel::<(Vec<CompactBlock>, ChainState)>(CHUNK_CHANNEL_CAPACITY);
+    let (send, mut receive) = mpsc::channel::<DownloadedChunk>(CHUNK_CHANNEL_CAPACITY);
+    let cache_budget = BlockCacheBudget::new(
+        state
+            .options
+            .max_cached_actions
+            .unwrap_or(DEFAULT_MAX_CACHED_ACTIONS),
+    );
     let priorities_changed_token = state.cancellation_token.child_token();
 
     // Download the blocks in `scan_range` into the block source, overwriting any
@@ -722,11 +1417,25 @@ async fn download_and_scan_blocks(
     let mut client = client.to_owned();
     let downloader_block_range = block_range.clone();
     let downloader_priorities_changed_token = priorities_changed_token.clone();
+    let downloader_pause_token = state.pause_token.clone();
+    let downloader_journal = state.journal.clone();
+    let downloader_backpressure = state.backpressure.clone();
+    let downloader_chunk_sizer = state.chunk_sizer.clone();
+    let downloader_network = state.network;
+    let downloader_server_uri = state.server_uri.clone();
+    report_scan_range_event(&state.progress, block_range, ScanRangePhase::Downloading);
     let downloader = tokio::spawn(async move {
         download_blocks(
             &mut client,
+            &downloader_server_uri,
             &downloader_block_range,
+            &downloader_network,
             send,
+            &downloader_journal,
+            &downloader_backpressure,
+            &downloader_chunk_sizer,
+            &cache_budget,
+            downloader_pause_token,
             downloader_priorities_changed_token,
         )
         .await
@@ -737,21 +1446,45 @@ async fn download_and_scan_blocks(
     let scanner_block_range = block_range.clone();
     let scanner = tokio::spawn(async move {
         let mut priorities_changed = false;
-        while let Some((chunk, chain_state)) = select! {
-            result = receive.recv() => Ok(result),
-            _ = state.cancellation_token.cancelled() => Err(Error::Canceled),
-        }? {
+        let mut scanning_reported = false;
+        loop {
+            let wait_started = Instant::now();
+            // The chunk's room in the block cache is released when this goes out of scope, after it is scanned.
+            let (chunk, chain_state, _cache_permit) = match select! {
+                result = receive.recv() => Ok(result),
+                _ = state.cancellation_token.cancelled() => Err(Error::Canceled),
+            }? {
+                Some(received) => received,
+                None => break,
+            };
+            state.chunk_sizer.scan_waited(wait_started.elapsed());
+
             let scan_range = ScanRange::from_parts(
                 chunk.first().unwrap().height()..chunk.last().unwrap().height() + 1,
                 scanner_block_range.priority(),
             );
 
+            state
+                .pause_token
+                .wait_while_paused(&state.cancellation_token)
+                .await?;
+
+            if !scanning_reported {
+                report_scan_range_event(
+                    &state.progress,
+                    &scanner_block_range,
+                    ScanRangePhase::Scanning,
+                );
+                scanning_reported = true;
+            }
+
             info!("Scanning {} blocks [{}).", chunk.len(), scan_range);
+            let (sapling_outputs, orchard_actions) = count_shielded_outputs(&chunk);
 
             // Insert the blocks into the block cache.
             db.blocks.insert_range(chunk);
 
-            if scan_blocks(&state.network, &mut db, &scan_range, &chain_state)?
+            if scan_blocks(&state, &mut db, &conn, &scan_range, &chain_state)?
                 && !priorities_changed
             {
                 // Notify the downloader to break out early because we'll be getting a new range request.
@@ -764,9 +1497,18 @@ async fn download_and_scan_blocks(
 
             // Now that they've been scanned, we don't need them any more.
             db.blocks.remove_range(scan_range.block_range());
+            if let Err(e) = state.journal.remove(scan_range.block_range().start) {
+                warn!(
+                    "Failed to remove journaled chunk at {}: {}",
+                    scan_range.block_range().start,
+                    e
+                );
+            }
 
             if let Some(s) = status.as_mut() {
                 s.current_step += scan_range.len() as u64;
+                s.sapling_outputs_scanned += sapling_outputs;
+                s.orchard_actions_scanned += orchard_actions;
                 update_and_report_status(
                     s,
                     &db.data,
@@ -775,6 +1517,8 @@ async fn download_and_scan_blocks(
                 )?;
             }
 
+            report_balance_changes(&db, &conn, state);
+
             if state.cancellation_token.is_cancelled() {
                 return Err(Error::Canceled);
             }
@@ -783,6 +1527,8 @@ async fn download_and_scan_blocks(
         Ok::<_, Error>(DownloadAndScanResult {
             priorities_changed,
             status,
+            db,
+            conn,
         })
     });
 
@@ -791,41 +1537,116 @@ async fn download_and_scan_blocks(
     scan_result
 }
 
+/// Counts the Sapling outputs and Orchard actions in a chunk of blocks.
+fn count_shielded_outputs(blocks: &[CompactBlock]) -> (u64, u64) {
+    blocks
+        .iter()
+        .flat_map(|b| b.vtx.iter())
+        .fold((0, 0), |(sapling, orchard), tx| {
+            (
+                sapling + tx.outputs.len() as u64,
+                orchard + tx.actions.len() as u64,
+            )
+        })
+}
+
 async fn download_blocks(
     client: &mut CompactTxStreamerClient<Channel>,
+    server_uri: &Uri,
     scan_range: &ScanRange,
-    sender: mpsc::Sender<(Vec<CompactBlock>, ChainState)>,
+    network: &Network,
+    sender: mpsc::Sender<DownloadedChunk>,
+    journal: &ChunkJournal,
+    backpressure: &Backpressure,
+    chunk_sizer: &ChunkSizer,
+    cache_budget: &BlockCacheBudget,
+    pause_token: PauseToken,
     cancellation_token: CancellationToken,
 ) -> Result<(), Status> {
     info!("Fetching {}", scan_range);
-    let mut start = service::BlockId::default();
-    start.height = scan_range.block_range().start.into();
-    let mut end = service::BlockId::default();
-    end.height = (scan_range.block_range().end - 1).into();
-    let range = service::BlockRange {
-        start: Some(start),
-        end: Some(end),
-    };
-
+    let mut next_height = scan_range.block_range().start;
+    let mut failure_count = 0;
     let mut blocks = Vec::new();
-    let mut stream = client.get_block_range(range).await?.into_inner();
     let mut accumulated_size = 0;
-    while let Some(block) = stream.try_next().await? {
-        // Process each block here
-        accumulated_size += block.vtx.iter().fold(0, |acc, tx| {
-            acc + tx.actions.len() + tx.outputs.len() + tx.spends.len()
-        });
-        blocks.push(block);
 
-        if accumulated_size > BLOCKS_CHUNK_THRESHOLD {
-            send_blocks_and_chainstate(client, blocks, &sender).await?;
-            blocks = Vec::new();
-            accumulated_size = 0;
+    // If the stream fails partway through, it is reopened from the next block we need,
+    // keeping the blocks already downloaded.
+    'download: while next_height < scan_range.block_range().end {
+        let range = service::BlockRange {
+            start: Some(service::BlockId {
+                height: next_height.into(),
+                ..Default::default()
+            }),
+            end: Some(service::BlockId {
+                height: (scan_range.block_range().end - 1).into(),
+                ..Default::default()
+            }),
+        };
+
+        let failure = match client.get_block_range(range).await {
+            Ok(response) => {
+                let mut stream = response.into_inner();
+                loop {
+                    let block = match stream.try_next().await {
+                        Ok(Some(block)) => block,
+                        Ok(None) => break 'download,
+                        Err(status) => break status,
+                    };
+
+                    failure_count = 0;
+                    next_height = block.height() + 1;
+                    accumulated_size += block.vtx.iter().fold(0, |acc, tx| {
+                        acc + tx.actions.len() + tx.outputs.len() + tx.spends.len()
+                    });
+                    blocks.push(block);
+
+                    // Smaller chunks keep us from losing as much work when a struggling server drops the stream.
+                    if accumulated_size > backpressure.scale(chunk_sizer.chunk_actions()) {
+                        send_blocks_and_chainstate(
+                            client,
+                            server_uri,
+                            network,
+                            blocks,
+                            journal,
+                            chunk_sizer,
+                            cache_budget,
+                            &sender,
+                        )
+                        .await?;
+                        backpressure.succeeded();
+                        blocks = Vec::new();
+                        accumulated_size = 0;
+                    }
+
+                    // While paused, hold on to the blocks downloaded so far.
+                    // A cancellation that interrupts the pause is handled just below.
+                    let _ = pause_token.wait_while_paused(&cancellation_token).await;
+
+                    if cancellation_token.is_cancelled() {
+                        info!("Breaking out of download loop due to cancellation.");
+                        break 'download;
+                    }
+                }
+            }
+            Err(status) => status,
+        };
+
+        if !is_transient(&failure) || failure_count == ATTEMPT_LIMIT {
+            return Err(failure);
         }
 
-        if cancellation_token.is_cancelled() {
-            info!("Breaking out of download loop due to cancellation.");
-            break;
+        failure_count += 1;
+        if is_throttled(&failure) {
+            backpressure.throttled();
+        }
+        let delay = retry_delay(&failure, failure_count);
+        warn!(
+            "Block download interrupted at {}. Resuming in {:?}. {:?}",
+            next_height, delay, failure
+        );
+        select! {
+            _ = tokio::time::sleep(delay) => {},
+            _ = cancellation_token.cancelled() => break 'download,
         }
     }
 
@@ -835,25 +1656,56 @@ async fn download_blocks(
     );
 
     if !blocks.is_empty() {
-        send_blocks_and_chainstate(client, blocks, &sender).await?;
+        send_blocks_and_chainstate(
+            client,
+            server_uri,
+            network,
+            blocks,
+            journal,
+            chunk_sizer,
+            cache_budget,
+            &sender,
+        )
+        .await?;
     }
 
     async fn send_blocks_and_chainstate(
         client: &mut CompactTxStreamerClient<Channel>,
+        server_uri: &Uri,
+        network: &Network,
         blocks: Vec<CompactBlock>,
-        sender: &mpsc::Sender<(Vec<CompactBlock>, ChainState)>,
+        journal: &ChunkJournal,
+        chunk_sizer: &ChunkSizer,
+        cache_budget: &BlockCacheBudget,
+        sender: &mpsc::Sender<DownloadedChunk>,
     ) -> Result<(), Status> {
         let base_height = blocks[0].height - 1;
-        let tree_state = client
-            .get_tree_state(service::BlockId {
-                height: base_height,
-                ..Default::default()
-            })
-            .await?
-            .into_inner();
+        let tree_state = get_tree_state(
+            client,
+            server_uri,
+            network,
+            base_height,
+            Some(&blocks[0].prev_hash),
+        )
+        .await?;
+
+        // Persist the chunk so that it need not be downloaded again if we're interrupted before it is scanned.
+        // The journal is only an optimization, so failing to write to it shouldn't fail the sync.
+        if let Err(e) = journal.write(&blocks, &tree_state) {
+            warn!("Failed to journal downloaded blocks: {}", e);
+        }
+
         let chain_state = tree_state.to_chain_state()?;
 
-        sender.send((blocks, chain_state)).await.unwrap();
+        // Time spent waiting here means scanning is the bottleneck.
+        let wait_started = Instant::now();
+        let cache_permit = cache_budget.reserve(&blocks).await;
+        sender
+            .send((blocks, chain_state, cache_permit))
+            .await
+            .unwrap();
+        chunk_sizer.download_waited(wait_started.elapsed());
+        chunk_sizer.adjust();
 
         Ok(())
     }
@@ -866,13 +1718,14 @@ async fn download_blocks(
 ///
 /// Returns `true` if scanning these blocks materially changed the suggested scan ranges.
 fn scan_blocks(
-    network: &Network,
+    state: &SyncState,
     db: &mut Db,
+    conn: &Connection,
     scan_range: &ScanRange,
     chain_state: &ChainState,
 ) -> Result<bool, Error> {
     let scan_result = scan_cached_blocks(
-        network,
+        &state.network,
         &db.blocks,
         &mut db.data,
         scan_range.block_range().start,
@@ -906,6 +1759,10 @@ fn scan_blocks(
                 rewind_height,
             );
 
+            // Note the transactions the rewind orphans before their heights are forgotten.
+            let affected_txids = get_txids_mined_above(conn, rewind_height)?;
+            record_pending_reorg(conn, err.at_height(), &affected_txids)?;
+
             // Rewind to the chosen height.
             db.data.truncate_to_height(rewind_height)?;
 
@@ -916,20 +1773,220 @@ fn scan_blocks(
             // occurred that orphaned some of those blocks.
             db.blocks.truncate_to_height(rewind_height);
 
+            // The rewind has already happened, so failing to log it mustn't stop the sync.
+            if let Err(e) = audit_log::record(
+                conn,
+                AuditOperation::Rewind,
+                None,
+                &format!(
+                    "Chain reorg detected at {}, rewound to {}",
+                    err.at_height(),
+                    rewind_height
+                ),
+            ) {
+                warn!("Failed to record the rewind in the audit log: {}", e);
+            }
+
+            report_reorg(&state.progress, rewind_height, &affected_txids);
+
             Ok(true)
         }
         Err(other) => Err(other.into()),
     }
 }
 
-async fn watch_mempool(client: &mut CompactTxStreamerClient<Channel>) -> Result<(), Error> {
+/// Gets the transactions mined above the given height.
+fn get_txids_mined_above(conn: &Connection, height: BlockHeight) -> Result<Vec<TxId>, Error> {
+    conn.prepare(GET_TXIDS_MINED_ABOVE)?
+        .query_map(named_params! { ":height": u32::from(height) }, |r| {
+            r.get::<_, [u8; 32]>(0).map(TxId::from_bytes)
+        })?
+        .map(|txid| Ok(txid?))
+        .collect()
+}
+
+/// Scans blocks that an earlier sync downloaded but was interrupted before scanning.
+///
+/// Chunks are only scanned if the wallet still needs them scanned. Either way, they are removed from the journal.
+fn scan_journaled_chunks(db: &mut Db, conn: &Connection, state: &SyncState) -> Result<(), Error> {
+    let chunks = state.journal.read_all()?;
+    if chunks.is_empty() {
+        return Ok(());
+    }
+
+    let mut scan_ranges = db.data.suggest_scan_ranges()?;
+    for chunk in chunks {
+        let block_range = chunk.block_range();
+
+        // A chunk that can't be scanned as it is will be downloaded again,
+        // and its tree state can still spare a request when it is.
+        remember_tree_state(&state.server_uri, &state.network, chunk.tree_state.clone());
+
+        let priority = scan_ranges
+            .iter()
+            .find(|r| {
+                r.priority() > ScanPriority::Scanned
+                    && r.block_range().start <= block_range.start
+                    && r.block_range().end >= block_range.end
+            })
+            .map(|r| r.priority());
+
+        let result = match priority {
+            Some(priority) => {
+                let scan_range = ScanRange::from_parts(block_range.clone(), priority);
+                info!(
+                    "Scanning {} journaled blocks [{}).",
+                    chunk.blocks.len(),
+                    scan_range
+                );
+                match chunk.tree_state.to_chain_state() {
+                    Ok(chain_state) => {
+                        db.blocks.insert_range(chunk.blocks);
+                        let result = scan_blocks(state, db, conn, &scan_range, &chain_state);
+                        db.blocks.remove_range(&block_range);
+                        result.map(|_| ())
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            }
+            None => Ok(()),
+        };
+
+        // Whether or not the chunk scanned, it must not be scanned again at the next sync.
+        // A chunk that failed to scan would likely fail again, and keep failing every sync that follows.
+        if let Err(e) = state.journal.remove(block_range.start) {
+            warn!("Failed to remove a scanned chunk from the journal: {}", e);
+        }
+
+        result?;
+        if priority.is_some() {
+            scan_ranges = db.data.suggest_scan_ranges()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Waits until the server reports a chain tip above `tip_height`.
+///
+/// The tip is checked right away, so a block that was mined since `tip_height` was fetched is not missed.
+async fn wait_for_next_block(
+    client: &CompactTxStreamerClient<Channel>,
+    tip_height: u32,
+    cancellation_token: &CancellationToken,
+) -> Result<(), Error> {
+    loop {
+        if get_tip_height(client, cancellation_token).await? > u64::from(tip_height) {
+            return Ok(());
+        }
+
+        select! {
+            _ = cancellation_token.cancelled() => return Err(Error::Canceled),
+            _ = tokio::time::sleep(CHAIN_TIP_POLL_INTERVAL) => {}
+        }
+    }
+}
+
+/// Watches the mempool until the next block is mined.
+///
+/// Transactions that send funds to any of the given transparent addresses are recorded in the wallet
+/// and reported as they arrive, so that incoming transparent payments are visible within seconds.
+async fn watch_mempool(
+    client: &mut CompactTxStreamerClient<Channel>,
+    db: &mut Db,
+    conn: &mut Connection,
+    state: &SyncState,
+    taddrs: &HashMap<TransparentAddress, AccountId>,
+    tip_height: u32,
+) -> Result<(), Error> {
+    let expired = conn.execute(
+        EXPIRE_MEMPOOL_RECEIVES,
+        named_params! { ":tip_height": tip_height },
+    )?;
+    if expired > 0 {
+        report_balance_changes(db, conn, state);
+    }
+
     let mut response = client.get_mempool_stream(Empty {}).await?.into_inner();
 
-    while let Some(_tx) = response.message().await? {}
+    // These transactions can be mined at the next block at the earliest.
+    let expected_height = BlockHeight::from_u32(tip_height + 1);
+    while let Some(rawtx) = response.message().await? {
+        if taddrs.is_empty() {
+            continue;
+        }
+
+        let tx = Transaction::read(
+            &rawtx.data[..],
+            BranchId::for_height(&state.network, expected_height),
+        )?;
+        let payments = record_mempool_receives(conn, &state.network, &tx, taddrs)?;
+        if !payments.is_empty() {
+            decrypt_and_store_transaction(&state.network, &mut db.data, &tx)?;
+            if let Some(sink) = state.progress.as_ref() {
+                sink.report_incoming_payments(payments);
+            }
+            report_balance_changes(db, conn, state);
+        }
+    }
 
     Ok(())
 }
 
+/// Records the outputs of a mempool transaction that pay the wallet's transparent addresses,
+/// and returns the payments that had not been seen before.
+///
+/// These outputs are not added to the wallet's UTXOs until the transaction is mined,
+/// since the wallet can't know which block will mine it.
+/// Downloading the transparent transactions for the address adds them then.
+fn record_mempool_receives(
+    conn: &Connection,
+    network: &Network,
+    tx: &Transaction,
+    taddrs: &HashMap<TransparentAddress, AccountId>,
+) -> Result<Vec<IncomingPayment>, Error> {
+    let mut payments = Vec::new();
+    let Some(bundle) = tx.transparent_bundle() else {
+        return Ok(payments);
+    };
+
+    let txid = tx.txid().as_ref().to_vec();
+    for (output_index, txout) in bundle.vout.iter().enumerate() {
+        let Some((address, account_id)) = txout
+            .recipient_address()
+            .and_then(|a| taddrs.get(&a).map(|account_id| (a, *account_id)))
+        else {
+            continue;
+        };
+
+        let inserted = conn.execute(
+            RECORD_MEMPOOL_RECEIVE,
+            named_params! {
+                ":txid": txid,
+                ":output_index": output_index as u32,
+                ":account_id": u32::from(account_id),
+                ":value": u64::from(txout.value),
+                ":expiry_height": u32::from(tx.expiry_height()),
+            },
+        )?;
+        if inserted > 0 {
+            payments.push(IncomingPayment {
+                account_id: u32::from(account_id),
+                txid: txid.clone(),
+                address: address.encode(network),
+                diversifier_index: None,
+                pool: Pool::Transparent,
+                value: txout.value.into(),
+                memo: None,
+                mined_height: None,
+                confirmations: 0,
+            });
+        }
+    }
+
+    Ok(payments)
+}
+
 /// Returns the transactions that match the given filters.
 ///
 /// `starting_block_filter` and `ending_block_filter` are inclusive.
@@ -942,11 +1999,16 @@ pub(crate) fn get_transactions(
     ending_block_filter: Option<u32>,
 ) -> Result<Vec<crate::interop::Transaction>, Error> {
     let ufvkeys = db.data.get_unified_full_viewing_keys()?;
+    let uuids = account_uuids(db, network)?;
+    let tip_height = db.data.chain_height()?.map(u32::from);
 
     rusqlite::vtab::array::load_module(conn)?;
 
     let mut stmt_txs = conn.prepare(GET_TRANSACTIONS_SQL)?;
 
+    // The transactions whose headers don't rule out a transparent bundle, which are the only ones worth decoding.
+    let mut transparent_txids: HashSet<Vec<u8>> = HashSet::new();
+
     let rows = stmt_txs.query_and_then(
         named_params! {
             ":account_id": account_id_filter,
@@ -955,7 +2017,70 @@ pub(crate) fn get_transactions(
         },
         |row| -> Result<crate::interop::Transaction, Error> {
             let account_id: u32 = row.get("account_id")?;
-            let output_pool: u32 = row.get("output_pool")?;
+            let mined_height: Option<u32> = row.get("mined_height")?;
+
+            // An expiry height of 0 means the transaction never expires.
+            let expiry_height = row
+                .get::<_, Option<u32>>("expiry_height")?
+                .filter(|h| *h > 0);
+
+            let raw_header: Option<Vec<u8>> = row.get("raw_header")?;
+            let header = raw_header.as_deref().map(read_header).transpose()?;
+            let txid: Vec<u8> = row.get("txid")?;
+            if header.as_ref().is_some_and(|h| h.may_have_transparent) {
+                transparent_txids.insert(txid.clone());
+            }
+
+            let mut tx = crate::interop::Transaction {
+                account_id,
+                account_uuid: uuids.get(&account_id).cloned().unwrap_or_default(),
+                txid,
+                mined_height,
+                expiry_height,
+                blocks_until_expiry: match (mined_height, expiry_height, tip_height) {
+                    (None, Some(expiry), Some(tip)) => Some(expiry.saturating_sub(tip)),
+                    _ => None,
+                },
+                expired_unmined: row
+                    .get::<_, Option<bool>>("expired_unmined")?
+                    .unwrap_or(false),
+                block_time: match row.get::<_, Option<i64>>("block_time")? {
+                    Some(v) => Some(
+                        time::OffsetDateTime::from_unix_timestamp(v)
+                            .map_err(|e| {
+                                Error::SqliteClient(SqliteClientError::CorruptedData(format!(
+                                    "Error translating unix timestamp: {}",
+                                    e
+                                )))
+                            })?
+                            .into(),
+                    ),
+                    None => None,
+                },
+                fee: row.get::<_, Option<u64>>("fee_paid")?,
+                version: header.as_ref().map(|h| h.version),
+                size: row.get("raw_size")?,
+                consensus_branch_id: header.as_ref().and_then(|h| h.consensus_branch_id),
+                account_balance_delta: row.get("account_balance_delta")?,
+                outgoing_recovered: row.get("outgoing_recovered")?,
+                label: row.get("label")?,
+                tags: row
+                    .get::<_, Option<String>>("tags")?
+                    .map(|tags| tags.split(',').filter_map(|t| t.parse().ok()).collect())
+                    .unwrap_or_default(),
+                incoming: Vec::new(),
+                outgoing: Vec::new(),
+                change: Vec::new(),
+                transparent_inputs: Vec::new(),
+                transparent_outputs: Vec::new(),
+            };
+
+            // A transaction that spends the account's funds may have no outputs that we know of,
+            // when they could not be recovered with the outgoing viewing key.
+            let output_pool: u32 = match row.get("output_pool")? {
+                Some(p) => p,
+                None => return Ok(tx),
+            };
             let from_account_id: Option<u32> = row.get("from_account_id")?;
             let to_account_id: Option<u32> = row.get("to_account_id")?;
             let mut recipient: Option<String> = row.get("to_address")?;
@@ -981,63 +2106,11 @@ pub(crate) fn get_transactions(
             if recipient.is_none() {
                 let diversifier: Option<Vec<u8>> = row.get("diversifier")?;
                 if let Some(diversifier) = diversifier {
-                    recipient = match output_pool {
-                        PoolType::SAPLING => ufvk.and_then(|k| {
-                            k.sapling().and_then(|s| {
-                                s.diversified_address(sapling::keys::Diversifier(
-                                    diversifier.try_into().unwrap(),
-                                ))
-                                .map(|a| a.encode(network))
-                            })
-                        }),
-                        PoolType::ORCHARD => ufvk.and_then(|k| {
-                            k.orchard().map(|o| {
-                                UnifiedAddress::from_receivers(
-                                    Some(o.address(
-                                        orchard::keys::Diversifier::from_bytes(
-                                            diversifier.try_into().unwrap(),
-                                        ),
-                                        Scope::External,
-                                    )),
-                                    None,
-                                    None,
-                                )
-                                .unwrap()
-                                .encode(network)
-                            })
-                        }),
-                        _ => None,
-                    }
+                    recipient = regenerate_address(ufvk, output_pool, diversifier, network);
                 }
             }
 
-            let mut tx = crate::interop::Transaction {
-                account_id,
-                txid: row.get::<_, Vec<u8>>("txid")?,
-                mined_height: row.get("mined_height")?,
-                expired_unmined: row
-                    .get::<_, Option<bool>>("expired_unmined")?
-                    .unwrap_or(false),
-                block_time: match row.get::<_, Option<i64>>("block_time")? {
-                    Some(v) => Some(
-                        time::OffsetDateTime::from_unix_timestamp(v)
-                            .map_err(|e| {
-                                Error::SqliteClient(SqliteClientError::CorruptedData(format!(
-                                    "Error translating unix timestamp: {}",
-                                    e
-                                )))
-                            })?
-                            .into(),
-                    ),
-                    None => None,
-                },
-                fee: row.get::<_, Option<u64>>("fee_paid")?,
-                account_balance_delta: row.get("account_balance_delta")?,
-                incoming: Vec::new(),
-                outgoing: Vec::new(),
-                change: Vec::new(),
-            };
-
+            let memo_fields = parse_memo(network, &memo);
             let note = TransactionNote {
                 value,
                 recipient: recipient.clone().unwrap(),
@@ -1051,17 +2124,12 @@ pub(crate) fn get_transactions(
                 } else {
                     Some(memo.clone())
                 },
+                reply_to: memo_fields.reply_to,
+                payment_label: memo_fields.label,
+                payment_message: memo_fields.message,
             };
 
-            // We establish change by all the following criteria holding true:
-            // * the recipient is to the same account
-            // * the recipient is shielded (since change will never be sent to the transparent pool).
-            // * the memo does not contain user text,
-            let is_change = to_account_id == from_account_id
-                && matches!(output_pool, PoolType::Shielded(_))
-                && Memo::from_bytes(&memo).is_ok_and(|m| !matches!(m, Memo::Text(_)));
-
-            if is_change {
+            if is_change(from_account_id, to_account_id, output_pool, &memo) {
                 tx.change.push(note);
             } else {
                 // A 'send to self' will appear as both incoming and outgoing.
@@ -1097,15 +2165,154 @@ pub(crate) fn get_transactions(
         }
     }
 
+    add_transparent_details(conn, network, &mut result, &transparent_txids)?;
+
     Ok(result)
 }
 
+/// Applies the requested presentation of change notes to transactions from [`get_transactions`],
+/// which lists them in [`crate::interop::Transaction::change`] and nets them into the balance delta.
+///
+/// The balance delta is left as is, so that it keeps matching the reported balances.
+pub(crate) fn present_change_notes(
+    transactions: &mut [crate::interop::Transaction],
+    change_notes: ChangeNotes,
+) {
+    if change_notes == ChangeNotes::Include {
+        return;
+    }
+
+    for tx in transactions.iter_mut() {
+        tx.change.clear();
+    }
+}
+
+/// Fills in the transparent inputs and outputs of the given transactions that have been downloaded in full.
+///
+/// Transactions that can't be decoded are left without transparent details rather than failing the listing.
+fn add_transparent_details(
+    conn: &Connection,
+    network: &Network,
+    transactions: &mut [crate::interop::Transaction],
+    transparent_txids: &HashSet<Vec<u8>>,
+) -> Result<(), Error> {
+    let mut get_raw = conn.prepare(GET_TRANSACTION_RAW)?;
+    let mut get_utxo_value = conn.prepare(GET_UTXO_VALUE)?;
+    let mut utxo_value = |txid: &[u8], index: u32| -> Result<Option<u64>, Error> {
+        Ok(get_utxo_value
+            .query_row(named_params! { ":txid": txid, ":index": index }, |row| {
+                row.get(0)
+            })
+            .optional()?)
+    };
+
+    // A transaction is listed once per account it involves, but only needs to be decoded once.
+    let mut details: HashMap<Vec<u8>, Option<(Vec<TransparentInput>, Vec<TransparentOutput>)>> =
+        HashMap::new();
+    for tx in transactions
+        .iter_mut()
+        .filter(|t| transparent_txids.contains(&t.txid))
+    {
+        if !details.contains_key(&tx.txid) {
+            let raw: Vec<u8> =
+                get_raw.query_row(named_params! { ":txid": tx.txid }, |row| row.get(0))?;
+            let entry = match decode_transaction(network, &raw) {
+                Ok(decoded) => {
+                    let mut inputs = Vec::with_capacity(decoded.transparent_inputs.len());
+                    for input in decoded.transparent_inputs {
+                        inputs.push(TransparentInput {
+                            value: utxo_value(&input.prevout_txid, input.prevout_index)?,
+                            prevout_txid: input.prevout_txid,
+                            prevout_index: input.prevout_index,
+                        });
+                    }
+                    let mut outputs = Vec::with_capacity(decoded.transparent_outputs.len());
+                    for (index, output) in decoded.transparent_outputs.into_iter().enumerate() {
+                        outputs.push(TransparentOutput {
+                            index: index as u32,
+                            owned: utxo_value(&tx.txid, index as u32)?.is_some(),
+                            address: output.address,
+                            value: output.value,
+                        });
+                    }
+                    Some((inputs, outputs))
+                }
+                Err(e) => {
+                    warn!(
+                        "Skipping transparent details of undecodable transaction {}: {}",
+                        TxId::from_bytes(tx.txid.clone().try_into().unwrap_or_default()),
+                        e
+                    );
+                    None
+                }
+            };
+            details.insert(tx.txid.clone(), entry);
+        }
+
+        if let Some(Some((inputs, outputs))) = details.get(&tx.txid) {
+            tx.transparent_inputs = inputs.clone();
+            tx.transparent_outputs = outputs.clone();
+        }
+    }
+
+    Ok(())
+}
+
+/// Regenerates the address that a shielded note was received with, from the diversifier recorded with the note.
+pub(crate) fn regenerate_address(
+    ufvk: Option<&UnifiedFullViewingKey>,
+    output_pool: PoolType,
+    diversifier: Vec<u8>,
+    network: &Network,
+) -> Option<String> {
+    match output_pool {
+        PoolType::SAPLING => ufvk.and_then(|k| {
+            k.sapling().and_then(|s| {
+                s.diversified_address(sapling::keys::Diversifier(diversifier.try_into().unwrap()))
+                    .map(|a| a.encode(network))
+            })
+        }),
+        PoolType::ORCHARD => ufvk.and_then(|k| {
+            k.orchard().map(|o| {
+                UnifiedAddress::from_receivers(
+                    Some(o.address(
+                        orchard::keys::Diversifier::from_bytes(diversifier.try_into().unwrap()),
+                        Scope::External,
+                    )),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .encode(network)
+            })
+        }),
+        _ => None,
+    }
+}
+
+/// Determines whether a note is implicit change.
+///
+/// We establish change by all the following criteria holding true:
+/// * the recipient is to the same account
+/// * the recipient is shielded (since change will never be sent to the transparent pool).
+/// * the memo does not contain user text,
+pub(crate) fn is_change(
+    from_account_id: Option<u32>,
+    to_account_id: Option<u32>,
+    output_pool: PoolType,
+    memo: &[u8],
+) -> bool {
+    to_account_id == from_account_id
+        && matches!(output_pool, PoolType::Shielded(_))
+        && Memo::from_bytes(memo).is_ok_and(|m| !matches!(m, Memo::Text(_)))
+}
+
 #[cfg(test)]
 mod tests {
     use zcash_client_backend::data_api::Account;
     use zcash_primitives::transaction::components::Amount;
 
-    use crate::test_constants::setup_test;
+    use crate::test_constants::{setup_local_test, setup_test};
 
     use super::*;
 
@@ -1138,6 +2345,8 @@ mod tests {
             None,
             setup.db_init.min_confirmations,
             false,
+            SyncOptions::default(),
+            PauseToken::default(),
             CancellationToken::new(),
         )
         .await
@@ -1168,24 +2377,375 @@ mod tests {
             info!("No summary found");
         }
 
-        let mut conn = Connection::open(setup.db_init.data_file).unwrap();
+        let mut conn = open_connection(setup.db_init.data_file).unwrap();
         let txs =
             get_transactions(&mut setup.db, &mut conn, &setup.network, None, None, None).unwrap();
         assert_eq!(txs.len(), 0);
     }
 
-    const COIN: u64 = 1_0000_0000;
+    #[tokio_shared_rt::test(flavor = "multi_thread")]
+    async fn test_sync_skip_transparent() {
+        let mut setup = setup_test().await;
+        setup.create_account().await.unwrap();
+        let taddrs_before = setup
+            .db
+            .data
+            .get_transparent_addresses_and_sync_heights()
+            .unwrap();
 
-    fn format_zec(value: impl Into<Amount>) -> String {
-        let value = i64::from(value.into());
-        let abs_value = value.unsigned_abs();
-        let abs_zec = abs_value / COIN;
-        let frac = abs_value % COIN;
-        let zec = if value.is_negative() {
-            -(abs_zec as i64)
-        } else {
-            abs_zec as i64
+        sync(
+            setup.server_uri.clone(),
+            &setup.data_file,
+            None,
+            setup.db_init.min_confirmations,
+            false,
+            SyncOptions {
+                skip_transparent: true,
+                ..Default::default()
+            },
+            PauseToken::default(),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        // No addresses should have been derived to fill the gap limit.
+        let taddrs_after = setup
+            .db
+            .data
+            .get_transparent_addresses_and_sync_heights()
+            .unwrap();
+        assert_eq!(taddrs_before.len(), taddrs_after.len());
+    }
+
+    #[tokio_shared_rt::test(flavor = "multi_thread")]
+    async fn test_sync_transparent_only_rejects_skip_transparent() {
+        let setup = setup_test().await;
+        let result = sync(
+            setup.server_uri.clone(),
+            &setup.data_file,
+            None,
+            setup.db_init.min_confirmations,
+            false,
+            SyncOptions {
+                mode: SyncMode::TransparentOnly,
+                skip_transparent: true,
+                verification_server: None,
+                max_cached_actions: None,
+                transparent_start_height: None,
+            },
+            PauseToken::default(),
+            CancellationToken::new(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[tokio_shared_rt::test(flavor = "multi_thread")]
+    async fn test_backfill_prevout_values_remembers_failures() {
+        let mut setup = setup_test().await;
+        let conn = open_connection(&setup.data_file).unwrap();
+        let candidates = |conn: &Connection| {
+            conn.prepare(GET_PREVOUT_BACKFILL_CANDIDATES)
+                .unwrap()
+                .query_map([], |r| r.get::<_, Vec<u8>>(0))
+                .unwrap()
+                .count()
+        };
+
+        // A mined transaction whose fee is unknown, and which can't even be parsed.
+        conn.execute(
+            "INSERT INTO transactions (txid, block, raw) VALUES (?, ?, ?)",
+            ([1u8; 32], 1, [1u8, 2, 3]),
+        )
+        .unwrap();
+        assert_eq!(1, candidates(&conn));
+
+        // The unparseable transaction doesn't fail the sync, and isn't tried again.
+        backfill_prevout_values(
+            &mut setup.client,
+            &mut setup.db,
+            &conn,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(0, candidates(&conn));
+    }
+
+    #[test]
+    fn test_pending_reorgs() {
+        let setup = setup_local_test();
+        let conn = open_connection(&setup.data_file).unwrap();
+        let [a, b, c] = [1, 2, 3].map(|i| TxId::from_bytes([i; 32]));
+
+        record_pending_reorg(&conn, BlockHeight::from(100), &[a, b]).unwrap();
+        record_pending_reorg(&conn, BlockHeight::from(90), &[c]).unwrap();
+
+        // A transaction orphaned again by a later reorg waits for the later one to be rescanned.
+        record_pending_reorg(&conn, BlockHeight::from(110), &[b]).unwrap();
+
+        assert!(get_rescanned_reorged_txids(&conn, BlockHeight::from(89))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            HashSet::from([a, c]),
+            get_rescanned_reorged_txids(&conn, BlockHeight::from(100)).unwrap()
+        );
+        assert_eq!(
+            HashSet::from([a, b, c]),
+            get_rescanned_reorged_txids(&conn, BlockHeight::from(110)).unwrap()
+        );
+
+        // They are kept in the wallet, so a later sync can report them.
+        let reopened = open_connection(&setup.data_file).unwrap();
+        assert_eq!(
+            3,
+            get_rescanned_reorged_txids(&reopened, BlockHeight::from(110))
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn test_transparent_start_height() {
+        let birthday = Some(BlockHeight::from(1_000));
+
+        // Without an explicit start, the search resumes where it left off, but not before the birthday.
+        assert_eq!(
+            BlockHeight::from(1_500),
+            transparent_start_height(None, BlockHeight::from(1_500), birthday)
+        );
+        assert_eq!(
+            BlockHeight::from(1_000),
+            transparent_start_height(None, BlockHeight::from(0), birthday)
+        );
+        assert_eq!(
+            BlockHeight::from(10),
+            transparent_start_height(None, BlockHeight::from(10), None)
+        );
+
+        // An explicit start can go back before both, e.g. for addresses imported from another wallet.
+        assert_eq!(
+            BlockHeight::from(500),
+            transparent_start_height(Some(500), BlockHeight::from(1_500), birthday)
+        );
+
+        // But it never skips blocks that haven't been searched yet.
+        assert_eq!(
+            BlockHeight::from(1_500),
+            transparent_start_height(Some(2_000), BlockHeight::from(1_500), birthday)
+        );
+    }
+
+    #[test]
+    fn test_order_for_fast_recovery() {
+        let ranges = vec![
+            ScanRange::from_parts(
+                BlockHeight::from_u32(100_000)..BlockHeight::from_u32(100_010),
+                ScanPriority::ChainTip,
+            ),
+            ScanRange::from_parts(
+                BlockHeight::from_u32(50_000)..BlockHeight::from_u32(75_000),
+                ScanPriority::Historic,
+            ),
+        ];
+
+        let ordered = order_for_fast_recovery(ranges);
+        let bounds = ordered
+            .iter()
+            .map(|r| {
+                (
+                    u32::from(r.block_range().start),
+                    u32::from(r.block_range().end),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bounds,
+            vec![
+                (100_000, 100_010),
+                (65_000, 75_000),
+                (55_000, 65_000),
+                (50_000, 55_000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_deferred_when_metered() {
+        let range = |priority| {
+            ScanRange::from_parts(
+                BlockHeight::from_u32(100_000)..BlockHeight::from_u32(100_010),
+                priority,
+            )
+        };
+        assert!(is_deferred_when_metered(&range(ScanPriority::Historic)));
+        assert!(is_deferred_when_metered(&range(ScanPriority::FoundNote)));
+        assert!(!is_deferred_when_metered(&range(ScanPriority::ChainTip)));
+        assert!(!is_deferred_when_metered(&range(ScanPriority::Verify)));
+    }
+
+    #[test]
+    fn test_remaining_blocks() {
+        let range = |start: u32, end: u32, priority| {
+            ScanRange::from_parts(
+                BlockHeight::from_u32(start)..BlockHeight::from_u32(end),
+                priority,
+            )
+        };
+        assert!(remaining_blocks(&[]).is_empty());
+        assert_eq!(
+            vec![
+                RemainingBlocks {
+                    priority: interop::ScanPriority::Verify,
+                    blocks: 10,
+                },
+                RemainingBlocks {
+                    priority: interop::ScanPriority::Historic,
+                    blocks: 300,
+                },
+            ],
+            remaining_blocks(&[
+                range(1000, 1100, ScanPriority::Historic),
+                range(2000, 2010, ScanPriority::Verify),
+                range(1100, 1300, ScanPriority::Historic),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_count_shielded_outputs() {
+        use zcash_client_backend::proto::compact_formats::{
+            CompactOrchardAction, CompactSaplingOutput, CompactTx,
+        };
+
+        let tx = |outputs: usize, actions: usize| CompactTx {
+            outputs: vec![CompactSaplingOutput::default(); outputs],
+            actions: vec![CompactOrchardAction::default(); actions],
+            ..Default::default()
+        };
+        let blocks = [
+            CompactBlock {
+                vtx: vec![tx(2, 0), tx(1, 3)],
+                ..Default::default()
+            },
+            CompactBlock {
+                vtx: vec![tx(0, 4)],
+                ..Default::default()
+            },
+        ];
+        assert_eq!((0, 0), count_shielded_outputs(&[]));
+        assert_eq!((3, 7), count_shielded_outputs(&blocks));
+    }
+
+    #[test]
+    fn test_check_subtree_roots() {
+        let roots = |hashes: &[u32]| {
+            hashes
+                .iter()
+                .enumerate()
+                .map(|(i, h)| {
+                    CommitmentTreeRoot::from_parts(BlockHeight::from_u32(i as u32 * 1000), *h)
+                })
+                .collect::<Vec<_>>()
         };
-        format!("{:3}.{:08} ZEC", zec, frac)
+        assert_eq!(
+            3,
+            check_subtree_roots("Sapling", &roots(&[1, 2, 3]), &roots(&[1, 2, 3])).unwrap()
+        );
+
+        // A server that is behind has fewer roots, which is not a disagreement,
+        // but the roots it can't vouch for aren't verified.
+        assert_eq!(
+            2,
+            check_subtree_roots("Sapling", &roots(&[1, 2, 3]), &roots(&[1, 2])).unwrap()
+        );
+        assert_eq!(
+            0,
+            check_subtree_roots("Sapling", &roots(&[1, 2, 3]), &roots(&[])).unwrap()
+        );
+        assert_eq!(
+            2,
+            check_subtree_roots("Sapling", &roots(&[1, 2]), &roots(&[1, 2, 3])).unwrap()
+        );
+
+        assert!(matches!(
+            check_subtree_roots("Sapling", &roots(&[1, 2, 3]), &roots(&[1, 5, 3])),
+            Err(Error::SubtreeRootMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_continual_sync_retry_delay() {
+        assert_eq!(CONTINUAL_SYNC_RETRY_DELAY, continual_sync_retry_delay(1));
+        assert_eq!(
+            CONTINUAL_SYNC_RETRY_DELAY * 4,
+            continual_sync_retry_delay(3)
+        );
+        assert_eq!(
+            MAX_CONTINUAL_SYNC_RETRY_DELAY,
+            continual_sync_retry_delay(100)
+        );
+    }
+
+    #[test]
+    fn test_is_recoverable() {
+        assert!(is_recoverable(&Error::TonicStatus(Status::unavailable(
+            "down for maintenance"
+        ))));
+        assert!(!is_recoverable(&Error::Canceled));
+        assert!(!is_recoverable(&Error::InvalidArgument("bad".to_string())));
+    }
+
+    #[test]
+    fn test_present_change_notes() {
+        let tx = crate::interop::Transaction {
+            account_id: 0,
+            account_uuid: Vec::new(),
+            txid: vec![1],
+            block_time: None,
+            mined_height: None,
+            expiry_height: None,
+            blocks_until_expiry: None,
+            expired_unmined: false,
+            account_balance_delta: -1_500,
+            fee: None,
+            version: None,
+            size: None,
+            consensus_branch_id: None,
+            outgoing_recovered: true,
+            label: None,
+            tags: Vec::new(),
+            outgoing: Vec::new(),
+            incoming: Vec::new(),
+            change: vec![TransactionNote {
+                value: 8_500,
+                recipient: "change".to_string(),
+                pool: Pool::Orchard,
+                memo: None,
+                reply_to: None,
+                payment_label: None,
+                payment_message: None,
+            }],
+            transparent_inputs: Vec::new(),
+            transparent_outputs: Vec::new(),
+        };
+
+        let mut included = [tx.clone()];
+        present_change_notes(&mut included, ChangeNotes::Include);
+        assert_eq!(1, included[0].change.len());
+        assert_eq!(-1_500, included[0].account_balance_delta);
+
+        for change_notes in [ChangeNotes::Hide, ChangeNotes::Net] {
+            let mut presented = [tx.clone()];
+            present_change_notes(&mut presented, change_notes);
+            assert!(presented[0].change.is_empty());
+            assert_eq!(-1_500, presented[0].account_balance_delta);
+        }
+    }
+
+    fn format_zec(value: impl Into<Amount>) -> String {
+        format!("{} ZEC", crate::amount::format_zec(i64::from(value.into())))
     }
 }
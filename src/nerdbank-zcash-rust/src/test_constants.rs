@@ -4,16 +4,17 @@ use secrecy::{Secret, SecretVec};
 use testdir::testdir;
 use tokio_util::sync::CancellationToken;
 use tonic::transport::Channel;
-use zcash_client_backend::data_api::Account;
+use zcash_client_backend::data_api::{Account, AccountBirthday};
 use zcash_client_backend::keys::UnifiedSpendingKey;
 use zcash_client_backend::proto::service::{
-    self, compact_tx_streamer_client::CompactTxStreamerClient, LightdInfo,
+    self, compact_tx_streamer_client::CompactTxStreamerClient, LightdInfo, TreeState,
 };
 use zcash_client_sqlite::AccountId;
 use zcash_primitives::consensus::Network;
 
 use crate::error::Error;
-use crate::interop::SyncUpdateData;
+use crate::interop::{SyncOptions, SyncUpdateData};
+use crate::pause::PauseToken;
 use crate::resilience::webrequest_with_retry;
 use crate::sync::sync;
 use crate::{backing_store::Db, grpc::get_client, interop::DbInit, lightclient::parse_network};
@@ -44,6 +45,66 @@ pub(crate) struct TestSetup {
     pub(crate) server_uri: Uri,
 }
 
+/// A wallet for tests that only exercise the wallet database, and so need no light wallet server.
+pub(crate) struct LocalTestSetup {
+    pub(crate) network: Network,
+    pub(crate) data_file: std::path::PathBuf,
+    pub(crate) db_init: DbInit,
+    pub(crate) db: Db,
+}
+
+/// The birthday height given to accounts created by [`LocalTestSetup::create_account`].
+pub(crate) const LOCAL_BIRTHDAY_HEIGHT: u64 = 2_500_000;
+
+pub(crate) fn setup_local_test() -> LocalTestSetup {
+    let network = Network::TestNetwork;
+    let data_file = testdir!().join("wallet.sqlite");
+    let db = Db::init(&data_file, network).unwrap();
+    LocalTestSetup {
+        network,
+        data_file: data_file.clone(),
+        db_init: DbInit {
+            data_file: data_file.into_os_string().into_string().unwrap(),
+            network: network.into(),
+            min_confirmations: MIN_CONFIRMATIONS,
+        },
+        db,
+    }
+}
+
+impl LocalTestSetup {
+    /// Adds an account whose birthday tree state is made up,
+    /// which is fine as long as the test doesn't scan any blocks.
+    pub fn create_account(
+        &mut self,
+    ) -> Result<(Secret<Vec<u8>>, u64, AccountId, UnifiedSpendingKey), Error> {
+        let seed: secrecy::Secret<Vec<u8>> = SecretVec::new(
+            Mnemonic::<English>::generate(Count::Words24)
+                .to_seed("")
+                .to_vec(),
+        );
+
+        let account =
+            self.db
+                .add_account_with_birthday(&seed, zip32::AccountId::ZERO, &local_birthday())?;
+        Ok((seed, LOCAL_BIRTHDAY_HEIGHT, account.0.id(), account.1))
+    }
+}
+
+/// The made-up birthday of the accounts that [`LocalTestSetup::create_account`] adds.
+pub(crate) fn local_birthday() -> AccountBirthday {
+    AccountBirthday::from_treestate(
+        TreeState {
+            network: "test".to_string(),
+            height: LOCAL_BIRTHDAY_HEIGHT - 1,
+            hash: "00".repeat(32),
+            ..Default::default()
+        },
+        None,
+    )
+    .unwrap()
+}
+
 pub(crate) async fn setup_test() -> TestSetup {
     let wallet_dir = testdir!();
     let server_info = webrequest_with_retry(
@@ -102,6 +163,8 @@ impl TestSetup {
             None,
             self.db_init.min_confirmations,
             false,
+            SyncOptions::default(),
+            PauseToken::default(),
             CancellationToken::new(),
         )
         .await
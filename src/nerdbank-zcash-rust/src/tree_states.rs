@@ -0,0 +1,174 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use http::Uri;
+use tonic::{transport::Channel, Status};
+use zcash_client_backend::proto::service::{
+    self, compact_tx_streamer_client::CompactTxStreamerClient, TreeState,
+};
+use zcash_primitives::consensus::Network;
+
+/// The most tree states to keep. Each takes up a few kilobytes.
+const MAX_CACHED_TREE_STATES: usize = 1_000;
+
+lazy_static! {
+    static ref TREE_STATES: Mutex<TreeStateCache> = Mutex::new(TreeStateCache::default());
+}
+
+/// Identifies a cached tree state by the server it came from, its chain and its height.
+type TreeStateKey = (String, &'static str, u64);
+
+/// The tree states fetched recently, so that each is only requested from the server once per session.
+///
+/// Tree states are kept per server, so that one server is never trusted with another's answer.
+#[derive(Default)]
+struct TreeStateCache {
+    states: HashMap<TreeStateKey, TreeState>,
+    /// The keys of `states`, oldest first.
+    order: VecDeque<TreeStateKey>,
+}
+
+impl TreeStateCache {
+    /// Gets the tree state at the given height, if it is cached and is for the expected block.
+    fn get(
+        &self,
+        server: &str,
+        chain: &'static str,
+        height: u64,
+        block_hash: &str,
+    ) -> Option<TreeState> {
+        self.states
+            .get(&(server.to_string(), chain, height))
+            .filter(|s| s.hash == block_hash)
+            .cloned()
+    }
+
+    fn insert(&mut self, server: &str, chain: &'static str, tree_state: TreeState) {
+        let key = (server.to_string(), chain, tree_state.height);
+        if self.states.insert(key.clone(), tree_state).is_none() {
+            self.order.push_back(key);
+        }
+
+        while self.order.len() > MAX_CACHED_TREE_STATES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.states.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Gets the note commitment tree state as of the end of the given block,
+/// reusing the one fetched from the same server earlier in this session if there is one.
+///
+/// `block_hash` is the hash of the block at `height` (as recorded in the `prev_hash` of the block after it), when known.
+/// A cached tree state is only reused when it is for that block, so one for a block that was since reorged away is fetched again.
+/// Without a hash, the tree state is always fetched.
+pub(crate) async fn get_tree_state(
+    client: &mut CompactTxStreamerClient<Channel>,
+    server_uri: &Uri,
+    network: &Network,
+    height: u64,
+    block_hash: Option<&[u8]>,
+) -> Result<TreeState, Status> {
+    let server = server_uri.to_string();
+    let chain = chain_name(network);
+
+    // Tree states identify their block by its hash in display order, which is the reverse of the byte order.
+    let block_hash = block_hash.map(|h| {
+        h.iter()
+            .rev()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    });
+
+    if let Some(block_hash) = block_hash {
+        if let Some(tree_state) =
+            TREE_STATES
+                .lock()
+                .unwrap()
+                .get(&server, chain, height, &block_hash)
+        {
+            return Ok(tree_state);
+        }
+    }
+
+    let tree_state = fetch_tree_state(client, height).await?;
+    TREE_STATES
+        .lock()
+        .unwrap()
+        .insert(&server, chain, tree_state.clone());
+    Ok(tree_state)
+}
+
+/// Fetches the note commitment tree state as of the end of the given block from the server, bypassing the cache.
+pub(crate) async fn fetch_tree_state(
+    client: &mut CompactTxStreamerClient<Channel>,
+    height: u64,
+) -> Result<TreeState, Status> {
+    Ok(client
+        .get_tree_state(service::BlockId {
+            height,
+            ..Default::default()
+        })
+        .await?
+        .into_inner())
+}
+
+/// Adds a tree state that was obtained some other way (e.g. persisted from an earlier session) to the cache
+/// of the given server.
+pub(crate) fn remember_tree_state(server_uri: &Uri, network: &Network, tree_state: TreeState) {
+    TREE_STATES
+        .lock()
+        .unwrap()
+        .insert(&server_uri.to_string(), chain_name(network), tree_state);
+}
+
+fn chain_name(network: &Network) -> &'static str {
+    match network {
+        Network::MainNetwork => "main",
+        Network::TestNetwork => "test",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree_state(height: u64, hash: &str) -> TreeState {
+        TreeState {
+            network: "test".to_string(),
+            height,
+            hash: hash.to_string(),
+            ..Default::default()
+        }
+    }
+
+    const SERVER: &str = "https://a.example/";
+
+    #[test]
+    fn test_tree_state_cache() {
+        let mut cache = TreeStateCache::default();
+        assert!(cache.get(SERVER, "test", 100, "aa").is_none());
+
+        cache.insert(SERVER, "test", tree_state(100, "aa"));
+        assert_eq!("aa", cache.get(SERVER, "test", 100, "aa").unwrap().hash);
+        assert!(cache.get(SERVER, "main", 100, "aa").is_none());
+
+        // Another server's tree state is never served.
+        assert!(cache.get("https://b.example/", "test", 100, "aa").is_none());
+
+        // A tree state for a block that was reorged away is not reused.
+        assert!(cache.get(SERVER, "test", 100, "bb").is_none());
+        cache.insert(SERVER, "test", tree_state(100, "bb"));
+        assert_eq!("bb", cache.get(SERVER, "test", 100, "bb").unwrap().hash);
+        assert_eq!(1, cache.order.len());
+
+        for height in 0..MAX_CACHED_TREE_STATES as u64 {
+            cache.insert(SERVER, "test", tree_state(1_000 + height, "cc"));
+        }
+        assert!(cache.get(SERVER, "test", 100, "bb").is_none());
+        assert_eq!(MAX_CACHED_TREE_STATES, cache.states.len());
+    }
+}
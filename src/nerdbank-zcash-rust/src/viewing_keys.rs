@@ -0,0 +1,423 @@
+use rusqlite::{named_params, Connection};
+use sapling::zip32::DiversifiableFullViewingKey;
+use zcash_client_backend::data_api::WalletRead;
+use zcash_client_sqlite::AccountId;
+use zcash_keys::{
+    address::Address,
+    keys::{UnifiedFullViewingKey, UnifiedIncomingViewingKey},
+};
+use zcash_primitives::{
+    consensus::Network,
+    legacy::keys::{AccountPubKey, NonHardenedChildIndex},
+    zip32::{DiversifierIndex, Scope},
+};
+
+use crate::{
+    backing_store::Db, error::Error, interop::Pool, sql_statements::GET_ACCOUNT_ADDRESSES,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyScope {
+    /// The scope of addresses that are given out to receive payments.
+    External,
+    /// The scope of addresses the wallet sends change and other internal transfers to.
+    Internal,
+}
+
+impl From<Scope> for KeyScope {
+    fn from(scope: Scope) -> Self {
+        match scope {
+            Scope::External => KeyScope::External,
+            Scope::Internal => KeyScope::Internal,
+        }
+    }
+}
+
+/// Describes how a viewing key produced an address.
+#[derive(Debug, Clone)]
+pub struct AddressOwnership {
+    /// The pool of the receiver that the viewing key recognized.
+    pub pool: Pool,
+    pub scope: KeyScope,
+    /// The 11-byte diversifier index the address was derived with.
+    pub diversifier_index: Vec<u8>,
+}
+
+/// An address the wallet has stored for an account, which the account's viewing key does not derive.
+#[derive(Debug, Clone)]
+pub struct AddressMismatch {
+    /// The 11-byte diversifier index the address is stored with.
+    pub diversifier_index: Vec<u8>,
+    /// The address as it is stored.
+    pub address: String,
+    /// The pools whose receivers differ from those the viewing key derives at the diversifier index.
+    /// Empty when the stored address can't be decoded at all.
+    pub pools: Vec<Pool>,
+}
+
+/// Assembles a unified full viewing key from the viewing keys of its individual pools.
+///
+/// The keys are expected in their raw encodings:
+/// * Transparent: the 65-byte account-level public key (the chain code followed by the compressed public key).
+/// * Sapling: the 128-byte diversifiable full viewing key.
+/// * Orchard: the 96-byte full viewing key.
+///
+/// At least one shielded key is required.
+/// Nothing ties the keys of different pools to one another, so there is no way to check that
+/// they came from the same account. That is up to the caller.
+pub fn build_ufvk(
+    transparent: Option<&[u8]>,
+    sapling: Option<&[u8]>,
+    orchard: Option<&[u8]>,
+) -> Result<UnifiedFullViewingKey, Error> {
+    let transparent = transparent
+        .map(|bytes| {
+            let bytes: &[u8; 65] = bytes.try_into().map_err(|_| {
+                Error::InvalidArgument(
+                    "A transparent account public key must be 65 bytes.".to_string(),
+                )
+            })?;
+            AccountPubKey::deserialize(bytes).map_err(|_| {
+                Error::InvalidArgument("Invalid transparent account public key.".to_string())
+            })
+        })
+        .transpose()?;
+    let sapling = sapling
+        .map(|bytes| {
+            let bytes: &[u8; 128] = bytes.try_into().map_err(|_| {
+                Error::InvalidArgument("A Sapling full viewing key must be 128 bytes.".to_string())
+            })?;
+            DiversifiableFullViewingKey::from_bytes(bytes).ok_or_else(|| {
+                Error::InvalidArgument("Invalid Sapling full viewing key.".to_string())
+            })
+        })
+        .transpose()?;
+    let orchard = orchard
+        .map(|bytes| {
+            let bytes: &[u8; 96] = bytes.try_into().map_err(|_| {
+                Error::InvalidArgument("An Orchard full viewing key must be 96 bytes.".to_string())
+            })?;
+            orchard::keys::FullViewingKey::from_bytes(bytes).ok_or_else(|| {
+                Error::InvalidArgument("Invalid Orchard full viewing key.".to_string())
+            })
+        })
+        .transpose()?;
+
+    if sapling.is_none() && orchard.is_none() {
+        return Err(Error::InvalidArgument(
+            "A unified full viewing key requires a Sapling or Orchard key.".to_string(),
+        ));
+    }
+
+    UnifiedFullViewingKey::new(transparent, sapling, orchard).ok_or_else(|| {
+        Error::InvalidArgument("The keys do not form a unified full viewing key.".to_string())
+    })
+}
+
+/// Gets the unified incoming viewing key of an account.
+///
+/// This key can detect payments to the account and see their amounts and memos,
+/// but not which of them have been spent, or anything the account has sent.
+pub fn get_uivk(db: &Db, account_id: AccountId) -> Result<UnifiedIncomingViewingKey, Error> {
+    Ok(db
+        .data
+        .get_unified_full_viewing_keys()?
+        .remove(&account_id)
+        .ok_or_else(|| Error::InvalidArgument("No such account.".to_string()))?
+        .to_unified_incoming_viewing_key())
+}
+
+/// Re-derives every address stored for an account from its UFVK and the diversifier index it is stored with,
+/// and reports the addresses that don't match.
+///
+/// The wallet only ever stores addresses it derived itself, so a mismatch means the database was corrupted
+/// or tampered with, and that payments to the address may not reach the account.
+pub fn audit_addresses(
+    db: &Db,
+    conn: &Connection,
+    network: &Network,
+    account_id: AccountId,
+) -> Result<Vec<AddressMismatch>, Error> {
+    let ufvk = db
+        .data
+        .get_unified_full_viewing_keys()?
+        .remove(&account_id)
+        .ok_or_else(|| Error::InvalidArgument("No such account.".to_string()))?;
+
+    let mut mismatches = Vec::new();
+    let mut stmt = conn.prepare(GET_ACCOUNT_ADDRESSES)?;
+    let mut rows = stmt.query(named_params! { ":account_id": u32::from(account_id) })?;
+    while let Some(row) = rows.next()? {
+        let mut diversifier_index: Vec<u8> = row.get("diversifier_index_be")?;
+        diversifier_index.reverse();
+        let address: String = row.get("address")?;
+        let pools = match mismatched_receivers(network, &ufvk, &diversifier_index, &address) {
+            Some(pools) if pools.is_empty() => continue,
+            Some(pools) => pools,
+            None => Vec::new(),
+        };
+        mismatches.push(AddressMismatch {
+            diversifier_index,
+            address,
+            pools,
+        });
+    }
+
+    Ok(mismatches)
+}
+
+/// Gets the pools whose receivers in a unified address differ from those the viewing key derives
+/// at the given diversifier index, or `None` if the address or index can't be decoded.
+fn mismatched_receivers(
+    network: &Network,
+    ufvk: &UnifiedFullViewingKey,
+    diversifier_index: &[u8],
+    address: &str,
+) -> Option<Vec<Pool>> {
+    let index: [u8; 11] = diversifier_index.try_into().ok()?;
+    let ua = match Address::decode(network, address)? {
+        Address::Unified(ua) => ua,
+        _ => return None,
+    };
+
+    let mut pools = Vec::new();
+    if let Some(receiver) = ua.orchard() {
+        let expected = ufvk.orchard().map(|fvk| {
+            fvk.address_at(
+                orchard::keys::DiversifierIndex::from(index),
+                orchard::keys::Scope::External,
+            )
+        });
+        if expected.as_ref() != Some(receiver) {
+            pools.push(Pool::Orchard);
+        }
+    }
+    if let Some(receiver) = ua.sapling() {
+        let expected = ufvk
+            .sapling()
+            .and_then(|dfvk| dfvk.address(DiversifierIndex::from(index)));
+        if expected.as_ref() != Some(receiver) {
+            pools.push(Pool::Sapling);
+        }
+    }
+    if let Some(receiver) = ua.transparent() {
+        // Transparent receivers are derived at the diversifier index, which must fit in a non-hardened child index.
+        let expected = ufvk.transparent().and_then(|key| {
+            let child = u32::try_from(DiversifierIndex::from(index)).ok()?;
+            key.derive_external_ivk()
+                .ok()?
+                .derive_address(NonHardenedChildIndex::from_index(child)?)
+                .ok()
+        });
+        if expected.as_ref() != Some(receiver) {
+            pools.push(Pool::Transparent);
+        }
+    }
+
+    Some(pools)
+}
+
+/// Finds the diversifier index (and scope) that a viewing key would derive a shielded address with.
+///
+/// The viewing key may be a UFVK or a UIVK. A UIVK can only recognize external addresses.
+/// The Orchard receiver of a unified address is checked before its Sapling receiver.
+/// Returns `None` if the address did not come from the viewing key.
+pub fn decrypt_diversifier(
+    network: &Network,
+    viewing_key: &str,
+    address: &str,
+) -> Result<Option<AddressOwnership>, Error> {
+    let (sapling_receiver, orchard_receiver) =
+        match Address::decode(network, address).ok_or(Error::InvalidAddress)? {
+            Address::Sapling(a) => (Some(a), None),
+            Address::Unified(ua) => (ua.sapling().copied(), ua.orchard().copied()),
+            _ => {
+                return Err(Error::InvalidArgument(
+                    "Only shielded addresses have a diversifier.".to_string(),
+                ))
+            }
+        };
+
+    let ownership = |pool, scope: Scope, index: &[u8; 11]| {
+        Some(AddressOwnership {
+            pool,
+            scope: scope.into(),
+            diversifier_index: index.to_vec(),
+        })
+    };
+
+    if let Ok(ufvk) = UnifiedFullViewingKey::decode(network, viewing_key) {
+        if let (Some(fvk), Some(receiver)) = (ufvk.orchard(), orchard_receiver.as_ref()) {
+            if let Some(scope) = fvk.scope_for_address(receiver) {
+                if let Some(index) = fvk.to_ivk(scope).diversifier_index(receiver) {
+                    let scope = match scope {
+                        orchard::keys::Scope::External => Scope::External,
+                        orchard::keys::Scope::Internal => Scope::Internal,
+                    };
+                    return Ok(ownership(Pool::Orchard, scope, index.as_bytes()));
+                }
+            }
+        }
+        if let (Some(dfvk), Some(receiver)) = (ufvk.sapling(), sapling_receiver.as_ref()) {
+            if let Some((index, scope)) = dfvk.decrypt_diversifier(receiver) {
+                return Ok(ownership(Pool::Sapling, scope, index.as_bytes()));
+            }
+        }
+
+        return Ok(None);
+    }
+
+    let uivk = UnifiedIncomingViewingKey::decode(network, viewing_key).map_err(|_| {
+        Error::InvalidArgument("The viewing key is not a valid UFVK or UIVK.".to_string())
+    })?;
+    if let (Some(ivk), Some(receiver)) = (uivk.orchard(), orchard_receiver.as_ref()) {
+        if let Some(index) = ivk.diversifier_index(receiver) {
+            return Ok(ownership(Pool::Orchard, Scope::External, index.as_bytes()));
+        }
+    }
+    if let (Some(ivk), Some(receiver)) = (uivk.sapling(), sapling_receiver.as_ref()) {
+        if let Some(index) = ivk.decrypt_diversifier(receiver) {
+            return Ok(ownership(Pool::Sapling, Scope::External, index.as_bytes()));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+    use zcash_client_backend::{address::UnifiedAddress, keys::UnifiedSpendingKey};
+
+    use crate::{backing_store::open_connection, test_constants::setup_local_test};
+
+    use super::*;
+
+    #[test]
+    fn test_build_ufvk() {
+        let network = Network::TestNetwork;
+        let usk =
+            UnifiedSpendingKey::from_seed(&network, &[0; 32], zip32::AccountId::ZERO).unwrap();
+        let expected = usk.to_unified_full_viewing_key();
+        let transparent = expected.transparent().unwrap().serialize();
+        let sapling = expected.sapling().unwrap().to_bytes();
+        let orchard = expected.orchard().unwrap().to_bytes();
+
+        let ufvk = build_ufvk(Some(&transparent), Some(&sapling), Some(&orchard)).unwrap();
+        assert_eq!(expected.encode(&network), ufvk.encode(&network));
+
+        let orchard_only = build_ufvk(None, None, Some(&orchard)).unwrap();
+        assert!(orchard_only.sapling().is_none());
+        assert!(orchard_only.orchard().is_some());
+
+        assert!(build_ufvk(Some(&transparent), None, None).is_err());
+        assert!(build_ufvk(None, Some(&sapling[..64]), None).is_err());
+    }
+
+    #[test]
+    fn test_get_uivk() {
+        let mut setup = setup_local_test();
+        let (_, _, account_id, usk) = setup.create_account().unwrap();
+        let expected = usk
+            .to_unified_full_viewing_key()
+            .to_unified_incoming_viewing_key();
+        assert_eq!(
+            expected.encode(&setup.network),
+            get_uivk(&setup.db, account_id)
+                .unwrap()
+                .encode(&setup.network)
+        );
+        assert!(get_uivk(&setup.db, AccountId::from(u32::from(account_id) + 1)).is_err());
+    }
+
+    #[test]
+    fn test_audit_addresses() {
+        let mut setup = setup_local_test();
+        let (_, _, account_id, _) = setup.create_account().unwrap();
+        let conn = open_connection(&setup.data_file).unwrap();
+        assert!(
+            audit_addresses(&setup.db, &conn, &setup.network, account_id)
+                .unwrap()
+                .is_empty()
+        );
+
+        // Replace the account's address with one from another key.
+        let other = UnifiedSpendingKey::from_seed(&setup.network, &[1; 32], zip32::AccountId::ZERO)
+            .unwrap()
+            .to_unified_full_viewing_key();
+        let forged = UnifiedAddress::from_receivers(
+            Some(
+                other
+                    .orchard()
+                    .unwrap()
+                    .address_at(0u32, orchard::keys::Scope::External),
+            ),
+            None,
+            None,
+        )
+        .unwrap()
+        .encode(&setup.network);
+        conn.execute(
+            "UPDATE addresses SET address = ? WHERE account_id = ?",
+            rusqlite::params![forged, u32::from(account_id)],
+        )
+        .unwrap();
+
+        let mismatches = audit_addresses(&setup.db, &conn, &setup.network, account_id).unwrap();
+        assert_eq!(1, mismatches.len());
+        assert_eq!(forged, mismatches[0].address);
+        assert_matches!(mismatches[0].pools[..], [Pool::Orchard]);
+    }
+
+    #[test]
+    fn test_decrypt_diversifier() {
+        let network = Network::TestNetwork;
+        let ufvk = UnifiedSpendingKey::from_seed(&network, &[0; 32], zip32::AccountId::ZERO)
+            .unwrap()
+            .to_unified_full_viewing_key();
+        let encoded_ufvk = ufvk.encode(&network);
+        let encoded_uivk = ufvk.to_unified_incoming_viewing_key().encode(&network);
+
+        let (index, sapling_address) = ufvk.sapling().unwrap().default_address();
+        let sapling_address = Address::Sapling(sapling_address).encode(&network);
+        for key in [&encoded_ufvk, &encoded_uivk] {
+            let ownership = decrypt_diversifier(&network, key, &sapling_address)
+                .unwrap()
+                .unwrap();
+            assert_matches!(ownership.pool, Pool::Sapling);
+            assert_eq!(KeyScope::External, ownership.scope);
+            assert_eq!(index.as_bytes().to_vec(), ownership.diversifier_index);
+        }
+
+        let change_address = UnifiedAddress::from_receivers(
+            Some(
+                ufvk.orchard()
+                    .unwrap()
+                    .address_at(3u32, orchard::keys::Scope::Internal),
+            ),
+            None,
+            None,
+        )
+        .unwrap()
+        .encode(&network);
+        let ownership = decrypt_diversifier(&network, &encoded_ufvk, &change_address)
+            .unwrap()
+            .unwrap();
+        assert_matches!(ownership.pool, Pool::Orchard);
+        assert_eq!(KeyScope::Internal, ownership.scope);
+        assert_eq!(3, ownership.diversifier_index[0]);
+        assert!(
+            decrypt_diversifier(&network, &encoded_uivk, &change_address)
+                .unwrap()
+                .is_none()
+        );
+
+        let other = UnifiedSpendingKey::from_seed(&network, &[1; 32], zip32::AccountId::ZERO)
+            .unwrap()
+            .to_unified_full_viewing_key()
+            .encode(&network);
+        assert!(decrypt_diversifier(&network, &other, &sapling_address)
+            .unwrap()
+            .is_none());
+    }
+}
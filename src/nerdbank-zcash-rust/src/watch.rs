@@ -0,0 +1,269 @@
+use std::collections::HashSet;
+
+use futures_util::TryStreamExt;
+use http::Uri;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tonic::{transport::Channel, Status};
+use zcash_client_backend::{
+    encoding::AddressCodec,
+    proto::service::{
+        self, compact_tx_streamer_client::CompactTxStreamerClient, BlockId, BlockRange, Empty,
+        TransparentAddressBlockFilter,
+    },
+};
+use zcash_primitives::{
+    consensus::{BlockHeight, BranchId, Network},
+    legacy::TransparentAddress,
+    transaction::{Transaction, TxId},
+};
+
+use crate::{
+    error::Error, grpc::get_client, interop::AddressWatcher, resilience::webrequest_with_retry,
+};
+
+/// Watches the blockchain and mempool for transactions involving the given transparent address,
+/// which need not belong to the wallet.
+///
+/// Transactions mined in any block after this function is called are reported along with their height.
+/// Transactions in the mempool are reported (without a height) if they send funds to the address.
+/// Spends from the address cannot be recognized until they are mined,
+/// since that requires knowing the outputs they consume.
+///
+/// This function only returns when cancellation is signaled or an error occurs.
+pub async fn watch_address(
+    uri: Uri,
+    network: Network,
+    address: TransparentAddress,
+    watcher: Box<dyn AddressWatcher>,
+    cancellation_token: CancellationToken,
+) -> Result<(), Error> {
+    let client = get_client(uri).await?;
+    let encoded_address = address.encode(&network);
+
+    let mut next_height = get_tip_height(&client, &cancellation_token).await? + 1;
+    let mut reported_from_mempool: HashSet<TxId> = HashSet::new();
+    loop {
+        let tip_height = get_tip_height(&client, &cancellation_token).await?;
+        if tip_height >= next_height {
+            let transactions = webrequest_with_retry(
+                || async {
+                    client
+                        .clone()
+                        .get_taddress_txids(TransparentAddressBlockFilter {
+                            address: encoded_address.clone(),
+                            range: Some(BlockRange {
+                                start: Some(BlockId {
+                                    height: next_height,
+                                    ..Default::default()
+                                }),
+                                end: Some(BlockId {
+                                    height: tip_height,
+                                    ..Default::default()
+                                }),
+                            }),
+                        })
+                        .await?
+                        .into_inner()
+                        .try_collect::<Vec<_>>()
+                        .await
+                },
+                cancellation_token.clone(),
+            )
+            .await?;
+            for rawtx in transactions {
+                let height = BlockHeight::from_u32(rawtx.height as u32);
+                let tx =
+                    Transaction::read(&rawtx.data[..], BranchId::for_height(&network, height))?;
+                reported_from_mempool.remove(&tx.txid());
+                watcher.transaction_seen(
+                    encoded_address.clone(),
+                    tx.txid().as_ref().to_vec(),
+                    Some(height.into()),
+                );
+            }
+
+            next_height = tip_height + 1;
+        }
+
+        // The mempool stream ends when the next block is mined, at which point we check the new block(s).
+        let expected_height = BlockHeight::from_u32(next_height as u32);
+        let mut mempool = client
+            .clone()
+            .get_mempool_stream(Empty {})
+            .await?
+            .into_inner();
+        loop {
+            let rawtx = select! {
+                r = mempool.message() => r?,
+                _ = cancellation_token.cancelled() => return Err(Error::Canceled),
+            };
+            let rawtx = match rawtx {
+                Some(t) => t,
+                None => break,
+            };
+
+            let tx = Transaction::read(
+                &rawtx.data[..],
+                BranchId::for_height(&network, expected_height),
+            )?;
+            let pays_address = tx.transparent_bundle().is_some_and(|b| {
+                b.vout
+                    .iter()
+                    .any(|o| o.recipient_address() == Some(address))
+            });
+            if pays_address && reported_from_mempool.insert(tx.txid()) {
+                watcher.transaction_seen(
+                    encoded_address.clone(),
+                    tx.txid().as_ref().to_vec(),
+                    None,
+                );
+            }
+        }
+    }
+}
+
+pub(crate) async fn get_tip_height(
+    client: &CompactTxStreamerClient<Channel>,
+    cancellation_token: &CancellationToken,
+) -> Result<u64, Status> {
+    webrequest_with_retry(
+        || async {
+            Ok(client
+                .clone()
+                .get_latest_block(service::ChainSpec::default())
+                .await?
+                .into_inner()
+                .height)
+        },
+        cancellation_token.clone(),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use zcash_client_backend::proto::{compact_formats::CompactBlock, service::RawTransaction};
+    use zcash_primitives::transaction::{
+        components::{
+            amount::NonNegativeAmount,
+            transparent::{self, TxOut},
+        },
+        Authorized, TransactionData, TxVersion,
+    };
+
+    use crate::mock_lightwalletd::{MockChain, MockLightwalletd};
+
+    use super::*;
+
+    #[derive(Debug, Default, Clone)]
+    struct RecordingWatcher {
+        seen: Arc<Mutex<Vec<(Vec<u8>, Option<u32>)>>>,
+    }
+
+    impl AddressWatcher for RecordingWatcher {
+        fn transaction_seen(&self, _address: String, txid: Vec<u8>, mined_height: Option<u32>) {
+            self.seen.lock().unwrap().push((txid, mined_height));
+        }
+    }
+
+    impl RecordingWatcher {
+        async fn wait_for(&self, count: usize) -> Vec<(Vec<u8>, Option<u32>)> {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            loop {
+                let seen = self.seen.lock().unwrap().clone();
+                if seen.len() >= count || Instant::now() > deadline {
+                    return seen;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    fn payment_to(address: TransparentAddress) -> Transaction {
+        TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            BranchId::Nu5,
+            0,
+            BlockHeight::from(0),
+            Some(transparent::Bundle {
+                vin: vec![],
+                vout: vec![TxOut {
+                    value: NonNegativeAmount::const_from_u64(50_000),
+                    script_pubkey: address.script(),
+                }],
+                authorization: transparent::Authorized,
+            }),
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap()
+    }
+
+    #[tokio_shared_rt::test]
+    async fn test_watch_address() {
+        let network = Network::TestNetwork;
+        let address = TransparentAddress::PublicKeyHash([7; 20]);
+        let tx = payment_to(address);
+        let txid = tx.txid().as_ref().to_vec();
+        let mut data = Vec::new();
+        tx.write(&mut data).unwrap();
+
+        let mut chain = MockChain::new("test");
+        for height in 100..=101 {
+            chain.push_block(CompactBlock {
+                height,
+                ..Default::default()
+            });
+        }
+        chain.mempool.push(RawTransaction {
+            data: data.clone(),
+            height: 0,
+        });
+        let server = MockLightwalletd::start(chain).await.unwrap();
+
+        let watcher = RecordingWatcher::default();
+        let cancellation_token = CancellationToken::new();
+        let task = tokio::spawn(watch_address(
+            server.uri.clone(),
+            network,
+            address,
+            Box::new(watcher.clone()),
+            cancellation_token.clone(),
+        ));
+
+        // The payment is reported once while it waits in the mempool, however often the mempool is checked.
+        assert_eq!(vec![(txid.clone(), None)], watcher.wait_for(1).await);
+
+        // Mine the payment.
+        {
+            let mut chain = server.chain.lock().unwrap();
+            chain.mempool.clear();
+            chain.push_block(CompactBlock {
+                height: 102,
+                ..Default::default()
+            });
+            chain
+                .transactions
+                .insert(txid.clone(), RawTransaction { data, height: 102 });
+            chain
+                .taddress_txids
+                .insert(address.encode(&network), vec![txid.clone()]);
+        }
+
+        assert_eq!(
+            vec![(txid.clone(), None), (txid, Some(102))],
+            watcher.wait_for(2).await
+        );
+
+        cancellation_token.cancel();
+        assert!(task.await.unwrap().is_err());
+    }
+}